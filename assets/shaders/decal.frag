@@ -0,0 +1,58 @@
+#version 420 core
+
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+out vec4 frag_colour;
+
+uniform sampler2D depth_texture; // Copy of the scene depth, the same size as the framebuffer
+uniform sampler2D decal_texture;
+uniform mat4 u_inverse_view_projection; // Of the matrix in the constant buffer, jitter included
+uniform mat4 u_inverse_decal_matrix; // World space to the unit cube
+uniform vec3 u_decal_normal; // Direction the decal faces, its local +Z
+uniform float u_normal_fade; // Surfaces facing the decal less than this (cosine) are left alone
+uniform vec4 u_viewport; // x, y, width, height in pixels
+uniform bool u_reversed_z; // Depth is clip space z as-is and the far plane is at 0, see Projection in camera.rs
+
+// Lit the same way as the diffuse part of lit.frag, so decals sit in the scene's lighting
+uniform vec3 u_sun_direction;
+uniform vec3 u_sun_colour;
+const vec3 ambient_colour = vec3(0.3);
+const float PI = 3.14159265;
+
+void main()
+{
+	// Reconstruct the world position of the opaque surface behind this pixel
+	float depth = texelFetch(depth_texture, ivec2(gl_FragCoord.xy), 0).r;
+	vec2 ndc = (gl_FragCoord.xy - u_viewport.xy) / u_viewport.zw * 2.0 - 1.0;
+	vec4 world = u_inverse_view_projection * vec4(ndc, u_reversed_z ? depth : depth * 2.0 - 1.0, 1.0);
+	vec3 position = world.xyz / world.w;
+
+	// Derivatives have to be taken before any pixel gets discarded
+	vec3 normal = normalize(cross(dFdx(position), dFdy(position)));
+	if (dot(normal, u_camera_position.xyz - position) < 0.0) {
+		normal = -normal;
+	}
+
+	// Only surfaces inside the box, facing the decal
+	vec3 local = (u_inverse_decal_matrix * vec4(position, 1.0)).xyz;
+	float facing = dot(normal, u_decal_normal);
+	if (any(greaterThan(abs(local), vec3(0.5))) || facing <= u_normal_fade) {
+		discard;
+	}
+	float fade = clamp((facing - u_normal_fade) / max(1.0 - u_normal_fade, 1e-4) * 4.0, 0.0, 1.0);
+
+	// Textures are stored gamma encoded, shade in linear space
+	vec4 albedo = texture(decal_texture, local.xy + 0.5);
+	vec3 base_colour = pow(albedo.rgb, vec3(2.2));
+	float n_dot_l = max(dot(normal, u_sun_direction), 0.0);
+	vec3 colour = base_colour / PI * u_sun_colour * n_dot_l + ambient_colour * base_colour;
+	frag_colour = vec4(colour, albedo.a * fade);
+}
@@ -0,0 +1,28 @@
+#version 420 core
+
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+uniform mat4 u_decal_matrix; // Unit cube to world space
+
+// Unit cube generated from the vertex index, bit 0 of a corner is x, bit 1 is y and bit 2 is z. Faces wind
+// counter-clockwise seen from outside
+const int cube_indices[36] = int[36](
+	4, 6, 2, 4, 2, 0,  1, 3, 7, 1, 7, 5,
+	0, 1, 5, 0, 5, 4,  6, 7, 3, 6, 3, 2,
+	2, 3, 1, 2, 1, 0,  4, 5, 7, 4, 7, 6
+);
+
+void main()
+{
+	int corner = cube_indices[gl_VertexID];
+	vec3 position = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) - 0.5;
+	gl_Position = u_view_projection_matrix * u_decal_matrix * vec4(position, 1.0);
+}
@@ -0,0 +1,91 @@
+#version 420 core
+
+// Dissolve, as an example of driving a custom shader with the material's custom parameters. Drawn with lit.vert.
+// The surface burns away in noise and comes back again, with a glowing edge where it's dissolving:
+// u_custom0    dissolve cycles per second
+// u_custom1    noise cells per texture coordinate unit
+// u_custom2    width of the glowing edge, in noise values
+// u_customVec0 edge colour in rgb, brightness in a
+
+// Vertex output / Fragment input
+in vec3 o_position;
+in vec4 o_colour;
+in vec3 o_normal;
+in vec3 o_tangent;
+in vec3 o_bitangent;
+in vec2 o_uv0;
+in vec2 o_uv1;
+
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+uniform sampler2D colour_texture;
+uniform sampler2DArray colour_array;
+uniform bool u_texture_arrays; // Material textures are layers of the arrays instead of the 2D textures
+uniform ivec3 u_texture_layers; // Albedo, metallic roughness and height layer
+uniform float u_lod_bias;
+uniform vec3 u_debug_tint; // White unless a debug view colours the mesh
+uniform vec4 u_tint; // Per submission, white unless drawn with draw_model_tinted
+uniform vec3 u_sun_direction; // Towards the sun
+uniform vec3 u_sun_colour;
+uniform float u_custom0;
+uniform float u_custom1;
+uniform float u_custom2;
+uniform vec4 u_customVec0;
+
+out vec4 frag_color;
+
+#include "random.glsl"
+
+const float TAU = 6.28318530718;
+const vec3 ambient_colour = vec3(0.3);
+
+vec4 sample_colour(vec2 uv) {
+    if (u_texture_arrays) {
+        return texture(colour_array, vec3(uv, u_texture_layers.x), u_lod_bias);
+    }
+    return texture(colour_texture, uv, u_lod_bias);
+}
+
+// The cell coordinates are whole numbers, their bits name the cell
+float hash(vec2 cell) {
+    return random_uniform(floatBitsToUint(cell.x), floatBitsToUint(cell.y), 0u, 0u);
+}
+
+// Value noise, smoothly interpolated between random values at the cell corners
+float noise(vec2 position) {
+    vec2 cell = floor(position);
+    vec2 f = fract(position);
+    vec2 weight = f * f * (3.0 - 2.0 * f);
+    float bottom = mix(hash(cell), hash(cell + vec2(1.0, 0.0)), weight.x);
+    float top = mix(hash(cell + vec2(0.0, 1.0)), hash(cell + vec2(1.0, 1.0)), weight.x);
+    return mix(bottom, top, weight.y);
+}
+
+void main() {
+    // Two octaves, so the edge isn't made of blobs of a single size
+    vec2 position = o_uv0 * max(u_custom1, 1e-3);
+    float value = noise(position) * 0.65 + noise(position * 2.7 + 17.0) * 0.35;
+
+    // Goes from nothing dissolved to everything and back, slightly past both ends so the edge leaves the surface
+    float threshold = (0.5 - 0.5 * cos(u_time * u_custom0 * TAU)) * (1.0 + 2.0 * u_custom2) - u_custom2;
+    if (value < threshold) {
+        discard;
+    }
+
+    vec4 albedo = sample_colour(o_uv0);
+    vec3 base_colour = pow(albedo.rgb, vec3(2.2)) * u_tint.rgb * u_debug_tint;
+    vec3 normal = normalize(o_normal);
+    vec3 colour = base_colour * (ambient_colour + u_sun_colour * max(dot(normal, u_sun_direction), 0.0));
+
+    float edge = 1.0 - smoothstep(0.0, max(u_custom2, 1e-4), value - threshold);
+    colour += u_customVec0.rgb * u_customVec0.a * edge;
+    frag_color = vec4(colour, u_tint.a);
+}
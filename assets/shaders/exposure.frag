@@ -0,0 +1,85 @@
+#version 420 core
+
+out vec4 frag_colour;
+
+uniform int u_stage; // 0 meters the scene, 1 adapts the exposure, 2 outputs it to multiply the scene, 3 draws the heat map
+uniform sampler2D source_texture; // The scene when metering, the metering grid when adapting
+uniform sampler2D exposure_texture; // Adapted EV, 1x1
+uniform vec2 u_target_size; // Pixels drawn by this stage
+uniform vec2 u_uv_scale; // Part of the scene texture that was rendered to
+
+// Metering, mirrors ExposureSettings in exposure.rs
+uniform int u_metering;
+uniform float u_centre_falloff;
+uniform vec2 u_spot; // Bottom left origin
+uniform float u_spot_radius;
+uniform float u_aspect;
+
+// Adaptation
+uniform int u_metering_level; // Mip level of the metering grid that's a single texel
+uniform bool u_history_valid;
+uniform float u_compensation;
+uniform float u_min_ev;
+uniform float u_max_ev;
+uniform float u_brighter_rate; // Fraction of the way to the metered EV covered this frame, see adaptation_rate
+uniform float u_darker_rate;
+
+const int METERING_CENTRE_WEIGHTED = 1;
+const int METERING_SPOT = 2;
+const float EV_ZERO_LUMINANCE = 0.125; // Reflected light meter calibration at ISO 100
+const float MIDDLE_GREY = 0.18;
+
+// How much a point on the screen counts towards the metered brightness, uv spans the rendered part of the screen
+float metering_weight(vec2 uv) {
+    if (u_metering == METERING_CENTRE_WEIGHTED) {
+        vec2 offset = (uv - 0.5) * vec2(u_aspect, 1.0);
+        return exp(-dot(offset, offset) / (u_centre_falloff * u_centre_falloff));
+    }
+    if (u_metering == METERING_SPOT) {
+        vec2 offset = (uv - u_spot) * vec2(u_aspect, 1.0);
+        return 1.0 - smoothstep(u_spot_radius * 0.5, u_spot_radius, length(offset));
+    }
+    return 1.0;
+}
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / u_target_size;
+
+    // Log luminance, so the average over the grid is a geometric mean and a few highlights don't dominate it
+    if (u_stage == 0) {
+        vec3 colour = max(texture(source_texture, uv * u_uv_scale).rgb, 0.0);
+        float luminance = dot(colour, vec3(0.2126, 0.7152, 0.0722));
+        float weight = metering_weight(uv);
+        frag_colour = vec4(log2(max(luminance, 1e-5)) * weight, weight, 0.0, 1.0);
+        return;
+    }
+
+    float previous = texelFetch(exposure_texture, ivec2(0), 0).r;
+    if (u_stage == 1) {
+        // Nothing was weighted, a spot off the screen for example, so the exposure stays where it is
+        vec2 metered = textureLod(source_texture, vec2(0.5), float(u_metering_level)).rg;
+        if (metered.y < 1e-6) {
+            frag_colour = vec4(u_history_valid ? previous : 0.0);
+            return;
+        }
+        float ev = clamp(metered.x / metered.y - log2(EV_ZERO_LUMINANCE), u_min_ev, u_max_ev) - u_compensation;
+        if (!u_history_valid) {
+            frag_colour = vec4(ev);
+            return;
+        }
+        float rate = ev > previous ? u_brighter_rate : u_darker_rate;
+        frag_colour = vec4(previous + (ev - previous) * rate);
+        return;
+    }
+
+    // Blended as a multiplier, exposes the metered average to middle grey
+    if (u_stage == 2) {
+        frag_colour = vec4(vec3(MIDDLE_GREY / (EV_ZERO_LUMINANCE * exp2(previous))), 1.0);
+        return;
+    }
+
+    // Blue where the metering ignores the screen, through green, to red where it counts fully
+    float weight = metering_weight(uv);
+    vec3 heat = clamp(vec3(weight * 2.0 - 1.0, 1.0 - abs(weight * 2.0 - 1.0), 1.0 - weight * 2.0), 0.0, 1.0);
+    frag_colour = vec4(heat, 0.4);
+}
@@ -1,17 +1,88 @@
-#version 460
+#version 420 core
 
 out vec4 frag_colour;
 in vec2 texcoord;
 
-uniform layout (binding = 0) sampler2D scene_colour;
+uniform sampler2D scene_colour; // Linear
+uniform bool u_dither;
+uniform bool u_anaglyph; // The left and right halves of the scene are a stereo pair, combine them into red and cyan
+uniform int u_output_encoding; // OutputEncoding in graphics.rs
+uniform float u_paper_white_nits; // Brightness of 1.0 in the scene, for the HDR encodings
+uniform float u_peak_nits;
+uniform vec2 u_uv_scale; // Same as in the vertex shader
+
+const int OUTPUT_SRGB_FRAMEBUFFER = 0;
+const int OUTPUT_SRGB_SHADER = 1;
+const int OUTPUT_SCRGB = 2;
+const int OUTPUT_PQ = 3;
+
+// 4x4 Bayer matrix, normalized to [0, 1)
+const float bayer[16] = float[16](
+	 0.0 / 16.0,  8.0 / 16.0,  2.0 / 16.0, 10.0 / 16.0,
+	12.0 / 16.0,  4.0 / 16.0, 14.0 / 16.0,  6.0 / 16.0,
+	 3.0 / 16.0, 11.0 / 16.0,  1.0 / 16.0,  9.0 / 16.0,
+	15.0 / 16.0,  7.0 / 16.0, 13.0 / 16.0,  5.0 / 16.0
+);
+
+// HDR10 uses the wider BT.2020 primaries, the scene is rendered with the sRGB (BT.709) ones
+const mat3 BT709_TO_BT2020 = mat3(
+	0.6274, 0.0691, 0.0164,
+	0.3293, 0.9195, 0.0880,
+	0.0433, 0.0114, 0.8956
+);
+
+vec3 linear_to_srgb(vec3 colour) {
+	return mix(colour * 12.92, 1.055 * pow(colour, vec3(1.0 / 2.4)) - 0.055, greaterThan(colour, vec3(0.0031308)));
+}
+
+vec3 srgb_to_linear(vec3 colour) {
+	return mix(colour / 12.92, pow((colour + 0.055) / 1.055, vec3(2.4)), greaterThan(colour, vec3(0.04045)));
+}
+
+// Leaves everything under 75% of the peak alone, and rolls off what's above so highlights don't clip
+vec3 tonemap_nits(vec3 nits) {
+	float knee = u_peak_nits * 0.75;
+	vec3 over = max(nits - knee, 0.0);
+	return min(nits, vec3(knee)) + (u_peak_nits - knee) * (1.0 - exp(-over / (u_peak_nits - knee)));
+}
+
+// SMPTE ST 2084 perceptual quantizer, 1.0 is 10000 nits
+vec3 nits_to_pq(vec3 nits) {
+	vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(0.1593017578125));
+	return pow((0.8359375 + 18.8515625 * y) / (1.0 + 18.6875 * y), vec3(78.84375));
+}
 
 void main()
 {
 	//Get scene colour
     vec4 colour = texture(scene_colour, texcoord);
+	if (u_anaglyph) {
+		vec2 eye_uv = texcoord * vec2(0.5, 1.0);
+		vec4 left = texture(scene_colour, eye_uv);
+		vec4 right = texture(scene_colour, eye_uv + vec2(u_uv_scale.x * 0.5, 0.0));
+		colour = vec4(left.r, right.gb, max(left.a, right.a));
+	}
 	if (colour.a < 0.01f)
 		discard;
-	
-	//Return color
-	frag_colour = colour;
-} 
\ No newline at end of file
+
+	//HDR outputs, scRGB is linear with 1.0 at 80 nits
+	vec3 linear = max(colour.rgb, 0.0);
+	if (u_output_encoding == OUTPUT_SCRGB) {
+		frag_colour = vec4(tonemap_nits(linear * u_paper_white_nits) / 80.0, colour.a);
+		return;
+	}
+	if (u_output_encoding == OUTPUT_PQ) {
+		frag_colour = vec4(nits_to_pq(tonemap_nits(BT709_TO_BT2020 * linear * u_paper_white_nits)), colour.a);
+		return;
+	}
+
+	//Offset by less than one 8-bit step of the encoded value, so gradients break up into a pattern instead of bands
+	vec3 encoded = linear_to_srgb(min(linear, 1.0));
+	if (u_dither) {
+		ivec2 pixel = ivec2(gl_FragCoord.xy) % 4;
+		encoded = clamp(encoded + (bayer[pixel.y * 4 + pixel.x] - 0.5) / 255.0, 0.0, 1.0);
+	}
+
+	//Return color, an sRGB framebuffer encodes it again on write
+	frag_colour = vec4(u_output_encoding == OUTPUT_SRGB_FRAMEBUFFER ? srgb_to_linear(encoded) : encoded, colour.a);
+}
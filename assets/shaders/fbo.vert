@@ -1,10 +1,12 @@
-#version 460
+#version 420 core
 in layout (location = 0) vec2 a_position;
 in layout (location = 1) vec2 a_texcoord;
 out vec2 texcoord;
 
+uniform vec2 u_uv_scale; // Rendered part of the scene texture, below 1 when rendering at a lower resolution
+
 void main()
 {
     gl_Position = vec4(a_position, 0, 1);
-	texcoord = a_texcoord;
+	texcoord = a_texcoord * u_uv_scale;
 }
\ No newline at end of file
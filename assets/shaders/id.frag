@@ -0,0 +1,42 @@
+#version 420 core
+
+// Writes what each pixel came from into the ID buffer, drawn with lit.vert. Only alpha masks are applied, everything
+// else about the material is ignored
+
+in vec2 o_uv0;
+
+uniform sampler2D colour_texture;
+uniform sampler2DArray colour_array;
+uniform bool u_texture_arrays; // Material textures are layers of the arrays instead of the 2D textures
+uniform ivec3 u_texture_layers; // Albedo, metallic roughness and height layer
+uniform float u_lod_bias;
+uniform float u_alpha_cutoff; // Albedo alpha below this is cut out, negative for materials that aren't alpha masked
+uniform uint u_entry; // Index of the mesh in the renderer's ID table, plus one so 0 is empty
+uniform uint u_key; // Palette key of the mesh in the current mode
+uniform bool u_triangle_keys; // Key each triangle separately
+
+out uvec4 frag_id; // Entry, triangle, palette key
+
+// Integer hash (lowbias32), the same as id_view::hash
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
+
+void main() {
+    if (u_alpha_cutoff >= 0.0) {
+        float alpha = u_texture_arrays
+            ? texture(colour_array, vec3(o_uv0, u_texture_layers.x), u_lod_bias).a
+            : texture(colour_texture, o_uv0, u_lod_bias).a;
+        if (alpha < u_alpha_cutoff) {
+            discard;
+        }
+    }
+    uint triangle = uint(gl_PrimitiveID);
+    uint key = u_triangle_keys ? hash(u_key ^ hash(triangle)) : u_key;
+    frag_id = uvec4(u_entry, triangle, key, 0u);
+}
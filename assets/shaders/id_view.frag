@@ -0,0 +1,32 @@
+#version 420 core
+
+// Shows the ID buffer in palette colours, over the presented frame. Pixels without geometry stay black
+
+uniform usampler2D id_texture;
+uniform vec2 u_uv_scale; // Render resolution over window resolution
+
+out vec4 frag_colour;
+
+// The same as id_view::hash and id_view::palette
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
+
+vec3 palette(uint key) {
+    uint bits = hash(key);
+    float hue = float(bits & 0xFFFFu) / 65536.0;
+    float saturation = 0.55 + 0.45 * float((bits >> 16) & 0xFFu) / 255.0;
+    float value = 0.65 + 0.35 * float(bits >> 24) / 255.0;
+    vec3 k = mod(vec3(5.0, 3.0, 1.0) + hue * 6.0, 6.0);
+    return value - value * saturation * clamp(min(k, 4.0 - k), 0.0, 1.0);
+}
+
+void main() {
+    uvec4 id = texelFetch(id_texture, ivec2(gl_FragCoord.xy * u_uv_scale), 0);
+    frag_colour = vec4(id.x == 0u ? vec3(0.0) : palette(id.z), 1.0);
+}
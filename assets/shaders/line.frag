@@ -0,0 +1,11 @@
+#version 420 core
+
+in vec4 o_colour;
+
+out vec4 frag_colour;
+
+void main()
+{
+	// Linear colour like the rest of the scene, blended over it by alpha
+	frag_colour = o_colour;
+}
@@ -0,0 +1,27 @@
+#version 420 core
+
+// Vertex input
+layout (location = 0) in vec3 i_position;
+layout (location = 1) in vec4 i_colour;
+
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+// Batch specific data
+uniform mat4 u_model_matrix;
+uniform vec4 u_colour; // Multiplies every vertex colour of the batch
+
+out vec4 o_colour;
+
+void main()
+{
+	o_colour = i_colour * u_colour;
+	gl_Position = u_view_projection_matrix * u_model_matrix * vec4(i_position, 1.0);
+}
@@ -1,6 +1,7 @@
 #version 420 core
 
 // Vertex output / Fragment input
+in vec3 o_position;
 in vec4 o_colour;
 in vec3 o_normal;
 in vec3 o_tangent;
@@ -8,11 +9,177 @@ in vec3 o_bitangent;
 in vec2 o_uv0;
 in vec2 o_uv1;
 
-layout (binding = 0) uniform sampler2D colour_texture;
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+uniform sampler2D colour_texture;
+uniform sampler2D mtl_rgh_texture;
+uniform sampler2D height_texture;
+uniform sampler2DArray colour_array;
+uniform sampler2DArray mtl_rgh_array;
+uniform sampler2DArray height_array;
+uniform bool u_texture_arrays; // Material textures are layers of the arrays instead of the 2D textures
+uniform ivec3 u_texture_layers; // Albedo, metallic roughness and height layer
+uniform float u_lod_bias;
+uniform float u_roughness;
+uniform float u_metallic;
+uniform bool u_has_mtl_rgh_texture;
+uniform bool u_has_height_texture;
+uniform float u_height_scale; // Depth of the height map's black below its white, in texture coordinates
+uniform float u_height_bias; // Height map value at the actual surface
+uniform vec2 u_parallax_steps; // Steps when looking straight at the surface, and at grazing angles
+uniform vec3 u_debug_tint; // White unless a debug view colours the mesh
+uniform vec4 u_tint; // Per submission, white unless drawn with draw_model_tinted
+uniform float u_alpha_cutoff; // Albedo alpha below this is cut out, negative for materials that aren't alpha masked
+uniform bool u_alpha_to_coverage; // MSAA is on and the mask is written as sample coverage instead of discarded
+uniform float u_custom0; // Material custom parameters, free for quick effects in custom shaders. Unused here
+uniform float u_custom1;
+uniform float u_custom2;
+uniform float u_custom3;
+uniform vec4 u_customVec0;
+uniform vec4 u_customVec1;
 
 out vec4 frag_color;
 
+const float PI = 3.14159265;
+
+// There are no scene lights yet, so everything is lit by the sun from the sky settings and a flat ambient term
+uniform vec3 u_sun_direction; // Towards the sun
+uniform vec3 u_sun_colour;
+const vec3 ambient_colour = vec3(0.3);
+
+// Material texture reads, from whichever kind of texture the renderer keeps them in
+vec4 sample_colour(vec2 uv) {
+    if (u_texture_arrays) {
+        return texture(colour_array, vec3(uv, u_texture_layers.x), u_lod_bias);
+    }
+    return texture(colour_texture, uv, u_lod_bias);
+}
+
+vec4 sample_mtl_rgh(vec2 uv) {
+    if (u_texture_arrays) {
+        return texture(mtl_rgh_array, vec3(uv, u_texture_layers.y), u_lod_bias);
+    }
+    return texture(mtl_rgh_texture, uv, u_lod_bias);
+}
+
+float sample_height(vec2 uv, vec2 uv_dx, vec2 uv_dy) {
+    if (u_texture_arrays) {
+        return textureGrad(height_array, vec3(uv, u_texture_layers.z), uv_dx, uv_dy).r;
+    }
+    return textureGrad(height_texture, uv, uv_dx, uv_dy).r;
+}
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * denominator * denominator);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Parallax occlusion mapping: march the view ray down through the height field in tangent space, and return the
+// texture coordinate where it first goes below the surface
+vec2 parallax_uv(vec2 uv, vec3 view) {
+    vec3 view_tangent = normalize(vec3(dot(view, o_tangent), dot(view, o_bitangent), dot(view, o_normal)));
+    vec2 offset_per_height = view_tangent.xy / max(view_tangent.z, 0.1) * u_height_scale;
+    float steps = floor(mix(u_parallax_steps.y, u_parallax_steps.x, clamp(view_tangent.z, 0.0, 1.0)));
+    float step_height = 1.0 / steps;
+
+    // Derivatives of the unshifted coordinate, so the march doesn't break mip selection
+    vec2 uv_dx = dFdx(uv);
+    vec2 uv_dy = dFdy(uv);
+
+    // The ray enters the height field at height 1 and is at the surface at height u_height_bias
+    float ray_height = 1.0;
+    vec2 ray_uv = uv + offset_per_height * (1.0 - u_height_bias);
+    float field_height = sample_height(ray_uv, uv_dx, uv_dy);
+    float previous_ray_height = ray_height;
+    float previous_field_height = field_height;
+    for (float i = 0.0; i < steps && field_height < ray_height; i += 1.0) {
+        previous_ray_height = ray_height;
+        previous_field_height = field_height;
+        ray_height -= step_height;
+        ray_uv -= offset_per_height * step_height;
+        field_height = sample_height(ray_uv, uv_dx, uv_dy);
+    }
+
+    // Intersect the ray with a straight line between the last two samples
+    float after = field_height - ray_height;
+    float before = previous_field_height - previous_ray_height;
+    float weight = after / max(after - before, 1e-5);
+    float hit_height = mix(ray_height, previous_ray_height, clamp(weight, 0.0, 1.0));
+    return uv + offset_per_height * (hit_height - u_height_bias);
+}
+
 void main() {
-    frag_color = vec4(1.0, 1.0, 1.0, 1.0) * texture(colour_texture, o_uv0);
-    //frag_color = vec4((o_normal + 1.0) / 2.0, 1);
-}
\ No newline at end of file
+    // Materials without a height map skip the march entirely
+    vec3 view = normalize(u_camera_position.xyz - o_position);
+    vec2 uv = o_uv0;
+    if (u_has_height_texture) {
+        uv = parallax_uv(o_uv0, view);
+    }
+
+    // Textures are stored gamma encoded, shade in linear space
+    vec4 albedo = sample_colour(uv);
+
+    // Alpha masks: rescale the alpha around the cutoff so it goes from 0 to 1 over about one pixel, which turns into
+    // a smooth edge as coverage. Without MSAA all that's left is cutting it out
+    float alpha = albedo.a;
+    if (u_alpha_cutoff >= 0.0) {
+        if (u_alpha_to_coverage) {
+            alpha = clamp((alpha - u_alpha_cutoff) / max(fwidth(alpha), 1e-4) + 0.5, 0.0, 1.0);
+        } else {
+            if (alpha < u_alpha_cutoff) {
+                discard;
+            }
+            alpha = 1.0;
+        }
+    }
+    vec3 base_colour = pow(albedo.rgb, vec3(2.2)) * u_tint.rgb * u_debug_tint;
+
+    // glTF convention: roughness in green, metallic in blue, both scaled by the material factors
+    float roughness = u_roughness;
+    float metallic = u_metallic;
+    if (u_has_mtl_rgh_texture) {
+        vec4 mtl_rgh = sample_mtl_rgh(uv);
+        roughness *= mtl_rgh.g;
+        metallic *= mtl_rgh.b;
+    }
+    roughness = clamp(roughness, 0.04, 1.0);
+
+    // Cook-Torrance specular with a Lambert diffuse, following the glTF metallic-roughness model
+    vec3 normal = normalize(o_normal);
+    vec3 halfway = normalize(view + u_sun_direction);
+    float n_dot_l = max(dot(normal, u_sun_direction), 0.0);
+    float n_dot_v = max(dot(normal, view), 1e-4);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+    float h_dot_v = max(dot(halfway, view), 0.0);
+
+    vec3 f0 = mix(vec3(0.04), base_colour, metallic);
+    vec3 fresnel = fresnel_schlick(h_dot_v, f0);
+    vec3 specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+                  / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_colour / PI;
+    vec3 colour = (diffuse + specular) * u_sun_colour * n_dot_l + ambient_colour * base_colour;
+
+    // Stays linear until the final blit encodes it for the window
+    frag_color = vec4(colour, alpha * u_tint.a);
+}
@@ -1,4 +1,4 @@
-#version 460
+#version 420 core
 
 // Vertex input
 layout (location = 0) in vec3 i_position;
@@ -12,26 +12,74 @@ layout (location = 5) in vec2 i_uv1;
 layout (std140, binding = 0) uniform const_buffer
 {
 	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
 };
 
 // Model specific data
 uniform mat4 u_model_matrix;
+uniform mat3 u_normal_matrix; // Inverse transpose of the model matrix
+
+// Wind, the amplitude is 0 for materials that don't sway
+uniform float u_wind_amplitude;
+uniform float u_wind_frequency; // Sways per second
+uniform vec2 u_wind_height_range; // World space height of the mesh's bottom and top
+uniform vec3 u_wind_direction;
+uniform float u_wind_strength;
+uniform float u_wind_gustiness;
+uniform vec3 u_world_up; // See WorldUp in structs.rs
+
+// Motion, only the object motion pass sets these. Last frame's model matrix, and both frames' view projection without
+// the TAA jitter
+uniform mat4 u_previous_model_matrix;
+uniform mat4 u_unjittered_view_projection;
+uniform mat4 u_previous_view_projection;
+
+const float TAU = 6.28318531;
 
 // Vertex output / Fragment input
+out vec3 o_position;
 out vec4 o_colour;
 out vec3 o_normal;
 out vec3 o_tangent;
 out vec3 o_bitangent;
 out vec2 o_uv0;
 out vec2 o_uv1;
+out vec4 o_clip_position;
+out vec4 o_previous_clip_position;
+
+// Sideways push of the wind. Neighbouring plants sway out of step, and the higher up the mesh a vertex is, the further
+// it moves, so the roots stay planted. Vertex colour alpha can hold vertices down further
+vec3 wind_offset(vec3 world_position, float time) {
+	float height = dot(world_position, u_world_up);
+	vec3 horizontal = world_position - u_world_up * height;
+	float mesh_height = u_wind_height_range.y - u_wind_height_range.x;
+	float weight = clamp((height - u_wind_height_range.x) / max(mesh_height, 1e-4), 0.0, 1.0);
+	weight *= weight * i_colour.a;
+	float phase = dot(horizontal, vec3(0.7, 0.43, 0.43)) / max(mesh_height, 1e-4);
+	float sway = sin(time * u_wind_frequency * TAU + phase) + 0.3 * sin(time * u_wind_frequency * 2.3 * TAU + phase * 1.7);
+	float gust = 1.0 + u_wind_gustiness * max(sin(time * 0.4 + dot(horizontal, u_wind_direction) * 0.05), 0.0);
+	return u_wind_direction * (u_wind_strength * u_wind_amplitude * mesh_height * weight * sway * gust);
+}
 
 void main()
 {
-	gl_Position = u_view_projection_matrix * /*u_model_matrix * */vec4(i_position, 1);
+	vec4 world_position = u_model_matrix * vec4(i_position, 1);
+	vec4 previous_world_position = u_previous_model_matrix * vec4(i_position, 1);
+	if (u_wind_amplitude > 0.0) {
+		world_position.xyz += wind_offset(world_position.xyz, u_time);
+		previous_world_position.xyz += wind_offset(previous_world_position.xyz, u_time - u_delta_time);
+	}
+	gl_Position = u_view_projection_matrix * world_position;
+    o_clip_position = u_unjittered_view_projection * world_position;
+    o_previous_clip_position = u_previous_view_projection * previous_world_position;
+    o_position = world_position.xyz;
     o_colour = i_colour;
-    o_normal = i_normal;
-    o_tangent = i_tangent.xyz;
-    o_bitangent = cross(i_normal, i_tangent.xyz) * i_tangent.w;
+    o_normal = u_normal_matrix * i_normal;
+    o_tangent = mat3(u_model_matrix) * i_tangent.xyz;
+    o_bitangent = cross(o_normal, o_tangent) * i_tangent.w;
     o_uv0 = i_uv0;
     o_uv1 = i_uv1;
 }
\ No newline at end of file
@@ -0,0 +1,23 @@
+#version 420 core
+
+out vec2 frag_velocity;
+
+uniform sampler2D depth_texture;
+uniform mat4 u_inverse_view_projection;
+uniform mat4 u_previous_view_projection;
+uniform ivec2 u_render_size;
+uniform bool u_reversed_z; // Depth is clip space z as-is, see Projection in camera.rs
+
+void main()
+{
+	//Reconstruct the world position, and find where it was on screen last frame. Only the camera moves for now
+	vec2 uv = gl_FragCoord.xy / vec2(u_render_size);
+	float depth = texelFetch(depth_texture, ivec2(gl_FragCoord.xy), 0).r;
+	float ndc_depth = u_reversed_z ? depth : depth * 2.0 - 1.0;
+	vec4 world_position = u_inverse_view_projection * vec4(uv * 2.0 - 1.0, ndc_depth, 1.0);
+	vec4 previous_clip = u_previous_view_projection * vec4(world_position.xyz / world_position.w, 1.0);
+	vec2 previous_uv = previous_clip.xy / previous_clip.w * 0.5 + 0.5;
+
+	//Velocity in uv units over the rendered area
+	frag_velocity = uv - previous_uv;
+}
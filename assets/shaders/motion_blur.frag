@@ -0,0 +1,55 @@
+#version 420 core
+
+out vec4 frag_colour;
+
+uniform sampler2D colour_texture; // Copy of the scene colour
+uniform sampler2D velocity_texture;
+uniform ivec2 u_size; // Scene colour resolution
+uniform ivec2 u_render_size; // Velocity resolution, lower than the scene colour's when upscaled temporally
+uniform float u_strength; // Fraction of the frame's motion to smear
+uniform float u_max_radius; // Pixels
+uniform int u_samples;
+
+// Written by object_motion.frag for meshes that teleported
+const float TELEPORTED = -2.0;
+// Render pixels between the velocities looked at around each pixel
+const int DILATION_STRIDE = 4;
+
+vec2 velocity_at(ivec2 pixel) {
+	vec2 velocity = texelFetch(velocity_texture, clamp(pixel, ivec2(0), u_render_size - 1), 0).xy;
+	return velocity.x <= TELEPORTED ? vec2(0.0) : velocity;
+}
+
+void main()
+{
+	vec2 position = gl_FragCoord.xy;
+	vec2 texture_size = vec2(textureSize(colour_texture, 0));
+
+	//Dilate to the fastest motion around the pixel, so a moving silhouette smears over what's behind it too
+	ivec2 render_pixel = ivec2(position / vec2(u_size) * vec2(u_render_size));
+	vec2 velocity = vec2(0.0);
+	for (int y = -1; y <= 1; y++) {
+		for (int x = -1; x <= 1; x++) {
+			vec2 neighbour = velocity_at(render_pixel + ivec2(x, y) * DILATION_STRIDE);
+			if (dot(neighbour, neighbour) > dot(velocity, velocity)) {
+				velocity = neighbour;
+			}
+		}
+	}
+
+	//Average along the motion, centred on the pixel
+	vec2 blur = velocity * vec2(u_size) * u_strength;
+	float length_pixels = length(blur);
+	if (length_pixels < 0.5) {
+		frag_colour = texelFetch(colour_texture, ivec2(position), 0);
+		return;
+	}
+	blur *= min(length_pixels, u_max_radius) / length_pixels;
+	vec4 sum = vec4(0.0);
+	for (int i = 0; i < u_samples; i++) {
+		float t = (float(i) + 0.5) / float(u_samples) - 0.5;
+		vec2 sample_position = clamp(position - blur * t, vec2(0.5), vec2(u_size) - 0.5);
+		sum += texture(colour_texture, sample_position / texture_size);
+	}
+	frag_colour = sum / float(u_samples);
+}
@@ -0,0 +1,41 @@
+#version 420 core
+
+// Writes the motion of a moving mesh into the velocity buffer, over the camera's motion, drawn with lit.vert. Only
+// alpha masks are applied, like in id.frag
+
+in vec2 o_uv0;
+in vec4 o_clip_position;
+in vec4 o_previous_clip_position;
+
+uniform sampler2D colour_texture;
+uniform sampler2DArray colour_array;
+uniform bool u_texture_arrays; // Material textures are layers of the arrays instead of the 2D textures
+uniform ivec3 u_texture_layers; // Albedo, metallic roughness and height layer
+uniform float u_lod_bias;
+uniform float u_alpha_cutoff; // Albedo alpha below this is cut out, negative for materials that aren't alpha masked
+uniform bool u_teleported; // Moved too far since last frame to have any history
+
+out vec2 frag_velocity;
+
+// Puts the history out of reach, so TAA starts over there. Motion blur treats it as still, the same as motion_blur.frag
+const float TELEPORTED = -2.0;
+
+void main() {
+    if (u_alpha_cutoff >= 0.0) {
+        float alpha = u_texture_arrays
+            ? texture(colour_array, vec3(o_uv0, u_texture_layers.x), u_lod_bias).a
+            : texture(colour_texture, o_uv0, u_lod_bias).a;
+        if (alpha < u_alpha_cutoff) {
+            discard;
+        }
+    }
+    if (u_teleported) {
+        frag_velocity = vec2(TELEPORTED);
+        return;
+    }
+
+    // Velocity in uv units over the rendered area, like motion.frag
+    vec2 uv = o_clip_position.xy / o_clip_position.w * 0.5 + 0.5;
+    vec2 previous_uv = o_previous_clip_position.xy / o_previous_clip_position.w * 0.5 + 0.5;
+    frag_velocity = uv - previous_uv;
+}
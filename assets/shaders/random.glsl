@@ -0,0 +1,22 @@
+// Counter-based random numbers, the same algorithm as src/random.rs, which tests that the two agree bit for bit.
+// Shaders pull it in with #include "random.glsl"
+
+// pcg4d from Jarzynski and Olano, "Hash Functions for GPU Rendering" (2020). Every output bit depends on every input
+uvec4 pcg4d(uvec4 v) {
+    v = v * 1664525u + 1013904223u;
+    v.x += v.y * v.w;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v.w += v.y * v.z;
+    v ^= v >> 16u;
+    v.x += v.y * v.w;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v.w += v.y * v.z;
+    return v;
+}
+
+// Uniform number in [0, 1) for sample `dimension` of pixel (x, y) in `frame`, like random::uniform
+float random_uniform(uint x, uint y, uint frame, uint dimension) {
+    return float(pcg4d(uvec4(x, y, frame, dimension)).x >> 8u) / 16777216.0;
+}
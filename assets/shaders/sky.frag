@@ -0,0 +1,45 @@
+#version 420 core
+
+in vec2 o_ndc;
+
+uniform mat4 u_inverse_view_rotation_projection; // Inverse of the projection times the view rotation, no translation
+uniform vec3 u_sun_direction; // Towards the sun
+uniform vec3 u_sun_colour;    // Sun radiance after the atmosphere, the same value the lit shader uses
+uniform float u_turbidity;
+uniform vec3 u_ground_albedo;
+uniform vec3 u_world_up; // Zenith, see WorldUp in structs.rs
+
+out vec4 frag_color;
+
+const float PI = 3.14159265;
+const float SUN_ANGULAR_RADIUS = 0.0047; // Radians, about the real sun's
+
+// Analytic gradient sky: blue zenith fading to a hazy horizon, a glow around the sun and the sun disc itself.
+// Turbidity washes the blue out and widens the glow, the whole sky darkens as the sun sets
+vec3 sky_radiance(vec3 direction) {
+    float haze = clamp((u_turbidity - 1.0) / 9.0, 0.0, 1.0);
+    vec3 zenith = mix(vec3(0.12, 0.30, 0.75), vec3(0.45, 0.52, 0.62), haze);
+    vec3 horizon = mix(vec3(0.65, 0.78, 0.92), vec3(0.85, 0.85, 0.82), haze);
+    float sun_elevation = dot(u_sun_direction, u_world_up);
+    float elevation = dot(direction, u_world_up);
+    float daylight = smoothstep(-0.1, 0.25, sun_elevation);
+    float sun_tint = pow(1.0 - max(sun_elevation, 0.0), 4.0);
+    horizon = mix(horizon, horizon * u_sun_colour / max(max(u_sun_colour.r, u_sun_colour.g), 1e-4), sun_tint);
+
+    float up = max(elevation, 0.0);
+    vec3 colour = mix(horizon, zenith, sqrt(up)) * mix(0.01, 1.0, daylight);
+
+    float cos_angle = dot(direction, u_sun_direction);
+    colour += u_sun_colour * pow(max(cos_angle, 0.0), mix(64.0, 8.0, haze)) * 0.15;
+    colour += u_sun_colour * 20.0 * smoothstep(cos(SUN_ANGULAR_RADIUS * 1.2), cos(SUN_ANGULAR_RADIUS), cos_angle);
+
+    // Below the horizon, a flat ground lit by the sun and the sky
+    vec3 ground = u_ground_albedo * (u_sun_colour * max(sun_elevation, 0.0) + zenith * daylight) / PI;
+    return mix(ground, colour, smoothstep(-0.01, 0.0, elevation));
+}
+
+void main() {
+    vec4 point = u_inverse_view_rotation_projection * vec4(o_ndc, 0.0, 1.0);
+    vec3 direction = normalize(point.xyz / point.w);
+    frag_color = vec4(sky_radiance(direction), 1.0);
+}
@@ -0,0 +1,13 @@
+#version 420 core
+
+uniform bool u_reversed_z;
+
+out vec2 o_ndc;
+
+void main()
+{
+    // Full-screen triangle generated from the vertex index, placed on the far plane so it only covers the background
+    vec2 position = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    o_ndc = position * 2.0 - 1.0;
+    gl_Position = vec4(o_ndc, u_reversed_z ? 0.0 : 1.0, 1);
+}
@@ -0,0 +1,59 @@
+#version 420 core
+
+out float frag_ao;
+
+uniform sampler2D depth_texture;
+uniform sampler2D noise_texture;
+uniform mat4 u_projection;
+uniform mat4 u_inverse_projection;
+uniform vec3 u_kernel[16];
+uniform float u_radius;
+uniform float u_bias;
+uniform float u_intensity;
+uniform ivec2 u_render_size; // Rendered part of the depth texture
+uniform bool u_reversed_z; // Depth is clip space z as-is and the far plane is at 0, see Projection in camera.rs
+
+// Converts a uv over the rendered area to one over the whole texture, staying inside the rendered area
+vec2 texture_uv(vec2 uv) {
+	return clamp(uv, 0.0, 1.0) * vec2(u_render_size) / vec2(textureSize(depth_texture, 0));
+}
+
+vec3 view_position(vec2 uv) {
+	float depth = texture(depth_texture, texture_uv(uv)).r;
+	float ndc_depth = u_reversed_z ? depth : depth * 2.0 - 1.0;
+	vec4 position = u_inverse_projection * vec4(uv * 2.0 - 1.0, ndc_depth, 1.0);
+	return position.xyz / position.w;
+}
+
+void main()
+{
+	//Nothing to occlude on the background
+	vec2 uv = gl_FragCoord.xy / vec2(u_render_size);
+	float far_depth = u_reversed_z ? 0.0 : 1.0;
+	if (texture(depth_texture, texture_uv(uv)).r == far_depth) {
+		frag_ao = 1.0;
+		return;
+	}
+
+	//Reconstruct the view space position, and the normal from its screen space derivatives
+	vec3 position = view_position(uv);
+	vec3 normal = normalize(cross(dFdx(position), dFdy(position)));
+
+	//Rotate the kernel around the normal with the tiled noise texture
+	vec3 random = vec3(texture(noise_texture, gl_FragCoord.xy / 4.0).xy, 0.0);
+	vec3 tangent = normalize(random - normal * dot(random, normal));
+	vec3 bitangent = cross(normal, tangent);
+	mat3 tbn = mat3(tangent, bitangent, normal);
+
+	//Count the samples that end up behind the depth buffer, ignoring geometry far outside the radius
+	float occlusion = 0.0;
+	for (int i = 0; i < 16; i++) {
+		vec3 sample_position = position + tbn * u_kernel[i] * u_radius;
+		vec4 sample_clip = u_projection * vec4(sample_position, 1.0);
+		vec2 sample_uv = sample_clip.xy / sample_clip.w * 0.5 + 0.5;
+		float sample_depth = view_position(sample_uv).z;
+		float range_check = smoothstep(0.0, 1.0, u_radius / abs(position.z - sample_depth));
+		occlusion += (sample_depth >= sample_position.z + u_bias ? 1.0 : 0.0) * range_check;
+	}
+	frag_ao = pow(1.0 - occlusion / 16.0, u_intensity);
+}
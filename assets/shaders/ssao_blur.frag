@@ -0,0 +1,20 @@
+#version 420 core
+
+out vec4 frag_colour;
+
+uniform sampler2D ao_texture;
+uniform ivec2 u_direction;
+uniform ivec2 u_render_size; // Rendered part of the texture, pixels outside it are stale
+
+void main()
+{
+	//5-tap box blur along one axis, run once horizontally and once vertically
+	ivec2 pixel = ivec2(gl_FragCoord.xy);
+	ivec2 max_pixel = u_render_size - 1;
+	float ao = 0.0;
+	for (int i = -2; i <= 2; i++) {
+		ao += texelFetch(ao_texture, clamp(pixel + u_direction * i, ivec2(0), max_pixel), 0).r;
+	}
+	ao /= 5.0;
+	frag_colour = vec4(ao, ao, ao, 1.0);
+}
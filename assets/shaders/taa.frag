@@ -0,0 +1,69 @@
+#version 420 core
+
+out vec4 frag_colour;
+
+uniform sampler2D scene_colour;
+uniform sampler2D history_texture;
+uniform sampler2D velocity_texture;
+uniform sampler2D previous_velocity_texture; // Only read when upscaling
+uniform ivec2 u_render_size;
+uniform ivec2 u_previous_render_size;
+uniform ivec2 u_output_size; // Size of the history, the window's when upscaling and the render size otherwise
+uniform bool u_upscale;
+uniform vec2 u_jitter; // This frame's projection offset, in render pixels
+uniform bool u_history_valid;
+uniform float u_blend; // Weight of the current frame
+
+//Difference between this frame's and last frame's motion, in render pixels, where history starts and stops being rejected
+const float VELOCITY_REJECTION_START = 1.0;
+const float VELOCITY_REJECTION_END = 3.0;
+
+void main()
+{
+	ivec2 output_pixel = ivec2(gl_FragCoord.xy);
+	vec2 uv = (vec2(output_pixel) + 0.5) / vec2(u_output_size);
+
+	//The rendered pixel whose jittered sample landed closest to this one, and how far off it landed in render pixels.
+	//Without upscaling, that's the pixel itself
+	vec2 render_position = uv * vec2(u_render_size);
+	ivec2 pixel = u_upscale ? clamp(ivec2(floor(render_position + u_jitter)), ivec2(0), u_render_size - 1) : output_pixel;
+	vec2 sample_offset = render_position - (vec2(pixel) + 0.5 - u_jitter);
+	vec4 current = texelFetch(scene_colour, pixel, 0);
+
+	//Colour range of the 3x3 neighbourhood, history outside of it is stale and gets clamped into it
+	vec3 neighbourhood_min = current.rgb;
+	vec3 neighbourhood_max = current.rgb;
+	for (int y = -1; y <= 1; y++) {
+		for (int x = -1; x <= 1; x++) {
+			vec3 colour = texelFetch(scene_colour, clamp(pixel + ivec2(x, y), ivec2(0), u_render_size - 1), 0).rgb;
+			neighbourhood_min = min(neighbourhood_min, colour);
+			neighbourhood_max = max(neighbourhood_max, colour);
+		}
+	}
+
+	//Reproject, and start over where the history has nothing for this pixel
+	vec2 velocity = texelFetch(velocity_texture, pixel, 0).xy;
+	vec2 previous_uv = uv - velocity;
+	if (!u_history_valid || any(lessThan(previous_uv, vec2(0.0))) || any(greaterThan(previous_uv, vec2(1.0)))) {
+		frag_colour = current;
+		return;
+	}
+	vec2 history_uv = previous_uv * vec2(u_output_size) / vec2(textureSize(history_texture, 0));
+	vec3 history = clamp(texture(history_texture, history_uv).rgb, neighbourhood_min, neighbourhood_max);
+
+	float blend = u_blend;
+	if (u_upscale) {
+		//A sample far from this pixel says less about it, so the history keeps the detail between the rendered pixels
+		blend *= exp(-2.0 * dot(sample_offset, sample_offset));
+
+		//Where the surface last frame moved differently than this one, it was something else in front of or behind
+		//it. The history there belongs to that, the neighbourhood clamp alone lets it ghost at a low render scale
+		ivec2 previous_pixel = clamp(ivec2(previous_uv * vec2(u_previous_render_size)), ivec2(0), u_previous_render_size - 1);
+		vec2 previous_velocity = texelFetch(previous_velocity_texture, previous_pixel, 0).xy;
+		float velocity_difference = length((velocity - previous_velocity) * vec2(u_render_size));
+		blend = mix(blend, 1.0, smoothstep(VELOCITY_REJECTION_START, VELOCITY_REJECTION_END, velocity_difference));
+	}
+
+	//Exponential blend
+	frag_colour = vec4(mix(history, current.rgb, blend), current.a);
+}
@@ -0,0 +1,15 @@
+#version 420 core
+
+out vec4 frag_colour;
+in vec2 texcoord;
+in vec4 colour;
+
+uniform sampler2D font_texture;
+
+void main()
+{
+	// The font is 1 bit per pixel, so there's nothing to blend at the glyph edges
+	if (texture(font_texture, texcoord).r < 0.5)
+		discard;
+	frag_colour = colour;
+}
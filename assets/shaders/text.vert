@@ -0,0 +1,17 @@
+#version 420 core
+in layout (location = 0) vec2 a_position;
+in layout (location = 1) vec2 a_texcoord;
+in layout (location = 2) vec4 a_colour;
+out vec2 texcoord;
+out vec4 colour;
+
+uniform vec2 u_screen_size;
+
+void main()
+{
+	// Positions are in pixels, with the origin in the top left corner
+	vec2 ndc = a_position / u_screen_size * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0, 1);
+	texcoord = a_texcoord;
+	colour = a_colour;
+}
@@ -0,0 +1,20 @@
+#version 420 core
+
+out vec4 frag_colour;
+
+// Global constant buffer, still bound from the raster pass
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+void main()
+{
+	// Multiplied onto the scene by the blend state, pulsing with the renderer clock
+	float pulse = 0.5 + 0.5 * sin(u_time * 4.0);
+	frag_colour = vec4(mix(vec3(1.0), vec3(1.0, 0.8, 0.6), pulse), 1.0);
+}
@@ -0,0 +1,86 @@
+#version 420 core
+
+// Cel shading, as an example of a custom model shader. Drawn with lit.vert, and gets the same uniforms and material
+// textures as lit.frag, so it only declares the ones it uses
+
+// Vertex output / Fragment input
+in vec3 o_position;
+in vec4 o_colour;
+in vec3 o_normal;
+in vec3 o_tangent;
+in vec3 o_bitangent;
+in vec2 o_uv0;
+in vec2 o_uv1;
+
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+uniform sampler2D colour_texture;
+uniform sampler2DArray colour_array;
+uniform bool u_texture_arrays; // Material textures are layers of the arrays instead of the 2D textures
+uniform ivec3 u_texture_layers; // Albedo, metallic roughness and height layer
+uniform float u_lod_bias;
+uniform float u_roughness;
+uniform vec3 u_debug_tint; // White unless a debug view colours the mesh
+uniform vec4 u_tint; // Per submission, white unless drawn with draw_model_tinted
+uniform float u_alpha_cutoff; // Albedo alpha below this is cut out, negative for materials that aren't alpha masked
+uniform bool u_alpha_to_coverage; // MSAA is on and the mask is written as sample coverage instead of discarded
+uniform vec3 u_sun_direction; // Towards the sun
+uniform vec3 u_sun_colour;
+
+out vec4 frag_color;
+
+const vec3 ambient_colour = vec3(0.3);
+const float bands = 3.0; // Lit steps between the ambient and the fully lit colour
+const vec3 rim_colour = vec3(0.35);
+
+vec4 sample_colour(vec2 uv) {
+    if (u_texture_arrays) {
+        return texture(colour_array, vec3(uv, u_texture_layers.x), u_lod_bias);
+    }
+    return texture(colour_texture, uv, u_lod_bias);
+}
+
+// Snaps the lighting to the bands, with a pixel wide transition so the steps don't alias
+float ramp(float n_dot_l) {
+    float scaled = n_dot_l * bands;
+    float step_edge = fract(scaled);
+    float smoothing = fwidth(scaled);
+    return (floor(scaled) + smoothstep(0.5 - smoothing, 0.5 + smoothing, step_edge)) / bands;
+}
+
+void main() {
+    vec4 albedo = sample_colour(o_uv0);
+    float alpha = albedo.a;
+    if (u_alpha_cutoff >= 0.0) {
+        if (u_alpha_to_coverage) {
+            alpha = clamp((alpha - u_alpha_cutoff) / max(fwidth(alpha), 1e-4) + 0.5, 0.0, 1.0);
+        } else {
+            if (alpha < u_alpha_cutoff) {
+                discard;
+            }
+            alpha = 1.0;
+        }
+    }
+    vec3 base_colour = pow(albedo.rgb, vec3(2.2)) * u_tint.rgb * u_debug_tint;
+
+    vec3 normal = normalize(o_normal);
+    vec3 view = normalize(u_camera_position.xyz - o_position);
+    float n_dot_l = max(dot(normal, u_sun_direction), 0.0);
+    float n_dot_v = max(dot(normal, view), 0.0);
+
+    // A hard highlight that shrinks as the material gets rougher, and a rim on the lit side of silhouettes
+    float n_dot_h = max(dot(normal, normalize(view + u_sun_direction)), 0.0);
+    float highlight = step(1.0 - 0.1 * (1.0 - clamp(u_roughness, 0.0, 1.0)), n_dot_h) * step(0.0, n_dot_l - 1e-3);
+    float rim = smoothstep(0.7, 0.75, 1.0 - n_dot_v) * ramp(n_dot_l);
+
+    vec3 colour = base_colour * (ambient_colour + u_sun_colour * ramp(n_dot_l)) + (highlight + rim) * rim_colour * u_sun_colour;
+    frag_color = vec4(colour, alpha * u_tint.a);
+}
@@ -0,0 +1,129 @@
+#version 420 core
+
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+in vec3 o_position;
+in vec2 o_local;
+
+out vec4 frag_colour;
+
+uniform sampler2D depth_texture;  // Copy of the scene depth, the same size as the framebuffer
+uniform sampler2D colour_texture; // Copy of the scene colour before the water, only with reflections
+uniform sampler2D normal_texture; // Tiling ripple normals
+uniform mat4 u_inverse_view_projection; // Of the matrix in the constant buffer, jitter included
+uniform vec4 u_viewport; // x, y, width, height in pixels
+uniform bool u_reversed_z; // Depth is clip space z as-is and the far plane is at 0, see Projection in camera.rs
+
+// The plane and its WaterConfig
+uniform vec3 u_water_tangent; // World space directions of the plane's local X, Y and Z
+uniform vec3 u_water_bitangent;
+uniform vec3 u_water_normal;
+uniform vec2 u_extents;
+uniform float u_wave_speed;
+uniform float u_wave_scale;
+uniform vec3 u_tint;
+uniform float u_reflectivity;
+uniform bool u_reflections; // Off draws a plain tinted surface, without refraction or reflection
+
+// Sky, the same uniforms as sky.frag, reflected by the water
+uniform vec3 u_sun_direction;
+uniform vec3 u_sun_colour;
+uniform float u_turbidity;
+uniform vec3 u_ground_albedo;
+uniform vec3 u_world_up;
+
+const vec3 ambient_colour = vec3(0.3);
+const float PI = 3.14159265;
+const float SUN_ANGULAR_RADIUS = 0.0047;
+const float EDGE_FADE_DISTANCE = 0.3; // World units of water it takes to fully cover the geometry below
+const float ABSORPTION = 0.6;         // Per world unit of water the light travels through
+const float REFRACTION_STRENGTH = 0.03; // Of the viewport height, at full ripple slope
+
+// Copied from sky.frag
+vec3 sky_radiance(vec3 direction) {
+    float haze = clamp((u_turbidity - 1.0) / 9.0, 0.0, 1.0);
+    vec3 zenith = mix(vec3(0.12, 0.30, 0.75), vec3(0.45, 0.52, 0.62), haze);
+    vec3 horizon = mix(vec3(0.65, 0.78, 0.92), vec3(0.85, 0.85, 0.82), haze);
+    float sun_elevation = dot(u_sun_direction, u_world_up);
+    float elevation = dot(direction, u_world_up);
+    float daylight = smoothstep(-0.1, 0.25, sun_elevation);
+    float sun_tint = pow(1.0 - max(sun_elevation, 0.0), 4.0);
+    horizon = mix(horizon, horizon * u_sun_colour / max(max(u_sun_colour.r, u_sun_colour.g), 1e-4), sun_tint);
+
+    float up = max(elevation, 0.0);
+    vec3 colour = mix(horizon, zenith, sqrt(up)) * mix(0.01, 1.0, daylight);
+
+    float cos_angle = dot(direction, u_sun_direction);
+    colour += u_sun_colour * pow(max(cos_angle, 0.0), mix(64.0, 8.0, haze)) * 0.15;
+    colour += u_sun_colour * 20.0 * smoothstep(cos(SUN_ANGULAR_RADIUS * 1.2), cos(SUN_ANGULAR_RADIUS), cos_angle);
+
+    vec3 ground = u_ground_albedo * (u_sun_colour * max(sun_elevation, 0.0) + zenith * daylight) / PI;
+    return mix(ground, colour, smoothstep(-0.01, 0.0, elevation));
+}
+
+// Distance from the camera to the opaque surface at a pixel, very far for the background
+float scene_distance(ivec2 pixel)
+{
+	float depth = texelFetch(depth_texture, pixel, 0).r;
+	if (u_reversed_z ? depth <= 0.0 : depth >= 1.0) {
+		return 1e9;
+	}
+	vec2 ndc = (vec2(pixel) + 0.5 - u_viewport.xy) / u_viewport.zw * 2.0 - 1.0;
+	vec4 world = u_inverse_view_projection * vec4(ndc, u_reversed_z ? depth : depth * 2.0 - 1.0, 1.0);
+	return distance(world.xyz / world.w, u_camera_position.xyz);
+}
+
+void main()
+{
+	// Two copies of the ripples scrolling in different directions, blended so neither one's pattern shows
+	vec2 plane = o_local * u_extents / u_wave_scale;
+	float scroll = u_time * u_wave_speed;
+	vec3 ripple_a = texture(normal_texture, plane + vec2(scroll, scroll * 0.4)).xyz * 2.0 - 1.0;
+	vec3 ripple_b = texture(normal_texture, plane * 0.63 + vec2(-scroll * 0.8, scroll)).xyz * 2.0 - 1.0;
+	vec3 ripple = normalize(vec3(ripple_a.xy + ripple_b.xy, ripple_a.z * ripple_b.z));
+
+	vec3 to_camera = u_camera_position.xyz - o_position;
+	float water_distance = length(to_camera);
+	vec3 view = to_camera / water_distance;
+	vec3 surface_normal = dot(u_water_normal, view) < 0.0 ? -u_water_normal : u_water_normal;
+	vec3 normal = normalize(u_water_tangent * ripple.x + u_water_bitangent * ripple.y + surface_normal * ripple.z);
+
+	// Fade in over the first bit of water, so it doesn't cut a hard line into the geometry it meets
+	ivec2 pixel = ivec2(gl_FragCoord.xy);
+	float edge = clamp((scene_distance(pixel) - water_distance) / EDGE_FADE_DISTANCE, 0.0, 1.0);
+
+	float n_dot_v = max(dot(normal, view), 0.0);
+	float fresnel = clamp((0.02 + 0.98 * pow(1.0 - n_dot_v, 5.0)) * u_reflectivity, 0.0, 1.0);
+	vec3 water_colour = u_tint * (u_sun_colour * max(dot(surface_normal, u_sun_direction), 0.0) / PI + ambient_colour);
+
+	if (!u_reflections) {
+		vec3 half_vector = normalize(view + u_sun_direction);
+		vec3 highlight = u_sun_colour * pow(max(dot(normal, half_vector), 0.0), 256.0) * fresnel * 4.0;
+		frag_colour = vec4(water_colour + highlight, mix(0.6, 0.95, fresnel) * edge);
+		return;
+	}
+
+	// Refraction: look up the scene a little off to the side along the ripples. Where that lands on something in
+	// front of the water, use what's straight behind it instead
+	vec2 offset = ripple.xy * REFRACTION_STRENGTH * u_viewport.w * edge;
+	ivec2 refracted_pixel = clamp(ivec2(gl_FragCoord.xy + offset), ivec2(u_viewport.xy), ivec2(u_viewport.xy + u_viewport.zw) - 1);
+	float refracted_distance = scene_distance(refracted_pixel);
+	if (refracted_distance < water_distance) {
+		refracted_pixel = pixel;
+		refracted_distance = scene_distance(pixel);
+	}
+	vec3 refracted = texelFetch(colour_texture, refracted_pixel, 0).rgb;
+	float transmittance = exp(-max(refracted_distance - water_distance, 0.0) * ABSORPTION);
+	vec3 below = mix(water_colour, refracted, transmittance);
+
+	vec3 reflected = sky_radiance(reflect(-view, normal));
+	frag_colour = vec4(mix(below, reflected, fresnel), edge);
+}
@@ -0,0 +1,30 @@
+#version 420 core
+
+// Global constant buffer
+layout (std140, binding = 0) uniform const_buffer
+{
+	uniform mat4 u_view_projection_matrix;
+	uniform vec4 u_camera_position;
+	uniform float u_time;
+	uniform float u_delta_time;
+	uniform uint u_frame_index;
+};
+
+uniform mat4 u_water_matrix; // Unit square to world space
+
+out vec3 o_position; // World space
+out vec2 o_local;    // -0.5 to 0.5 across the plane
+
+// Two triangles generated from the vertex index, winding counter-clockwise seen from local +Z
+const vec2 corners[6] = vec2[6](
+	vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+	vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+);
+
+void main()
+{
+	o_local = corners[gl_VertexID];
+	vec4 world = u_water_matrix * vec4(o_local, 0.0, 1.0);
+	o_position = world.xyz;
+	gl_Position = u_view_projection_matrix * world;
+}
@@ -1,7 +1,20 @@
 use copy_to_output::copy_to_output;
-use std::env;
+use std::{env, process::Command};
 
 fn main() {
     println!("cargo:rerun-if-changed=res/*");
-    copy_to_output("assets", &env::var("PROFILE").unwrap()).expect("Failed to copy assets folder!")
+    copy_to_output("assets", &env::var("PROFILE").unwrap()).expect("Failed to copy assets folder!");
+
+    // Identifies the build in benchmark reports
+    let describe = Command::new("git")
+        .args(["describe", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rustc-env=GIT_DESCRIBE={describe}");
+    println!("cargo:rustc-env=BUILD_PROFILE={}", env::var("PROFILE").unwrap());
 }
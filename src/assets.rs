@@ -0,0 +1,141 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+// Extra asset roots, separated like PATH. Searched last, in order
+pub const ASSET_ROOTS_ENV: &str = "RUST_RENDER_GL_ASSET_ROOTS";
+
+// Finds asset files by trying a list of root directories in order, so the renderer works from any working directory.
+// The executable's directory comes first, then the crate's directory in debug builds and the working directory. Roots
+// added with add_root follow, and the environment variable's roots come last
+#[derive(Debug, Clone)]
+pub struct AssetResolver {
+    default_roots: Vec<PathBuf>,
+    added_roots: Vec<PathBuf>,
+    env_roots: Vec<PathBuf>,
+}
+
+impl AssetResolver {
+    pub fn new() -> Self {
+        let mut default_roots = Vec::new();
+        if let Some(directory) = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+            default_roots.push(directory);
+        }
+        #[cfg(debug_assertions)]
+        default_roots.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        if let Ok(directory) = env::current_dir() {
+            default_roots.push(directory);
+        }
+        let env_roots = env::var_os(ASSET_ROOTS_ENV).iter().flat_map(env::split_paths).collect();
+        AssetResolver::with_roots(default_roots, env_roots)
+    }
+
+    fn with_roots(default_roots: Vec<PathBuf>, env_roots: Vec<PathBuf>) -> Self {
+        AssetResolver {
+            default_roots,
+            added_roots: Vec::new(),
+            env_roots,
+        }
+    }
+
+    // Searched after the default roots and the roots added before it, but before the environment variable's roots
+    pub fn add_root(&mut self, root: impl Into<PathBuf>) {
+        self.added_roots.push(root.into());
+    }
+
+    // Every root, in search order. A root listed twice is only searched the first time
+    pub fn roots(&self) -> Vec<&PathBuf> {
+        let mut roots = Vec::new();
+        for root in self.default_roots.iter().chain(&self.added_roots).chain(&self.env_roots) {
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+        roots
+    }
+
+    // The first existing file at `path` under one of the roots. Absolute paths are only checked for existence. The
+    // error lists every place that was tried
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, String> {
+        if path.is_absolute() {
+            return match path.exists() {
+                true => Ok(path.to_path_buf()),
+                false => Err(format!("\"{}\" does not exist", path.display())),
+            };
+        }
+        let roots = self.roots();
+        if let Some(found) = roots.iter().map(|root| root.join(path)).find(|candidate| candidate.exists()) {
+            return Ok(found);
+        }
+        let searched: Vec<String> = roots.iter().map(|root| format!("\"{}\"", root.display())).collect();
+        Err(format!("\"{}\" was not found in any asset root, searched {}", path.display(), searched.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A root directory per name, each with a shaders directory, in a fresh directory under the system temp directory
+    fn temp_roots(test: &str, names: &[&str]) -> Vec<PathBuf> {
+        let directory = env::temp_dir().join(format!("rust_render_gl_{test}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        names
+            .iter()
+            .map(|name| {
+                let root = directory.join(name);
+                fs::create_dir_all(root.join("shaders")).unwrap();
+                root
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roots_in_priority_order() {
+        let roots = temp_roots("priority", &["exe", "manifest", "current", "added", "env"]);
+        let mut resolver = AssetResolver::with_roots(roots[..3].to_vec(), vec![roots[4].clone(), roots[0].clone()]);
+        resolver.add_root(&roots[3]);
+        resolver.add_root(&roots[1]);
+        assert_eq!(resolver.roots(), roots.iter().collect::<Vec<_>>());
+
+        // Each file resolves to the first root that has it, taking the roots away from the front one at a time
+        let shader = Path::new("shaders/lit.frag");
+        for (index, root) in roots.iter().enumerate().rev() {
+            fs::write(root.join(shader), "").unwrap();
+            assert_eq!(resolver.resolve(shader), Ok(root.join(shader)), "root {index}");
+        }
+        let _ = fs::remove_dir_all(roots[0].parent().unwrap());
+    }
+
+    #[test]
+    fn absolute_paths_skip_the_roots() {
+        let roots = temp_roots("absolute", &["root", "elsewhere"]);
+        let file = roots[1].join("model.gltf");
+        fs::write(&file, "").unwrap();
+        let resolver = AssetResolver::with_roots(vec![roots[0].clone()], Vec::new());
+        assert_eq!(resolver.resolve(&file), Ok(file.clone()));
+        fs::remove_file(&file).unwrap();
+        assert_eq!(resolver.resolve(&file), Err(format!("\"{}\" does not exist", file.display())));
+        let _ = fs::remove_dir_all(roots[0].parent().unwrap());
+    }
+
+    #[test]
+    fn not_found_lists_the_roots_searched() {
+        let roots = temp_roots("not_found", &["first", "second", "third"]);
+        let mut resolver = AssetResolver::with_roots(vec![roots[0].clone()], vec![roots[2].clone()]);
+        resolver.add_root(&roots[1]);
+        let error = resolver.resolve(Path::new("models/missing.gltf")).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "\"models/missing.gltf\" was not found in any asset root, searched \"{}\", \"{}\", \"{}\"",
+                roots[0].display(),
+                roots[1].display(),
+                roots[2].display()
+            )
+        );
+        let _ = fs::remove_dir_all(roots[0].parent().unwrap());
+    }
+}
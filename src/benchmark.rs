@@ -0,0 +1,246 @@
+use std::{collections::BTreeMap, f32::consts::TAU, fmt::Write, path::Path, time::Instant};
+
+use glam::{Mat4, Quat, Vec3};
+use log::warn;
+
+use crate::{
+    camera::Camera,
+    graphics::{DynamicResolution, Renderer},
+    input::UserInput,
+    quality::QualityGovernorConfig,
+    structs::{Transform, WorldUp, AABB},
+};
+
+// Rendered before measuring each mode, so shader compiles and texture streaming stay out of the numbers
+const WARMUP_FRAMES: u32 = 30;
+// The camera circles the scene once every this many frames, however long they take
+const TRACK_FRAMES: u32 = 600;
+// Changes smaller than this are treated as noise by --compare, in percent
+pub const DEFAULT_THRESHOLD: f32 = 5.0;
+
+// Renderer configurations that get measured, one after the other: name and whether occlusion culling is on
+const MODES: [(&str, bool); 2] = [("raster", true), ("raster_no_occlusion_culling", false)];
+
+struct ModeResult {
+    name: &'static str,
+    frame_times_ms: Vec<f32>, // Sorted
+    drawn: f32,               // Averages per frame
+    occlusion_culled: f32,
+}
+
+impl ModeResult {
+    fn percentile(&self, percentile: f32) -> f32 {
+        let index = ((self.frame_times_ms.len() - 1) as f32 * percentile / 100.0).round() as usize;
+        self.frame_times_ms[index]
+    }
+
+    fn mean(&self) -> f32 {
+        self.frame_times_ms.iter().sum::<f32>() / self.frame_times_ms.len() as f32
+    }
+}
+
+// Renders `frames` frames per mode along a fixed camera track around the models, with vsync off and the renderer
+// clock paused, then writes the report as JSON to `path` and as text next to it. The workload only depends on the
+// frame number, so two runs of the same build only differ by how fast the machine is
+pub fn run(renderer: &mut Renderer, models: &[u64], frames: u32, path: &Path) -> Result<(), String> {
+    let mut bounds = AABB::new();
+    for &model in models {
+        if let Some(info) = renderer.model_info(model) {
+            bounds.grow_aabb(&info.bounds);
+        }
+    }
+    if bounds.is_empty() {
+        return Err("Nothing to benchmark, no models with geometry were loaded".to_string());
+    }
+
+    // Same internal resolution every frame
+    renderer.set_vsync(false);
+    renderer.set_time_paused(true);
+    renderer.set_frame_history(None);
+    renderer.set_dynamic_resolution(DynamicResolution {
+        enabled: false,
+        ..Default::default()
+    });
+    renderer.set_quality_governor(QualityGovernorConfig {
+        enabled: false,
+        ..renderer.quality_governor_config().clone()
+    });
+
+    let mut user_input = UserInput::new();
+    let world_up = renderer.world_up();
+    let mut camera = Camera::new(track_transform(&bounds, 0, world_up), 0.0, 0.0);
+    let mut results = Vec::new();
+    for (name, occlusion_culling) in MODES {
+        if occlusion_culling && !renderer.capabilities().compute_shaders {
+            warn!("Skipping {name}, occlusion culling is not supported by this OpenGL context");
+            continue;
+        }
+        println!("Benchmarking {name}, {frames} frames");
+        renderer.set_occlusion_culling(occlusion_culling);
+        let mut result = ModeResult {
+            name,
+            frame_times_ms: Vec::with_capacity(frames as usize),
+            drawn: 0.0,
+            occlusion_culled: 0.0,
+        };
+        for frame in 0..WARMUP_FRAMES + frames {
+            if renderer.should_close() {
+                return Err("Benchmark cancelled, the window was closed".to_string());
+            }
+            let start = Instant::now();
+            renderer.update_input(&mut user_input);
+            camera.transform = track_transform(&bounds, frame, world_up);
+            renderer.update_camera(&camera);
+            renderer.begin_frame();
+            for model in models {
+                renderer.draw_model(model);
+            }
+            renderer.end_frame();
+            if frame >= WARMUP_FRAMES {
+                let stats = renderer.culling_stats();
+                result.frame_times_ms.push(start.elapsed().as_secs_f32() * 1000.0);
+                result.drawn += stats.drawn as f32 / frames as f32;
+                result.occlusion_culled += stats.occlusion_culled as f32 / frames as f32;
+            }
+        }
+        result.frame_times_ms.sort_by(f32::total_cmp);
+        results.push(result);
+    }
+    renderer.set_vsync(true);
+    renderer.set_time_paused(false);
+
+    // Both reports hold the same numbers, the JSON one is what --compare reads
+    let metrics = metrics(&results);
+    let header = [
+        ("git", env!("GIT_DESCRIBE").to_string()),
+        ("profile", env!("BUILD_PROFILE").to_string()),
+        ("resolution", format!("{}x{}", renderer.render_resolution()[0], renderer.render_resolution()[1])),
+        ("frames_per_mode", frames.to_string()),
+        ("warmup_frames", WARMUP_FRAMES.to_string()),
+    ];
+    let mut json = String::from("{\n");
+    for (key, value) in &header {
+        writeln!(json, "  \"{key}\": \"{value}\",").unwrap();
+    }
+    json += "  \"metrics\": {\n";
+    for (index, (key, value)) in metrics.iter().enumerate() {
+        let separator = if index + 1 < metrics.len() { "," } else { "" };
+        writeln!(json, "    \"{key}\": {value}{separator}").unwrap();
+    }
+    json += "  }\n}\n";
+
+    let mut text = String::new();
+    for (key, value) in &header {
+        writeln!(text, "{key}: {value}").unwrap();
+    }
+    writeln!(text, "\n{:<28} {:>9} {:>9} {:>9} {:>9} {:>8} {:>8}", "mode", "mean ms", "p50 ms", "p95 ms", "p99 ms", "drawn", "culled").unwrap();
+    for result in &results {
+        writeln!(
+            text,
+            "{:<28} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>8.1} {:>8.1}",
+            result.name,
+            result.mean(),
+            result.percentile(50.0),
+            result.percentile(95.0),
+            result.percentile(99.0),
+            result.drawn,
+            result.occlusion_culled
+        )
+        .unwrap();
+    }
+    if let Some(peak) = metrics.get("peak_memory_mib") {
+        writeln!(text, "\nPeak resident memory: {peak:.1} MiB").unwrap();
+    }
+
+    std::fs::write(path, json).map_err(|error| format!("Failed to write \"{}\": {error}", path.display()))?;
+    let text_path = path.with_extension("txt");
+    std::fs::write(&text_path, &text).map_err(|error| format!("Failed to write \"{}\": {error}", text_path.display()))?;
+    print!("{text}");
+    Ok(())
+}
+
+// Prints every metric that changed by more than `threshold` percent between two reports, returns how many got worse
+pub fn compare(old_path: &Path, new_path: &Path, threshold: f32) -> Result<usize, String> {
+    let old = read_metrics(old_path)?;
+    let new = read_metrics(new_path)?;
+    let mut regressions = 0;
+    for (key, &old_value) in &old {
+        let Some(&new_value) = new.get(key) else {
+            println!("{key}: missing from \"{}\"", new_path.display());
+            continue;
+        };
+        if old_value == 0.0 {
+            continue;
+        }
+        let change = (new_value - old_value) / old_value * 100.0;
+        if change.abs() < threshold {
+            continue;
+        }
+        // Culling more meshes is the only metric where a higher number is better
+        let worse = if key.ends_with("occlusion_culled") { change < 0.0 } else { change > 0.0 };
+        println!(
+            "{}{key}: {old_value:.3} -> {new_value:.3} ({change:+.1}%)",
+            if worse { "REGRESSION " } else { "improvement " }
+        );
+        regressions += worse as usize;
+    }
+    println!("{regressions} regressions above {threshold}%");
+    Ok(regressions)
+}
+
+fn metrics(results: &[ModeResult]) -> BTreeMap<String, f32> {
+    let mut metrics = BTreeMap::new();
+    for result in results {
+        let name = result.name;
+        metrics.insert(format!("{name}.frame_time_ms.mean"), result.mean());
+        metrics.insert(format!("{name}.frame_time_ms.p50"), result.percentile(50.0));
+        metrics.insert(format!("{name}.frame_time_ms.p95"), result.percentile(95.0));
+        metrics.insert(format!("{name}.frame_time_ms.p99"), result.percentile(99.0));
+        metrics.insert(format!("{name}.drawn"), result.drawn);
+        metrics.insert(format!("{name}.occlusion_culled"), result.occlusion_culled);
+    }
+    if let Some(peak) = peak_memory_bytes() {
+        metrics.insert("peak_memory_mib".to_string(), peak as f32 / (1024.0 * 1024.0));
+    }
+    metrics
+}
+
+// Reads the metrics back from a JSON report, which has one "name": value pair per line
+fn read_metrics(path: &Path) -> Result<BTreeMap<String, f32>, String> {
+    let json = std::fs::read_to_string(path).map_err(|error| format!("Failed to read \"{}\": {error}", path.display()))?;
+    let metrics: BTreeMap<String, f32> = json
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().trim_end_matches(',').split_once(": ")?;
+            Some((key.trim_matches('"').to_string(), value.parse().ok()?))
+        })
+        .collect();
+    if metrics.is_empty() {
+        return Err(format!("\"{}\" is not a benchmark report", path.display()));
+    }
+    Ok(metrics)
+}
+
+// Circles the scene from slightly above, looking at its centre
+fn track_transform(bounds: &AABB, frame: u32, world_up: WorldUp) -> Transform {
+    let centre = (bounds.min + bounds.max) * 0.5;
+    let radius = (bounds.max - bounds.min).length().max(0.1);
+    let angle = (frame % TRACK_FRAMES) as f32 / TRACK_FRAMES as f32 * TAU;
+    let position = centre + world_up.y_up_rotation() * Vec3::new(angle.cos() * radius, radius * 0.3, angle.sin() * radius);
+
+    // look_at gives a Y-up camera rotation, which the convention's own forward axis gets rotated into
+    let look = Quat::from_mat4(&Mat4::look_at_rh(position, centre, world_up.up()).inverse());
+    Transform {
+        translation: position,
+        rotation: look * world_up.y_up_rotation().inverse(),
+        scale: Vec3::ONE,
+    }
+}
+
+// Peak resident set size of the process. GPU memory isn't tracked, and other platforms don't report anything
+fn peak_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
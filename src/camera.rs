@@ -1,17 +1,164 @@
 use std::f32::consts::PI;
 
-use glfw::{Key, MouseButton};
+use glam::{Mat4, Vec3};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-use crate::{input::UserInput, structs::Transform};
+use crate::{
+    input::{KeyCode, MouseButton, UserInput},
+    structs::{Transform, WorldUp},
+};
+
+// Which screen axis a field of view angle spans. Vertical keeps the same view height at every aspect ratio,
+// horizontal keeps the same width, which suits ultra-wide screens
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FovAxis {
+    Vertical,
+    Horizontal,
+}
+
+// Perspective projection settings, the single source for every projection matrix the renderer builds.
+// Standard depth follows the OpenGL convention: clip space z from -1 at the near plane to 1 at the far plane, and
+// depth values from 0 to 1, cleared to 1 and tested with LESS. Reversed-Z uses a [0, 1] clip range (glClipControl),
+// puts the near plane at 1 and the far plane at 0, clears to 0 and tests with GREATER. Together with a float depth
+// buffer, that spreads the precision evenly over the whole range
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Projection {
+    pub fov: f32, // Radians, along fov_axis
+    pub fov_axis: FovAxis,
+    pub near: f32,
+    pub far: f32,
+    pub reversed_z: bool,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection {
+            fov: PI / 4.0,
+            fov_axis: FovAxis::Vertical,
+            near: 0.1,
+            far: 1000.0,
+            reversed_z: false,
+        }
+    }
+}
+
+impl Projection {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.fov > 0.0 && self.fov < PI) {
+            return Err(format!("Field of view must be between 0 and 180 degrees, got {}", self.fov.to_degrees()));
+        }
+        if !(self.near > 0.0 && self.near.is_finite()) {
+            return Err(format!("Near plane must be greater than 0, got {}", self.near));
+        }
+        if !(self.far > self.near && self.far.is_finite()) {
+            return Err(format!("Far plane must be further away than the near plane ({}), got {}", self.near, self.far));
+        }
+        Ok(())
+    }
+
+    pub fn vertical_fov(&self, aspect_ratio: f32) -> f32 {
+        match self.fov_axis {
+            FovAxis::Vertical => self.fov,
+            FovAxis::Horizontal => horizontal_to_vertical_fov(self.fov, aspect_ratio),
+        }
+    }
+
+    pub fn horizontal_fov(&self, aspect_ratio: f32) -> f32 {
+        match self.fov_axis {
+            FovAxis::Vertical => vertical_to_horizontal_fov(self.fov, aspect_ratio),
+            FovAxis::Horizontal => self.fov,
+        }
+    }
+
+    pub fn matrix(&self, aspect_ratio: f32) -> Mat4 {
+        let fov = self.vertical_fov(aspect_ratio);
+        if self.reversed_z {
+            // Swapping the planes of a [0, 1] projection maps the near plane to 1 and the far plane to 0
+            Mat4::perspective_rh(fov, aspect_ratio, self.far, self.near)
+        } else {
+            Mat4::perspective_rh_gl(fov, aspect_ratio, self.near, self.far)
+        }
+    }
+
+    // Clip space z range, as (near plane, far plane)
+    pub fn clip_depth_range(&self) -> (f32, f32) {
+        if self.reversed_z {
+            (1.0, 0.0)
+        } else {
+            (-1.0, 1.0)
+        }
+    }
+
+    // Value the depth buffer gets cleared to, the far plane
+    pub fn depth_clear_value(&self) -> f64 {
+        if self.reversed_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+}
+
+// Aspect ratio is width / height
+pub fn horizontal_to_vertical_fov(horizontal_fov: f32, aspect_ratio: f32) -> f32 {
+    2.0 * ((horizontal_fov / 2.0).tan() / aspect_ratio).atan()
+}
+
+pub fn vertical_to_horizontal_fov(vertical_fov: f32, aspect_ratio: f32) -> f32 {
+    2.0 * ((vertical_fov / 2.0).tan() * aspect_ratio).atan()
+}
+
+// How quickly the fly camera follows its input. Velocity and rotation ease towards what the input asks for, closing
+// all but 1/e of the gap every time constant, which looks the same at any frame rate. A time constant of 0 follows
+// the input immediately, exactly like a camera without smoothing
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraSmoothing {
+    pub movement_time: f32, // Seconds
+    pub rotation_time: f32, // Seconds
+}
+
+impl CameraSmoothing {
+    pub const NONE: CameraSmoothing = CameraSmoothing {
+        movement_time: 0.0,
+        rotation_time: 0.0,
+    };
+
+    // Heavy smoothing for captures, hides the steps of keyboard and mouse input
+    pub const CINEMATIC: CameraSmoothing = CameraSmoothing {
+        movement_time: 0.6,
+        rotation_time: 0.3,
+    };
+}
+
+impl Default for CameraSmoothing {
+    fn default() -> Self {
+        CameraSmoothing {
+            movement_time: 0.1,
+            rotation_time: 0.0,
+        }
+    }
+}
+
+// Below these the smoothed motion snaps to its target, so the camera comes to a complete stop instead of drifting
+const VELOCITY_EPSILON: f32 = 1e-4;
+const ANGLE_EPSILON: f32 = 1e-5;
 
 pub struct Camera {
     pub transform: Transform,
     pub move_speed: f32,
     pub mouse_sensitivity: f32,
+    pub smoothing: CameraSmoothing,
+    pub world_up: WorldUp, // Should match the renderer's, see Renderer::world_up
     mouse_pos_old: (f32, f32),
     should_skip_mouse_update: bool,
     pub pitch: f32,
     pub yaw: f32,
+    velocity: Vec3,
+    target_pitch: f32,
+    target_yaw: f32,
 }
 
 impl Camera {
@@ -20,40 +167,48 @@ impl Camera {
             transform,
             move_speed,
             mouse_sensitivity,
+            smoothing: CameraSmoothing::default(),
+            world_up: WorldUp::Y,
             mouse_pos_old: (0.0, 0.0),
             pitch: 0.0,
             yaw: 0.0,
             should_skip_mouse_update: true,
+            velocity: Vec3::ZERO,
+            target_pitch: 0.0,
+            target_yaw: 0.0,
         }
     }
-    pub fn update(&mut self, input: &UserInput, delta_time: f32) {
-        // Moving forwards, backwards, left and right
-        if input.is_key_down(Key::A) {
-            self.transform.translation -= self.move_speed * delta_time * self.transform.right()
-        }
-        if input.is_key_down(Key::D) {
-            self.transform.translation += self.move_speed * delta_time * self.transform.right()
-        }
-        if input.is_key_down(Key::W) {
-            self.transform.translation += self.move_speed * delta_time * self.transform.forward()
-        }
-        if input.is_key_down(Key::S) {
-            self.transform.translation -= self.move_speed * delta_time * self.transform.forward()
-        }
 
-        // Moving up and down, Minecraft style
-        if input.is_key_down(Key::Space) {
-            self.transform.translation += self.move_speed * delta_time * glam::vec3(0.0, 1.0, 0.0);
-        }
-        if input.is_key_down(Key::LeftShift) {
-            self.transform.translation -= self.move_speed * delta_time * glam::vec3(0.0, 1.0, 0.0);
+    pub fn update(&mut self, input: &UserInput, delta_time: f32) {
+        // Moving forwards, backwards, left and right, and up and down Minecraft style
+        let directions = [
+            (KeyCode::A, -self.transform.right()),
+            (KeyCode::D, self.transform.right()),
+            (KeyCode::W, self.transform.forward(self.world_up)),
+            (KeyCode::S, -self.transform.forward(self.world_up)),
+            (KeyCode::Space, self.world_up.up()),
+            (KeyCode::LeftShift, -self.world_up.up()),
+        ];
+        let held = directions.iter().filter(|(key, _)| input.is_key_down(*key));
+        if self.smoothing.movement_time > 0.0 {
+            let target_velocity: Vec3 = held.map(|(_, direction)| self.move_speed * *direction).sum();
+            self.velocity = target_velocity + (self.velocity - target_velocity) * decay(delta_time, self.smoothing.movement_time);
+            if (self.velocity - target_velocity).length() < VELOCITY_EPSILON {
+                self.velocity = target_velocity;
+            }
+            self.transform.translation += self.velocity * delta_time;
+        } else {
+            self.velocity = Vec3::ZERO;
+            for (_, direction) in held {
+                self.transform.translation += self.move_speed * delta_time * *direction;
+            }
         }
 
         // Movement speed increase, like in Minecraft spectator mode
         self.move_speed *= 1.005_f32.powf(input.get_scroll_wheel());
 
         // Mouse rotation
-        if input.get_mouse_down(MouseButton::Button1) {
+        if input.get_mouse_down(MouseButton::Left) {
             // Update mouse position
             let mouse_pos = input.get_mouse_pos();
             let delta_mouse = (
@@ -64,16 +219,248 @@ impl Camera {
 
             // If the mouse position is a specific high value, that means we're still settling in after starting to hold right click
             if !self.should_skip_mouse_update {
-                self.pitch -= delta_mouse.1 * self.mouse_sensitivity;
-                self.pitch = self.pitch.clamp(-PI * 0.4999, PI * 0.4999);
-                self.yaw -= delta_mouse.0 * self.mouse_sensitivity;
-                self.transform.rotation =
-                    glam::Quat::from_euler(glam::EulerRot::YXZ, self.yaw, self.pitch, 0.0)
+                self.target_pitch -= delta_mouse.1 * self.mouse_sensitivity;
+                self.target_pitch = self.target_pitch.clamp(-PI * 0.4999, PI * 0.4999);
+                self.target_yaw -= delta_mouse.0 * self.mouse_sensitivity;
+                if self.smoothing.rotation_time <= 0.0 {
+                    self.pitch = self.target_pitch;
+                    self.yaw = self.target_yaw;
+                    self.transform.rotation = self.rotation();
+                }
             } else {
                 self.should_skip_mouse_update = false;
             }
         } else {
             self.should_skip_mouse_update = true;
         }
+
+        // Smoothed rotation keeps easing in after the mouse button is released
+        if self.smoothing.rotation_time > 0.0 && (self.pitch != self.target_pitch || self.yaw != self.target_yaw) {
+            let decay = decay(delta_time, self.smoothing.rotation_time);
+            self.pitch = self.target_pitch + (self.pitch - self.target_pitch) * decay;
+            self.yaw = self.target_yaw + (self.yaw - self.target_yaw) * decay;
+            if (self.pitch - self.target_pitch).abs() < ANGLE_EPSILON && (self.yaw - self.target_yaw).abs() < ANGLE_EPSILON {
+                self.pitch = self.target_pitch;
+                self.yaw = self.target_yaw;
+            }
+            self.transform.rotation = self.rotation();
+        }
+    }
+
+    // Turns the camera right away, without smoothing
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-PI * 0.4999, PI * 0.4999);
+        self.target_yaw = self.yaw;
+        self.target_pitch = self.pitch;
+        self.transform.rotation = self.rotation();
+    }
+
+    // Yaw turns around the world's up axis, pitch around the camera's right axis
+    fn rotation(&self) -> glam::Quat {
+        match self.world_up {
+            WorldUp::Y => glam::Quat::from_euler(glam::EulerRot::YXZ, self.yaw, self.pitch, 0.0),
+            WorldUp::Z => glam::Quat::from_euler(glam::EulerRot::ZXY, self.yaw, self.pitch, 0.0),
+        }
+    }
+}
+
+// How much of the distance to a target is left after `delta_time`, exp(-dt / tau)
+fn decay(delta_time: f32, time_constant: f32) -> f32 {
+    (-delta_time / time_constant).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputEvent;
+
+    const ASPECT_RATIOS: [f32; 5] = [0.5, 1.0, 4.0 / 3.0, 16.0 / 9.0, 32.0 / 9.0];
+
+    #[test]
+    fn fov_conversions_round_trip() {
+        for aspect_ratio in ASPECT_RATIOS {
+            for degrees in [10.0f32, 45.0, 90.0, 120.0, 170.0] {
+                let fov = degrees.to_radians();
+                let there_and_back = horizontal_to_vertical_fov(vertical_to_horizontal_fov(fov, aspect_ratio), aspect_ratio);
+                assert!((there_and_back - fov).abs() < 1e-5, "{degrees} degrees at {aspect_ratio}");
+                let there_and_back = vertical_to_horizontal_fov(horizontal_to_vertical_fov(fov, aspect_ratio), aspect_ratio);
+                assert!((there_and_back - fov).abs() < 1e-5, "{degrees} degrees at {aspect_ratio}");
+            }
+        }
+    }
+
+    #[test]
+    fn projection_fov_along_its_axis() {
+        for aspect_ratio in ASPECT_RATIOS {
+            for fov_axis in [FovAxis::Vertical, FovAxis::Horizontal] {
+                let projection = Projection { fov: 1.2, fov_axis, ..Projection::default() };
+                let (vertical, horizontal) = (projection.vertical_fov(aspect_ratio), projection.horizontal_fov(aspect_ratio));
+                match fov_axis {
+                    FovAxis::Vertical => assert_eq!(vertical, 1.2),
+                    FovAxis::Horizontal => assert_eq!(horizontal, 1.2),
+                }
+                assert!((horizontal_to_vertical_fov(horizontal, aspect_ratio) - vertical).abs() < 1e-5);
+
+                // Square screens have the same angle both ways, wider ones more horizontally
+                if aspect_ratio == 1.0 {
+                    assert!((horizontal - vertical).abs() < 1e-6);
+                } else {
+                    assert_eq!(horizontal > vertical, aspect_ratio > 1.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fov_edges_land_on_the_screen_edges() {
+        for aspect_ratio in ASPECT_RATIOS {
+            for (fov_axis, reversed_z) in [(FovAxis::Vertical, false), (FovAxis::Horizontal, false), (FovAxis::Horizontal, true)] {
+                let projection = Projection { fov: 1.0, fov_axis, reversed_z, ..Projection::default() };
+                let matrix = projection.matrix(aspect_ratio);
+                let distance = 10.0;
+                let right = (projection.horizontal_fov(aspect_ratio) / 2.0).tan() * distance;
+                let top = (projection.vertical_fov(aspect_ratio) / 2.0).tan() * distance;
+                let corner = matrix.project_point3(Vec3::new(right, top, -distance));
+                assert!((corner.x - 1.0).abs() < 1e-4 && (corner.y - 1.0).abs() < 1e-4, "{corner} at {aspect_ratio}");
+            }
+        }
+    }
+
+    fn camera(smoothing: CameraSmoothing) -> Camera {
+        let transform = Transform {
+            translation: Vec3::new(1.0, 2.0, 3.0),
+            rotation: glam::Quat::IDENTITY,
+            scale: Vec3::ONE,
+        };
+        Camera {
+            smoothing,
+            ..Camera::new(transform, 5.0, 0.004)
+        }
+    }
+
+    // Frame lengths of an uneven frame rate, and the input of each frame: keys going down and up and the mouse
+    // dragging the view around with the left button
+    const DELTA_TIMES: [f32; 6] = [1.0 / 60.0, 1.0 / 144.0, 1.0 / 30.0, 0.1, 1.0 / 60.0, 0.0];
+
+    fn scripted_input(input: &mut UserInput, frame: usize) {
+        input.begin_frame();
+        let events = match frame {
+            0 => vec![InputEvent::KeyDown(KeyCode::W), InputEvent::KeyDown(KeyCode::D)],
+            20 => vec![InputEvent::MouseButton(MouseButton::Left, true), InputEvent::KeyDown(KeyCode::Space)],
+            21..=59 => vec![InputEvent::MouseMove(frame as f32 * 3.0, frame as f32 * -1.5)],
+            60 => vec![InputEvent::MouseButton(MouseButton::Left, false), InputEvent::KeyUp(KeyCode::W)],
+            80 => vec![InputEvent::KeyDown(KeyCode::LeftShift), InputEvent::KeyDown(KeyCode::A), InputEvent::Scroll(0.0, 2.0)],
+            100 => vec![InputEvent::KeyUp(KeyCode::D), InputEvent::KeyUp(KeyCode::Space), InputEvent::KeyDown(KeyCode::S)],
+            _ => Vec::new(),
+        };
+        events.iter().for_each(|event| input.process_event(event));
+    }
+
+    // Camera::update from before motion smoothing, which an unsmoothed camera has to match to the bit
+    fn unsmoothed_update(camera: &mut Camera, input: &UserInput, delta_time: f32) {
+        if input.is_key_down(KeyCode::A) {
+            camera.transform.translation -= camera.move_speed * delta_time * camera.transform.right()
+        }
+        if input.is_key_down(KeyCode::D) {
+            camera.transform.translation += camera.move_speed * delta_time * camera.transform.right()
+        }
+        if input.is_key_down(KeyCode::W) {
+            camera.transform.translation += camera.move_speed * delta_time * camera.transform.forward(WorldUp::Y)
+        }
+        if input.is_key_down(KeyCode::S) {
+            camera.transform.translation -= camera.move_speed * delta_time * camera.transform.forward(WorldUp::Y)
+        }
+        if input.is_key_down(KeyCode::Space) {
+            camera.transform.translation += camera.move_speed * delta_time * glam::vec3(0.0, 1.0, 0.0);
+        }
+        if input.is_key_down(KeyCode::LeftShift) {
+            camera.transform.translation -= camera.move_speed * delta_time * glam::vec3(0.0, 1.0, 0.0);
+        }
+        camera.move_speed *= 1.005_f32.powf(input.get_scroll_wheel());
+        if input.get_mouse_down(MouseButton::Left) {
+            let mouse_pos = input.get_mouse_pos();
+            let delta_mouse = (mouse_pos.0 - camera.mouse_pos_old.0, mouse_pos.1 - camera.mouse_pos_old.1);
+            camera.mouse_pos_old = mouse_pos;
+            if !camera.should_skip_mouse_update {
+                camera.pitch -= delta_mouse.1 * camera.mouse_sensitivity;
+                camera.pitch = camera.pitch.clamp(-PI * 0.4999, PI * 0.4999);
+                camera.yaw -= delta_mouse.0 * camera.mouse_sensitivity;
+                camera.transform.rotation = glam::Quat::from_euler(glam::EulerRot::YXZ, camera.yaw, camera.pitch, 0.0)
+            } else {
+                camera.should_skip_mouse_update = false;
+            }
+        } else {
+            camera.should_skip_mouse_update = true;
+        }
+    }
+
+    #[test]
+    fn zero_time_constants_move_like_before() {
+        let (mut smoothed, mut reference) = (camera(CameraSmoothing::NONE), camera(CameraSmoothing::NONE));
+        let mut input = UserInput::new();
+        for frame in 0..120 {
+            scripted_input(&mut input, frame);
+            let delta_time = DELTA_TIMES[frame % DELTA_TIMES.len()];
+            smoothed.update(&input, delta_time);
+            unsmoothed_update(&mut reference, &input, delta_time);
+            let bits = |camera: &Camera| {
+                let (translation, rotation) = (camera.transform.translation, camera.transform.rotation);
+                (translation.to_array().map(f32::to_bits), rotation.to_array().map(f32::to_bits))
+            };
+            assert_eq!(bits(&smoothed), bits(&reference), "frame {frame}");
+            assert_eq!(smoothed.move_speed.to_bits(), reference.move_speed.to_bits());
+        }
+    }
+
+    #[test]
+    fn smoothed_motion_comes_to_a_complete_stop() {
+        for smoothing in [CameraSmoothing::default(), CameraSmoothing::CINEMATIC] {
+            let mut camera = camera(smoothing);
+            let mut input = UserInput::new();
+            for frame in 0..60 {
+                scripted_input(&mut input, frame);
+                camera.update(&input, DELTA_TIMES[frame % DELTA_TIMES.len()]);
+            }
+            for key in [KeyCode::W, KeyCode::D, KeyCode::Space] {
+                input.process_event(&InputEvent::KeyUp(key));
+            }
+
+            // Within 15 time constants both have closed the gap to below their epsilons and snapped to a standstill
+            let mut time = 0.0;
+            while time < smoothing.movement_time.max(smoothing.rotation_time) * 15.0 {
+                input.begin_frame();
+                camera.update(&input, 1.0 / 60.0);
+                time += 1.0 / 60.0;
+            }
+            assert_eq!(camera.velocity, Vec3::ZERO);
+            assert_eq!((camera.pitch, camera.yaw), (camera.target_pitch, camera.target_yaw));
+            let (translation, rotation) = (camera.transform.translation, camera.transform.rotation);
+            for delta_time in DELTA_TIMES.iter().cycle().take(600) {
+                camera.update(&input, *delta_time);
+            }
+            assert_eq!(camera.transform.translation, translation);
+            assert_eq!(camera.transform.rotation, rotation);
+        }
+    }
+
+    #[test]
+    fn smoothing_is_frame_rate_independent() {
+        let velocity_after_a_second = |frames: usize| {
+            let mut camera = camera(CameraSmoothing::CINEMATIC);
+            let mut input = UserInput::new();
+            input.process_event(&InputEvent::KeyDown(KeyCode::W));
+            for _ in 0..frames {
+                camera.update(&input, 0.5 / frames as f32);
+            }
+            input.process_event(&InputEvent::KeyUp(KeyCode::W));
+            for _ in 0..frames {
+                camera.update(&input, 0.5 / frames as f32);
+            }
+            camera.velocity
+        };
+        let (slow, fast) = (velocity_after_a_second(15), velocity_after_a_second(120));
+        assert!(slow.length() > 0.5);
+        assert!(slow.abs_diff_eq(fast, 1e-4), "{slow} at 30 fps, {fast} at 240 fps");
     }
 }
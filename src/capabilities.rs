@@ -0,0 +1,131 @@
+use std::ffi::CStr;
+
+use gl::types::GLenum;
+use log::{info, warn};
+
+// Oldest context the raster shaders compile on, they need explicit uniform block bindings
+pub const MINIMUM_VERSION: (u32, u32) = (4, 2);
+
+// What the OpenGL context supports, queried once when the renderer is created. Features the context can't run get
+// turned off with a warning, instead of calling through entry points the driver never loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuCapabilities {
+    pub version: (u32, u32),
+    pub renderer: String,
+    pub compute_shaders: bool,         // GL 4.3 or ARB_compute_shader, needed by occlusion culling
+    pub clip_control: bool,            // GL 4.5 or ARB_clip_control, needed by reversed-Z
+    pub program_interface_query: bool, // GL 4.3 or ARB_program_interface_query, used to check GPU struct layouts
+    pub program_binary: bool,          // At least one program binary format, needed by the shader cache
+    pub float_render_targets: bool,    // RGBA16F colour attachments are renderable, otherwise RGBA8 is used
+}
+
+impl GpuCapabilities {
+    // Queries the current context
+    pub fn query() -> Self {
+        unsafe {
+            let version_string = gl_string(gl::VERSION);
+            let renderer = gl_string(gl::RENDERER);
+            let version = parse_version(&version_string);
+
+            // Indexed extension strings only exist since GL 3.0, older contexts fail the minimum version anyway
+            let mut extensions = Vec::new();
+            if version >= (3, 0) && gl::GetStringi::is_loaded() {
+                let mut count = 0;
+                gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+                for index in 0..count.max(0) as u32 {
+                    let name = gl::GetStringi(gl::EXTENSIONS, index);
+                    if !name.is_null() {
+                        extensions.push(CStr::from_ptr(name as *const _).to_string_lossy().into_owned());
+                    }
+                }
+            }
+            let mut binary_formats = 0;
+            if gl::GetProgramBinary::is_loaded() && gl::ProgramBinary::is_loaded() {
+                gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut binary_formats);
+            }
+
+            let mut capabilities = Self::from_query(version, renderer, &extensions, binary_formats, float_target_complete());
+
+            // Advertised isn't always loaded, a missing entry point turns the feature off as well
+            capabilities.compute_shaders &= gl::DispatchCompute::is_loaded() && gl::BindImageTexture::is_loaded();
+            capabilities.clip_control &= gl::ClipControl::is_loaded();
+            capabilities.program_interface_query &= gl::GetProgramResourceIndex::is_loaded() && gl::GetProgramResourceiv::is_loaded();
+            capabilities
+        }
+    }
+
+    // Decides what's supported from the raw query results, without touching the context
+    pub fn from_query(version: (u32, u32), renderer: String, extensions: &[String], binary_formats: i32, float_render_targets: bool) -> Self {
+        let has = |extension: &str| extensions.iter().any(|name| name == extension);
+        GpuCapabilities {
+            version,
+            renderer,
+            compute_shaders: version >= (4, 3) || has("GL_ARB_compute_shader"),
+            clip_control: version >= (4, 5) || has("GL_ARB_clip_control"),
+            program_interface_query: version >= (4, 3) || has("GL_ARB_program_interface_query"),
+            program_binary: binary_formats > 0,
+            float_render_targets,
+        }
+    }
+
+    // Err with a readable message when the renderer can't run on this context at all
+    pub fn check_minimum(&self) -> Result<(), String> {
+        if self.version < MINIMUM_VERSION {
+            return Err(format!(
+                "OpenGL {}.{} is required, but \"{}\" only provides OpenGL {}.{}",
+                MINIMUM_VERSION.0, MINIMUM_VERSION.1, self.renderer, self.version.0, self.version.1
+            ));
+        }
+        Ok(())
+    }
+
+    // Logs the context, and every feature that got turned off
+    pub fn report(&self) {
+        info!("OpenGL {}.{} on \"{}\"", self.version.0, self.version.1, self.renderer);
+        let missing = [
+            (self.compute_shaders, "no compute shaders, occlusion culling is unavailable"),
+            (self.clip_control, "no glClipControl, reversed-Z is unavailable"),
+            (self.program_interface_query, "no program interface queries, GPU struct layouts are not checked"),
+            (self.program_binary, "no program binary formats, the shader cache is disabled"),
+            (self.float_render_targets, "RGBA16F is not renderable, falling back to RGBA8 render targets"),
+        ];
+        for (_, warning) in missing.iter().filter(|(supported, _)| !supported) {
+            warn!("{warning}");
+        }
+    }
+}
+
+// "4.6.0 NVIDIA 535.54" and the like, (0, 0) if the string can't be parsed
+fn parse_version(version: &str) -> (u32, u32) {
+    let number = version.split_whitespace().next().unwrap_or("");
+    let mut parts = number.split('.').map(|part| part.parse::<u32>().ok());
+    match (parts.next().flatten(), parts.next().flatten()) {
+        (Some(major), Some(minor)) => (major, minor),
+        _ => (0, 0),
+    }
+}
+
+// Renders into a tiny RGBA16F texture to see whether the driver accepts it as a colour attachment
+unsafe fn float_target_complete() -> bool {
+    let (mut texture, mut framebuffer) = (0, 0);
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA16F as i32, 4, 4, 0, gl::RGBA, gl::FLOAT, std::ptr::null());
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    gl::GenFramebuffers(1, &mut framebuffer);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+    let complete = gl::CheckFramebufferStatus(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE;
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    gl::DeleteFramebuffers(1, &framebuffer);
+    gl::DeleteTextures(1, &texture);
+    complete
+}
+
+pub(crate) unsafe fn gl_string(name: GLenum) -> String {
+    let string = gl::GetString(name);
+    if string.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(string as *const _).to_string_lossy().into_owned()
+}
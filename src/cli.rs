@@ -0,0 +1,366 @@
+use std::path::PathBuf;
+
+use crate::benchmark::DEFAULT_THRESHOLD;
+use crate::camera::{FovAxis, Projection};
+use crate::graphics::{FramebufferFormat, StereoOutput, UpscaleMode, UpscaleSettings};
+use crate::mesh::{CpuData, DegenerateTriangles, LoadOptions, SceneSelection, UpAxis};
+use crate::shader_cache::ShaderCacheConfig;
+use crate::structs::WorldUp;
+use crate::texture::TextureQuality;
+use crate::texture_store::TextureBackend;
+
+const USAGE: &str = "Usage: rust_render_gl [options]
+    --model <path>      Load a .gltf, .glb or .obj model, can be repeated
+    --scale <factor>    Uniform scale applied to every --model (default 1)
+    --up-axis <axis>    Up axis of every --model: y (default) or z
+    --world-up <axis>   Up axis of the world, camera and sky: y (default) or z. Models are converted to it
+    --weld <epsilon>    Merge vertices of every --model that are within epsilon of each other
+    --flip-winding      Reverse the triangle winding of every --model
+    --keep-degenerate   Keep zero-area triangles of every --model instead of dropping them
+    --lods <ratios>     Generate LODs for every --model at these triangle ratios, e.g. 0.5,0.25,0.1
+    --cpu-data <policy> Vertex data every --model keeps in memory after uploading it: all (default), positions for
+                        raycasts and picking only, or none
+    --material <path>   Draw every mesh of every --model with one material, using this image as its albedo
+    --gltf-scene <n>    Scene of every glTF --model to load: an index, or all (default: the file's default scene)
+    --inspect <path>    Load a model and show it in a generated scene, on a pedestal over a checker ground
+    --inspect-layout <layout>
+                        Scene --inspect shows its model in: pedestal (default), materials for each of its materials
+                        on a sphere, or balls for a roughness and metallic sweep, which needs no --inspect
+    --hot-reload        Reload models and textures when their files change on disk
+    --shader-cache <dir>
+                        Directory the example's own shaders are cached in, or off to compile them every run (default
+                        shader_cache). The built-in shaders always use shader_cache
+    --asset-root <dir>  Also look for models and textures in this directory, can be repeated. More roots can be
+                        listed in RUST_RENDER_GL_ASSET_ROOTS, separated like PATH
+    --mode <mode>       Render mode, only \"raster\" is available
+    --width <pixels>    Window width (default 1280)
+    --height <pixels>   Window height (default 720)
+    --format <format>   Framebuffer format: rgba16f (default), rgba32f, r11g11b10f or rgba8
+    --fov <degrees>     Field of view (default 45)
+    --fov-axis <axis>   Axis the field of view spans: vertical (default) or horizontal
+    --near <distance>   Near plane distance (default 0.1)
+    --far <distance>    Far plane distance (default 1000)
+    --reversed-z        Use a reversed floating point depth buffer, for scenes with a large depth range
+    --hdr-nits <paper white>,<peak>
+                        Brightness in nits of the scene's 1.0 and of the display's peak, for HDR output (default
+                        200,1000)
+    --sort-epsilon <units>
+                        Transparent meshes closer together than this many world units keep a fixed draw order
+                        (default 0.01)
+    --stereo <output>   Render a stereo pair: side-by-side or anaglyph (red/cyan)
+    --upscale <mode>    How frames rendered below the window's resolution are brought up to it: off, bilinear
+                        (default) or temporal, which accumulates the jittered frames at the window's resolution
+    --render-scale <fraction>
+                        Fraction of the window's resolution to render (default 1), with dynamic resolution going
+                        no higher
+    --embed             Present into a texture the way an embedding application would, and show that texture in a
+                        panel of the window
+    --embed-framebuffer Like --embed, but hand the renderer a framebuffer object with the texture attached
+    --textures <kind>   How material textures are stored: individual (default), or arrays for a texture array per
+                        power of two size from 256 to 2048
+    --max-texture-size <pixels>
+                        Scale material textures down by powers of two while decoding, until they fit in this size
+    --texture-lod-bias <bias>
+                        Added to the mip level of every texture lookup, negative for sharper textures (default 0)
+    --headless          Render without showing a window, requires --out
+    --frames <count>    Number of frames to render in headless mode (default 1)
+    --out <directory>   Directory to write headless frame captures to
+    --supersample <n>   Capture headless frames as the average of n*n sub-pixel offset renders (default 1)
+    --trace <path>      Record a profile and write it as chrome://tracing JSON on exit
+    --benchmark <path>  Render a fixed camera track around the models and write a JSON report to path, plus a .txt one
+    --bench-frames <n>  Frames measured per render mode in --benchmark (default 300)
+    --compare <a> <b>   Print the metrics that changed between two --benchmark reports, exits with 1 on regressions
+    --threshold <pct>   Smallest change in percent --compare reports (default 5)
+    --help              Show this message";
+
+// Generated scene of --inspect, see InspectionLayout
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InspectLayout {
+    Pedestal,
+    Materials,
+    ShaderBalls,
+}
+
+pub struct Options {
+    pub models: Vec<PathBuf>,
+    pub load_options: LoadOptions,
+    pub world_up: WorldUp,
+    pub material: Option<PathBuf>,
+    pub inspect: Option<PathBuf>,
+    pub inspect_layout: Option<InspectLayout>, // Set whenever a scene should be generated
+    pub hot_reload: bool,
+    pub shader_cache: Option<ShaderCacheConfig>,
+    pub asset_roots: Vec<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    pub framebuffer_format: FramebufferFormat,
+    pub hdr_nits: Option<(f32, f32)>, // Paper white and peak
+    pub projection: Projection,
+    pub stereo: Option<StereoOutput>,
+    pub transparent_sort_epsilon: Option<f32>,
+    pub upscale: UpscaleSettings,
+    pub embed: bool,
+    pub embed_framebuffer: bool, // Present into the host's framebuffer object instead of its texture
+    pub texture_backend: TextureBackend,
+    pub texture_quality: TextureQuality,
+    pub texture_lod_bias: f32,
+    pub headless: bool,
+    pub frames: u32,
+    pub out: Option<PathBuf>,
+    pub supersample: u32,
+    pub trace: Option<PathBuf>,
+    pub benchmark: Option<PathBuf>,
+    pub benchmark_frames: u32,
+    pub compare: Option<(PathBuf, PathBuf)>,
+    pub threshold: f32,
+}
+
+impl Options {
+    // Parses the command line arguments, without the executable name. Returns the usage text on --help
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            models: Vec::new(),
+            load_options: LoadOptions::default(),
+            world_up: WorldUp::Y,
+            material: None,
+            inspect: None,
+            inspect_layout: None,
+            hot_reload: false,
+            shader_cache: Some(ShaderCacheConfig::default()),
+            asset_roots: Vec::new(),
+            width: 1280,
+            height: 720,
+            framebuffer_format: FramebufferFormat::Rgba16F,
+            hdr_nits: None,
+            projection: Projection::default(),
+            stereo: None,
+            transparent_sort_epsilon: None,
+            upscale: UpscaleSettings::default(),
+            embed: false,
+            embed_framebuffer: false,
+            texture_backend: TextureBackend::Individual,
+            texture_quality: TextureQuality::default(),
+            texture_lod_bias: 0.0,
+            headless: false,
+            frames: 1,
+            out: None,
+            supersample: 1,
+            trace: None,
+            benchmark: None,
+            benchmark_frames: 300,
+            compare: None,
+            threshold: DEFAULT_THRESHOLD,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                "--headless" => options.headless = true,
+                "--hot-reload" => options.hot_reload = true,
+                "--embed" => options.embed = true,
+                "--embed-framebuffer" => {
+                    options.embed = true;
+                    options.embed_framebuffer = true;
+                }
+                "--shader-cache" => {
+                    options.shader_cache = match value(&mut args, &arg)?.as_str() {
+                        "off" => None,
+                        directory => Some(ShaderCacheConfig {
+                            directory: PathBuf::from(directory),
+                            ..Default::default()
+                        }),
+                    }
+                }
+                "--asset-root" => options.asset_roots.push(PathBuf::from(value(&mut args, &arg)?)),
+                "--model" => options.models.push(PathBuf::from(value(&mut args, &arg)?)),
+                "--scale" => options.load_options.uniform_scale = float(&mut args, &arg)?,
+                "--weld" => options.load_options.weld_vertices = Some(float(&mut args, &arg)?),
+                "--flip-winding" => options.load_options.flip_winding = true,
+                "--keep-degenerate" => options.load_options.degenerate_triangles = DegenerateTriangles::Keep,
+                "--lods" => {
+                    let value = value(&mut args, &arg)?;
+                    let ratios = value
+                        .split(',')
+                        .map(|ratio| match ratio.trim().parse::<f32>() {
+                            Ok(ratio) if ratio > 0.0 && ratio < 1.0 => Ok(ratio),
+                            _ => Err(format!("--lods expects ratios between 0 and 1, got \"{ratio}\"")),
+                        })
+                        .collect::<Result<Vec<f32>, String>>()?;
+                    options.load_options.generate_lods = Some(ratios);
+                }
+                "--cpu-data" => {
+                    options.load_options.cpu_data = match value(&mut args, &arg)?.as_str() {
+                        "all" => CpuData::KeepAll,
+                        "positions" => CpuData::KeepPositionsOnly,
+                        "none" => CpuData::DropAfterUpload,
+                        policy => return Err(format!("Unknown vertex data policy \"{policy}\", expected all, positions or none")),
+                    }
+                }
+                "--up-axis" => {
+                    options.load_options.up_axis = match value(&mut args, &arg)?.as_str() {
+                        "y" | "Y" => UpAxis::Y,
+                        "z" | "Z" => UpAxis::Z,
+                        axis => return Err(format!("Unknown up axis \"{axis}\", expected y or z")),
+                    }
+                }
+                "--gltf-scene" => {
+                    options.load_options.scene_selection = match value(&mut args, &arg)?.as_str() {
+                        "all" => SceneSelection::All,
+                        index => SceneSelection::Index(
+                            index.parse().map_err(|_| format!("Invalid glTF scene \"{index}\", expected an index or all"))?,
+                        ),
+                    }
+                }
+                "--material" => options.material = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--inspect" => options.inspect = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--inspect-layout" => {
+                    options.inspect_layout = Some(match value(&mut args, &arg)?.as_str() {
+                        "pedestal" => InspectLayout::Pedestal,
+                        "materials" => InspectLayout::Materials,
+                        "balls" => InspectLayout::ShaderBalls,
+                        layout => return Err(format!("Unknown inspection layout \"{layout}\", expected pedestal, materials or balls")),
+                    })
+                }
+                "--world-up" => {
+                    options.world_up = match value(&mut args, &arg)?.as_str() {
+                        "y" | "Y" => WorldUp::Y,
+                        "z" | "Z" => WorldUp::Z,
+                        axis => return Err(format!("Unknown world up axis \"{axis}\", expected y or z")),
+                    }
+                }
+                "--out" => options.out = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--trace" => options.trace = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--benchmark" => options.benchmark = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--bench-frames" => options.benchmark_frames = number(&mut args, &arg)?,
+                "--compare" => {
+                    let old = PathBuf::from(value(&mut args, &arg)?);
+                    options.compare = Some((old, PathBuf::from(value(&mut args, &arg)?)));
+                }
+                "--threshold" => options.threshold = float(&mut args, &arg)?,
+                "--width" => options.width = number(&mut args, &arg)?,
+                "--height" => options.height = number(&mut args, &arg)?,
+                "--frames" => options.frames = number(&mut args, &arg)?,
+                "--supersample" => options.supersample = number(&mut args, &arg)?,
+                "--format" => {
+                    options.framebuffer_format = match value(&mut args, &arg)?.as_str() {
+                        "rgba16f" => FramebufferFormat::Rgba16F,
+                        "rgba32f" => FramebufferFormat::Rgba32F,
+                        "r11g11b10f" => FramebufferFormat::R11G11B10F,
+                        "rgba8" => FramebufferFormat::Rgba8,
+                        format => {
+                            return Err(format!("Unknown framebuffer format \"{format}\", expected rgba16f, rgba32f, r11g11b10f or rgba8"))
+                        }
+                    }
+                }
+                "--hdr-nits" => {
+                    let value = value(&mut args, &arg)?;
+                    let nits: Option<Vec<f32>> = value.split(',').map(|nits| nits.trim().parse().ok()).collect();
+                    match nits.as_deref() {
+                        Some(&[paper_white, peak]) if paper_white > 0.0 && peak >= paper_white => options.hdr_nits = Some((paper_white, peak)),
+                        _ => return Err(format!("--hdr-nits expects a paper white and a peak at least as bright, got \"{value}\"")),
+                    }
+                }
+                "--sort-epsilon" => options.transparent_sort_epsilon = Some(float(&mut args, &arg)?),
+                "--stereo" => {
+                    options.stereo = Some(match value(&mut args, &arg)?.as_str() {
+                        "side-by-side" => StereoOutput::SideBySide,
+                        "anaglyph" => StereoOutput::Anaglyph,
+                        output => return Err(format!("Unknown stereo output \"{output}\", expected side-by-side or anaglyph")),
+                    })
+                }
+                "--upscale" => {
+                    options.upscale.mode = match value(&mut args, &arg)?.as_str() {
+                        "off" => UpscaleMode::Off,
+                        "bilinear" => UpscaleMode::Bilinear,
+                        "temporal" => UpscaleMode::Temporal,
+                        mode => return Err(format!("Unknown upscale mode \"{mode}\", expected off, bilinear or temporal")),
+                    }
+                }
+                "--render-scale" => options.upscale.render_scale = float(&mut args, &arg)?,
+                "--textures" => {
+                    options.texture_backend = match value(&mut args, &arg)?.as_str() {
+                        "individual" => TextureBackend::Individual,
+                        "arrays" => TextureBackend::ArrayPerSize,
+                        kind => return Err(format!("Unknown texture storage \"{kind}\", expected individual or arrays")),
+                    }
+                }
+                "--max-texture-size" => options.texture_quality.max_dimension = number(&mut args, &arg)?.max(1),
+                "--texture-lod-bias" => options.texture_lod_bias = float(&mut args, &arg)?,
+                "--fov" => options.projection.fov = float(&mut args, &arg)?.to_radians(),
+                "--near" => options.projection.near = float(&mut args, &arg)?,
+                "--far" => options.projection.far = float(&mut args, &arg)?,
+                "--reversed-z" => options.projection.reversed_z = true,
+                "--fov-axis" => {
+                    options.projection.fov_axis = match value(&mut args, &arg)?.as_str() {
+                        "vertical" | "v" => FovAxis::Vertical,
+                        "horizontal" | "h" => FovAxis::Horizontal,
+                        axis => return Err(format!("Unknown field of view axis \"{axis}\", expected vertical or horizontal")),
+                    }
+                }
+                "--mode" => match value(&mut args, &arg)?.as_str() {
+                    "raster" => {}
+                    mode @ ("cpu" | "gpu") => {
+                        return Err(format!("Render mode \"{mode}\" is not available, this renderer only rasterizes"))
+                    }
+                    mode => return Err(format!("Unknown render mode \"{mode}\", expected raster")),
+                },
+                "--scene" | "--raytrace-scale" | "--camera" => {
+                    return Err(format!("{arg} is not supported yet"));
+                }
+                _ => return Err(format!("Unknown argument \"{arg}\"\n\n{USAGE}")),
+            }
+        }
+
+        // Validate combinations
+        if options.inspect.is_some() && options.inspect_layout.is_none() {
+            options.inspect_layout = Some(InspectLayout::Pedestal);
+        }
+        if options.inspect.is_none() && matches!(options.inspect_layout, Some(InspectLayout::Pedestal | InspectLayout::Materials)) {
+            return Err("--inspect-layout pedestal and materials need a model, pass one with --inspect <path>".to_string());
+        }
+        if options.headless && options.out.is_none() {
+            return Err("--headless needs an output directory, pass one with --out <directory>".to_string());
+        }
+        if !options.headless && options.out.is_some() {
+            return Err("--out only works together with --headless".to_string());
+        }
+        if options.supersample == 0 {
+            return Err("--supersample must be at least 1".to_string());
+        }
+        if options.supersample > 1 && !options.headless {
+            return Err("--supersample only works together with --headless".to_string());
+        }
+        if options.benchmark.is_some() && options.headless {
+            return Err("--benchmark measures the window's frames, it can't be combined with --headless".to_string());
+        }
+        if options.benchmark_frames == 0 {
+            return Err("--bench-frames must be at least 1".to_string());
+        }
+        if !(options.upscale.render_scale >= 0.1 && options.upscale.render_scale <= 1.0) {
+            return Err(format!("--render-scale must be between 0.1 and 1, got {}", options.upscale.render_scale));
+        }
+        if options.width == 0 || options.height == 0 {
+            return Err("--width and --height must be greater than zero".to_string());
+        }
+        options.projection.validate()?;
+        Ok(options)
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{name} expects a value"))
+}
+
+fn number(args: &mut impl Iterator<Item = String>, name: &str) -> Result<u32, String> {
+    let value = value(args, name)?;
+    value
+        .parse::<u32>()
+        .map_err(|_| format!("{name} expects a whole number, got \"{value}\""))
+}
+
+fn float(args: &mut impl Iterator<Item = String>, name: &str) -> Result<f32, String> {
+    let value = value(args, name)?;
+    value
+        .parse::<f32>()
+        .map_err(|_| format!("{name} expects a number, got \"{value}\""))
+}
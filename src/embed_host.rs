@@ -0,0 +1,88 @@
+use std::ptr::null;
+
+use crate::graphics::{ExternalImage, ExternalTarget, OutputEncoding, Renderer};
+
+// Space around the panel, in window pixels
+const BORDER: i32 = 32;
+
+// Stand-in for an application the renderer is embedded in, for --embed. It owns a texture the renderer presents
+// into, and draws that texture into a panel of the window itself, like an editor drawing its viewport widget. Only
+// GL ids and a size cross over to the renderer
+pub struct EmbedHost {
+    texture: u32,
+    framebuffer_object: u32, // Has the texture attached, to blit it to the window
+    size: [i32; 2],
+    share_framebuffer: bool, // Hand the renderer the framebuffer object instead of the texture
+}
+
+impl EmbedHost {
+    pub fn new(share_framebuffer: bool) -> Self {
+        let (mut texture, mut framebuffer_object) = (0, 0);
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::GenFramebuffers(1, &mut framebuffer_object);
+        }
+        EmbedHost {
+            texture,
+            framebuffer_object,
+            size: [0, 0],
+            share_framebuffer,
+        }
+    }
+
+    // Sizes the texture to fill the window apart from the border. The renderer is only told when its target has a
+    // different size, that's how a host passes resizes on
+    pub fn fit(&mut self, renderer: &mut Renderer) -> Result<(), String> {
+        let window = renderer.window_size();
+        let size = [(window[0] - 2 * BORDER).max(1), (window[1] - 2 * BORDER).max(1)];
+        if renderer.render_target().is_some_and(|target| [target.width, target.height] == size) {
+            return Ok(());
+        }
+        self.size = size;
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::SRGB8_ALPHA8 as i32, size[0], size[1], 0, gl::RGBA, gl::UNSIGNED_BYTE, null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.texture, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        renderer.set_render_target(Some(ExternalTarget {
+            image: match self.share_framebuffer {
+                true => ExternalImage::Framebuffer(self.framebuffer_object),
+                false => ExternalImage::Texture(self.texture),
+            },
+            width: size[0],
+            height: size[1],
+            encoding: OutputEncoding::SrgbFramebuffer, // The texture is sRGB
+        }))
+    }
+
+    // Draws the panel over a plain background and presents the window. Call it after end_frame
+    pub fn present(&self, renderer: &mut Renderer) {
+        let window = renderer.window_size();
+        unsafe {
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, window[0], window[1]);
+            gl::ClearBufferfv(gl::COLOR, 0, [0.1f32, 0.1, 0.12, 1.0].as_ptr());
+            // Both sides are sRGB and GL_FRAMEBUFFER_SRGB is off, so the encoded values are copied as they are
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer_object);
+            gl::BlitFramebuffer(
+                0,
+                0,
+                self.size[0],
+                self.size[1],
+                BORDER,
+                BORDER,
+                BORDER + self.size[0],
+                BORDER + self.size[1],
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        renderer.swap_window_buffers();
+    }
+}
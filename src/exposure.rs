@@ -0,0 +1,354 @@
+use glam::Vec2;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::texture::{GpuTexture, TextureBinder, TextureSlot};
+
+// Width and height of the grid the scene gets metered at, its mip chain averages it down to a single texel
+const METERING_SIZE: i32 = 64;
+
+// Which parts of the screen count towards the metered brightness
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MeteringMode {
+    Average = 0,        // The whole screen equally
+    CentreWeighted = 1, // Mostly the centre, falling off towards the edges
+    Spot = 2,           // Only a small circle around a point
+}
+
+// Automatic exposure - the scene's brightness is metered every frame, and the exposure follows it over time. All EVs
+// are at ISO 100, EV 0 is an average scene luminance of 0.125, and the metered average gets exposed to middle grey
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExposureSettings {
+    pub enabled: bool,
+    pub metering: MeteringMode,
+    pub centre_falloff: f32, // Distance from the centre where the weight has dropped to 1/e, in screen heights
+    pub spot: Vec2,          // Spot metering point, 0 to 1 across the screen from the top left
+    pub spot_radius: f32,    // In screen heights
+    pub compensation: f32,   // EV added after metering, positive is brighter
+    pub min_ev: f32,         // The metered EV is clamped to this range before compensation
+    pub max_ev: f32,
+    pub brighter_time: f32, // Seconds to adapt to a scene getting brighter, roughly
+    pub darker_time: f32,   // Eyes adapt to darkness slower
+    pub heat_map: bool,     // Debug view, tints the screen by how much each part counts towards the metering
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        ExposureSettings {
+            enabled: false,
+            metering: MeteringMode::Average,
+            centre_falloff: 0.35,
+            spot: Vec2::splat(0.5),
+            spot_radius: 0.05,
+            compensation: 0.0,
+            min_ev: -6.0,
+            max_ev: 12.0,
+            brighter_time: 0.5,
+            darker_time: 3.0,
+            heat_map: false,
+        }
+    }
+}
+
+// Fraction of the way from the previous EV to the metered one that one frame covers. The adaptation is exponential,
+// so it takes the same time at any frame rate
+pub fn adaptation_rate(time: f32, delta_time: f32) -> f32 {
+    1.0 - (-delta_time / time.max(1e-4)).exp()
+}
+
+// GPU side of automatic exposure. The metering grid holds the weighted log luminance and the weight of each cell,
+// its last mip level is their average. The adapted EV lives on the GPU in a pair of 1x1 textures, so nothing is ever
+// read back
+pub struct AutoExposure {
+    metering_texture: u32,
+    metering_framebuffer_object: u32,
+    ev_textures: [GpuTexture; 2],
+    ev_framebuffer_objects: [u32; 2],
+    current: usize,
+    valid: bool, // The current EV texture holds an adapted value, otherwise the next frame starts at its metered EV
+}
+
+impl AutoExposure {
+    pub fn new() -> Self {
+        let mut auto_exposure = AutoExposure {
+            metering_texture: 0,
+            metering_framebuffer_object: 0,
+            ev_textures: [0, 1].map(|_| GpuTexture::new(gl::R32F, gl::RED, gl::FLOAT)),
+            ev_framebuffer_objects: [0, 0],
+            current: 0,
+            valid: false,
+        };
+        auto_exposure.create_gl_resources();
+        auto_exposure
+    }
+
+    fn create_gl_resources(&mut self) {
+        unsafe {
+            gl::GenTextures(1, &mut self.metering_texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.metering_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG32F as _, METERING_SIZE, METERING_SIZE, 0, gl::RG, gl::FLOAT, std::ptr::null());
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::GenFramebuffers(1, &mut self.metering_framebuffer_object);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.metering_framebuffer_object);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.metering_texture, 0);
+
+            gl::GenFramebuffers(2, self.ev_framebuffer_objects.as_mut_ptr());
+            for i in 0..2 {
+                self.ev_textures[i].resize(1, 1);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.ev_framebuffer_objects[i]);
+                self.ev_textures[i].attach(gl::COLOR_ATTACHMENT0);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        self.valid = false;
+    }
+
+    // Meters the scene, moves the exposure towards the metered EV and multiplies it into the scene. The caller sets
+    // up the fullscreen draw state, and `render_size` is the part of the scene texture that was rendered to
+    pub fn apply(&mut self, shader: u32, settings: &ExposureSettings, scene: &GpuTexture, scene_framebuffer: u32, render_size: [i32; 2], delta_time: f32) {
+        let (width, height) = (render_size[0], render_size[1]);
+        let scene_size = scene.size();
+        let next = 1 - self.current;
+        unsafe {
+            let location = |name: &std::ffi::CStr| gl::GetUniformLocation(shader, name.as_ptr());
+            gl::UseProgram(shader);
+            gl::Uniform1i(location(c"u_metering"), settings.metering as i32);
+            gl::Uniform1f(location(c"u_centre_falloff"), settings.centre_falloff.max(1e-3));
+            gl::Uniform2f(location(c"u_spot"), settings.spot.x, 1.0 - settings.spot.y);
+            gl::Uniform1f(location(c"u_spot_radius"), settings.spot_radius.max(1e-3));
+            gl::Uniform1f(location(c"u_aspect"), width as f32 / height.max(1) as f32);
+
+            // Meter the scene into the grid, and average it down
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.metering_framebuffer_object);
+            gl::Viewport(0, 0, METERING_SIZE, METERING_SIZE);
+            gl::Uniform1i(location(c"u_stage"), 0);
+            gl::Uniform2f(location(c"u_target_size"), METERING_SIZE as f32, METERING_SIZE as f32);
+            gl::Uniform2f(
+                location(c"u_uv_scale"),
+                width as f32 / scene_size[0].max(1) as f32,
+                height as f32 / scene_size[1].max(1) as f32,
+            );
+            scene.bind(TextureSlot::Albedo);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindTexture(gl::TEXTURE_2D, self.metering_texture);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            // Adapt from the previous EV
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.ev_framebuffer_objects[next]);
+            gl::Viewport(0, 0, 1, 1);
+            gl::Uniform1i(location(c"u_stage"), 1);
+            gl::Uniform1i(location(c"u_metering_level"), METERING_SIZE.ilog2() as i32);
+            gl::Uniform1i(location(c"u_history_valid"), self.valid as i32);
+            gl::Uniform1f(location(c"u_compensation"), settings.compensation);
+            gl::Uniform1f(location(c"u_min_ev"), settings.min_ev);
+            gl::Uniform1f(location(c"u_max_ev"), settings.max_ev.max(settings.min_ev));
+            gl::Uniform1f(location(c"u_brighter_rate"), adaptation_rate(settings.brighter_time, delta_time));
+            gl::Uniform1f(location(c"u_darker_rate"), adaptation_rate(settings.darker_time, delta_time));
+            TextureBinder::bind(TextureSlot::Albedo, self.metering_texture as i32);
+            self.ev_textures[self.current].bind(TextureSlot::Exposure);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            // Multiply the exposure into the scene, the same way SSAO is
+            gl::BindFramebuffer(gl::FRAMEBUFFER, scene_framebuffer);
+            gl::Viewport(0, 0, width, height);
+            gl::Uniform1i(location(c"u_stage"), 2);
+            gl::Uniform2f(location(c"u_target_size"), width as f32, height as f32);
+            self.ev_textures[next].bind(TextureSlot::Exposure);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ZERO, gl::SRC_COLOR);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            if settings.heat_map {
+                gl::Uniform1i(location(c"u_stage"), 3);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            gl::Disable(gl::BLEND);
+
+            TextureBinder::bind(TextureSlot::Albedo, 0);
+            TextureBinder::bind(TextureSlot::Exposure, 0);
+        }
+        self.current = next;
+        self.valid = true;
+    }
+
+    // Jumps straight to the metered exposure on the next frame, after a cut for example
+    pub fn reset(&mut self) {
+        self.valid = false;
+    }
+
+    pub fn recreate_gl_resources(&mut self) {
+        self.delete();
+        self.create_gl_resources();
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.metering_texture);
+            gl::DeleteFramebuffers(1, &self.metering_framebuffer_object);
+            gl::DeleteFramebuffers(2, self.ev_framebuffer_objects.as_ptr());
+        }
+        for texture in &mut self.ev_textures {
+            texture.delete();
+        }
+        self.metering_texture = 0;
+        self.metering_framebuffer_object = 0;
+        self.ev_framebuffer_objects = [0, 0];
+    }
+}
+
+// CPU versions of what exposure.frag computes, so the metering and adaptation maths can be tested without a GL
+// context. Keep them in sync with the shader
+#[cfg(test)]
+const EV_ZERO_LUMINANCE: f32 = 0.125;
+
+// uv is 0 to 1 across the screen from the top left, like ExposureSettings::spot
+#[cfg(test)]
+fn metering_weight(settings: &ExposureSettings, uv: Vec2, aspect: f32) -> f32 {
+    let aspect = Vec2::new(aspect, 1.0);
+    match settings.metering {
+        MeteringMode::Average => 1.0,
+        MeteringMode::CentreWeighted => {
+            let offset = (uv - 0.5) * aspect;
+            let falloff = settings.centre_falloff.max(1e-3);
+            (-offset.dot(offset) / (falloff * falloff)).exp()
+        }
+        MeteringMode::Spot => {
+            let radius = settings.spot_radius.max(1e-3);
+            let distance = ((uv - settings.spot) * aspect).length();
+            let t = ((distance - radius * 0.5) / (radius * 0.5)).clamp(0.0, 1.0);
+            1.0 - t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+// The clamped and compensated EV of a luminance image, rows top to bottom. None when nothing was weighted
+#[cfg(test)]
+fn metered_ev(settings: &ExposureSettings, luminance: &[f32], width: usize) -> Option<f32> {
+    let height = luminance.len() / width;
+    let aspect = width as f32 / height as f32;
+    let (mut log_sum, mut weight_sum) = (0.0, 0.0);
+    for (index, value) in luminance.iter().enumerate() {
+        let uv = (Vec2::new((index % width) as f32, (index / width) as f32) + 0.5) / Vec2::new(width as f32, height as f32);
+        let weight = metering_weight(settings, uv, aspect);
+        log_sum += value.max(1e-5).log2() * weight;
+        weight_sum += weight;
+    }
+    if weight_sum < 1e-6 {
+        return None;
+    }
+    let ev = (log_sum / weight_sum - EV_ZERO_LUMINANCE.log2()).clamp(settings.min_ev, settings.max_ev.max(settings.min_ev));
+    Some(ev - settings.compensation)
+}
+
+// One frame of adaptation. Without history the exposure jumps straight to the metered EV
+#[cfg(test)]
+fn adapt_ev(settings: &ExposureSettings, previous: Option<f32>, metered: Option<f32>, delta_time: f32) -> f32 {
+    match (previous, metered) {
+        (None, metered) => metered.unwrap_or(0.0),
+        (Some(previous), None) => previous,
+        (Some(previous), Some(ev)) => {
+            let time = if ev > previous { settings.brighter_time } else { settings.darker_time };
+            previous + (ev - previous) * adaptation_rate(time, delta_time)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(metering: MeteringMode) -> ExposureSettings {
+        ExposureSettings {
+            enabled: true,
+            metering,
+            ..ExposureSettings::default()
+        }
+    }
+
+    // A 16x16 image, bright in the top left quarter
+    fn bright_corner() -> Vec<f32> {
+        (0..256).map(|index| if index % 16 < 8 && index / 16 < 8 { 8.0 } else { 0.125 }).collect()
+    }
+
+    #[test]
+    fn average_metering_is_the_log_average() {
+        let settings = settings(MeteringMode::Average);
+        assert_eq!(metered_ev(&settings, &[0.125; 64], 8), Some(0.0));
+        assert_eq!(metered_ev(&settings, &[1.0; 64], 8), Some(3.0));
+
+        // A quarter at 6 EV and the rest at 0 averages to 1.5 EV, instead of being dominated by the highlights
+        let ev = metered_ev(&settings, &bright_corner(), 16).unwrap();
+        assert!((ev - 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn metering_clamps_before_compensation() {
+        let settings = ExposureSettings {
+            compensation: 1.0,
+            max_ev: 2.0,
+            ..settings(MeteringMode::Average)
+        };
+        assert_eq!(metered_ev(&settings, &[64.0; 16], 4), Some(1.0));
+    }
+
+    #[test]
+    fn centre_weighted_metering_favours_the_centre() {
+        let settings = settings(MeteringMode::CentreWeighted);
+        assert!((metering_weight(&settings, Vec2::splat(0.5), 1.0) - 1.0).abs() < 1e-6);
+        assert!(metering_weight(&settings, Vec2::ZERO, 1.0) < 0.1);
+
+        // Bright centre, dark edges
+        let luminance: Vec<f32> = (0..256)
+            .map(|index| if (4..12).contains(&(index % 16)) && (4..12).contains(&(index / 16)) { 8.0 } else { 0.125 })
+            .collect();
+        let average = metered_ev(&ExposureSettings { metering: MeteringMode::Average, ..settings }, &luminance, 16).unwrap();
+        let centre = metered_ev(&settings, &luminance, 16).unwrap();
+        assert!(centre > average + 1.0);
+    }
+
+    #[test]
+    fn spot_metering_only_sees_the_spot() {
+        let settings = ExposureSettings {
+            spot: Vec2::splat(0.25),
+            spot_radius: 0.1,
+            ..settings(MeteringMode::Spot)
+        };
+        assert!((metered_ev(&settings, &bright_corner(), 16).unwrap() - 6.0).abs() < 1e-4);
+        let settings = ExposureSettings { spot: Vec2::new(0.75, 0.25), ..settings };
+        assert!(metered_ev(&settings, &bright_corner(), 16).unwrap().abs() < 1e-4);
+
+        // Off the screen nothing is weighted
+        let settings = ExposureSettings { spot: Vec2::splat(2.0), ..settings };
+        assert_eq!(metered_ev(&settings, &bright_corner(), 16), None);
+    }
+
+    #[test]
+    fn adaptation_without_history_jumps() {
+        let settings = settings(MeteringMode::Average);
+        assert_eq!(adapt_ev(&settings, None, Some(4.0), 1.0 / 60.0), 4.0);
+        assert_eq!(adapt_ev(&settings, Some(4.0), None, 1.0 / 60.0), 4.0);
+    }
+
+    #[test]
+    fn adaptation_is_exponential() {
+        let settings = settings(MeteringMode::Average);
+
+        // After brighter_time seconds, 1 - 1/e of the way there, at any frame rate
+        for frames in [10, 60, 240] {
+            let delta_time = settings.brighter_time / frames as f32;
+            let ev = (0..frames).fold(0.0, |ev, _| adapt_ev(&settings, Some(ev), Some(1.0), delta_time));
+            assert!((ev - (1.0 - (-1.0f32).exp())).abs() < 1e-4);
+        }
+
+        // Darker scenes take longer
+        let brighter = adapt_ev(&settings, Some(0.0), Some(1.0), 0.1);
+        let darker = 1.0 - adapt_ev(&settings, Some(1.0), Some(0.0), 0.1);
+        assert!(brighter > darker);
+    }
+}
@@ -0,0 +1,167 @@
+use std::{fmt::Write, time::Instant};
+
+use gl::types::GLuint;
+
+use crate::profiler::ProfileScope;
+
+#[derive(Debug, Clone)]
+pub enum PassStatus {
+    Ran,
+    Skipped(&'static str), // Reason
+}
+
+#[derive(Debug, Clone)]
+pub struct PassRecord {
+    pub name: &'static str,
+    pub reads: &'static [&'static str],  // Textures and buffers the pass samples from
+    pub writes: &'static [&'static str], // Attachments and buffers the pass renders into
+    pub status: PassStatus,
+    pub cpu_time_ms: f64,
+    query: Option<GLuint>, // GL_TIME_ELAPSED query around the pass, read back when the graph gets dumped
+}
+
+// Record of the passes that actually ran during one frame, in execution order. Passes don't nest, every pass has to
+// end before the next one begins, since GL timer queries can't overlap
+#[derive(Default)]
+pub struct FrameGraph {
+    passes: Vec<PassRecord>,
+    open_pass: Option<(Instant, ProfileScope)>,
+    queries: Vec<GLuint>, // Pool of timer queries, the first queries_used of them belong to this frame
+    queries_used: usize,
+}
+
+impl FrameGraph {
+    pub fn clear(&mut self) {
+        debug_assert!(self.open_pass.is_none(), "Frame graph cleared while a pass was still open");
+        self.passes.clear();
+        self.open_pass = None;
+        self.queries_used = 0;
+    }
+
+    pub fn begin_pass(&mut self, name: &'static str, reads: &'static [&'static str], writes: &'static [&'static str]) {
+        debug_assert!(self.open_pass.is_none(), "Pass \"{name}\" began while another pass was still open");
+        if self.queries_used == self.queries.len() {
+            let mut query = 0;
+            unsafe {
+                gl::GenQueries(1, &mut query);
+            }
+            self.queries.push(query);
+        }
+        let query = self.queries[self.queries_used];
+        self.queries_used += 1;
+        unsafe {
+            gl::BeginQuery(gl::TIME_ELAPSED, query);
+        }
+        self.passes.push(PassRecord {
+            name,
+            reads,
+            writes,
+            status: PassStatus::Ran,
+            cpu_time_ms: 0.0,
+            query: Some(query),
+        });
+        self.open_pass = Some((Instant::now(), ProfileScope::new(name)));
+    }
+
+    pub fn end_pass(&mut self) {
+        let Some((start, _profile_scope)) = self.open_pass.take() else {
+            debug_assert!(false, "end_pass without a matching begin_pass");
+            return;
+        };
+        unsafe {
+            gl::EndQuery(gl::TIME_ELAPSED);
+        }
+        if let Some(pass) = self.passes.last_mut() {
+            pass.cpu_time_ms = start.elapsed().as_secs_f64() * 1000.0;
+        }
+    }
+
+    pub fn skip_pass(
+        &mut self,
+        name: &'static str,
+        reads: &'static [&'static str],
+        writes: &'static [&'static str],
+        reason: &'static str,
+    ) {
+        self.passes.push(PassRecord {
+            name,
+            reads,
+            writes,
+            status: PassStatus::Skipped(reason),
+            cpu_time_ms: 0.0,
+            query: None,
+        });
+    }
+
+    // Waits for the pass's timer query, so only call this on a finished frame
+    pub fn gpu_time_ms(&self, pass: &PassRecord) -> Option<f64> {
+        let query = pass.query?;
+        let mut nanoseconds = 0u64;
+        unsafe {
+            gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanoseconds);
+        }
+        Some(nanoseconds as f64 / 1_000_000.0)
+    }
+
+    // Ordered list of the passes, one per line
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (index, pass) in self.passes.iter().enumerate() {
+            let _ = write!(text, "{index:2}. {:<20}", pass.name);
+            match pass.status {
+                PassStatus::Ran => {
+                    let _ = write!(text, " cpu {:6.3} ms", pass.cpu_time_ms);
+                    if let Some(gpu_time_ms) = self.gpu_time_ms(pass) {
+                        let _ = write!(text, "  gpu {gpu_time_ms:6.3} ms");
+                    }
+                }
+                PassStatus::Skipped(reason) => {
+                    let _ = write!(text, " skipped ({reason})");
+                }
+            }
+            let _ = writeln!(text, "  reads [{}] writes [{}]", pass.reads.join(", "), pass.writes.join(", "));
+        }
+        text
+    }
+
+    // GraphViz graph with an edge from the last pass that wrote a resource to every later pass that reads it.
+    // Skipped passes are drawn dashed and don't count as writers
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph frame {\n    rankdir=LR;\n    node [shape=box];\n");
+        let mut last_writer: Vec<(&str, usize)> = Vec::new();
+        for (index, pass) in self.passes.iter().enumerate() {
+            let label = match pass.status {
+                PassStatus::Ran => match self.gpu_time_ms(pass) {
+                    Some(gpu_time_ms) => format!("{}\\ncpu {:.3} ms, gpu {gpu_time_ms:.3} ms", pass.name, pass.cpu_time_ms),
+                    None => format!("{}\\ncpu {:.3} ms", pass.name, pass.cpu_time_ms),
+                },
+                PassStatus::Skipped(reason) => format!("{}\\nskipped: {reason}", pass.name),
+            };
+            let style = if matches!(pass.status, PassStatus::Skipped(_)) { ", style=dashed" } else { "" };
+            let _ = writeln!(dot, "    pass{index} [label=\"{label}\"{style}];");
+
+            for resource in pass.reads {
+                if let Some((_, writer)) = last_writer.iter().find(|(name, _)| name == resource) {
+                    let _ = writeln!(dot, "    pass{writer} -> pass{index} [label=\"{resource}\"];");
+                }
+            }
+            if matches!(pass.status, PassStatus::Ran) {
+                for resource in pass.writes {
+                    last_writer.retain(|(name, _)| name != resource);
+                    last_writer.push((resource, index));
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteQueries(self.queries.len() as i32, self.queries.as_ptr());
+        }
+        self.queries.clear();
+        self.passes.clear();
+        self.queries_used = 0;
+    }
+}
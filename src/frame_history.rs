@@ -0,0 +1,161 @@
+use std::{collections::VecDeque, ffi::c_void, path::Path, ptr::null};
+
+use crate::helpers::write_ppm;
+
+// Readbacks are spread over this many pixel buffers, and each one is only mapped once the GPU has had
+// that many frames to finish the copy, so capturing never stalls the pipeline
+const PIXEL_BUFFER_COUNT: usize = 3;
+
+pub struct HistoryFrame {
+    pub frame_index: u64,
+    pub time: f64,
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u8>, // RGBA8, bottom row first
+}
+
+// Rolling buffer of the last few presented frames, kept on the CPU at reduced resolution
+pub struct FrameHistory {
+    capacity: usize,
+    frames: VecDeque<HistoryFrame>,
+    pixel_buffers: [u32; PIXEL_BUFFER_COUNT],
+    pending: [Option<HistoryFrame>; PIXEL_BUFFER_COUNT],
+    next_buffer: usize,
+    framebuffer_object: u32,
+    texture: u32,
+    width: i32,
+    height: i32,
+}
+
+impl FrameHistory {
+    pub fn new(capacity: usize) -> Self {
+        let mut pixel_buffers = [0; PIXEL_BUFFER_COUNT];
+        let mut framebuffer_object = 0;
+        unsafe {
+            gl::GenBuffers(PIXEL_BUFFER_COUNT as i32, pixel_buffers.as_mut_ptr());
+            gl::GenFramebuffers(1, &mut framebuffer_object);
+        }
+        FrameHistory {
+            capacity: capacity.max(1),
+            frames: VecDeque::new(),
+            pixel_buffers,
+            pending: Default::default(),
+            next_buffer: 0,
+            framebuffer_object,
+            texture: 0,
+            width: 0,
+            height: 0,
+        }
+    }
+
+    // Downscales the source framebuffer and starts reading it back, collecting the readback started a few frames ago
+    pub fn capture(&mut self, source: u32, source_size: [i32; 2], downscale: i32, frame_index: u64, time: f64) {
+        let width = (source_size[0] / downscale.max(1)).max(1);
+        let height = (source_size[1] / downscale.max(1)).max(1);
+        if width != self.width || height != self.height {
+            self.resize(width, height);
+        }
+
+        unsafe {
+            // Finish the oldest readback in this slot
+            let slot = self.next_buffer;
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.pixel_buffers[slot]);
+            if let Some(mut frame) = self.pending[slot].take() {
+                let size = (frame.width * frame.height * 4) as usize;
+                let mapped = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, size as isize, gl::MAP_READ_BIT) as *const u8;
+                if !mapped.is_null() {
+                    frame.pixels = std::slice::from_raw_parts(mapped, size).to_vec();
+                    gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+                    if self.frames.len() == self.capacity {
+                        self.frames.pop_front();
+                    }
+                    self.frames.push_back(frame);
+                }
+            }
+
+            // Downscale into our own target, then start reading it back into the pixel buffer. The scene is linear, the
+            // sRGB target encodes it on the way in so the dumps look like the window
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, source);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.framebuffer_object);
+            gl::Enable(gl::FRAMEBUFFER_SRGB);
+            gl::BlitFramebuffer(0, 0, source_size[0], source_size[1], 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::LINEAR);
+            gl::Disable(gl::FRAMEBUFFER_SRGB);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer_object);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(0, 0, width, height, gl::RGBA, gl::UNSIGNED_BYTE, null::<c_void>() as *mut c_void);
+            self.pending[slot] = Some(HistoryFrame {
+                frame_index,
+                time,
+                width,
+                height,
+                pixels: Vec::new(),
+            });
+            self.next_buffer = (slot + 1) % PIXEL_BUFFER_COUNT;
+
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    fn resize(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        self.pending = Default::default();
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::GenTextures(1, &mut self.texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::SRGB8_ALPHA8 as _, width, height, 0, gl::RGBA, gl::UNSIGNED_BYTE, null());
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.texture, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            for pixel_buffer in self.pixel_buffers {
+                gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pixel_buffer);
+                gl::BufferData(gl::PIXEL_PACK_BUFFER, (width * height * 4) as isize, null(), gl::STREAM_READ);
+            }
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+    }
+
+    // Writes every stored frame as a PPM file named after its frame index and timestamp, returns how many were written
+    pub fn dump(&self, directory: &Path) -> Result<usize, String> {
+        std::fs::create_dir_all(directory)
+            .map_err(|error| format!("Failed to create \"{}\": {error}", directory.display()))?;
+        for frame in &self.frames {
+            let rgb: Vec<u8> = frame.pixels.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+            let path = directory.join(format!("frame_{:06}_{:.3}s.ppm", frame.frame_index, frame.time));
+            write_ppm(&path, frame.width as usize, frame.height as usize, &rgb)?;
+        }
+        Ok(self.frames.len())
+    }
+
+    // CPU and GPU memory used by the history, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        let frame_size = (self.width * self.height * 4) as usize;
+        let cpu: usize = self.frames.iter().map(|frame| frame.pixels.len()).sum();
+        cpu + frame_size * (PIXEL_BUFFER_COUNT + 1)
+    }
+
+    // Replaces the GL objects, keeping the frames already read back. Readbacks still in flight are lost
+    pub fn recreate_gl_resources(&mut self) {
+        self.delete();
+        unsafe {
+            gl::GenBuffers(PIXEL_BUFFER_COUNT as i32, self.pixel_buffers.as_mut_ptr());
+            gl::GenFramebuffers(1, &mut self.framebuffer_object);
+        }
+        self.pending = Default::default();
+        self.texture = 0;
+        self.width = 0;
+        self.height = 0;
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(PIXEL_BUFFER_COUNT as i32, self.pixel_buffers.as_ptr());
+            gl::DeleteFramebuffers(1, &self.framebuffer_object);
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
@@ -0,0 +1,665 @@
+use crate::gpu_buffer::GpuBuffer;
+use crate::graphics::Renderer;
+use crate::material::{AlphaMode, Material};
+use crate::mesh::{generate_flat_normals, LoadOptions, Mesh, Model, SceneSelection};
+use crate::obj::load_texture;
+use crate::structs::{normal_matrix, LocalPoint, Transform, WorldPoint, WorldUp, AABB};
+use crate::{profile_scope, structs::Vertex, texture::{Texture, TextureSlot}, workers};
+use glam::Vec4Swizzles;
+use log::{debug, warn};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use gltf::buffer::Data;
+use std::{collections::HashMap, path::{Path, PathBuf}};
+
+// So what this function needs to do: &[u8] -(reinterpret)> &[SrcCompType] -(convert)> &[DstCompType]
+fn reinterpret_then_convert<SrcCompType, DstCompType>(input_buffer: &[u8]) -> Vec<DstCompType>
+where
+    DstCompType: From<SrcCompType>,
+    SrcCompType: Copy,
+{
+    // &[u8] -> &[SrcCompType]
+    let input_ptr = input_buffer.as_ptr().cast::<SrcCompType>();
+    let src_comp_buffer: &[SrcCompType] = unsafe {
+        std::slice::from_raw_parts(
+            input_ptr,
+            input_buffer.len() / std::mem::size_of::<SrcCompType>(),
+        )
+    };
+
+    // &[SrcCompType] -> Vec<DstCompType>
+    let mut dst_comp_vec = Vec::<DstCompType>::new();
+    for item in src_comp_buffer {
+        dst_comp_vec.push(DstCompType::from(*item));
+    }
+
+    // Return
+    dst_comp_vec
+}
+
+fn convert_gltf_buffer_to_f32(input_buffer: &[u8], accessor: &gltf::Accessor) -> Vec<f32> {
+    // Convert based on data type
+    // First we make a f64 vector (this way we can do fancy generics magic and still convert u32 to f32)
+    let values64 = match accessor.data_type() {
+        gltf::accessor::DataType::I8 => reinterpret_then_convert::<i8, f64>(input_buffer),
+        gltf::accessor::DataType::U8 => reinterpret_then_convert::<u8, f64>(input_buffer),
+        gltf::accessor::DataType::I16 => reinterpret_then_convert::<i16, f64>(input_buffer),
+        gltf::accessor::DataType::U16 => reinterpret_then_convert::<u16, f64>(input_buffer),
+        gltf::accessor::DataType::U32 => reinterpret_then_convert::<u32, f64>(input_buffer),
+        gltf::accessor::DataType::F32 => reinterpret_then_convert::<f32, f64>(input_buffer),
+    };
+
+    // Then we convert that to a f32 vector - this feels cursed as heck but let's ignore that, it'll be fine!
+    let mut values32 = Vec::<f32>::new();
+    values32.resize(values64.len(), 0.0);
+    for i in 0..values32.len() {
+        values32[i] = values64[i] as f32;
+    }
+
+    // Return
+    values32
+}
+
+fn create_vertex_array(
+    primitive: &gltf::Primitive,
+    mesh_data: &[Data],
+    local_matrix: Mat4,
+) -> Mesh {
+    let mut position_vec = Vec::<Vec3>::new();
+    let mut normal_vec = Vec::<Vec3>::new();
+    let mut tangent_vec = Vec::<Vec4>::new();
+    let mut colour_vec = Vec::<Vec4>::new();
+    let mut texcoord0_vec = Vec::<Vec2>::new();
+    let mut texcoord1_vec = Vec::<Vec2>::new();
+    let mut indices = Vec::<u32>::new();
+
+    // Loop over all the primitive attributes
+    for (name, accessor) in primitive.attributes() {
+        // Get buffer view, sparse accessors without one aren't supported
+        let Some(bufferview) = accessor.view() else {
+            continue;
+        };
+
+        // Find location in buffer
+        let buffer_index = bufferview.buffer().index();
+        let buffer_offset = bufferview.offset();
+        let buffer_end = bufferview.offset() + bufferview.length();
+
+        // Find location in buffer
+        let buffer_base = &mesh_data[buffer_index].0;
+        let buffer_slice = buffer_base.get(buffer_offset..buffer_end).unwrap();
+
+        // Assign to the vectors
+        match name.to_string().as_str() {
+            "POSITION" => {
+                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
+                for i in (0..accessor.count() * 3).step_by(3) {
+                    let slice = &values[i..i + 3];
+                    position_vec.push(Vec3::from_slice(slice));
+                }
+            }
+            "NORMAL" => {
+                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
+                for i in (0..accessor.count() * 3).step_by(3) {
+                    let slice = &values[i..i + 3];
+                    normal_vec.push(Vec3::from_slice(slice));
+                }
+            }
+            "TANGENT" => {
+                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
+                for i in (0..accessor.count() * 4).step_by(4) {
+                    let slice = &values[i..i + 4];
+                    tangent_vec.push(Vec4::from_slice(slice));
+                }
+            }
+            "TEXCOORD_0" => {
+                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
+                for i in (0..accessor.count() * 2).step_by(2) {
+                    let slice = &values[i..i + 2];
+                    texcoord0_vec.push(Vec2::from_slice(slice));
+                }
+            }
+            "TEXCOORD_1" => {
+                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
+                for i in (0..accessor.count() * 2).step_by(2) {
+                    let slice = &values[i..i + 2];
+                    texcoord1_vec.push(Vec2::from_slice(slice));
+                }
+            }
+            "COLOR_0" => {
+                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
+                for i in (0..accessor.count() * 4).step_by(4) {
+                    let slice = &values[i..i + 4];
+                    colour_vec.push(Vec4::from_slice(slice));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Find indices, non-indexed primitives just use every vertex in order
+    let index_data = primitive
+        .indices()
+        .and_then(|accessor| Some((accessor.view()?, accessor)));
+    if let Some((bufferview, accessor)) = index_data {
+        // Find location in buffer
+        let buffer_index = bufferview.buffer().index();
+        let buffer_offset = bufferview.offset();
+        let buffer_end = bufferview.offset() + bufferview.length();
+
+        // Find location in buffer
+        let buffer_base = &mesh_data[buffer_index].0;
+        let buffer_slice = buffer_base.get(buffer_offset..buffer_end).unwrap();
+
+        // Convert from raw buffer to f32 vec - this is incredibly cursed but it'll have to do
+        let indices_f32 = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
+        for index in indices_f32 {
+            indices.push(index as u32);
+        }
+    } else {
+        indices.extend(0..position_vec.len() as u32);
+    }
+
+    // Drop incomplete triangles and triangles that point outside the vertex data
+    let indices: Vec<u32> = indices
+        .chunks_exact(3)
+        .filter(|triangle| triangle.iter().all(|index| (*index as usize) < position_vec.len()))
+        .flatten()
+        .copied()
+        .collect();
+
+    // Normals use the inverse-transpose, tangents are surface directions so they use the regular matrix.
+    // A mirroring transform flips the bitangent, so the tangent handedness needs to flip with it
+    let normal_matrix = normal_matrix(&local_matrix);
+    let handedness = local_matrix.determinant().signum();
+
+    // Create vertex array
+    let mut mesh_out = Mesh {
+        verts: Vec::new(),
+        positions: Vec::new(),
+        vao: 0,
+        vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
+        bounds: AABB::new(),
+        lods: Vec::new(),
+        lod_level: 0,
+    };
+    for index in indices {
+        let index = index as usize;
+        let mut vertex = Vertex {
+            position: Vec3::new(0., 0., 0.),
+            normal: Vec3::new(0., 0., 0.),
+            tangent: Vec4::new(0., 0., 0., 0.),
+            colour: Vec4::new(1., 1., 1., 1.),
+            uv0: Vec2::new(0., 0.),
+            uv1: Vec2::new(0., 0.),
+        };
+        // Attributes can have fewer elements than positions in broken files, those vertices keep the defaults
+        let local_position = LocalPoint(position_vec[index]);
+        let world_position = WorldPoint(local_matrix.transform_point3(local_position.0));
+        vertex.position = world_position.0;
+        if let Some(normal) = normal_vec.get(index) {
+            vertex.normal = (normal_matrix * *normal).normalize_or_zero();
+        }
+        if let Some(tangent) = tangent_vec.get(index) {
+            let tangent_vec3 = local_matrix.transform_vector3(tangent.xyz()).normalize_or_zero();
+            vertex.tangent.x = tangent_vec3.x;
+            vertex.tangent.y = tangent_vec3.y;
+            vertex.tangent.z = tangent_vec3.z;
+            vertex.tangent.w = tangent.w * handedness;
+        }
+        if let Some(uv0) = texcoord0_vec.get(index) {
+            vertex.uv0 = *uv0;
+        }
+        if let Some(uv1) = texcoord1_vec.get(index) {
+            vertex.uv1 = *uv1;
+        }
+        if let Some(colour) = colour_vec.get(index) {
+            vertex.colour.x = f32::powf(colour.x, 1.0 / 2.2);
+            if vertex.colour.x > 1.0 {
+                vertex.colour.x = 1.0
+            }
+            vertex.colour.y = f32::powf(colour.y, 1.0 / 2.2);
+            if vertex.colour.y > 1.0 {
+                vertex.colour.y = 1.0
+            }
+            vertex.colour.z = f32::powf(colour.z, 1.0 / 2.2);
+            if vertex.colour.z > 1.0 {
+                vertex.colour.z = 1.0
+            }
+        }
+        mesh_out.bounds.grow(vertex.position);
+        mesh_out.verts.push(vertex);
+    }
+
+    // Without normals in the file, fall back to flat shading
+    if normal_vec.is_empty() {
+        generate_flat_normals(&mut mesh_out.verts);
+    }
+    mesh_out
+}
+
+// Path of a file the glTF refers to, None for data embedded in the file or in a data URI
+fn external_file(directory: &Path, uri: &str) -> Option<PathBuf> {
+    (!uri.starts_with("data:")).then(|| directory.join(uri))
+}
+
+// An image of the glTF, decoded on a worker thread and uploaded by the first material that uses it
+enum GltfImage {
+    Unused,
+    Decoded(Result<Texture, String>),
+    Uploaded(Result<u32, String>),
+}
+
+impl GltfImage {
+    fn into_decoded(self) -> Result<Texture, String> {
+        match self {
+            GltfImage::Decoded(decoded) => decoded,
+            _ => Err("the image was not decoded".to_string()),
+        }
+    }
+}
+
+// Decodes the images the materials use across the worker threads, scaled down to the texture quality. Images in files
+// another model already uploaded get shared instead, so they're left alone
+fn decode_images(
+    document: &gltf::Document,
+    directory: &Path,
+    buffers: &[Data],
+    renderer: &Renderer,
+    image_file: impl Fn(&gltf::Texture) -> Option<PathBuf>,
+) -> Vec<GltfImage> {
+    let mut used = vec![false; document.images().len()];
+    for material in document.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        let textures = [pbr.base_color_texture().map(|info| info.texture()), pbr.metallic_roughness_texture().map(|info| info.texture())];
+        for texture in textures.into_iter().flatten() {
+            if !image_file(&texture).is_some_and(|file| renderer.has_shared_texture(&file)) {
+                used[texture.source().index()] = true;
+            }
+        }
+    }
+    let images: Vec<gltf::Image> = document.images().filter(|image| used[image.index()]).collect();
+
+    let max_dimension = renderer.texture_quality().max_dimension as usize;
+    let decode = |image: &gltf::Image| {
+        profile_scope!("decode_image");
+        let data = gltf::image::Data::from_source(image.source(), Some(directory), buffers).map_err(|error| error.to_string())?;
+        Texture::load_texture_from_gltf_image(&data, max_dimension)
+    };
+    let progress = |finished| debug!("Decoded {finished} of {} images", images.len());
+    let mut decoded = workers::parallel_map(&images, decode, progress).into_iter();
+
+    used.iter()
+        .map(|&used| match used {
+            true => GltfImage::Decoded(decoded.next().unwrap()),
+            false => GltfImage::Unused,
+        })
+        .collect()
+}
+
+fn upload_gltf_texture(
+    renderer: &mut Renderer,
+    model: &mut Model,
+    image: &mut GltfImage,
+    file: Option<PathBuf>,
+    material: &str,
+    slot: TextureSlot,
+) -> i32 {
+    // Only images in their own file can be shared with other models. Embedded images used by several materials are
+    // uploaded once
+    let gl_id = match (std::mem::replace(image, GltfImage::Unused), &file) {
+        (GltfImage::Uploaded(Err(reason)), _) => Err(reason),
+        (GltfImage::Uploaded(gl_id), None) => gl_id,
+        (state, Some(file)) => renderer.load_shared_texture(file, || state.into_decoded()),
+        (state, None) => state.into_decoded().map(|mut texture| renderer.upload_texture(&mut texture)),
+    };
+    *image = GltfImage::Uploaded(gl_id.clone());
+    match gl_id {
+        Ok(gl_id) => {
+            if let Some(file) = file {
+                model.add_source_texture(&file, gl_id);
+            }
+            gl_id as i32
+        }
+        Err(reason) => renderer.missing_texture(material, slot, reason),
+    }
+}
+
+// Renderer specific material settings that glTF has no place for, read from the material's extras:
+// "wind_amplitude" and "wind_frequency" override the wind effect the material name picked, "custom" (up to 4 numbers)
+// and "custom_vec" (up to 2 arrays of 4) fill the custom shader parameters, and "sort_bias" moves a transparent
+// material back or forward in the draw order
+fn apply_material_extras(material: &mut Material, extras: &gltf::json::Value) {
+    let numbers = |value: &gltf::json::Value| -> Vec<f32> {
+        value.as_array().map_or_else(Vec::new, |values| values.iter().filter_map(|value| value.as_f64()).map(|value| value as f32).collect())
+    };
+    if let Some(custom) = extras.get("custom") {
+        for (slot, value) in material.custom.scalars.iter_mut().zip(numbers(custom)) {
+            *slot = value;
+        }
+    }
+    if let Some(vectors) = extras.get("custom_vec").and_then(|value| value.as_array()) {
+        for (slot, vector) in material.custom.vectors.iter_mut().zip(vectors) {
+            for (component, value) in slot.as_mut().iter_mut().zip(numbers(vector)) {
+                *component = value;
+            }
+        }
+    }
+    if let Some(amplitude) = extras.get("wind_amplitude").and_then(|value| value.as_f64()) {
+        material.scl_wind = amplitude as f32;
+    }
+    if let Some(frequency) = extras.get("wind_frequency").and_then(|value| value.as_f64()) {
+        material.frq_wind = frequency as f32;
+    }
+    if let Some(bias) = extras.get("sort_bias").and_then(|value| value.as_f64()) {
+        material.sort_bias = bias as f32;
+    }
+}
+
+// glTF has no height maps. They come from the material's extras ("height_texture", a path relative to the glTF, and
+// optionally "height_scale" and "height_bias"), or from a "<albedo>_height.png" file next to the albedo texture
+fn load_height_map(
+    renderer: &mut Renderer,
+    model: &mut Model,
+    name: &str,
+    extras: &gltf::json::Value,
+    material: &mut Material,
+    directory: &Path,
+    albedo_file: Option<&Path>,
+) {
+    if let Some(scale) = extras.get("height_scale").and_then(|value| value.as_f64()) {
+        material.scl_hgt = scale as f32;
+    }
+    if let Some(bias) = extras.get("height_bias").and_then(|value| value.as_f64()) {
+        material.bias_hgt = bias as f32;
+    }
+
+    let sidecar = albedo_file.and_then(|albedo| {
+        let stem = albedo.file_stem()?.to_string_lossy();
+        Some(albedo.with_file_name(format!("{stem}_height.png")))
+    });
+    let file = match (extras.get("height_texture").and_then(|value| value.as_str()), sidecar) {
+        (Some(uri), _) => directory.join(uri),
+        (None, Some(sidecar)) if sidecar.exists() => sidecar,
+        _ => return,
+    };
+    material.tex_hgt = load_texture(&file, renderer, model, name, TextureSlot::Height);
+}
+
+fn traverse_nodes(
+    node: &gltf::Node,
+    mesh_data: &[Data],
+    local_transform: Mat4,
+    prefix: &str,
+    primitives_processed: &mut HashMap<String, Mesh>,
+) {
+    // Convert translation in GLTF model to a Mat4.
+    let node_transform = Transform {
+        scale: glam::vec3(
+            node.transform().decomposed().2[0],
+            node.transform().decomposed().2[1],
+            node.transform().decomposed().2[2],
+        ),
+        rotation: glam::quat(
+            node.transform().decomposed().1[0],
+            node.transform().decomposed().1[1],
+            node.transform().decomposed().1[2],
+            node.transform().decomposed().1[3],
+        ),
+        translation: glam::vec3(
+            node.transform().decomposed().0[0],
+            node.transform().decomposed().0[1],
+            node.transform().decomposed().0[2],
+        ),
+    };
+
+    let new_local_transform = local_transform * node_transform.local_matrix();
+
+    // If it has a mesh, process it
+    let mesh = node.mesh();
+    if let Some(mesh) = mesh {
+        // Get mesh
+        let primitives = mesh.primitives();
+
+        for primitive in primitives {
+            let mut mesh_buffer_data =
+                create_vertex_array(&primitive, mesh_data, new_local_transform);
+            let material = format!("{prefix}{}", primitive.material().name().unwrap_or("None"));
+            if mesh_buffer_data.verts.is_empty() {
+                warn!(
+                    "Skipping empty primitive in mesh \"{}\" (material \"{material}\")",
+                    mesh.name().unwrap_or("unnamed")
+                );
+                continue;
+            }
+            #[allow(clippy::map_entry)] // This was really annoying and made the code less readable
+            if primitives_processed.contains_key(&material) {
+                let mesh: &mut Mesh = primitives_processed.get_mut(&material).unwrap();
+                mesh.verts.append(&mut mesh_buffer_data.verts);
+                mesh.bounds.grow_aabb(&mesh_buffer_data.bounds);
+            } else {
+                primitives_processed.insert(material, mesh_buffer_data);
+            }
+        }
+    }
+
+    // If it has children, process those
+    for child in node.children() {
+        traverse_nodes(&child, mesh_data, new_local_transform, prefix, primitives_processed);
+    }
+}
+
+// The meshes of the scenes the options select, keyed by material, and the prefix each scene's mesh names got. With
+// every scene loaded each scene's meshes get the scene index as a prefix to keep them apart
+fn load_scene_meshes(
+    path: &Path,
+    document: &gltf::Document,
+    mesh_data: &[Data],
+    options: &LoadOptions,
+    world_up: WorldUp,
+) -> Result<(HashMap<String, Mesh>, Vec<String>), String> {
+    let scenes: Vec<gltf::Scene> = match options.scene_selection {
+        SceneSelection::Default => match document.default_scene() {
+            Some(scene) => vec![scene],
+            None => {
+                if document.scenes().len() > 1 {
+                    warn!("\"{}\" has no default scene, loading scene 0", path.display());
+                }
+                document.scenes().take(1).collect()
+            }
+        },
+        SceneSelection::Index(index) => match document.scenes().nth(index) {
+            Some(scene) => vec![scene],
+            None => {
+                return Err(format!(
+                    "Scene {index} does not exist in \"{}\", it has {} scenes",
+                    path.display(),
+                    document.scenes().len()
+                ))
+            }
+        },
+        SceneSelection::All => document.scenes().collect(),
+    };
+    let prefixes: Vec<String> = scenes
+        .iter()
+        .map(|scene| match options.scene_selection {
+            SceneSelection::All => format!("{}/", scene.index()),
+            _ => String::new(),
+        })
+        .collect();
+    let mut meshes = HashMap::new();
+    for (scene, prefix) in scenes.iter().zip(&prefixes) {
+        for node in scene.nodes() {
+            traverse_nodes(&node, mesh_data, options.root_matrix(world_up), prefix, &mut meshes);
+        }
+    }
+    Ok((meshes, prefixes))
+}
+
+// Reads a glTF file and the buffers it refers to
+fn open_gltf(path: &Path) -> Result<(gltf::Document, Vec<Data>), String> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    gltf::Gltf::open(path)
+        .and_then(|gltf| {
+            let buffers = gltf::import_buffers(&gltf.document, Some(directory), gltf.blob)?;
+            Ok((gltf.document, buffers))
+        })
+        .map_err(|error| format!("Failed to load glTF file \"{}\": {error}", path.display()))
+}
+
+impl Model {
+    pub(crate) fn load_gltf(path: &Path, renderer: &mut Renderer, options: &LoadOptions) -> Result<Model, String> {
+        let mut model = Model::new();
+
+        // Load GLTF from file. Images are decoded later, only the ones the materials use
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let (gltf_document, mesh_data) = open_gltf(path)?;
+
+        // Remember every file the model came from, images get added along with their textures
+        model.add_source_file(path);
+        for buffer in gltf_document.buffers() {
+            if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+                if let Some(file) = external_file(directory, uri) {
+                    model.add_source_file(&file);
+                }
+            }
+        }
+        let image_file = |texture: &gltf::Texture| match texture.source().source() {
+            gltf::image::Source::Uri { uri, .. } => external_file(directory, uri),
+            gltf::image::Source::View { .. } => None,
+        };
+
+        model.scenes = gltf_document
+            .scenes()
+            .map(|scene| scene.name().map_or_else(|| format!("Scene {}", scene.index()), String::from))
+            .collect();
+        let (meshes, prefixes) = load_scene_meshes(path, &gltf_document, &mesh_data, options, renderer.world_up())?;
+        model.meshes = meshes;
+
+        // Get all the textures from the GLTF
+        let mut images = decode_images(&gltf_document, directory, &mesh_data, renderer, image_file);
+        for material in gltf_document.materials() {
+            let material_name = material.name().unwrap_or("untitled");
+            let mut new_material = Material::named(material_name);
+            let extras = material
+                .extras()
+                .as_ref()
+                .and_then(|extras| gltf::json::deserialize::from_str::<gltf::json::Value>(extras.get()).ok())
+                .unwrap_or_default();
+            apply_material_extras(&mut new_material, &extras);
+
+            // Get PBR parameters
+            new_material.scl_rgh = material.pbr_metallic_roughness().roughness_factor();
+            new_material.scl_mtl = material.pbr_metallic_roughness().metallic_factor();
+            new_material.scl_emm = material.emissive_factor().into();
+            new_material.alpha_mode = match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            };
+            new_material.alpha_cutoff = material.alpha_cutoff().unwrap_or(0.5);
+
+            // Try to find textures
+            let tex_info_alb = material.pbr_metallic_roughness().base_color_texture();
+            let tex_info_mtl_rgh = material
+                .pbr_metallic_roughness()
+                .metallic_roughness_texture();
+            let _tex_info_nrm = material.normal_texture();
+            let _tex_info_emm = material.emissive_texture();
+
+            // Get the texture data
+            let albedo_file = tex_info_alb.as_ref().and_then(|tex| image_file(&tex.texture()));
+            if let Some(tex) = tex_info_alb {
+                let image = &mut images[tex.texture().source().index()];
+                new_material.tex_alb =
+                    upload_gltf_texture(renderer, &mut model, image, albedo_file.clone(), material_name, TextureSlot::Albedo);
+            }
+            if let Some(tex) = tex_info_mtl_rgh {
+                let image = &mut images[tex.texture().source().index()];
+                let file = image_file(&tex.texture());
+                new_material.tex_mtl_rgh =
+                    upload_gltf_texture(renderer, &mut model, image, file, material_name, TextureSlot::MetallicRoughness);
+            }
+
+            load_height_map(renderer, &mut model, material_name, &extras, &mut new_material, directory, albedo_file.as_deref());
+
+            model.materials.insert(
+                String::from(material.name().unwrap_or("untitled")),
+                new_material,
+            );
+        }
+
+        // Prefixed meshes get a copy of their material under the same key
+        if options.scene_selection == SceneSelection::All {
+            let copies: Vec<(String, Material)> = model
+                .meshes
+                .keys()
+                .filter_map(|name| {
+                    let material = prefixes.iter().find_map(|prefix| name.strip_prefix(prefix.as_str()))?;
+                    Some((name.clone(), model.materials.get(material)?.clone()))
+                })
+                .collect();
+            model.materials.extend(copies);
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::UpAxis;
+
+    fn load_meshes(file: &str, options: &LoadOptions, world_up: WorldUp) -> Result<HashMap<String, Mesh>, String> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/models").join(file);
+        let (document, mesh_data) = open_gltf(&path)?;
+        Ok(load_scene_meshes(&path, &document, &mesh_data, options, world_up)?.0)
+    }
+
+    fn load_bounds(file: &str, options: &LoadOptions, world_up: WorldUp) -> AABB {
+        let mut model = Model::new();
+        model.meshes = load_meshes(file, options, world_up).unwrap();
+        model.bounds()
+    }
+
+    #[test]
+    fn up_axis_conversion_keeps_the_cube_bounds() {
+        let z_up = LoadOptions {
+            up_axis: UpAxis::Z,
+            ..LoadOptions::default()
+        };
+        for world_up in [WorldUp::Y, WorldUp::Z] {
+            let bounds = load_bounds("test_cube.gltf", &LoadOptions::default(), world_up);
+            assert!(bounds.min.abs_diff_eq(Vec3::NEG_ONE, 1.0e-5) && bounds.max.abs_diff_eq(Vec3::ONE, 1.0e-5));
+            let converted = load_bounds("test_cube.gltf", &z_up, world_up);
+            assert!(converted.min.abs_diff_eq(bounds.min, 1.0e-5) && converted.max.abs_diff_eq(bounds.max, 1.0e-5));
+        }
+    }
+
+    // two_scenes.gltf has no default scene. Both scenes hold a triangle with the same material, the first at the
+    // origin and the second 10 units along x
+    fn load_scenes(scene_selection: SceneSelection) -> Result<Vec<(String, f32)>, String> {
+        let options = LoadOptions {
+            scene_selection,
+            ..LoadOptions::default()
+        };
+        let mut meshes: Vec<(String, f32)> = load_meshes("two_scenes.gltf", &options, WorldUp::Y)?
+            .into_iter()
+            .map(|(name, mesh)| (name, mesh.bounds.min.x))
+            .collect();
+        meshes.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(meshes)
+    }
+
+    #[test]
+    fn scene_selection() {
+        assert_eq!(load_scenes(SceneSelection::Default), Ok(vec![(String::from("shared"), 0.0)]));
+        assert_eq!(load_scenes(SceneSelection::Index(0)), Ok(vec![(String::from("shared"), 0.0)]));
+        assert_eq!(load_scenes(SceneSelection::Index(1)), Ok(vec![(String::from("shared"), 10.0)]));
+        assert_eq!(
+            load_scenes(SceneSelection::All),
+            Ok(vec![(String::from("0/shared"), 0.0), (String::from("1/shared"), 10.0)])
+        );
+        let error = load_scenes(SceneSelection::Index(2)).unwrap_err();
+        assert!(error.starts_with("Scene 2 does not exist in") && error.ends_with("two_scenes.gltf\", it has 2 scenes"), "{error}");
+    }
+}
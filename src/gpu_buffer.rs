@@ -0,0 +1,139 @@
+#![allow(dead_code)]
+use std::{ffi::c_void, marker::PhantomData, mem::size_of};
+
+use gl::types::GLenum;
+
+// An OpenGL buffer holding elements of type T. The GL object gets created on the first upload and deleted on drop,
+// so the buffer must be dropped (or deleted) while its context is still current
+pub struct GpuBuffer<T> {
+    id: u32,
+    target: GLenum,
+    usage: GLenum,
+    len: usize,
+    _element: PhantomData<T>,
+}
+
+impl<T> GpuBuffer<T> {
+    pub fn new(target: GLenum, usage: GLenum) -> Self {
+        GpuBuffer {
+            id: 0,
+            target,
+            usage,
+            len: 0,
+            _element: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Number of elements
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn byte_size(&self) -> usize {
+        self.len * size_of::<T>()
+    }
+
+    // Replaces the contents and size of the buffer. Leaves the buffer bound to its target, since unbinding an element
+    // buffer would also detach it from the bound vertex array
+    pub fn upload(&mut self, data: &[T]) {
+        unsafe {
+            if self.id == 0 {
+                gl::GenBuffers(1, &mut self.id);
+            }
+            gl::BindBuffer(self.target, self.id);
+            gl::BufferData(self.target, size_of_val(data) as isize, data.as_ptr() as *const c_void, self.usage);
+        }
+        self.len = data.len();
+    }
+
+    // Overwrites part of the buffer without reallocating it, `offset` is in elements. Leaves the buffer bound
+    pub fn update_range(&mut self, offset: usize, data: &[T]) {
+        assert!(
+            offset + data.len() <= self.len,
+            "Buffer update of {} elements at {offset} is out of range for a buffer of {} elements",
+            data.len(),
+            self.len
+        );
+        unsafe {
+            gl::BindBuffer(self.target, self.id);
+            gl::BufferSubData(
+                self.target,
+                (offset * size_of::<T>()) as isize,
+                size_of_val(data) as isize,
+                data.as_ptr() as *const c_void,
+            );
+        }
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindBuffer(self.target, self.id);
+        }
+    }
+
+    // Binds the buffer to an indexed binding point, for uniform and shader storage buffers
+    pub fn bind_base(&self, index: u32) {
+        unsafe {
+            gl::BindBufferBase(self.target, index, self.id);
+        }
+    }
+
+    // Frees the GL object early, the buffer can be uploaded to again afterwards
+    pub fn delete(&mut self) {
+        if self.id != 0 {
+            unsafe {
+                gl::DeleteBuffers(1, &self.id);
+            }
+        }
+        self.id = 0;
+        self.len = 0;
+    }
+}
+
+impl<T> Drop for GpuBuffer<T> {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Mat4;
+
+    // None of these get as far as creating a GL object, so they run without a context
+    #[test]
+    fn new_buffer_is_empty() {
+        let buffer = GpuBuffer::<Mat4>::new(gl::UNIFORM_BUFFER, gl::DYNAMIC_DRAW);
+        assert_eq!(buffer.id(), 0);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.byte_size(), 0);
+    }
+
+    #[test]
+    fn byte_size_counts_elements() {
+        let mut buffer = GpuBuffer::<Mat4>::new(gl::UNIFORM_BUFFER, gl::DYNAMIC_DRAW);
+        buffer.len = 3;
+        assert_eq!(buffer.byte_size(), 3 * 64);
+
+        // Deleting a buffer that was never created is fine, and leaves it empty
+        buffer.delete();
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn update_past_the_end_panics() {
+        let mut buffer = GpuBuffer::<u32>::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW);
+        buffer.len = 4;
+        buffer.update_range(3, &[1, 2]);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{ffi::{c_char, CStr}, mem::size_of, ptr::null_mut};
+
+use gl::types::GLenum;
+
+// The kind of interface block a struct is bound to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockKind {
+    Uniform,
+    #[allow(dead_code)] // Nothing is shared through shader storage blocks yet
+    ShaderStorage,
+}
+
+// One member of a struct shared with GLSL, with the offset it has on the Rust side
+pub struct GpuField {
+    pub rust_name: &'static str,
+    pub glsl_name: &'static CStr,
+    pub offset: usize,
+}
+
+// A struct whose layout is duplicated in GLSL as an interface block
+pub trait GpuLayout {
+    const STRUCT_NAME: &'static str;
+    const BLOCK_NAME: &'static CStr;
+    const KIND: BlockKind;
+    fn fields() -> Vec<GpuField>;
+}
+
+// Compares the Rust offsets against the ones the driver assigned in a linked program. Programs that don't declare
+// the block pass, since they don't use the struct
+pub fn validate<T: GpuLayout>(program: u32) -> Result<(), String> {
+    let (block_interface, member_interface) = match T::KIND {
+        BlockKind::Uniform => (gl::UNIFORM_BLOCK, gl::UNIFORM),
+        BlockKind::ShaderStorage => (gl::SHADER_STORAGE_BLOCK, gl::BUFFER_VARIABLE),
+    };
+    unsafe {
+        let block = gl::GetProgramResourceIndex(program, block_interface, T::BLOCK_NAME.as_ptr());
+        if block == gl::INVALID_INDEX {
+            return Ok(());
+        }
+        let mut block_size = 0;
+        gl::GetProgramResourceiv(program, block_interface, block, 1, &gl::BUFFER_DATA_SIZE, 1, null_mut(), &mut block_size);
+        check_offsets::<T>(block_size as usize, |glsl_name| {
+            let index = gl::GetProgramResourceIndex(program, member_interface, glsl_name.as_ptr());
+            if index == gl::INVALID_INDEX {
+                return None;
+            }
+            let mut offset = 0;
+            gl::GetProgramResourceiv(program, member_interface, index, 1, &gl::OFFSET, 1, null_mut(), &mut offset);
+            Some(offset as usize)
+        })
+    }
+}
+
+// The driver independent part of validate. `glsl_offset` gives the offset of a block member, None when the block
+// doesn't have it
+fn check_offsets<T: GpuLayout>(block_size: usize, glsl_offset: impl Fn(&CStr) -> Option<usize>) -> Result<(), String> {
+    let block_name = T::BLOCK_NAME.to_string_lossy();
+
+    // The buffer gets uploaded straight from the struct, so it has to cover the whole block
+    if block_size > size_of::<T>() {
+        return Err(format!(
+            "{} is {} bytes, but GLSL block \"{block_name}\" is {block_size} bytes",
+            T::STRUCT_NAME,
+            size_of::<T>()
+        ));
+    }
+
+    for field in T::fields() {
+        let glsl_name = field.glsl_name.to_string_lossy();
+        let Some(offset) = glsl_offset(field.glsl_name) else {
+            return Err(format!(
+                "{}::{} has no matching \"{glsl_name}\" in GLSL block \"{block_name}\"",
+                T::STRUCT_NAME,
+                field.rust_name
+            ));
+        };
+        if offset != field.offset {
+            return Err(format!(
+                "{}::{} is at offset {}, but \"{glsl_name}\" in GLSL block \"{block_name}\" is at offset {offset}",
+                T::STRUCT_NAME,
+                field.rust_name,
+                field.offset
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Whether a linked program declares the struct's block at all
+pub fn declares<T: GpuLayout>(program: u32) -> bool {
+    let block_interface = match T::KIND {
+        BlockKind::Uniform => gl::UNIFORM_BLOCK,
+        BlockKind::ShaderStorage => gl::SHADER_STORAGE_BLOCK,
+    };
+    unsafe { gl::GetProgramResourceIndex(program, block_interface, T::BLOCK_NAME.as_ptr()) != gl::INVALID_INDEX }
+}
+
+// A vertex attribute the vertex buffers provide
+pub struct VertexInput {
+    pub name: &'static str, // What the attribute holds, only for error messages
+    pub location: i32,
+    pub glsl_type: GLenum,
+}
+
+// Checks that every input a linked program's vertex shader reads is one of the attributes, at its location and with
+// its type. Inputs the attributes don't feed would read zeroes
+pub fn validate_vertex_inputs(program: u32, attributes: &[VertexInput]) -> Result<(), String> {
+    unsafe {
+        let mut count = 0;
+        gl::GetProgramInterfaceiv(program, gl::PROGRAM_INPUT, gl::ACTIVE_RESOURCES, &mut count);
+        for index in 0..count.max(0) as u32 {
+            let mut name = [0 as c_char; 64];
+            gl::GetProgramResourceName(program, gl::PROGRAM_INPUT, index, name.len() as i32, null_mut(), name.as_mut_ptr());
+            let name = CStr::from_ptr(name.as_ptr()).to_string_lossy();
+            if name.starts_with("gl_") {
+                continue;
+            }
+            let properties = [gl::LOCATION, gl::TYPE];
+            let mut values = [0; 2];
+            gl::GetProgramResourceiv(program, gl::PROGRAM_INPUT, index, 2, properties.as_ptr(), 2, null_mut(), values.as_mut_ptr());
+            let [location, glsl_type] = values;
+            match attributes.iter().find(|attribute| attribute.location == location) {
+                Some(attribute) if attribute.glsl_type == glsl_type as GLenum => {}
+                Some(attribute) => {
+                    return Err(format!("vertex input \"{name}\" at location {location} has a different type than the {} attribute", attribute.name))
+                }
+                None => return Err(format!("vertex input \"{name}\" is at location {location}, which no vertex attribute feeds")),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memoffset::offset_of;
+
+    // A vec3 followed by a float, which std140 packs into a single vec4
+    #[repr(C)]
+    struct Light {
+        position: [f32; 3],
+        intensity: f32,
+    }
+
+    impl GpuLayout for Light {
+        const STRUCT_NAME: &'static str = "Light";
+        const BLOCK_NAME: &'static CStr = c"light_buffer";
+        const KIND: BlockKind = BlockKind::Uniform;
+
+        fn fields() -> Vec<GpuField> {
+            vec![
+                GpuField { rust_name: "position", glsl_name: c"u_light_position", offset: offset_of!(Light, position) },
+                GpuField { rust_name: "intensity", glsl_name: c"u_light_intensity", offset: offset_of!(Light, intensity) },
+            ]
+        }
+    }
+
+    fn glsl_offsets<'a>(offsets: &'a [(&'static CStr, usize)]) -> impl Fn(&CStr) -> Option<usize> + 'a {
+        |name| offsets.iter().find(|(member, _)| *member == name).map(|&(_, offset)| offset)
+    }
+
+    #[test]
+    fn matching_layout_passes() {
+        let offsets = [(c"u_light_position", 0), (c"u_light_intensity", 12)];
+        assert_eq!(check_offsets::<Light>(16, glsl_offsets(&offsets)), Ok(()));
+    }
+
+    #[test]
+    fn padded_vec3_is_reported() {
+        // What std430 arrays of vec3 or an extra padding member would do
+        let offsets = [(c"u_light_position", 0), (c"u_light_intensity", 16)];
+        let error = check_offsets::<Light>(16, glsl_offsets(&offsets)).unwrap_err();
+        assert_eq!(error, "Light::intensity is at offset 12, but \"u_light_intensity\" in GLSL block \"light_buffer\" is at offset 16");
+    }
+
+    #[test]
+    fn missing_member_is_reported() {
+        let offsets = [(c"u_light_position", 0)];
+        let error = check_offsets::<Light>(16, glsl_offsets(&offsets)).unwrap_err();
+        assert!(error.starts_with("Light::intensity has no matching \"u_light_intensity\""));
+    }
+
+    #[test]
+    fn larger_block_is_reported() {
+        let offsets = [(c"u_light_position", 0), (c"u_light_intensity", 12)];
+        let error = check_offsets::<Light>(32, glsl_offsets(&offsets)).unwrap_err();
+        assert_eq!(error, "Light is 16 bytes, but GLSL block \"light_buffer\" is 32 bytes");
+    }
+}
@@ -1,40 +1,304 @@
 use gl::types::GLenum;
-use glam::Mat4;
+use glam::{DVec3, Mat3, Mat4, Vec2, Vec3, Vec4};
 use glfw::{Context, Glfw, Window, WindowEvent};
+use log::{debug, error, info, trace, warn};
 use memoffset::offset_of;
-use queues::{queue, IsQueue, Queue};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::{
-    f32::consts::PI, ffi::c_void, fs::File, io::Read, mem::size_of, path::Path, sync::mpsc::Receiver, collections::{HashMap, hash_map::DefaultHasher}, hash::Hasher, ptr::null,
+    cmp::Reverse, ffi::{c_void, CStr}, fs::File, io::Read, mem::size_of, path::{Path, PathBuf}, sync::mpsc::Receiver, time::SystemTime, collections::{BTreeMap, HashMap, HashSet, hash_map::{DefaultHasher, Entry}}, hash::Hasher,
 };
 use std::hash::Hash;
+use std::fmt::Write;
 
-use crate::{camera::Camera, input::UserInput, structs::Vertex, mesh::Model, texture::Texture};
+mod decals;
+mod line_batches;
+mod picking;
+mod snapshots;
+mod water_planes;
+
+use crate::{
+    assets::AssetResolver,
+    camera::{Camera, Projection},
+    capabilities::GpuCapabilities,
+    exposure::{AutoExposure, ExposureSettings, MeteringMode},
+    frame_graph::FrameGraph,
+    frame_history::FrameHistory,
+    gpu_buffer::GpuBuffer,
+    gpu_layout::{self, BlockKind, GpuField, GpuLayout, VertexInput},
+    helpers::{linear_to_srgb, Image, Pixel32},
+    hiz::HiZBuffer,
+    hooks::{PassContext, PassHook, PassPoint},
+    id_view::{self, IdSample, IdView},
+    input::UserInput,
+    input_glfw,
+    inspection::{self, InspectionConfig, InspectionLayout, OrbitCamera, SceneHandles, PREVIEW_RADIUS, PREVIEW_SPACING},
+    journal::{ChangeJournal, ChangeOperation, ChangeRecord},
+    material::{AlphaMode, CustomParameters, Material, MaterialDescriptor, MaterialHandle},
+    mesh::{self, modified_time, LoadOptions, Mesh, Model},
+    profile_scope, profiler,
+    quality::{LeverState, QualityGovernor, QualityGovernorConfig, QualityLever},
+    random,
+    shader_cache::{ShaderCache, ShaderCacheConfig},
+    structs::{LineVertex, Rect, Transform, Vertex, WorldUp, AABB},
+    text::TextOverlay,
+    texture::{GpuTexture, MissingTexture, Texture, TextureBinder, TextureQuality, TextureSlot, TextureStreamingConfig},
+    texture_store::{self, TextureBackend, TextureStore},
+    water::WaterPlane,
+};
+
+// Largest dimension of the preview textures uploaded at load time when texture streaming is enabled
+const STREAMING_PREVIEW_SIZE: usize = 64;
+// Seconds between checks for changed model files while hot reloading
+const HOT_RELOAD_POLL_INTERVAL: f64 = 0.25;
+// How far the camera can get from the origin before follow_camera moves the origin to it
+const ORIGIN_FOLLOW_DISTANCE: f32 = 2048.0;
 
 pub struct Renderer {
-    // Window stuff
-    glfw: Glfw,
-    window: Window,
-    events: Receiver<(f64, WindowEvent)>,
-	depth_buffer_texture: u32,
-	framebuffer_texture: u32,
+    // Window stuff, the context itself is at the bottom
+    title: String,
+    title_stats: bool,
+    title_stats_buffer: String,
+    title_stats_frame_count: u32,
+    title_stats_last_update: f64,
+	depth_buffer_texture: GpuTexture,
+	framebuffer_texture: GpuTexture,
 	framebuffer_object: u32,
-	quad_vbo: u32,
+	quad_vbo: GpuBuffer<f32>,
 	quad_vao: u32,
 	fbo_shader: u32,
 	window_resolution_prev: [i32; 2],
+    render_resolution: [i32; 2], // Part of the framebuffer that is rendered to, smaller than the window when scaled down
+    post_resolution: [i32; 2],   // Part of the framebuffer holding the scene colour after TAA, the window when upscaled temporally
+    framebuffer_complete: bool,
+    framebuffer_format: FramebufferFormat,
+    dithering: bool,
+    output_encoding: OutputEncoding, // The window's, see output_encoding() for the one in use
+    external_target: Option<ExternalTarget>, // Presented into instead of the window
+    external_framebuffer_object: u32, // Has the external target's texture attached, for ExternalImage::Texture
+    hdr_paper_white_nits: f32,
+    hdr_peak_nits: f32,
+
+    // Dynamic resolution - the render scale follows the averaged frame time
+    dynamic_resolution: DynamicResolution,
+    render_scale: f32,
+    frame_time_average: f32,
+    render_scale_cooldown: u32,
+
+    // Quality governor - turns settings down while frames stay too slow. Keeps what each lowered setting was, to give
+    // it back once there's room again
+    quality_governor: QualityGovernor,
+    quality_baseline: QualityBaseline,
+
+    // Multisampled render targets for the raster pass, resolved into framebuffer_texture
+    msaa_samples: i32,
+    msaa_framebuffer_object: u32,
+    msaa_colour_texture: u32,
+    msaa_depth_texture: u32,
+
+    // Screen-space ambient occlusion, computed from the resolved depth buffer and multiplied into the frame
+    ssao: SsaoSettings,
+    ssao_shader: u32,
+    ssao_blur_shader: u32,
+    ssao_noise_texture: u32,
+    ssao_framebuffer_objects: [u32; 2],
+    ssao_textures: [GpuTexture; 2],
+    fullscreen_vao: u32,
+
+    // Automatic exposure, metered from the frame after TAA and multiplied into it. The GPU side only exists while enabled
+    exposure: ExposureSettings,
+    auto_exposure: Option<AutoExposure>,
+    exposure_shader: u32,
+
+    // Stereo preview, the camera rendered once per eye into the two halves of the framebuffer
+    stereo: StereoConfig,
+
+    // Procedural sky, drawn over the background after the opaque pass
+    sky: SkyConfig,
+    sky_shader: u32,
+    world_up: WorldUp,
+
+    // Vertex animation of foliage
+    wind: WindConfig,
+
+    // Copies of the depth and colour buffers for the passes that read what was drawn before them, the buffers
+    // themselves can't be sampled while they're attached
+    scene_depth_copy: GpuTexture,
+    scene_colour_copy: GpuTexture,
+    scene_copy_framebuffer_object: u32,
+
+    // Decals projected onto the opaque geometry, drawn in the order they were added
+    decals: BTreeMap<u32, Decal>,
+    next_decal: u32,
+    decal_textures: HashMap<PathBuf, u32>,
+    decal_shader: u32,
+
+    // ID view - what each pixel came from, drawn after the transparent pass and shown over the presented frame. Its
+    // pixels point into the table of meshes drawn last frame
+    id_view: IdView,
+    id_entries: Vec<IdEntry>,
+    id_texture: GpuTexture,
+    id_depth: GpuTexture,
+    id_framebuffer_object: u32,
+    id_shader: u32,
+    id_view_shader: u32,
+    gpu_picks: Vec<GpuPick>, // pick_gpu calls waiting for the ID buffer to be drawn and read back
+
+    // Programs registered to draw models with instead of the lit shader, and the models using them
+    custom_shaders: BTreeMap<u32, CustomShader>,
+    next_custom_shader: u32,
+    model_shaders: HashMap<u64, ShaderHandle>,
+
+    // Water planes, drawn after the decals. Reflections turned off draws them as plain tinted surfaces
+    water_planes: BTreeMap<u32, WaterPlane>,
+    next_water_plane: u32,
+    water_shader: u32,
+    water_normal_texture: u32, // Generated when the first plane is added
+    reflections: bool,
+
+    // Line batches - uploaded once, then drawn from their own buffer every frame they're queued with draw_line_batch
+    line_batches: BTreeMap<u32, LineBatch>,
+    next_line_batch: u32,
+    line_queue: Vec<(u32, Mat4, Vec4)>, // Batch, model matrix and colour multiplier
+    line_shader: u32,
+
+    // Temporal anti-aliasing - the projection is jittered every frame, and the frames are blended together along the
+    // camera motion. Uses the matrices of the last view rendered in a frame
+    taa_enabled: bool,
+    taa_shader: u32,
+    motion_shader: u32,
+    velocity_texture: GpuTexture,
+    velocity_framebuffer_object: u32,
+    taa_history_textures: [GpuTexture; 2],
+    taa_history_framebuffer_objects: [u32; 2],
+    taa_history_index: usize,
+    taa_history_valid: bool,
+    taa_history_resolution: [i32; 2],
+    taa_jitter: Vec2, // This frame's projection offset, in render pixels
+    upscale: UpscaleSettings,
+    previous_velocity_texture: GpuTexture, // Last frame's velocity, temporal upscaling rejects history that moved differently
+    previous_velocity_framebuffer_object: u32,
+    taa_previous_render_resolution: [i32; 2],
+    view_projection_matrix: Mat4, // Without jitter
+    supersample_offset: Vec2, // Sub-pixel offset of the current render_supersampled sub-frame, in pixels
+    previous_view_projection_matrix: Mat4,
+    motion_vectors_frame: Option<u64>, // Last frame the velocity buffer was drawn in
+
+    // Object motion - moving submissions draw their own motion over the camera's in the velocity buffer. Submissions
+    // are matched up with last frame's by model and by the order they're made in
+    object_transforms: HashMap<(u64, u32), Mat4>, // This frame's model matrices
+    previous_object_transforms: HashMap<(u64, u32), Mat4>,
+    object_submissions: HashMap<u64, u32>, // Submissions of each model so far this frame
+    object_motion_shader: u32,
+    object_motion_framebuffer_object: u32, // The velocity texture, depth tested against the scene's depth
+
+    motion_blur: MotionBlurSettings,
+    motion_blur_shader: u32,
 
     // Resources
     models: HashMap<u64, Model>,
+    model_tags: HashMap<u64, String>, // Set by the app, to find its models again after restoring a snapshot
+    next_generated_model: u64, // Generated models have no path to hash, they're numbered instead
+
+    // Material textures, in whichever kind of GL texture the backend picked at construction keeps them
+    textures: Box<dyn TextureStore>,
+    texture_backend: TextureBackend,
+
+    // Fallback textures - the checkerboard replaces textures that failed to load, white stands in for none at all
+    placeholder_texture: u32,
+    white_texture: u32,
+    missing_textures: Vec<MissingTexture>,
+
+    // Material library - textures loaded from a file are shared by every model and material using that file, and
+    // materials used on their own are kept by the hash of their contents
+    shared_textures: HashMap<PathBuf, (u32, usize)>, // GL id and size in bytes
+    shared_texture_bytes_saved: usize,
+    material_library: HashMap<MaterialHandle, Material>,
+    material_descriptors: HashMap<MaterialHandle, MaterialDescriptor>, // What each library material was loaded from
+
+    // Texture streaming - full resolution textures waiting for upload, and when each texture was last drawn
+    texture_quality: TextureQuality,
+    texture_streaming: TextureStreamingConfig,
+    streamed_textures: HashMap<u32, Texture>,
+    texture_last_used: HashMap<u32, u64>,
+    frame_index: u64,
+
+    // Every state change of the last frames, to explain the work they caused
+    change_journal: ChangeJournal,
+
+    // Asset hot reloading - how each model was loaded, so it can be loaded again when its files change
+    hot_reload: bool,
+    hot_reload_last_poll: f64,
+    model_load_args: HashMap<u64, (PathBuf, LoadOptions)>,
+
+    // Floating origin - the world position the camera, draw transforms, rays and everything sent to the GPU are
+    // relative to. Moves along with the camera through big scenes, so f32 positions stay precise around it
+    origin: DVec3,
 
     // Mesh render queue
-    mesh_queue: Queue<MeshQueueEntry>,
+    mesh_queue: Vec<MeshQueueEntry>,
+    camera_view_matrix: Mat4,
+    projection: Projection,
+    projection_matrix: Mat4,
+    view_rendered: bool,
+
+    // Where asset paths are looked up, relative paths are tried against each root in turn
+    assets: AssetResolver,
+
+    // Linked program binaries from earlier runs, None when disabled
+    shader_cache: Option<ShaderCache>,
+    capabilities: GpuCapabilities,
+    shaders_from_cache: u32,
+    shaders_compiled: u32,
 
     // Main triangle shader
     triangle_shader: u32,
+    texture_lod_bias: f32,
+    lod_settings: LodSettings,
+    parallax: ParallaxSettings,
+    transparent_sort_epsilon: f32, // World units, transparent meshes closer together than this count as equally far
+
+    // Occlusion culling against the depth of earlier frames, None while disabled
+    hi_z: Option<HiZBuffer>,
+    hi_z_shader: u32,
+    occlusion_visible: HashSet<(u64, u64)>,      // (model, material hash) of the meshes that passed this frame
+    occlusion_visible_last: HashSet<(u64, u64)>, // The same for the last frame, these are never culled
+    frame_culling_stats: CullingStats, // Of the frame being rendered
+    culling_stats: CullingStats,       // Of the last finished frame
 
     // Constant buffers
     const_buffer_cpu: GlobalConstBuffer,
-    const_buffer_gpu: u32,
+    const_buffer_gpu: GpuBuffer<GlobalConstBuffer>,
+
+    // User hooks around render passes
+    pass_hooks: Vec<(PassPoint, PassHook)>,
+
+    // Text drawn on top of the presented frame
+    text_overlay: TextOverlay,
+    text_shader: u32,
+
+    // Last few presented frames, for catching one-frame glitches
+    frame_history: Option<FrameHistory>,
+    frame_graph: FrameGraph,      // Passes of the frame being rendered
+    last_frame_graph: FrameGraph, // Passes of the last finished frame
+    frame_history_downscale: i32,
+
+    // Clock - time only advances while not paused, so animated shaders can be frozen
+    time_prev: f64,
+    time: f64,
+    delta_time: f32,
+    time_paused: bool,
+
+    // Window lifecycle - nothing gets rendered while minimized, and the first delta time after coming back is dropped
+    focused: bool,
+    iconified: bool,
+    frame_skipped: bool,
+    resume_clock: bool,
+
+    // Declared last, so it gets dropped after every field that owns GL objects
+    events: Receiver<(f64, WindowEvent)>,
+    window: Window,
+    glfw: Glfw,
 }
 
 #[derive(Clone)]
@@ -43,22 +307,569 @@ pub struct MeshQueueEntry {
     vbo: u32,
     n_vertices: i32,
     material: crate::material::Material,
+    bounds: AABB, // World space, including the model matrix
+    lod_level: usize,
+    model_matrix: Mat4,
+    previous_model_matrix: Option<Mat4>, // Last frame's, None when the submission teleported since
+    tint: Vec4, // Multiplies the material colour and alpha, below 1 alpha the mesh is drawn in the transparent pass
+    program: u32, // The lit shader, or the model's custom shader
+    sort_key: DrawSortKey,
+}
+
+impl MeshQueueEntry {
+    // Alpha masked materials stay in the opaque pass, they write depth like any other opaque surface
+    fn is_transparent(&self) -> bool {
+        self.tint.w < 1.0 || self.material.alpha_mode == AlphaMode::Blend
+    }
+}
+
+// Queue entries are drawn in ascending key order, which keeps the draw order the same between runs even though
+// meshes are stored in hash maps. Ties keep the order draw_model was called in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DrawSortKey {
+    layer: u8,
+    alpha_mask: bool, // Groups the masked materials, so alpha-to-coverage is only toggled once per layer
+    program: u32, // Groups the meshes drawn with each shader, so programs only switch at the group's edges
+    material_hash: u64, // Hash of the material name, which is also the mesh name within a model
+    model_id: u64,
+}
+
+// What the app had set before the quality governor turned a lever down, None while the lever is at full quality.
+// Only the fields a lever changes are kept, so the app can still change the rest in the meantime
+#[derive(Debug, Copy, Clone, Default)]
+struct QualityBaseline {
+    ssao_enabled: Option<bool>,
+    msaa_samples: Option<i32>,
+    parallax: Option<(bool, u32)>, // Enabled and max steps
+    lod_switch_distance: Option<f32>,
+    render_scale: Option<f32>,
+}
+
+// Frames to wait after changing the render scale, so the averaged frame time can catch up before the next change
+const RENDER_SCALE_COOLDOWN: u32 = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DynamicResolution {
+    pub enabled: bool,
+    pub target_frame_time: f32, // Seconds
+    pub min_scale: f32,
+    pub max_scale: f32, // Render targets are allocated at window size, so this is clamped to 1
+    pub step: f32,      // Change in scale per adjustment
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        DynamicResolution {
+            enabled: false,
+            target_frame_time: 1.0 / 60.0,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            step: 0.05,
+        }
+    }
+}
+
+// How a frame rendered below the window's resolution gets brought up to it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UpscaleMode {
+    Off,      // Always render at the window's resolution
+    Bilinear, // Stretch the rendered pixels in the final blit
+    Temporal, // Accumulate the jittered frames into a history at the window's resolution, like TAA does at the render resolution
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UpscaleSettings {
+    pub mode: UpscaleMode,
+    pub render_scale: f32, // Fraction of the window's resolution to render, dynamic resolution stays at or below it
+}
+
+impl Default for UpscaleSettings {
+    fn default() -> Self {
+        UpscaleSettings {
+            mode: UpscaleMode::Bilinear,
+            render_scale: 1.0,
+        }
+    }
+}
+
+// Mesh counts of one frame
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CullingStats {
+    pub submitted: usize,
+    pub occlusion_culled: usize,
+    pub drawn: usize, // Draw calls, a mesh drawn by several views counts once per view
+}
+
+// How much the material library saved by sharing textures between models
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MaterialLibraryStats {
+    pub shared_textures: usize, // Textures loaded from files, each uploaded once
+    pub bytes_saved: usize,     // Uploads skipped because another model had already loaded the file
+    pub materials: usize,       // Materials in the library, see Renderer::load_material
+}
+
+// Distance based mesh LOD selection. A mesh uses LOD n once the camera is more than switch_distance * 2^(n - 1)
+// bounding radii away from it, and only switches back after getting `hysteresis` (a fraction of that distance) closer
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LodSettings {
+    pub enabled: bool,
+    pub switch_distance: f32,
+    pub hysteresis: f32,
+    pub debug_view: bool, // Tints each LOD level a different colour
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings {
+            enabled: true,
+            switch_distance: 8.0,
+            hysteresis: 0.1,
+            debug_view: false,
+        }
+    }
+}
+
+// Parallax occlusion mapping of materials with a height map. Each pixel marches the height map in min_steps steps
+// when looking straight at the surface, up to max_steps at grazing angles. More steps shrink the stair-stepping near
+// silhouettes, at the cost of more texture reads
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParallaxSettings {
+    pub enabled: bool,
+    pub min_steps: u32,
+    pub max_steps: u32,
+}
+
+impl Default for ParallaxSettings {
+    fn default() -> Self {
+        ParallaxSettings {
+            enabled: true,
+            min_steps: 8,
+            max_steps: 32,
+        }
+    }
+}
+
+impl LodSettings {
+    // Level for a mesh of the given bounding radius at this distance, without hysteresis
+    fn level_at(&self, distance: f32, radius: f32) -> usize {
+        let ratio = distance / (radius * self.switch_distance).max(f32::EPSILON);
+        if ratio < 1.0 {
+            0
+        } else {
+            ratio.log2() as usize + 1
+        }
+    }
+
+    fn select(&self, current: usize, lod_count: usize, distance: f32, radius: f32) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+        let further = self.level_at(distance * (1.0 - self.hysteresis), radius).min(lod_count);
+        let closer = self.level_at(distance * (1.0 + self.hysteresis), radius).min(lod_count);
+        if further > current {
+            further
+        } else if closer < current {
+            closer
+        } else {
+            current
+        }
+    }
+}
+
+// Tint per LOD level in the debug view, the last one is used for every level after it
+const LOD_DEBUG_COLOURS: [[f32; 3]; 4] = [[0.2, 1.0, 0.2], [1.0, 1.0, 0.2], [1.0, 0.5, 0.1], [1.0, 0.1, 0.1]];
+
+// Summary of a loaded model
+#[derive(Debug, Clone)]
+pub struct ModelInfo {
+    pub mesh_count: usize,
+    pub meshes: Vec<String>, // Mesh names, sorted, for Renderer::reassign_material
+    pub bounds: AABB, // World space, as loaded
+    pub lod_triangle_counts: Vec<usize>, // Level 0 is the full detail model
+    pub scenes: Vec<String>, // Scenes in the file by index, to pick from with LoadOptions::scene_selection
+    pub cpu_bytes: usize, // Vertex data still in memory after the upload, see LoadOptions::cpu_data
+}
+
+// Procedural sky drawn behind the scene. Its sun is also the light the lit shader uses, so the sun in the sky and the
+// lighting always agree, whether or not the sky itself is drawn
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SkyConfig {
+    pub enabled: bool,       // Draw the sky instead of the clear colour
+    pub sun_direction: Vec3, // Towards the sun
+    pub turbidity: f32,      // Haziness of the atmosphere, from 1 (clear) to 10 (hazy)
+    pub ground_albedo: Vec3,
+    pub sun_intensity: f32,
+}
+
+impl Default for SkyConfig {
+    fn default() -> Self {
+        SkyConfig {
+            enabled: false,
+            sun_direction: Vec3::new(0.4, 1.0, 0.3).normalize(),
+            turbidity: 3.0,
+            ground_albedo: Vec3::splat(0.3),
+            sun_intensity: 2.5,
+        }
+    }
+}
+
+impl SkyConfig {
+    // Sun radiance after passing through the atmosphere. The longer path near the horizon and more haze both dim it
+    // and filter out the blue, and it fades out just below the horizon
+    pub fn sun_colour(&self, world_up: WorldUp) -> Vec3 {
+        let elevation = self.sun_direction.normalize_or_zero().dot(world_up.up());
+        let air_mass = 1.0 / (elevation.max(0.0) + 0.05);
+        let extinction = Vec3::new(0.01, 0.03, 0.08) * self.turbidity * 0.5 * air_mass;
+        let visible = ((elevation + 0.05) / 0.05).clamp(0.0, 1.0);
+        Vec3::new((-extinction.x).exp(), (-extinction.y).exp(), (-extinction.z).exp()) * self.sun_intensity * visible
+    }
+}
+
+// Wind that sways the vertices of foliage materials (Material::scl_wind above 0). Vertices move along `direction` by
+// up to `strength` times the mesh's height, scaled by how high up the mesh they are, so the roots stay planted.
+// `gustiness` adds slow swells on top of the steady sway
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct WindConfig {
+    pub enabled: bool,
+    pub direction: Vec3,
+    pub strength: f32,
+    pub gustiness: f32,
+}
+
+impl Default for WindConfig {
+    fn default() -> Self {
+        WindConfig {
+            enabled: true,
+            direction: Vec3::new(1.0, 0.0, 0.3).normalize(),
+            strength: 0.03,
+            gustiness: 0.5,
+        }
+    }
+}
+
+impl WindConfig {
+    // Furthest a vertex of a mesh this tall can get pushed, with an amplitude of 1
+    fn reach(&self, height: f32) -> f32 {
+        self.strength * (1.0 + self.gustiness) * 1.3 * height
+    }
+}
+
+// Identifies a decal added with add_decal
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DecalHandle(u32);
+
+struct Decal {
+    matrix: Mat4, // Unit cube to world space
+    texture: u32,
+    normal_fade: f32,
+}
+
+// A mesh drawn into the ID buffer, its pixels hold its index in the table plus one
+#[derive(Clone)]
+struct IdEntry {
+    model: u64,
+    mesh_hash: u64, // The sort key's material_hash, of the mesh name
+    material_key: u64, // Hash of the material's contents
+    lod_level: usize,
+}
+
+impl IdEntry {
+    fn mesh_key(&self) -> u32 {
+        id_view::fold(self.model.rotate_left(32) ^ self.mesh_hash)
+    }
+
+    // Palette key in a mode, triangles get theirs from the mesh's key on the GPU
+    fn key(&self, view: IdView) -> u32 {
+        match view {
+            IdView::Material => id_view::fold(self.material_key),
+            _ => self.mesh_key(),
+        }
+    }
+}
+
+// Frames between starting a GPU pick's readback and mapping it, so the GPU has finished the copy by then
+const GPU_PICK_LATENCY: u64 = 2;
+
+// A pick_gpu call. The readback is the pixel buffer the ID is copied into, the frame it was started in, and the ID
+// table of that frame
+struct GpuPick {
+    screen: Vec2,
+    callback: Box<dyn FnOnce(Option<IdSample>)>,
+    drawn: bool, // The ID buffer was drawn since the pick was made
+    readback: Option<(u32, u64, Vec<IdEntry>)>,
+}
+
+// Identifies a program registered with register_shader
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderHandle(u32);
+
+// The files are kept to build the program again when they change, or when the GL resources get recreated
+struct CustomShader {
+    files: [PathBuf; 2], // Vertex and fragment shader, resolved
+    modified: [Option<SystemTime>; 2],
+    program: u32,
+}
+
+// The vertex attributes upload_mesh sets up, which custom shaders have to read the same way
+const STANDARD_VERTEX_INPUTS: &[VertexInput] = &[
+    VertexInput { name: "position", location: 0, glsl_type: gl::FLOAT_VEC3 },
+    VertexInput { name: "normal", location: 1, glsl_type: gl::FLOAT_VEC3 },
+    VertexInput { name: "tangent", location: 2, glsl_type: gl::FLOAT_VEC4 },
+    VertexInput { name: "colour", location: 3, glsl_type: gl::FLOAT_VEC4 },
+    VertexInput { name: "uv0", location: 4, glsl_type: gl::FLOAT_VEC2 },
+    VertexInput { name: "uv1", location: 5, glsl_type: gl::FLOAT_VEC2 },
+];
+
+// Identifies a line batch made with create_line_batch
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LineBatchHandle(u32);
+
+// The vertices stay on the CPU too, so recreate_gl_resources can upload them again
+struct LineBatch {
+    vao: u32,
+    vbo: GpuBuffer<LineVertex>,
+    vertices: Vec<LineVertex>,
+}
+
+// How the two eyes of a stereo preview are shown
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StereoOutput {
+    SideBySide, // Left eye on the left half of the window, for cross-eyed viewing or a headset mirror
+    Anaglyph,   // Red from the left eye and cyan from the right, full width, for red/cyan glasses
+}
+
+// Renders the camera twice per frame, each eye moved sideways by half the eye separation along the camera's right
+// axis. Toed-in eyes turn inwards so their view directions cross at the convergence distance, parallel eyes keep
+// looking straight ahead. Each eye gets half the framebuffer, so its projection uses the half-width aspect ratio
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StereoConfig {
+    pub enabled: bool,
+    pub eye_separation: f32,       // World units between the eyes
+    pub convergence_distance: f32, // Only used when toed in
+    pub toe_in: bool,
+    pub output: StereoOutput,
+}
+
+impl Default for StereoConfig {
+    fn default() -> Self {
+        StereoConfig {
+            enabled: false,
+            eye_separation: 0.064,
+            convergence_distance: 2.0,
+            toe_in: true,
+            output: StereoOutput::SideBySide,
+        }
+    }
+}
+
+impl StereoConfig {
+    // View matrix of one eye, from the centre camera's. `side` is -1 for the left eye and 1 for the right one
+    pub fn eye_view_matrix(&self, view_matrix: Mat4, side: f32) -> Mat4 {
+        let offset = side * self.eye_separation * 0.5;
+        let eye = Mat4::from_translation(Vec3::new(-offset, 0.0, 0.0)) * view_matrix;
+        if !self.toe_in || self.convergence_distance <= 0.0 {
+            return eye;
+        }
+        // Turn the eye so the convergence point, straight ahead of the centre camera, ends up straight ahead of it
+        Mat4::from_rotation_y((-offset / self.convergence_distance).atan()) * eye
+    }
+}
+
+const SSAO_KERNEL_SIZE: usize = 16;
+
+// Length of the jitter sequence, and how much of each new frame goes into the TAA history
+const TAA_JITTER_SAMPLES: u64 = 8;
+const TAA_BLEND: f32 = 0.1;
+
+// Default bucket size of the transparent sort's distances, see set_transparent_sort_epsilon
+const TRANSPARENT_SORT_EPSILON: f32 = 0.01;
+
+// A submission whose bounds moved further than this in a frame, in world units, teleported. Its pixels start over
+// instead of blending with or blurring towards where it was
+const TELEPORT_DISTANCE: f32 = 5.0;
+
+// Blurs the frame along the motion of each pixel, the camera's and the models', from the same velocity buffer as TAA
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MotionBlurSettings {
+    pub enabled: bool,
+    pub strength: f32,   // Fraction of a frame's motion that gets smeared, the shutter angle over 360 degrees
+    pub max_radius: f32, // Pixels
+    pub samples: u32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        MotionBlurSettings {
+            enabled: false,
+            strength: 0.5,
+            max_radius: 32.0,
+            samples: 12,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    pub radius: f32,    // View space distance to look for occluders in
+    pub bias: f32,      // Depth difference needed to count as occluded, avoids self-shadowing acne
+    pub intensity: f32, // Exponent applied to the final ambient visibility
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings {
+            enabled: false,
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+        }
+    }
 }
 
+// How the final blit encodes the linear scene for the window's framebuffer. Picked from what the driver actually
+// created, which isn't always what the window hints asked for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputEncoding {
+    SrgbFramebuffer = 0, // 8-bit sRGB framebuffer, GL_FRAMEBUFFER_SRGB encodes on write
+    SrgbShader = 1,      // The framebuffer isn't sRGB capable, so the blit shader encodes
+    ScRgb = 2,           // 16-bit float framebuffer, linear with 1.0 at 80 nits (hdr-output feature)
+    Pq = 3,              // 10-bit framebuffer, HDR10 PQ with BT.2020 primaries (hdr-output feature)
+}
+
+// An image of the application the renderer is embedded in, like an editor viewport or a UI widget
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExternalImage {
+    Framebuffer(u32), // A framebuffer object of the host, drawn into with the draw buffers it has set up
+    Texture(u32),     // A 2D texture, attached to a framebuffer of the renderer's own
+}
+
+// Where frames get presented instead of the window. The host presents them itself, so end_frame doesn't swap the
+// window's buffers. The encoding has to match the image's format, SrgbFramebuffer only works for sRGB formats
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ExternalTarget {
+    pub image: ExternalImage,
+    pub width: i32,
+    pub height: i32,
+    pub encoding: OutputEncoding,
+}
+
+// Colour format of the offscreen framebuffer the scene gets rendered into
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FramebufferFormat {
+    Rgba16F,
+    Rgba32F,
+    R11G11B10F, // No alpha channel, reads back as 1.0
+    Rgba8,      // Fallback for contexts without renderable float formats, clamps the scene to [0, 1]
+}
+
+impl FramebufferFormat {
+    fn internal_format(self) -> GLenum {
+        match self {
+            FramebufferFormat::Rgba16F => gl::RGBA16F,
+            FramebufferFormat::Rgba32F => gl::RGBA32F,
+            FramebufferFormat::R11G11B10F => gl::R11F_G11F_B10F,
+            FramebufferFormat::Rgba8 => gl::RGBA8,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FramebufferError {
+    Undefined,
+    IncompleteAttachment,
+    MissingAttachment,
+    IncompleteDrawBuffer,
+    IncompleteReadBuffer,
+    Unsupported,
+    IncompleteMultisample,
+    IncompleteLayerTargets,
+    Unknown(GLenum),
+}
+
+impl std::fmt::Display for FramebufferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FramebufferError::Undefined => write!(f, "the framebuffer does not exist"),
+            FramebufferError::IncompleteAttachment => write!(f, "one of the colour/depth attachments is incomplete"),
+            FramebufferError::MissingAttachment => write!(f, "the framebuffer has no attachments"),
+            FramebufferError::IncompleteDrawBuffer => write!(f, "a draw buffer points to a missing colour attachment"),
+            FramebufferError::IncompleteReadBuffer => write!(f, "the read buffer points to a missing colour attachment"),
+            FramebufferError::Unsupported => write!(f, "the attachment format combination is unsupported"),
+            FramebufferError::IncompleteMultisample => write!(f, "the colour and depth attachments have mismatched sample counts"),
+            FramebufferError::IncompleteLayerTargets => write!(f, "the attachments have mismatched layer counts"),
+            FramebufferError::Unknown(status) => write!(f, "unknown framebuffer status 0x{:X}", status),
+        }
+    }
+}
+
+// Laid out to match the std140 const_buffer block in the shaders
+#[repr(C)]
 pub struct GlobalConstBuffer {
     view_projection_matrix: Mat4,
+    camera_position: Vec4, // w is unused
+    time_seconds: f32,
+    delta_time: f32,
+    frame_index: u32,
+    _padding: f32,
+}
+
+impl GpuLayout for GlobalConstBuffer {
+    const STRUCT_NAME: &'static str = "GlobalConstBuffer";
+    const BLOCK_NAME: &'static CStr = c"const_buffer";
+    const KIND: BlockKind = BlockKind::Uniform;
+
+    fn fields() -> Vec<GpuField> {
+        vec![
+            GpuField { rust_name: "view_projection_matrix", glsl_name: c"u_view_projection_matrix", offset: offset_of!(GlobalConstBuffer, view_projection_matrix) },
+            GpuField { rust_name: "camera_position", glsl_name: c"u_camera_position", offset: offset_of!(GlobalConstBuffer, camera_position) },
+            GpuField { rust_name: "time_seconds", glsl_name: c"u_time", offset: offset_of!(GlobalConstBuffer, time_seconds) },
+            GpuField { rust_name: "delta_time", glsl_name: c"u_delta_time", offset: offset_of!(GlobalConstBuffer, delta_time) },
+            GpuField { rust_name: "frame_index", glsl_name: c"u_frame_index", offset: offset_of!(GlobalConstBuffer, frame_index) },
+        ]
+    }
 }
 
+type LayoutCheck = fn(u32) -> Result<(), String>;
+
+// Every struct shared with GLSL, checked against each shader program after it's linked
+const GPU_LAYOUTS: &[LayoutCheck] = &[gpu_layout::validate::<GlobalConstBuffer>];
+
 impl Renderer {
     pub fn new(
         width: u32,
         height: u32,
         title: &str,
+        visible: bool,
+        world_up: WorldUp,
+        texture_backend: TextureBackend,
     ) -> Result<Self, ()> {
         // Initialize GLFW
         let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
 
-        // Create window
+        // Create window, hidden windows are used for headless rendering
+        glfw.window_hint(glfw::WindowHint::Visible(visible));
+        if cfg!(feature = "hdr-output") {
+            // Experimental: ask for a deep colour back buffer, which becomes PQ output when the driver provides one
+            for bits in [glfw::WindowHint::RedBits(Some(10)), glfw::WindowHint::GreenBits(Some(10)), glfw::WindowHint::BlueBits(Some(10))] {
+                glfw.window_hint(bits);
+            }
+            glfw.window_hint(glfw::WindowHint::AlphaBits(Some(2)));
+        } else {
+            glfw.window_hint(glfw::WindowHint::SRgbCapable(true));
+        }
         let (mut window, events) = glfw
             .create_window(width, height, title, glfw::WindowMode::Windowed)
             .expect("Failed to create window.");
@@ -76,68 +887,296 @@ impl Renderer {
             }
         }
 
+        // Refuse contexts the shaders can't compile on, and turn off what the context can't run
+        let capabilities = GpuCapabilities::query();
+        capabilities.report();
+        if let Err(error) = capabilities.check_minimum() {
+            error!("{error}");
+            return Err(());
+        }
+
+        let output_encoding = detect_output_encoding();
+        info!("Output encoding: {output_encoding:?}");
+
         // Create renderer
         let mut renderer = Renderer {
             glfw,
             window,
             events,
-            mesh_queue: queue![],
+            title: title.to_string(),
+            title_stats: false,
+            title_stats_buffer: String::new(),
+            title_stats_frame_count: 0,
+            title_stats_last_update: 0.0,
+            mesh_queue: Vec::new(),
+            origin: DVec3::ZERO,
+            camera_view_matrix: Mat4::IDENTITY,
+            projection: Projection::default(),
+            projection_matrix: Mat4::IDENTITY,
+            view_rendered: false,
             triangle_shader: 0,
+            texture_lod_bias: 0.0,
+            lod_settings: LodSettings::default(),
+            transparent_sort_epsilon: TRANSPARENT_SORT_EPSILON,
+            parallax: ParallaxSettings::default(),
+            hi_z: None,
+            hi_z_shader: 0,
+            occlusion_visible: HashSet::new(),
+            occlusion_visible_last: HashSet::new(),
+            frame_culling_stats: CullingStats::default(),
+            culling_stats: CullingStats::default(),
             const_buffer_cpu: GlobalConstBuffer {
                 view_projection_matrix: Mat4::IDENTITY,
+                camera_position: Vec4::ZERO,
+                time_seconds: 0.0,
+                delta_time: 0.0,
+                frame_index: 0,
+                _padding: 0.0,
+            },
+            const_buffer_gpu: GpuBuffer::new(gl::UNIFORM_BUFFER, gl::DYNAMIC_DRAW),
+            pass_hooks: Vec::new(),
+            text_overlay: TextOverlay::new(),
+            text_shader: 0,
+            frame_history: None,
+            frame_graph: FrameGraph::default(),
+            last_frame_graph: FrameGraph::default(),
+            frame_history_downscale: 2,
+            time_prev: 0.0,
+            time: 0.0,
+            delta_time: 0.0,
+            time_paused: false,
+            focused: true,
+            iconified: false,
+            frame_skipped: false,
+            resume_clock: false,
+            exposure: ExposureSettings::default(),
+            auto_exposure: None,
+            exposure_shader: 0,
+            stereo: StereoConfig::default(),
+            ssao: SsaoSettings::default(),
+            ssao_shader: 0,
+            ssao_blur_shader: 0,
+            ssao_noise_texture: 0,
+            ssao_framebuffer_objects: [0, 0],
+            ssao_textures: [0, 1].map(|_| GpuTexture::new(gl::R8, gl::RED, gl::UNSIGNED_BYTE)),
+            fullscreen_vao: 0,
+            supersample_offset: Vec2::ZERO,
+            // The defaults are Y-up
+            sky: SkyConfig {
+                sun_direction: world_up.y_up_rotation() * SkyConfig::default().sun_direction,
+                ..Default::default()
             },
-            const_buffer_gpu: 0,
+            wind: WindConfig {
+                direction: world_up.y_up_rotation() * WindConfig::default().direction,
+                ..Default::default()
+            },
+            world_up,
+            decals: BTreeMap::new(),
+            next_decal: 0,
+            decal_textures: HashMap::new(),
+            decal_shader: 0,
+            id_view: IdView::Off,
+            id_entries: Vec::new(),
+            id_texture: GpuTexture::new(gl::RGBA32UI, gl::RGBA_INTEGER, gl::UNSIGNED_INT),
+            id_depth: GpuTexture::new(gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8),
+            id_framebuffer_object: 0,
+            id_shader: 0,
+            id_view_shader: 0,
+            gpu_picks: Vec::new(),
+            custom_shaders: BTreeMap::new(),
+            next_custom_shader: 0,
+            model_shaders: HashMap::new(),
+            water_planes: BTreeMap::new(),
+            next_water_plane: 0,
+            water_shader: 0,
+            water_normal_texture: 0,
+            reflections: true,
+            scene_depth_copy: GpuTexture::new(gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8),
+            scene_colour_copy: GpuTexture::new(gl::RGBA16F, gl::RGBA, gl::FLOAT),
+            scene_copy_framebuffer_object: 0,
+            line_batches: BTreeMap::new(),
+            next_line_batch: 0,
+            line_queue: Vec::new(),
+            line_shader: 0,
+            sky_shader: 0,
+            taa_enabled: false,
+            taa_shader: 0,
+            motion_shader: 0,
+            velocity_texture: GpuTexture::new(gl::RG16F, gl::RG, gl::FLOAT),
+            velocity_framebuffer_object: 0,
+            taa_history_textures: [0, 1].map(|_| GpuTexture::new(gl::RGBA16F, gl::RGBA, gl::FLOAT).with_filter(gl::LINEAR)),
+            taa_history_framebuffer_objects: [0, 0],
+            taa_history_index: 0,
+            taa_history_valid: false,
+            taa_history_resolution: [0, 0],
+            taa_jitter: Vec2::ZERO,
+            upscale: UpscaleSettings::default(),
+            previous_velocity_texture: GpuTexture::new(gl::RG16F, gl::RG, gl::FLOAT),
+            previous_velocity_framebuffer_object: 0,
+            taa_previous_render_resolution: [0, 0],
+            view_projection_matrix: Mat4::IDENTITY,
+            previous_view_projection_matrix: Mat4::IDENTITY,
+            motion_vectors_frame: None,
+            object_transforms: HashMap::new(),
+            previous_object_transforms: HashMap::new(),
+            object_submissions: HashMap::new(),
+            object_motion_shader: 0,
+            object_motion_framebuffer_object: 0,
+            motion_blur: MotionBlurSettings::default(),
+            motion_blur_shader: 0,
             models: HashMap::new(),
-            depth_buffer_texture: 0,
-            framebuffer_texture: 0,
+            model_tags: HashMap::new(),
+            next_generated_model: 0,
+            textures: texture_store::new_store(texture_backend),
+            texture_backend,
+            placeholder_texture: 0,
+            white_texture: 0,
+            missing_textures: Vec::new(),
+            shared_textures: HashMap::new(),
+            shared_texture_bytes_saved: 0,
+            material_library: HashMap::new(),
+            material_descriptors: HashMap::new(),
+            change_journal: ChangeJournal::new(),
+            texture_quality: TextureQuality::default(),
+            texture_streaming: TextureStreamingConfig {
+                enabled: false,
+                bytes_per_frame: 4 * 1024 * 1024,
+            },
+            streamed_textures: HashMap::new(),
+            texture_last_used: HashMap::new(),
+            frame_index: 0,
+            hot_reload: false,
+            hot_reload_last_poll: 0.0,
+            model_load_args: HashMap::new(),
+            // Formats get set when the framebuffer is resized. The colour is filtered, so a scaled down render gets
+            // upscaled smoothly in the final blit
+            depth_buffer_texture: GpuTexture::new(gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8),
+            framebuffer_texture: GpuTexture::new(gl::RGBA16F, gl::RGBA, gl::FLOAT).with_filter(gl::LINEAR),
             framebuffer_object: 0,
-            quad_vbo: 0,
+            quad_vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
             quad_vao: 0,
             fbo_shader: 0,
             window_resolution_prev: [0, 0],
+            render_resolution: [0, 0],
+            post_resolution: [0, 0],
+            framebuffer_complete: false,
+            framebuffer_format: if capabilities.float_render_targets { FramebufferFormat::Rgba16F } else { FramebufferFormat::Rgba8 },
+            dithering: false,
+            output_encoding,
+            external_target: None,
+            external_framebuffer_object: 0,
+            hdr_paper_white_nits: 200.0,
+            hdr_peak_nits: 1000.0,
+            dynamic_resolution: DynamicResolution::default(),
+            render_scale: 1.0,
+            frame_time_average: 0.0,
+            render_scale_cooldown: 0,
+            quality_governor: QualityGovernor::new(QualityGovernorConfig::default()),
+            quality_baseline: QualityBaseline::default(),
+            msaa_samples: 0,
+            msaa_framebuffer_object: 0,
+            msaa_colour_texture: 0,
+            msaa_depth_texture: 0,
+            assets: AssetResolver::new(),
+            shader_cache: capabilities.program_binary.then(|| ShaderCache::new(ShaderCacheConfig::default())),
+            shaders_from_cache: 0,
+            shaders_compiled: 0,
+            capabilities,
         };
 
-        // Load shaders
-		renderer.fbo_shader = renderer
-			.load_shader(Path::new("assets/shaders/fbo"))
-			.expect("Shader loading failed");
-        renderer.triangle_shader = renderer
-            .load_shader(Path::new("assets/shaders/lit"))
-            .expect("Shader loading failed!");
+        if let Err(error) = renderer.create_gl_resources() {
+            error!("{error}");
+            return Err(());
+        }
 
-        // Create const buffer
-        unsafe {
-            gl::GenBuffers(1, &mut renderer.const_buffer_gpu);
-            gl::BindBuffer(gl::UNIFORM_BUFFER, renderer.const_buffer_gpu);
-            gl::BufferData(
-                gl::UNIFORM_BUFFER,
-                size_of::<GlobalConstBuffer>() as isize,
-                &renderer.const_buffer_cpu as *const GlobalConstBuffer as *const c_void,
-                gl::STATIC_DRAW,
-            );
+        // Create fallback textures
+        let magenta = Pixel32::new(255, 0, 255, 255);
+        let black = Pixel32::new(0, 0, 0, 255);
+        renderer.placeholder_texture = renderer.upload_texture(&mut Texture::checkerboard(16, 4, magenta, black));
+        renderer.white_texture = renderer.upload_texture(&mut Texture::solid(Pixel32::new(255, 255, 255, 255)));
+
+        // Return a new renderer object
+        Ok(renderer)
+    }
+
+    // Creates every GL object the renderer owns, except textures. Models are uploaded separately
+    fn create_gl_resources(&mut self) -> Result<(), String> {
+        // Load shaders, and report how much the shader cache saved
+        let shader_start = self.glfw.get_time();
+        (self.shaders_from_cache, self.shaders_compiled) = (0, 0);
+		self.fbo_shader = self.load_shader(Path::new("assets/shaders/fbo"))?;
+        self.triangle_shader = self.load_shader(Path::new("assets/shaders/lit"))?;
+        TextureBinder::assign_sampler(self.fbo_shader, c"scene_colour", TextureSlot::Albedo);
+        self.assign_lit_samplers(self.triangle_shader);
+        self.ssao_shader = self.load_shader(Path::new("assets/shaders/ssao"))?;
+        self.ssao_blur_shader = self.load_shader(Path::new("assets/shaders/ssao_blur"))?;
+        TextureBinder::assign_sampler(self.ssao_shader, c"depth_texture", TextureSlot::SceneDepth);
+        TextureBinder::assign_sampler(self.ssao_shader, c"noise_texture", TextureSlot::Noise);
+        TextureBinder::assign_sampler(self.ssao_blur_shader, c"ao_texture", TextureSlot::Albedo);
+        self.sky_shader = self.load_shader(Path::new("assets/shaders/sky"))?;
+        self.text_shader = self.load_shader(Path::new("assets/shaders/text"))?;
+        TextureBinder::assign_sampler(self.text_shader, c"font_texture", TextureSlot::Albedo);
+        self.motion_shader = self.load_shader(Path::new("assets/shaders/motion"))?;
+        self.taa_shader = self.load_shader(Path::new("assets/shaders/taa"))?;
+        TextureBinder::assign_sampler(self.motion_shader, c"depth_texture", TextureSlot::SceneDepth);
+        TextureBinder::assign_sampler(self.taa_shader, c"scene_colour", TextureSlot::Albedo);
+        TextureBinder::assign_sampler(self.taa_shader, c"history_texture", TextureSlot::History);
+        TextureBinder::assign_sampler(self.taa_shader, c"velocity_texture", TextureSlot::Velocity);
+        TextureBinder::assign_sampler(self.taa_shader, c"previous_velocity_texture", TextureSlot::PreviousVelocity);
+        if self.capabilities.compute_shaders {
+            self.hi_z_shader = self.load_compute_shader(Path::new("assets/shaders/hiz"))?;
+            TextureBinder::assign_sampler(self.hi_z_shader, c"source_texture", TextureSlot::SceneDepth);
         }
+        self.decal_shader = self.load_shader(Path::new("assets/shaders/decal"))?;
+        TextureBinder::assign_sampler(self.decal_shader, c"depth_texture", TextureSlot::SceneDepth);
+        TextureBinder::assign_sampler(self.decal_shader, c"decal_texture", TextureSlot::Albedo);
+        self.water_shader = self.load_shader(Path::new("assets/shaders/water"))?;
+        TextureBinder::assign_sampler(self.water_shader, c"depth_texture", TextureSlot::SceneDepth);
+        TextureBinder::assign_sampler(self.water_shader, c"colour_texture", TextureSlot::SceneColour);
+        TextureBinder::assign_sampler(self.water_shader, c"normal_texture", TextureSlot::Normal);
+        self.line_shader = self.load_shader(Path::new("assets/shaders/line"))?;
+        self.id_shader = self.build_program(&[
+            (gl::VERTEX_SHADER, PathBuf::from("assets/shaders/lit.vert")),
+            (gl::FRAGMENT_SHADER, PathBuf::from("assets/shaders/id.frag")),
+        ])?;
+        self.assign_lit_samplers(self.id_shader);
+        self.object_motion_shader = self.build_program(&[
+            (gl::VERTEX_SHADER, PathBuf::from("assets/shaders/lit.vert")),
+            (gl::FRAGMENT_SHADER, PathBuf::from("assets/shaders/object_motion.frag")),
+        ])?;
+        self.assign_lit_samplers(self.object_motion_shader);
+        self.motion_blur_shader = self.load_shader(Path::new("assets/shaders/motion_blur"))?;
+        TextureBinder::assign_sampler(self.motion_blur_shader, c"colour_texture", TextureSlot::SceneColour);
+        TextureBinder::assign_sampler(self.motion_blur_shader, c"velocity_texture", TextureSlot::Velocity);
+        self.id_view_shader = self.load_shader(Path::new("assets/shaders/id_view"))?;
+        TextureBinder::assign_sampler(self.id_view_shader, c"id_texture", TextureSlot::Albedo);
+        self.exposure_shader = self.load_shader(Path::new("assets/shaders/exposure"))?;
+        TextureBinder::assign_sampler(self.exposure_shader, c"source_texture", TextureSlot::Albedo);
+        TextureBinder::assign_sampler(self.exposure_shader, c"exposure_texture", TextureSlot::Exposure);
+        info!(
+            "Loaded shaders in {:.1} ms, {} from the cache and {} compiled",
+            (self.glfw.get_time() - shader_start) * 1000.0,
+            self.shaders_from_cache,
+            self.shaders_compiled
+        );
+        self.init_ssao();
+
+        // Create const buffer
+        self.const_buffer_gpu.upload(std::slice::from_ref(&self.const_buffer_cpu));
 
-		// Create framebuffer
-		let window_resolution = renderer.window.get_framebuffer_size();
-		unsafe { 
-			// Color
-			gl::GenFramebuffers(1, &mut renderer.framebuffer_object);
-			gl::BindFramebuffer(gl::FRAMEBUFFER, renderer.framebuffer_object);
-			gl::GenTextures(1, &mut renderer.framebuffer_texture);
-			gl::BindTexture(gl::TEXTURE_2D, renderer.framebuffer_texture);
-			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA16F as _, window_resolution.0, window_resolution.1, 0, gl::RGBA, gl::FLOAT, null());
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
-			gl::BindTexture(gl::TEXTURE_2D, 0);
-			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, renderer.framebuffer_texture, 0);
-
-			// Depth
-			gl::BindFramebuffer(gl::FRAMEBUFFER, renderer.framebuffer_object);
-			gl::GenTextures(1, &mut renderer.depth_buffer_texture);
-			gl::BindTexture(gl::TEXTURE_2D, renderer.depth_buffer_texture);
-			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH24_STENCIL8 as _, window_resolution.0, window_resolution.1, 0, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8, null());
-			gl::BindTexture(gl::TEXTURE_2D, 0);
-			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D, renderer.depth_buffer_texture, 0);
+		// Create framebuffer. Its render targets get created at the current size, or at the start of the first frame the
+		// window isn't minimized
+		self.window_resolution_prev = [0, 0];
+		self.framebuffer_complete = false;
+		unsafe {
+			gl::GenFramebuffers(1, &mut self.framebuffer_object);
+		}
+		let window_resolution = self.output_size();
+		if window_resolution.0 > 0 && window_resolution.1 > 0 {
+			self.update_framebuffer_resolution();
+			if !self.framebuffer_complete {
+				return Err("Failed to create the main framebuffer".to_string());
+			}
 		}
 
 		// Create screen quad
@@ -159,11 +1198,9 @@ impl Renderer {
 				1.0, 0.0,
 				0.0, 0.0,
 			];
-			gl::GenVertexArrays(1, &mut renderer.quad_vao);
-			gl::GenBuffers(1, &mut renderer.quad_vbo);
-			gl::BindVertexArray(renderer.quad_vao);
-			gl::BindBuffer(gl::ARRAY_BUFFER, renderer.quad_vbo);
-			gl::BufferData(gl::ARRAY_BUFFER, (quad.len() * size_of::<f32>()) as isize, quad.as_ptr() as *const c_void, gl::STATIC_DRAW);
+			gl::GenVertexArrays(1, &mut self.quad_vao);
+			gl::BindVertexArray(self.quad_vao);
+			self.quad_vbo.upload(&quad);
 			gl::EnableVertexAttribArray(0);
 			gl::EnableVertexAttribArray(1);
 			gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 0, std::ptr::null::<c_void>());
@@ -172,245 +1209,2791 @@ impl Renderer {
 			gl::BindVertexArray(0);
 		}
 
-        // Return a new renderer object
-        Ok(renderer)
-    }
-
-    pub fn should_close(&self) -> bool {
-        self.window.should_close()
+        Ok(())
     }
 
-    pub fn update_camera(&mut self, camera: &Camera) {
-        // Update CPU-side buffer
-        let view_matrix = camera.transform.view_matrix();
-        let proj_matrix = Mat4::perspective_rh(PI / 4.0, 16.0 / 9.0, 0.1, 1000.0);
-        self.const_buffer_cpu.view_projection_matrix = proj_matrix * view_matrix;
-
-        // Update GPU-side buffer
+    // Material texture samplers of the lit shader, and of custom shaders that use the same names
+    fn assign_lit_samplers(&self, program: u32) {
+        TextureBinder::assign_sampler(program, c"colour_texture", TextureSlot::Albedo);
+        TextureBinder::assign_sampler(program, c"mtl_rgh_texture", TextureSlot::MetallicRoughness);
+        TextureBinder::assign_sampler(program, c"height_texture", TextureSlot::Height);
+        TextureBinder::assign_sampler(program, c"colour_array", TextureSlot::AlbedoArray);
+        TextureBinder::assign_sampler(program, c"mtl_rgh_array", TextureSlot::MetallicRoughnessArray);
+        TextureBinder::assign_sampler(program, c"height_array", TextureSlot::HeightArray);
         unsafe {
-            gl::BindBuffer(gl::UNIFORM_BUFFER, self.const_buffer_gpu);
-            gl::BufferData(
-                gl::UNIFORM_BUFFER,
-                size_of::<GlobalConstBuffer>() as isize,
-                &self.const_buffer_cpu as *const GlobalConstBuffer as *const c_void,
-                gl::STATIC_DRAW,
-            );
-            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            gl::UseProgram(program);
+            let arrays = self.texture_backend == TextureBackend::ArrayPerSize;
+            gl::Uniform1i(gl::GetUniformLocation(program, c"u_texture_arrays".as_ptr()), arrays as i32);
+            gl::UseProgram(0);
         }
     }
 
-    pub fn begin_frame(&mut self) {
-        // Clear the screen
-		self.update_framebuffer_resolution();
+    fn delete_gl_resources(&mut self) {
         unsafe {
-			gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
-            gl::ClearColor(0.1, 0.1, 0.2, 1.0);
-			gl::ClearDepth(1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            for shader in [self.fbo_shader, self.triangle_shader, self.ssao_shader, self.ssao_blur_shader, self.sky_shader, self.text_shader, self.motion_shader, self.taa_shader, self.hi_z_shader, self.decal_shader, self.water_shader, self.line_shader, self.exposure_shader, self.id_shader, self.id_view_shader, self.object_motion_shader, self.motion_blur_shader] {
+                gl::DeleteProgram(shader);
+            }
+            for shader in self.custom_shaders.values_mut() {
+                gl::DeleteProgram(shader.program);
+                shader.program = 0;
+            }
+            gl::DeleteFramebuffers(1, &self.framebuffer_object);
+            gl::DeleteVertexArrays(1, &self.quad_vao);
+            gl::DeleteVertexArrays(1, &self.fullscreen_vao);
+            gl::DeleteTextures(1, &self.ssao_noise_texture);
+            gl::DeleteFramebuffers(2, self.ssao_framebuffer_objects.as_ptr());
+            gl::DeleteFramebuffers(1, &self.velocity_framebuffer_object);
+            gl::DeleteFramebuffers(1, &self.previous_velocity_framebuffer_object);
+            gl::DeleteFramebuffers(1, &self.object_motion_framebuffer_object);
+            gl::DeleteFramebuffers(2, self.taa_history_framebuffer_objects.as_ptr());
+            gl::DeleteFramebuffers(1, &self.scene_copy_framebuffer_object);
+            gl::DeleteFramebuffers(1, &self.id_framebuffer_object);
+        }
+        self.id_framebuffer_object = 0;
+        self.delete_external_framebuffer();
+        // Picks in flight start over once the ID buffer is drawn again
+        for pick in &mut self.gpu_picks {
+            pick.drawn = false;
+            if let Some((pixel_buffer, _, _)) = pick.readback.take() {
+                unsafe { gl::DeleteBuffers(1, &pixel_buffer) };
+            }
         }
+        self.id_texture.delete();
+        self.id_depth.delete();
+        self.const_buffer_gpu.delete();
+        self.quad_vbo.delete();
+        self.framebuffer_texture.delete();
+        self.depth_buffer_texture.delete();
+        for texture in self.ssao_textures.iter_mut().chain(&mut self.taa_history_textures) {
+            texture.delete();
+        }
+        self.velocity_texture.delete();
+        self.previous_velocity_texture.delete();
+        self.scene_depth_copy.delete();
+        self.scene_colour_copy.delete();
+        self.scene_copy_framebuffer_object = 0;
+        self.frame_graph.delete();
+        self.last_frame_graph.delete();
+        self.ssao_framebuffer_objects = [0, 0];
+        self.velocity_framebuffer_object = 0;
+        self.previous_velocity_framebuffer_object = 0;
+        self.object_motion_framebuffer_object = 0;
+        self.taa_history_framebuffer_objects = [0, 0];
+        self.delete_msaa_targets();
     }
 
-    pub fn end_frame(&mut self) {
-        // Enable depth testing
-        // todo: separate all the unsafe gl parts into separate functions
-        unsafe {
-            gl::Enable(gl::DEPTH_TEST);
-            gl::Enable(gl::CULL_FACE);
-            gl::UseProgram(self.triangle_shader);
+    // Throws away and recreates the renderer's GL objects, and re-uploads every model from its CPU-side vertices.
+    // Recovers from a lost context, and doubles as a check that every GPU object is tracked. Textures are not
+    // recreated, their pixel data isn't kept after upload. Call it outside of begin_frame/end_frame
+    pub fn recreate_gl_resources(&mut self) -> Result<(), String> {
+        profile_scope!("recreate_gl_resources");
+        self.mesh_queue.clear();
+        self.line_queue.clear();
+        self.delete_gl_resources();
+        self.create_gl_resources()?;
+        let mut reread = Vec::new();
+        for (path_hash, model) in &mut self.models {
+            // Models that didn't keep their vertices have to be read from their files again
+            if model.meshes.values().any(|mesh| mesh.verts.is_empty()) {
+                reread.push(*path_hash);
+                continue;
+            }
+            for (name, mesh) in &mut model.meshes {
+                unsafe {
+                    gl::DeleteVertexArrays(1, &mesh.vao);
+                }
+                mesh.vbo.delete();
+                Self::upload_mesh(mesh)
+                    .map_err(|error| format!("Failed to upload mesh \"{name}\" of model {path_hash:016X}: GL error 0x{error:X}"))?;
+                for lod in &mut mesh.lods {
+                    unsafe {
+                        gl::DeleteVertexArrays(1, &lod.vao);
+                    }
+                    lod.vbo.delete();
+                    Self::upload_mesh(lod).map_err(|error| {
+                        format!("Failed to upload a LOD of mesh \"{name}\" of model {path_hash:016X}: GL error 0x{error:X}")
+                    })?;
+                }
+            }
         }
-
-        // Render mesh queue
-        while let Ok(mesh) = self.mesh_queue.remove() {
-            // Render the first mesh in the queue
+        for handle in reread {
+            self.reload_model(handle);
+        }
+        if let Some(history) = &mut self.frame_history {
+            history.recreate_gl_resources();
+        }
+        if let Some(hi_z) = &mut self.hi_z {
+            hi_z.recreate_gl_resources();
+        }
+        if let Some(auto_exposure) = &mut self.auto_exposure {
+            auto_exposure.recreate_gl_resources();
+        }
+        for batch in self.line_batches.values_mut() {
             unsafe {
-                // Bind the vertex buffer
-                gl::BindVertexArray(mesh.vao);
-                gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vbo);
+                gl::DeleteVertexArrays(1, &batch.vao);
+            }
+            batch.vbo.delete();
+            Self::upload_line_batch(batch);
+        }
+        let custom_shaders: Vec<u32> = self.custom_shaders.keys().copied().collect();
+        for handle in custom_shaders {
+            self.rebuild_custom_shader(handle);
+        }
+        self.text_overlay.recreate_gl_resources();
+        // The host's image survives, only the framebuffer it's attached to is ours
+        self.set_render_target(self.external_target)
+    }
+
+    pub fn should_close(&self) -> bool {
+        self.window.should_close()
+    }
 
-                // Bind the constant buffer
-                gl::BindBufferBase(gl::UNIFORM_BUFFER, 0, self.const_buffer_gpu);
+    pub fn update_camera(&mut self, camera: &Camera) {
+        self.camera_view_matrix = camera.transform.view_matrix(self.world_up);
+    }
 
-                // Bind the texture
-                gl::BindTexture(gl::TEXTURE_2D, mesh.material.tex_alb as u32);
+    // The world position everything is relative to, see set_origin
+    pub fn origin(&self) -> DVec3 {
+        self.origin
+    }
 
-                // Draw the model
-                gl::DrawArrays(gl::TRIANGLES, 0, mesh.n_vertices);
-            }
+    // Moves the floating origin. Positions relative to the old origin, the camera's and the transforms line batches are
+    // drawn with included, have to be moved by the returned shift. Models, decals and water stay where they are
+    pub fn set_origin(&mut self, origin: DVec3) -> Vec3 {
+        let shift = (origin - self.origin).as_vec3();
+        if shift == Vec3::ZERO {
+            return shift;
         }
+        self.origin = origin;
 
-		// Render to window buffer
+        // Keep everything that's already relative to the origin in place, last frame's views included so TAA and
+        // occlusion culling carry on
+        let unshift = Mat4::from_translation(-shift);
+        for decal in self.decals.values_mut() {
+            decal.matrix = unshift * decal.matrix;
+        }
+        for plane in self.water_planes.values_mut() {
+            plane.matrix = unshift * plane.matrix;
+        }
+        self.previous_view_projection_matrix *= Mat4::from_translation(shift);
+        for transform in self.object_transforms.values_mut() {
+            *transform = unshift * *transform;
+        }
+        if let Some(hi_z) = &mut self.hi_z {
+            hi_z.shift_origin(shift);
+        }
+        info!("Moved the origin to ({:.0}, {:.0}, {:.0})", origin.x, origin.y, origin.z);
+        self.record_change(ChangeOperation::Setting("origin"), 0, 0);
+        shift
+    }
+
+    // Moves the origin along with the camera once it gets further than ORIGIN_FOLLOW_DISTANCE away, and moves the
+    // camera to match. Call before update_camera every frame when the scene is bigger than f32 positions can cover
+    pub fn follow_camera(&mut self, camera: &mut Camera) {
+        let position = camera.transform.translation;
+        if position.abs().max_element() < ORIGIN_FOLLOW_DISTANCE {
+            return;
+        }
+        let target = self.origin + (position.as_dvec3() / mesh::ORIGIN_GRID).round() * mesh::ORIGIN_GRID;
+        camera.transform.translation -= self.set_origin(target);
+    }
+
+    // Converts between world positions and positions relative to the origin
+    pub fn to_world(&self, position: Vec3) -> DVec3 {
+        self.origin + position.as_dvec3()
+    }
+
+    pub fn to_relative(&self, position: DVec3) -> Vec3 {
+        (position - self.origin).as_vec3()
+    }
+
+    // Where a model's vertices are relative to the origin
+    fn model_offset(&self, model: &Model) -> Vec3 {
+        (model.origin - self.origin).as_vec3()
+    }
+
+    // Field of view used by every view, rejects angles outside of (0, 180) degrees
+    pub fn set_projection(&mut self, projection: Projection) -> Result<(), String> {
+        projection.validate()?;
+        if projection.reversed_z && !self.capabilities.clip_control {
+            return Err("Reversed-Z needs glClipControl, which this OpenGL context doesn't support".to_string());
+        }
+
+        // Reversed-Z uses a different depth format, recreate the render targets at the start of the next frame
+        if projection.reversed_z != self.projection.reversed_z {
+            self.window_resolution_prev = [0, 0];
+        }
+        self.projection = projection;
+        self.record_change(ChangeOperation::Setting("projection"), 0, 0);
+        Ok(())
+    }
+
+    // Internal format and pixel type of the depth buffers, reversed-Z needs floating point depth to be worth it
+    fn depth_format(&self) -> (GLenum, GLenum) {
+        if self.projection.reversed_z {
+            (gl::DEPTH32F_STENCIL8, gl::FLOAT_32_UNSIGNED_INT_24_8_REV)
+        } else {
+            (gl::DEPTH24_STENCIL8, gl::UNSIGNED_INT_24_8)
+        }
+    }
+
+    fn depth_func(&self) -> GLenum {
+        if self.projection.reversed_z {
+            gl::GREATER
+        } else {
+            gl::LESS
+        }
+    }
+
+    fn update_const_buffer(&mut self, view_matrix: Mat4, viewport: Rect) {
+        // Update CPU-side buffer
+        let proj_matrix = self.projection.matrix(viewport.aspect_ratio());
+        self.projection_matrix = proj_matrix;
+        self.view_projection_matrix = proj_matrix * view_matrix;
+
+        // Shift the projection by a different sub-pixel offset every frame, which TAA accumulates into a smooth image
+        let mut offset = self.supersample_offset;
+        if self.temporal_accumulation() {
+            let index = (self.frame_index % TAA_JITTER_SAMPLES) as u32 + 1;
+            offset += Vec2::new(halton(index, 2) - 0.5, halton(index, 3) - 0.5);
+        }
+        self.taa_jitter = offset;
+        let offset = offset * 2.0 / Vec2::new(viewport.width as f32, viewport.height as f32);
+        let jitter = Mat4::from_translation(offset.extend(0.0));
+        self.const_buffer_cpu.view_projection_matrix = jitter * self.view_projection_matrix;
+        self.const_buffer_cpu.camera_position = view_matrix.inverse().w_axis;
+        self.const_buffer_cpu.time_seconds = self.time as f32;
+        self.const_buffer_cpu.delta_time = self.delta_time;
+        self.const_buffer_cpu.frame_index = self.frame_index as u32;
+
+        // Update GPU-side buffer
+        self.const_buffer_gpu.update_range(0, std::slice::from_ref(&self.const_buffer_cpu));
+    }
+
+    pub fn begin_frame(&mut self) {
+        profile_scope!("begin_frame");
+        // Update the clock
+        let time = self.glfw.get_time();
+        let frame_time = (time - self.time_prev) as f32;
+        self.delta_time = if self.time_paused || self.resume_clock { 0.0 } else { frame_time };
+        self.time += self.delta_time as f64;
+        self.time_prev = time;
+        if !self.resume_clock {
+            self.update_render_scale(frame_time);
+            self.update_quality_governor(frame_time);
+        }
+        self.resume_clock = false;
+
+        // Last frame's submissions are where this frame's moved from
+        self.previous_object_transforms = std::mem::take(&mut self.object_transforms);
+        self.object_submissions.clear();
+
+        // Don't touch the render targets while there is nothing to render to
+        // A minimized window doesn't matter to an external target
+        let window_resolution = self.output_size();
+        self.frame_skipped = (self.iconified && self.external_target.is_none()) || window_resolution.0 <= 0 || window_resolution.1 <= 0;
+        if self.frame_skipped {
+            return;
+        }
+
+        // Clear the screen
+        self.frame_graph.clear();
+		self.update_framebuffer_resolution();
+        self.render_resolution = [
+            ((self.window_resolution_prev[0] as f32 * self.render_scale).round() as i32).max(1),
+            ((self.window_resolution_prev[1] as f32 * self.render_scale).round() as i32).max(1),
+        ];
+        self.post_resolution = self.render_resolution;
+        self.clear_render_targets();
+    }
+
+    fn clear_render_targets(&mut self) {
+        self.frame_graph.begin_pass("clear", &[], self.raster_targets());
+        unsafe {
+			if self.capabilities.clip_control {
+				let clip_depth_range = if self.projection.reversed_z { gl::ZERO_TO_ONE } else { gl::NEGATIVE_ONE_TO_ONE };
+				gl::ClipControl(gl::LOWER_LEFT, clip_depth_range);
+			}
+			gl::BindFramebuffer(gl::FRAMEBUFFER, self.raster_framebuffer_object());
+            gl::ClearColor(0.1, 0.1, 0.2, 1.0);
+			gl::ClearDepth(self.projection.depth_clear_value());
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+        self.frame_graph.end_pass();
+    }
+
+    // Attachments the scene gets rasterized into, as named in the frame graph
+    fn raster_targets(&self) -> &'static [&'static str] {
+        if self.msaa_samples > 0 {
+            &["msaa_colour", "msaa_depth"]
+        } else {
+            &["scene_colour", "scene_depth"]
+        }
+    }
+
+    // Ordered list of the passes that ran last frame, with their timings and the resources they used
+    pub fn dump_frame_graph(&self) -> String {
+        self.last_frame_graph.to_text()
+    }
+
+    // The same as dump_frame_graph, as a GraphViz graph
+    pub fn dump_frame_graph_dot(&self) -> String {
+        self.last_frame_graph.to_dot()
+    }
+
+    pub fn end_frame(&mut self) {
+        profile_scope!("end_frame");
+        // Drop this frame's queue while minimized
+        if self.frame_skipped {
+            self.mesh_queue.clear();
+            self.line_queue.clear();
+            self.text_overlay.clear();
+            self.frame_graph.clear();
+            self.view_rendered = false;
+            return;
+        }
+
+        // Don't render into a broken framebuffer, just drop this frame's queue
+        if !self.framebuffer_complete {
+            self.mesh_queue.clear();
+            self.line_queue.clear();
+            self.text_overlay.clear();
+            self.frame_graph.clear();
+            self.view_rendered = false;
+            if self.external_target.is_none() {
+                self.window.swap_buffers();
+            }
+            return;
+        }
+
+        // Without any explicit views, render the camera from update_camera() to the whole framebuffer, or once per
+        // eye in stereo
+        if !self.view_rendered && self.stereo.enabled {
+            let full = self.full_viewport();
+            let half = full.width / 2;
+            let left = Rect { width: half, ..full };
+            let right = Rect { x: half, width: full.width - half, ..full };
+            self.render_raster_view(self.stereo.eye_view_matrix(self.camera_view_matrix, -1.0), left);
+            self.render_raster_view(self.stereo.eye_view_matrix(self.camera_view_matrix, 1.0), right);
+        } else if !self.view_rendered {
+            self.render_raster_view(self.camera_view_matrix, self.full_viewport());
+        }
+        // Remember which textures were drawn, so streaming can prioritize them
+        for mesh in &self.mesh_queue {
+            for gl_id in [mesh.material.tex_alb as u32, mesh.material.tex_mtl_rgh as u32, mesh.material.tex_hgt as u32] {
+                if self.streamed_textures.contains_key(&gl_id) {
+                    self.texture_last_used.insert(gl_id, self.frame_index);
+                }
+            }
+        }
+        self.line_queue.clear();
+        self.view_rendered = false;
+
+        self.resolve_msaa();
+        self.build_hi_z();
+        self.apply_ssao();
+        // Moving meshes draw their motion from the queue, it's dropped after
+        self.compute_motion_vectors();
+        self.mesh_queue.clear();
+        self.apply_taa();
+        self.apply_motion_blur();
+        self.apply_exposure();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
+        }
+        self.run_pass_hooks(PassPoint::BeforePostFx, self.framebuffer_object, self.post_viewport());
+
+        // Keep a copy of what's about to be presented
+        if let Some(history) = &mut self.frame_history {
+            self.frame_graph.begin_pass("frame_history", &["scene_colour"], &["frame_history"]);
+            history.capture(self.framebuffer_object, self.post_resolution, self.frame_history_downscale, self.frame_index, self.time);
+            self.frame_graph.end_pass();
+        } else {
+            self.frame_graph.skip_pass("frame_history", &["scene_colour"], &["frame_history"], "frame history disabled");
+        }
+
+		// Render to window buffer
+		self.frame_graph.begin_pass("present_blit", &["scene_colour"], &["window"]);
+		self.present_blit();
+		self.frame_graph.end_pass();
+        if self.id_view == IdView::Off {
+            self.frame_graph.skip_pass("id_view", &["ids"], &["window"], "ID view off");
+        } else {
+            self.frame_graph.begin_pass("id_view", &["ids"], &["window"]);
+            self.present_id_view();
+            self.frame_graph.end_pass();
+        }
+        self.update_gpu_picks();
+        let screen_size = Vec2::new(self.window_resolution_prev[0] as f32, self.window_resolution_prev[1] as f32);
+        if self.text_overlay.is_empty() {
+            self.frame_graph.skip_pass("text_overlay", &[], &["window"], "no text queued");
+        } else {
+            self.frame_graph.begin_pass("text_overlay", &[], &["window"]);
+            self.text_overlay.draw(self.text_shader, screen_size);
+            self.frame_graph.end_pass();
+        }
+        self.run_pass_hooks(PassPoint::AfterPresentBlit, self.output_framebuffer(), self.window_viewport());
+
+        // Any errors left at this point came from this frame
+        #[cfg(debug_assertions)]
+        unsafe {
+            loop {
+                let error = gl::GetError();
+                if error == gl::NO_ERROR {
+                    break;
+                }
+                error!("OpenGL error 0x{:X} during frame {}", error, self.frame_index);
+            }
+        }
+
+        // Keep this frame's passes around for dump_frame_graph, the older graph gets reused for the next frame
+        std::mem::swap(&mut self.frame_graph, &mut self.last_frame_graph);
+        self.culling_stats = std::mem::take(&mut self.frame_culling_stats);
+        std::mem::swap(&mut self.occlusion_visible, &mut self.occlusion_visible_last);
+        self.occlusion_visible.clear();
+
+        // Swap front and back buffers, unless the host presents the external target
+        if self.external_target.is_none() {
+            self.window.swap_buffers();
+        }
+        self.update_title_stats();
+        self.stream_textures();
+        self.poll_hot_reload();
+        self.frame_index += 1;
+    }
+
+    // Builds the depth pyramid that the next frames get occlusion culled against
+    fn build_hi_z(&mut self) {
+        let Some(hi_z) = &mut self.hi_z else {
+            self.frame_graph.skip_pass("hi_z", &["scene_depth"], &["hi_z"], "occlusion culling disabled");
+            return;
+        };
+        self.frame_graph.begin_pass("hi_z", &["scene_depth"], &["hi_z"]);
+        hi_z.build(
+            self.hi_z_shader,
+            self.depth_buffer_texture.id(),
+            self.render_resolution,
+            self.const_buffer_cpu.view_projection_matrix,
+            self.projection.reversed_z,
+        );
+        self.frame_graph.end_pass();
+    }
+
+    // Encodes the linear scene colour into the window's framebuffer, or the external target. Only this draw writes
+    // through GL_FRAMEBUFFER_SRGB, everything before it stays linear and the text overlay after it is already display
+    // referred
+    fn present_blit(&self) {
 		unsafe {
-			gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+			gl::BindFramebuffer(gl::FRAMEBUFFER, self.output_framebuffer());
 			gl::Viewport(0, 0, self.window_resolution_prev[0], self.window_resolution_prev[1]);
             gl::Disable(gl::DEPTH_TEST);
             gl::Disable(gl::CULL_FACE);
-			gl::UseProgram(self.fbo_shader);
-			gl::BindTexture(gl::TEXTURE_2D, self.framebuffer_texture);
-			gl::BindVertexArray(self.quad_vao);
-			gl::DrawArrays(gl::TRIANGLES, 0, 6);
-			gl::BindTexture(gl::TEXTURE_2D, 0);
-		}
+			gl::UseProgram(self.fbo_shader);
+			gl::Uniform1i(gl::GetUniformLocation(self.fbo_shader, c"u_dither".as_ptr()), self.dithering as i32);
+			let anaglyph = self.stereo.enabled && self.stereo.output == StereoOutput::Anaglyph;
+			gl::Uniform1i(gl::GetUniformLocation(self.fbo_shader, c"u_anaglyph".as_ptr()), anaglyph as i32);
+			gl::Uniform1i(gl::GetUniformLocation(self.fbo_shader, c"u_output_encoding".as_ptr()), self.output_encoding() as i32);
+			gl::Uniform1f(gl::GetUniformLocation(self.fbo_shader, c"u_paper_white_nits".as_ptr()), self.hdr_paper_white_nits);
+			gl::Uniform1f(gl::GetUniformLocation(self.fbo_shader, c"u_peak_nits".as_ptr()), self.hdr_peak_nits);
+			gl::Uniform2f(
+				gl::GetUniformLocation(self.fbo_shader, c"u_uv_scale".as_ptr()),
+				self.post_resolution[0] as f32 / self.window_resolution_prev[0] as f32,
+				self.post_resolution[1] as f32 / self.window_resolution_prev[1] as f32,
+			);
+			self.framebuffer_texture.bind(TextureSlot::Albedo);
+			if self.output_encoding() == OutputEncoding::SrgbFramebuffer {
+				gl::Enable(gl::FRAMEBUFFER_SRGB);
+			}
+			gl::BindVertexArray(self.quad_vao);
+			gl::DrawArrays(gl::TRIANGLES, 0, 6);
+			gl::Disable(gl::FRAMEBUFFER_SRGB);
+			TextureBinder::bind(TextureSlot::Albedo, 0);
+		}
+    }
+
+    // Presents a flat 50% grey (sRGB 128, linear 0.214) and reads the window's back buffer, to check the output
+    // encoding ends up where it should. Only SDR output has a known expected value. Call it outside of
+    // begin_frame/end_frame, it overwrites the render targets. The encoding of an external target is the host's word
+    pub fn check_output_encoding(&mut self) -> Result<(), String> {
+        if matches!(self.output_encoding, OutputEncoding::ScRgb | OutputEncoding::Pq) || !self.framebuffer_complete || self.external_target.is_some() {
+            return Ok(());
+        }
+        let grey = 0.2140;
+        let mut presented = [0u8; 4];
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
+            gl::ClearColor(grey, grey, grey, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            self.present_blit();
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            gl::ReadBuffer(gl::BACK);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(0, 0, 1, 1, gl::RGBA, gl::UNSIGNED_BYTE, presented.as_mut_ptr() as *mut c_void);
+        }
+        let expected = (linear_to_srgb(grey) * 255.0).round() as i32;
+        match presented[..3].iter().all(|&channel| (channel as i32 - expected).abs() <= 1) {
+            true => Ok(()),
+            false => Err(format!(
+                "Output encoding {:?} presented 50% grey as {:?}, expected {expected}",
+                self.output_encoding,
+                &presented[..3]
+            )),
+        }
+    }
+
+    // The external target's encoding while there is one, otherwise the window's
+    pub fn output_encoding(&self) -> OutputEncoding {
+        self.external_target.map_or(self.output_encoding, |target| target.encoding)
+    }
+
+    // Presents frames into an image of the host application instead of the window, or into the window again for
+    // None. The render targets follow its size, so hosts call this again with the new size when their image resizes.
+    // The window's size and minimizing don't matter while there is one. Call it outside of begin_frame/end_frame
+    pub fn set_render_target(&mut self, target: Option<ExternalTarget>) -> Result<(), String> {
+        if let Some(target) = target {
+            if target.width <= 0 || target.height <= 0 {
+                return Err(format!("The render target has to be at least 1x1 pixels, got {}x{}", target.width, target.height));
+            }
+        }
+        self.delete_external_framebuffer();
+        self.external_target = None;
+        let framebuffer = match target.map(|target| target.image) {
+            Some(ExternalImage::Texture(texture)) => unsafe {
+                gl::GenFramebuffers(1, &mut self.external_framebuffer_object);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.external_framebuffer_object);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                Some(self.external_framebuffer_object)
+            },
+            Some(ExternalImage::Framebuffer(framebuffer)) => Some(framebuffer),
+            None => None,
+        };
+        if let Some(Err(error)) = framebuffer.map(check_framebuffer_status) {
+            self.delete_external_framebuffer();
+            return Err(format!("Can't render into the render target: {error}"));
+        }
+        self.external_target = target;
+        self.record_change(ChangeOperation::Setting("render_target"), 0, 0);
+        Ok(())
+    }
+
+    pub fn render_target(&self) -> Option<ExternalTarget> {
+        self.external_target
+    }
+
+    // Size of the window's framebuffer, for hosts that size their external target after it
+    pub fn window_size(&self) -> [i32; 2] {
+        let (width, height) = self.window.get_framebuffer_size();
+        [width, height]
+    }
+
+    // Swaps the window's buffers. end_frame does that by itself without an external target, hosts that present
+    // their target through this renderer's window call it after drawing
+    pub fn swap_window_buffers(&mut self) {
+        self.window.swap_buffers();
+    }
+
+    fn delete_external_framebuffer(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.external_framebuffer_object);
+        }
+        self.external_framebuffer_object = 0;
+    }
+
+    // What frames get presented into, the window's framebuffer or the external target's
+    fn output_framebuffer(&self) -> u32 {
+        match self.external_target.map(|target| target.image) {
+            Some(ExternalImage::Framebuffer(framebuffer)) => framebuffer,
+            Some(ExternalImage::Texture(_)) => self.external_framebuffer_object,
+            None => 0,
+        }
+    }
+
+    // Size of what frames get presented into. Every render target is sized after it
+    fn output_size(&self) -> (i32, i32) {
+        match self.external_target {
+            Some(target) => (target.width, target.height),
+            None => self.window.get_framebuffer_size(),
+        }
+    }
+
+    // What the OpenGL context supports, features it lacks are turned off
+    pub fn capabilities(&self) -> &GpuCapabilities {
+        &self.capabilities
+    }
+
+    // Brightness of the scene's 1.0 and the display's peak brightness, only used by the HDR output encodings
+    pub fn set_hdr_nits(&mut self, paper_white: f32, peak: f32) {
+        self.hdr_paper_white_nits = paper_white.max(1.0);
+        self.hdr_peak_nits = peak.max(self.hdr_paper_white_nits);
+    }
+
+    // Resolves the multisampled render targets into the regular framebuffer
+    fn resolve_msaa(&mut self) {
+        let (resolve_reads, resolve_writes): (&[&str], &[&str]) = (&["msaa_colour", "msaa_depth"], &["scene_colour", "scene_depth"]);
+        if self.msaa_samples > 0 {
+            self.frame_graph.begin_pass("msaa_resolve", resolve_reads, resolve_writes);
+            unsafe {
+                let (width, height) = (self.render_resolution[0], self.render_resolution[1]);
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.msaa_framebuffer_object);
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.framebuffer_object);
+                gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+            }
+            self.frame_graph.end_pass();
+        } else {
+            self.frame_graph.skip_pass("msaa_resolve", resolve_reads, resolve_writes, "MSAA disabled");
+        }
+    }
+
+    // Brute-force reference render of the queued meshes: renders the camera factor^2 times at the current render
+    // resolution, each time offset by a different sub-pixel amount, and averages the results. TAA and temporal
+    // upscaling are off for the sub-frames, SSAO and MSAA apply as usual. The offsets are stratified over the pixel and randomized from `seed`, so
+    // the same seed gives the same image. Use it between begin_frame and end_frame, after queueing the models
+    pub fn render_supersampled(&mut self, camera: &Camera, factor: u32, seed: u32) -> Result<Image, String> {
+        if self.frame_skipped || !self.framebuffer_complete {
+            return Err("Nothing can be rendered right now, the window is minimized or the framebuffer is incomplete".to_string());
+        }
+        profile_scope!("render_supersampled");
+        let factor = factor.max(1);
+        let (width, height) = (self.render_resolution[0], self.render_resolution[1]);
+        let mut accumulated = vec![Vec4::ZERO; (width * height) as usize];
+        let mut sub_frame = vec![Vec4::ZERO; (width * height) as usize];
+
+        let (taa_enabled, upscale_mode) = (self.taa_enabled, self.upscale.mode);
+        self.taa_enabled = false;
+        if upscale_mode == UpscaleMode::Temporal {
+            self.upscale.mode = UpscaleMode::Bilinear;
+        }
+        let view_matrix = camera.transform.view_matrix(self.world_up);
+        for y in 0..factor {
+            for x in 0..factor {
+                // The seed takes the place of the frame, so the offsets don't depend on anything but it
+                let jitter = Vec2::new(random::uniform(x, y, seed, 0), random::uniform(x, y, seed, 1));
+                self.supersample_offset = (Vec2::new(x as f32, y as f32) + jitter) / factor as f32 - 0.5;
+                self.clear_render_targets();
+                self.render_raster_view(view_matrix, self.full_viewport());
+                self.resolve_msaa();
+                self.apply_ssao();
+                unsafe {
+                    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer_object);
+                    gl::ReadPixels(0, 0, width, height, gl::RGBA, gl::FLOAT, sub_frame.as_mut_ptr() as *mut c_void);
+                    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+                }
+                for (sum, sample) in accumulated.iter_mut().zip(&sub_frame) {
+                    *sum += *sample;
+                }
+            }
+        }
+        self.supersample_offset = Vec2::ZERO;
+        self.taa_enabled = taa_enabled;
+        self.upscale.mode = upscale_mode;
+
+        // Leave the targets as begin_frame did, for the regular frame
+        self.clear_render_targets();
+
+        let sample_count = (factor * factor) as f32;
+        Ok(Image {
+            width: width as usize,
+            height: height as usize,
+            pixels: accumulated.into_iter().map(|sum| sum / sample_count).collect(),
+        })
+    }
+
+    // Queues text for this frame, drawn on top of everything after post-processing. `x` and `y` are the top left
+    // corner in pixels from the top left of the window, and each glyph is 8 * `scale` pixels in size
+    pub fn draw_text_2d(&mut self, x: f32, y: f32, scale: f32, colour: Vec4, text: &str) {
+        let window_resolution = self.output_size();
+        let screen_size = Vec2::new(window_resolution.0 as f32, window_resolution.1 as f32);
+        self.text_overlay.queue(Vec2::new(x, y), scale, colour, text, screen_size);
+    }
+
+    // Like draw_text_2d, but anchored to a point in the world as seen from the camera. Hidden when the point is
+    // behind the camera
+    pub fn draw_text_3d(&mut self, world_position: Vec3, scale: f32, colour: Vec4, text: &str) {
+        let clip = self.projection_matrix * self.camera_view_matrix * world_position.extend(1.0);
+        if clip.w <= 0.0 {
+            return;
+        }
+        let window_resolution = self.output_size();
+        let ndc = clip.truncate() / clip.w;
+        let x = (ndc.x * 0.5 + 0.5) * window_resolution.0 as f32;
+        let y = (0.5 - ndc.y * 0.5) * window_resolution.1 as f32;
+        self.draw_text_2d(x, y, scale, colour, text);
+    }
+
+    // Writes the last rendered frame to a binary PPM file, at the window's resolution when upscaled temporally
+    pub fn capture_frame(&self, path: &Path) -> Result<(), String> {
+        let (width, height) = (self.post_resolution[0], self.post_resolution[1]);
+        let mut pixels = vec![Vec4::ZERO; (width * height) as usize];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer_object);
+            gl::ReadPixels(0, 0, width, height, gl::RGBA, gl::FLOAT, pixels.as_mut_ptr() as *mut c_void);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        Image {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        }
+        .write_ppm(path)
+    }
+
+    // Starts or stops recording profile_scope! timings
+    pub fn set_profiling(&mut self, enabled: bool) {
+        profiler::set_enabled(enabled);
+    }
+
+    // Writes the recorded timings as a chrome://tracing / Perfetto JSON file
+    pub fn dump_trace(&self, path: &Path) -> Result<(), String> {
+        profiler::dump_trace(path)
+    }
+
+    // Seconds the renderer clock has been running, not counting time spent paused
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    // False while another window has focus, apps can use this to pause their simulation
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    // Seconds between the start of this frame and the previous one, 0 while paused
+    pub fn delta_time(&self) -> f32 {
+        self.delta_time
+    }
+
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    pub fn set_time_paused(&mut self, paused: bool) {
+        self.time_paused = paused;
+    }
+
+    // Keeps the last `frames` presented frames around for dump_frame_history, None turns it off
+    pub fn set_frame_history(&mut self, frames: Option<usize>) {
+        if let Some(mut history) = self.frame_history.take() {
+            history.delete();
+        }
+        self.frame_history = frames.map(FrameHistory::new);
+    }
+
+    // Frames in the history are stored at 1/factor of the render resolution
+    pub fn set_frame_history_downscale(&mut self, factor: i32) {
+        self.frame_history_downscale = factor.max(1);
+    }
+
+    // Writes the frame history to a directory, returns the number of frames written
+    pub fn dump_frame_history(&self, directory: &Path) -> Result<usize, String> {
+        match &self.frame_history {
+            Some(history) => history.dump(directory),
+            None => Err("Frame history is not enabled".to_string()),
+        }
+    }
+
+    pub fn frame_history_memory(&self) -> usize {
+        self.frame_history.as_ref().map_or(0, |history| history.memory_bytes())
+    }
+
+    // Textures loaded or hot reloaded from now on get scaled down to fit, the ones already loaded keep their size
+    pub fn set_texture_quality(&mut self, quality: TextureQuality) {
+        self.texture_quality = quality;
+        self.record_change(ChangeOperation::Setting("texture_quality"), 0, 0);
+    }
+
+    pub fn texture_quality(&self) -> TextureQuality {
+        self.texture_quality
+    }
+
+    pub fn set_texture_streaming(&mut self, config: TextureStreamingConfig) {
+        self.texture_streaming = config;
+    }
+
+    fn stream_textures(&mut self) {
+        profile_scope!("stream_textures");
+        if self.streamed_textures.is_empty() {
+            return;
+        }
+
+        // Textures drawn most recently go first, textures that were never drawn go last
+        let mut candidates: Vec<(u32, Option<u64>)> = self
+            .streamed_textures
+            .keys()
+            .map(|gl_id| (*gl_id, self.texture_last_used.get(gl_id).copied()))
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        // Upload full resolution textures until the budget runs out, but always at least one so big textures can't starve
+        let mut bytes_uploaded = 0;
+        for (gl_id, _) in candidates {
+            let size = {
+                let texture = &self.streamed_textures[&gl_id];
+                texture.width * texture.height * 4
+            };
+            if bytes_uploaded > 0 && bytes_uploaded + size > self.texture_streaming.bytes_per_frame {
+                break;
+            }
+            let texture = self.streamed_textures.remove(&gl_id).unwrap();
+            self.textures.update(gl_id, &texture);
+            self.texture_last_used.remove(&gl_id);
+            self.record_change(ChangeOperation::TextureStream, gl_id as u64, size);
+            bytes_uploaded += size;
+            trace!("Streamed in texture {gl_id} at {}x{}", texture.width, texture.height);
+        }
+    }
+
+    // Searches `root` for models, textures and shaders after the default roots and the roots added earlier, and before
+    // the environment variable's roots. The built-in shaders are loaded by new, so added roots don't apply to those
+    pub fn add_asset_root(&mut self, root: impl Into<PathBuf>) {
+        self.assets.add_root(root);
+    }
+
+    // Where a relative asset path points to, or an error listing every root that was searched
+    pub fn resolve_asset(&self, path: &Path) -> Result<PathBuf, String> {
+        self.assets.resolve(path)
+    }
+
+    // Watches the files every loaded model came from, and reloads models whose files changed
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        self.hot_reload = enabled;
+        if enabled {
+            // Files changed while not watching don't count
+            for model in self.models.values_mut() {
+                for file in &mut model.source_files {
+                    file.modified = modified_time(&file.path);
+                }
+            }
+        }
+    }
+
+    // Changed images get decoded and re-uploaded into their existing textures. Any other changed file reloads the
+    // whole model under the same handle, keeping the old model if that fails
+    fn poll_hot_reload(&mut self) {
+        let time = self.glfw.get_time();
+        if !self.hot_reload || time - self.hot_reload_last_poll < HOT_RELOAD_POLL_INTERVAL {
+            return;
+        }
+        self.hot_reload_last_poll = time;
+        profile_scope!("poll_hot_reload");
+
+        let mut changed_images = Vec::new();
+        let mut changed_models = Vec::new();
+        for (handle, model) in &mut self.models {
+            let mut images = Vec::new();
+            let mut model_changed = false;
+            for file in &mut model.source_files {
+                let modified = modified_time(&file.path);
+                if modified == file.modified {
+                    continue;
+                }
+                file.modified = modified;
+                if file.textures.is_empty() {
+                    model_changed = true;
+                } else {
+                    images.push((file.path.clone(), file.textures.clone()));
+                }
+            }
+            if model_changed {
+                changed_models.push(*handle);
+            } else {
+                changed_images.extend(images);
+            }
+        }
+
+        for (path, textures) in changed_images {
+            match Texture::load(&path, self.texture_quality.max_dimension as usize) {
+                Ok(texture) => {
+                    for gl_id in textures {
+                        // A full resolution upload still waiting to be streamed in would undo the reload
+                        self.streamed_textures.remove(&gl_id);
+                        self.textures.update(gl_id, &texture);
+                        self.record_change(ChangeOperation::TextureReload, gl_id as u64, texture.data.len() * size_of::<u32>());
+                    }
+                    info!("Reloaded texture \"{}\"", path.display());
+                }
+                Err(error) => warn!("Failed to reload texture \"{}\", keeping the old one: {error}", path.display()),
+            }
+        }
+        for handle in changed_models {
+            self.reload_model(handle);
+        }
+
+        let changed_shaders: Vec<u32> = self
+            .custom_shaders
+            .iter_mut()
+            .filter_map(|(handle, shader)| {
+                let modified = shader.files.clone().map(|file| modified_time(&file));
+                let changed = modified != shader.modified;
+                shader.modified = modified;
+                changed.then_some(*handle)
+            })
+            .collect();
+        for handle in changed_shaders {
+            self.rebuild_custom_shader(handle);
+        }
+    }
+
+    fn reload_model(&mut self, handle: u64) {
+        let Some((path, options)) = self.model_load_args.get(&handle).cloned() else {
+            return;
+        };
+        let Some(old_model) = self.models.remove(&handle) else {
+            return;
+        };
+        if self.load_model_with_options(&path, &options).is_err() {
+            warn!("Failed to reload \"{}\", keeping the old version", path.display());
+            self.models.insert(handle, old_model);
+            return;
+        }
+        self.record_change(ChangeOperation::ModelReload, handle, 0);
+
+        // Keep the reassigned materials of meshes that still exist
+        if let Some(model) = self.models.get_mut(&handle) {
+            let mut overrides = old_model.material_overrides.clone();
+            overrides.retain(|mesh, _| model.meshes.contains_key(mesh));
+            model.material_overrides = overrides;
+        }
+
+        self.free_model(old_model);
+        info!("Reloaded \"{}\"", path.display());
+    }
+
+    // Unloads a model, along with the textures nothing else uses. Its handle is invalid afterwards
+    pub fn unload_model(&mut self, handle: u64) -> bool {
+        let Some(model) = self.models.remove(&handle) else {
+            return false;
+        };
+        // Already queued meshes would draw the deleted vertex arrays
+        self.mesh_queue.retain(|entry| entry.sort_key.model_id != handle);
+        self.model_load_args.remove(&handle);
+        self.model_tags.remove(&handle);
+        self.model_shaders.remove(&handle);
+        self.record_change(ChangeOperation::ModelUnload, handle, model.vertex_bytes());
+        self.free_model(model);
+        true
+    }
+
+    // Deletes the GL objects of a model that was taken out of the model map, its vertex buffers go with it
+    fn free_model(&mut self, old_model: Model) {
+        for mesh in old_model.meshes.values() {
+            for lod in std::iter::once(mesh).chain(&mesh.lods) {
+                unsafe {
+                    gl::DeleteVertexArrays(1, &lod.vao);
+                }
+            }
+        }
+        // Shared textures stay alive while a new version of the model, or anything else, still uses them
+        let in_use: HashSet<i32> = self
+            .models
+            .values()
+            .flat_map(|model| model.materials.values())
+            .chain(self.material_library.values())
+            .flat_map(|material| [material.tex_alb, material.tex_nrm, material.tex_mtl_rgh, material.tex_emm, material.tex_hgt])
+            .collect();
+        for material in old_model.materials.values() {
+            for texture in [material.tex_alb, material.tex_nrm, material.tex_mtl_rgh, material.tex_emm, material.tex_hgt] {
+                if texture <= 0 || texture as u32 == self.placeholder_texture || texture as u32 == self.white_texture || in_use.contains(&texture) {
+                    continue;
+                }
+                let texture = texture as u32;
+                self.shared_textures.retain(|_, (gl_id, _)| *gl_id != texture);
+                self.streamed_textures.remove(&texture);
+                self.texture_last_used.remove(&texture);
+                self.textures.delete(texture);
+            }
+        }
+    }
+
+    // Renders the current queues from a camera into a sub-rectangle of the framebuffer, use between begin_frame and end_frame
+    pub fn render_view(&mut self, camera: &Camera, viewport: Rect) {
+        if !self.framebuffer_complete || self.frame_skipped {
+            return;
+        }
+        // Viewports are given at window resolution, scale them down to the part of the framebuffer that gets rendered
+        let scale = self.render_scale;
+        let viewport = Rect {
+            x: (viewport.x as f32 * scale).round() as i32,
+            y: (viewport.y as f32 * scale).round() as i32,
+            width: ((viewport.width as f32 * scale).round() as i32).max(1),
+            height: ((viewport.height as f32 * scale).round() as i32).max(1),
+        };
+        self.render_raster_view(camera.transform.view_matrix(self.world_up), viewport);
+        self.view_rendered = true;
+    }
+
+    // The rendered part of the offscreen framebuffer
+    fn full_viewport(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.render_resolution[0],
+            height: self.render_resolution[1],
+        }
+    }
+
+    // The part of the offscreen framebuffer holding the scene colour once TAA has run
+    fn post_viewport(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.post_resolution[0],
+            height: self.post_resolution[1],
+        }
+    }
+
+    fn window_viewport(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.window_resolution_prev[0],
+            height: self.window_resolution_prev[1],
+        }
+    }
+
+    // Size of what gets drawn into a framebuffer, the window or external target for the output framebuffer and the
+    // scene colour's resolution for the others
+    fn target_resolution(&self, framebuffer: u32) -> [i32; 2] {
+        if framebuffer == self.output_framebuffer() {
+            self.window_resolution_prev
+        } else {
+            self.post_resolution
+        }
+    }
+
+    fn apply_viewport(viewport: Rect) {
+        unsafe {
+            gl::Viewport(viewport.x, viewport.y, viewport.width, viewport.height);
+            gl::Scissor(viewport.x, viewport.y, viewport.width, viewport.height);
+            gl::Enable(gl::SCISSOR_TEST);
+        }
+    }
+
+    fn render_raster_view(&mut self, view_matrix: Mat4, viewport: Rect) {
+        profile_scope!("raster_view");
+        // Set up the view
+        self.update_const_buffer(view_matrix, viewport);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.raster_framebuffer_object());
+        }
+        Self::apply_viewport(viewport);
+
+        // Set up the opaque pass
+        if self.run_pass_hooks(PassPoint::BeforeOpaque, self.raster_framebuffer_object(), viewport) {
+            Self::apply_viewport(viewport);
+        }
+        self.bind_opaque_state();
+
+        // Render mesh queue in a fixed order. Occlusion culling only knows the camera's own view
+        let hi_z = self.hi_z.as_ref().filter(|_| view_matrix == self.camera_view_matrix);
+        self.frame_graph.begin_pass("opaque", &["const_buffer", "material_textures", "hi_z"], self.raster_targets());
+        self.mesh_queue.sort_by_key(|mesh| mesh.sort_key);
+        let mut alpha_to_coverage = false;
+        let mut program = self.triangle_shader;
+        for mesh in &self.mesh_queue {
+            self.frame_culling_stats.submitted += 1;
+            if mesh.is_transparent() {
+                continue;
+            }
+            if let Some(hi_z) = hi_z {
+                // Meshes visible last frame are always drawn, so stale depth can't make them pop out for a frame
+                let key = (mesh.sort_key.model_id, mesh.sort_key.material_hash);
+                if hi_z.is_occluded(&mesh.bounds) {
+                    if !self.occlusion_visible_last.contains(&key) {
+                        self.frame_culling_stats.occlusion_culled += 1;
+                        continue;
+                    }
+                } else {
+                    self.occlusion_visible.insert(key);
+                }
+            }
+            // Masked materials and programs are grouped by the sort key, so these only switch at the group's edges
+            if mesh.program != program {
+                program = mesh.program;
+                Self::use_lit_program(program, alpha_to_coverage);
+            }
+            let coverage = mesh.sort_key.alpha_mask && self.msaa_samples > 0;
+            if coverage != alpha_to_coverage {
+                Self::set_alpha_to_coverage(program, coverage);
+                alpha_to_coverage = coverage;
+            }
+            self.draw_queue_entry(mesh, mesh.program);
+            self.frame_culling_stats.drawn += 1;
+        }
+        if alpha_to_coverage {
+            Self::set_alpha_to_coverage(program, false);
+        }
+        self.frame_graph.end_pass();
+        if self.sky.enabled {
+            self.draw_sky(view_matrix);
+            self.bind_opaque_state();
+        } else {
+            self.frame_graph.skip_pass("sky", &[], self.raster_targets(), "sky disabled");
+        }
+        if self.run_pass_hooks(PassPoint::AfterOpaque, self.raster_framebuffer_object(), viewport) {
+            Self::apply_viewport(viewport);
+            self.bind_opaque_state();
+        }
+
+        // Decals only land on opaque surfaces, the transparent ones get blended over them
+        if self.decals.is_empty() {
+            self.frame_graph.skip_pass("decals", &[], self.raster_targets(), "no decals");
+        } else {
+            self.draw_decals(viewport);
+            self.bind_opaque_state();
+        }
+
+        if self.water_planes.is_empty() {
+            self.frame_graph.skip_pass("water", &[], self.raster_targets(), "no water planes");
+        } else {
+            self.draw_water(viewport);
+            self.bind_opaque_state();
+        }
+
+        if self.line_queue.is_empty() {
+            self.frame_graph.skip_pass("lines", &[], self.raster_targets(), "no line batches queued");
+        } else {
+            self.draw_line_batches();
+            self.bind_opaque_state();
+        }
+
+        // Blend the transparent meshes over the scene, furthest first, without writing depth
+        let camera_position = view_matrix.inverse().w_axis.truncate();
+        let mut transparent: Vec<&MeshQueueEntry> = self.mesh_queue.iter().filter(|mesh| mesh.is_transparent()).collect();
+        if transparent.is_empty() {
+            self.frame_graph.skip_pass("transparent", &[], self.raster_targets(), "no transparent meshes");
+        } else {
+            self.frame_graph.begin_pass("transparent", &["const_buffer", "material_textures"], self.raster_targets());
+            transparent.sort_by_cached_key(|mesh| transparent_sort_key(mesh, camera_position, self.transparent_sort_epsilon));
+            let transparent_count = transparent.len();
+            unsafe {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                gl::DepthMask(gl::FALSE);
+            }
+            // Sorted by distance, so the program can change from one mesh to the next
+            let mut program = self.triangle_shader;
+            for mesh in transparent {
+                if mesh.program != program {
+                    program = mesh.program;
+                    Self::use_lit_program(program, false);
+                }
+                self.draw_queue_entry(mesh, mesh.program);
+            }
+            unsafe {
+                gl::UseProgram(self.triangle_shader);
+            }
+            self.frame_culling_stats.drawn += transparent_count;
+            unsafe {
+                gl::Disable(gl::BLEND);
+                gl::DepthMask(gl::TRUE);
+            }
+            self.frame_graph.end_pass();
+        }
+        self.run_pass_hooks(PassPoint::AfterTransparent, self.raster_framebuffer_object(), viewport);
+
+        if self.id_view == IdView::Off && self.gpu_picks.iter().all(|pick| pick.drawn) {
+            self.frame_graph.skip_pass("id_buffer", &[], &["ids"], "ID view off and no picks waiting");
+        } else {
+            self.draw_id_buffer(viewport);
+        }
+
+        // Restore the full viewport
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::Viewport(0, 0, self.render_resolution[0], self.render_resolution[1]);
+        }
+    }
+
+    // Switches to the lit shader or a custom shader in the middle of a pass, carrying the alpha-to-coverage state over
+    fn use_lit_program(program: u32, alpha_to_coverage: bool) {
+        unsafe {
+            gl::UseProgram(program);
+            gl::Uniform1i(gl::GetUniformLocation(program, c"u_alpha_to_coverage".as_ptr()), alpha_to_coverage as i32);
+        }
+    }
+
+    // Masked materials write their sharpened alpha as sample coverage instead of discarding, which MSAA can smooth.
+    // Alpha-to-one keeps the stored alpha opaque, the same as discarding would
+    fn set_alpha_to_coverage(lit_shader: u32, enabled: bool) {
+        unsafe {
+            if enabled {
+                gl::Enable(gl::SAMPLE_ALPHA_TO_COVERAGE);
+                gl::Enable(gl::SAMPLE_ALPHA_TO_ONE);
+            } else {
+                gl::Disable(gl::SAMPLE_ALPHA_TO_COVERAGE);
+                gl::Disable(gl::SAMPLE_ALPHA_TO_ONE);
+            }
+            gl::Uniform1i(gl::GetUniformLocation(lit_shader, c"u_alpha_to_coverage".as_ptr()), enabled as i32);
+        }
+    }
+
+    // Draws one queued mesh with a program that bind_opaque_state has set up, and the caller has bound. That's the
+    // mesh's own program, except for the ID buffer
+    fn draw_queue_entry(&self, mesh: &MeshQueueEntry, program: u32) {
+        unsafe {
+            // Bind the vertex buffer
+            gl::BindVertexArray(mesh.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vbo);
+
+            // Bind the textures
+            let albedo = if mesh.material.tex_alb < 0 { self.white_texture as i32 } else { mesh.material.tex_alb };
+            let parallax = self.parallax.enabled && mesh.material.tex_hgt >= 0;
+            let layers = [
+                self.textures.bind(TextureSlot::Albedo, albedo),
+                self.textures.bind(TextureSlot::MetallicRoughness, mesh.material.tex_mtl_rgh),
+                self.textures.bind(TextureSlot::Height, if parallax { mesh.material.tex_hgt } else { -1 }),
+            ];
+            gl::Uniform3i(gl::GetUniformLocation(program, c"u_texture_layers".as_ptr()), layers[0], layers[1], layers[2]);
+
+            // Set the material parameters
+            gl::Uniform1f(gl::GetUniformLocation(program, c"u_roughness".as_ptr()), mesh.material.scl_rgh);
+            gl::Uniform1f(gl::GetUniformLocation(program, c"u_metallic".as_ptr()), mesh.material.scl_mtl);
+            gl::Uniform1i(
+                gl::GetUniformLocation(program, c"u_has_mtl_rgh_texture".as_ptr()),
+                (mesh.material.tex_mtl_rgh >= 0) as i32,
+            );
+            gl::Uniform1i(gl::GetUniformLocation(program, c"u_has_height_texture".as_ptr()), parallax as i32);
+            let alpha_cutoff = match mesh.material.alpha_mode {
+                AlphaMode::Mask => mesh.material.alpha_cutoff,
+                _ => -1.0,
+            };
+            gl::Uniform1f(gl::GetUniformLocation(program, c"u_alpha_cutoff".as_ptr()), alpha_cutoff);
+            if parallax {
+                gl::Uniform1f(gl::GetUniformLocation(program, c"u_height_scale".as_ptr()), mesh.material.scl_hgt);
+                gl::Uniform1f(gl::GetUniformLocation(program, c"u_height_bias".as_ptr()), mesh.material.bias_hgt);
+            }
+            let tint = match self.lod_settings.debug_view {
+                true => LOD_DEBUG_COLOURS[mesh.lod_level.min(LOD_DEBUG_COLOURS.len() - 1)],
+                false => [1.0; 3],
+            };
+            gl::Uniform3fv(gl::GetUniformLocation(program, c"u_debug_tint".as_ptr()), 1, tint.as_ptr());
+            gl::Uniform4fv(gl::GetUniformLocation(program, c"u_tint".as_ptr()), 1, mesh.tint.as_ref().as_ptr());
+            let custom = &mesh.material.custom;
+            for (name, value) in [c"u_custom0", c"u_custom1", c"u_custom2", c"u_custom3"].iter().zip(custom.scalars) {
+                gl::Uniform1f(gl::GetUniformLocation(program, name.as_ptr()), value);
+            }
+            for (name, value) in [c"u_customVec0", c"u_customVec1"].iter().zip(&custom.vectors) {
+                gl::Uniform4fv(gl::GetUniformLocation(program, name.as_ptr()), 1, value.as_ref().as_ptr());
+            }
+
+            // Foliage sways relative to its height, queue_model only widened the bounds, so their heights still hold
+            let wind_amplitude = if self.wind.enabled { mesh.material.scl_wind } else { 0.0 };
+            gl::Uniform1f(gl::GetUniformLocation(program, c"u_wind_amplitude".as_ptr()), wind_amplitude);
+            if wind_amplitude > 0.0 {
+                gl::Uniform1f(gl::GetUniformLocation(program, c"u_wind_frequency".as_ptr()), mesh.material.frq_wind);
+                gl::Uniform2f(
+                    gl::GetUniformLocation(program, c"u_wind_height_range".as_ptr()),
+                    mesh.bounds.min.dot(self.world_up.up()),
+                    mesh.bounds.max.dot(self.world_up.up()),
+                );
+            }
+
+            // Set the submission's transform
+            let normal_matrix = Mat3::from_mat4(mesh.model_matrix).inverse().transpose();
+            gl::UniformMatrix4fv(gl::GetUniformLocation(program, c"u_model_matrix".as_ptr()), 1, gl::FALSE, mesh.model_matrix.as_ref().as_ptr());
+            gl::UniformMatrix3fv(gl::GetUniformLocation(program, c"u_normal_matrix".as_ptr()), 1, gl::FALSE, normal_matrix.as_ref().as_ptr());
+
+            // Draw the model
+            gl::DrawArrays(gl::TRIANGLES, 0, mesh.n_vertices);
+        }
+    }
+
+    pub fn add_pass_hook(&mut self, point: PassPoint, hook: PassHook) {
+        self.pass_hooks.push((point, hook));
+    }
+
+    // Returns true if a hook changed GL state, in which case the common state has already been reset
+    fn run_pass_hooks(&mut self, point: PassPoint, framebuffer: u32, viewport: Rect) -> bool {
+        let writes: &[&str] = if framebuffer == self.output_framebuffer() { &["window"] } else { self.raster_targets() };
+        if !self.pass_hooks.iter().any(|(hook_point, _)| *hook_point == point) {
+            self.frame_graph.skip_pass(point.pass_name(), &[], writes, "no hooks registered");
+            return false;
+        }
+        self.frame_graph.begin_pass(point.pass_name(), &[], writes);
+        let mut context = PassContext {
+            framebuffer,
+            resolution: self.target_resolution(framebuffer),
+            viewport,
+            view_projection_matrix: self.const_buffer_cpu.view_projection_matrix,
+            delta_time: self.delta_time,
+            state_dirty: false,
+        };
+        for (hook_point, hook) in &mut self.pass_hooks {
+            if *hook_point == point {
+                hook(&mut context);
+            }
+        }
+        self.frame_graph.end_pass();
+        if context.state_dirty {
+            self.reset_gl_state(framebuffer);
+        }
+        context.state_dirty
+    }
+
+    fn reset_gl_state(&self, framebuffer: u32) {
+        let resolution = self.target_resolution(framebuffer);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::Viewport(0, 0, resolution[0], resolution[1]);
+            gl::Disable(gl::BLEND);
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::Disable(gl::STENCIL_TEST);
+            gl::DepthMask(gl::TRUE);
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindVertexArray(0);
+            gl::UseProgram(0);
+        }
+    }
+
+    fn bind_opaque_state(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.raster_framebuffer_object());
+            gl::Enable(gl::DEPTH_TEST);
+            gl::DepthFunc(self.depth_func());
+            gl::Enable(gl::CULL_FACE);
+
+            // Custom shaders get the same per-frame uniforms as the lit shader, which is left bound
+            let custom_programs = self.custom_shaders.values().map(|shader| shader.program).filter(|&program| program != 0);
+            for program in custom_programs.chain([self.id_shader, self.object_motion_shader, self.triangle_shader]) {
+                gl::UseProgram(program);
+                gl::Uniform1f(
+                    gl::GetUniformLocation(program, c"u_lod_bias".as_ptr()),
+                    self.texture_lod_bias(),
+                );
+                gl::Uniform2f(
+                    gl::GetUniformLocation(program, c"u_parallax_steps".as_ptr()),
+                    self.parallax.min_steps as f32,
+                    self.parallax.max_steps as f32,
+                );
+                let sun_direction = self.sky.sun_direction.normalize_or_zero();
+                gl::Uniform3fv(gl::GetUniformLocation(program, c"u_sun_direction".as_ptr()), 1, sun_direction.as_ref().as_ptr());
+                gl::Uniform3fv(gl::GetUniformLocation(program, c"u_sun_colour".as_ptr()), 1, self.sky.sun_colour(self.world_up).as_ref().as_ptr());
+                gl::Uniform3fv(gl::GetUniformLocation(program, c"u_wind_direction".as_ptr()), 1, self.wind.direction.as_ref().as_ptr());
+                gl::Uniform1f(gl::GetUniformLocation(program, c"u_wind_strength".as_ptr()), self.wind.strength);
+                gl::Uniform1f(gl::GetUniformLocation(program, c"u_wind_gustiness".as_ptr()), self.wind.gustiness);
+                gl::Uniform3fv(gl::GetUniformLocation(program, c"u_world_up".as_ptr()), 1, self.world_up.up().as_ref().as_ptr());
+                gl::Uniform1i(gl::GetUniformLocation(program, c"u_alpha_to_coverage".as_ptr()), 0);
+            }
+
+            // Bind the constant buffer
+            self.const_buffer_gpu.bind_base(0);
+        }
+    }
+
+    pub fn set_window_icon(&mut self, image: &Texture) {
+        // Our texture data is already one RGBA8 pixel per u32, which is what GLFW wants
+        self.window.set_icon_from_pixels(vec![glfw::PixelImage {
+            width: image.width as u32,
+            height: image.height as u32,
+            pixels: image.data.clone(),
+        }]);
+    }
+
+    // Whether buffer swaps wait for the display's vertical blank, on by default on most drivers
+    pub fn set_vsync(&mut self, enabled: bool) {
+        self.glfw.set_swap_interval(match enabled {
+            true => glfw::SwapInterval::Sync(1),
+            false => glfw::SwapInterval::None,
+        });
+    }
+
+    pub fn set_title_stats(&mut self, enabled: bool) {
+        self.title_stats = enabled;
+        self.title_stats_frame_count = 0;
+        self.title_stats_last_update = self.glfw.get_time();
+        if !enabled {
+            self.window.set_title(&self.title);
+        }
+    }
+
+    fn update_title_stats(&mut self) {
+        if !self.title_stats {
+            return;
+        }
+
+        // Update the title about once per second, reusing the same string buffer
+        self.title_stats_frame_count += 1;
+        let time = self.glfw.get_time();
+        let elapsed = time - self.title_stats_last_update;
+        if elapsed >= 1.0 {
+            let fps = self.title_stats_frame_count as f64 / elapsed;
+            self.title_stats_buffer.clear();
+            write!(self.title_stats_buffer, "{} - {:.0} FPS (Rasterized)", self.title, fps).unwrap();
+            self.window.set_title(&self.title_stats_buffer);
+            self.title_stats_frame_count = 0;
+            self.title_stats_last_update = time;
+        }
+    }
+
+	fn update_framebuffer_resolution(&mut self) {
+		let window_resolution = self.output_size();
+		let window_resolution = [window_resolution.0, window_resolution.1];
+		if window_resolution != self.window_resolution_prev {
+			self.framebuffer_texture.set_format(self.framebuffer_format.internal_format(), gl::RGBA, gl::FLOAT);
+			self.framebuffer_texture.resize(window_resolution[0], window_resolution[1]);
+			let (depth_format, depth_type) = self.depth_format();
+			self.depth_buffer_texture.set_format(depth_format, gl::DEPTH_STENCIL, depth_type);
+			self.depth_buffer_texture.resize(window_resolution[0], window_resolution[1]);
+			unsafe {
+				gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
+			}
+			self.framebuffer_texture.attach(gl::COLOR_ATTACHMENT0);
+			self.depth_buffer_texture.attach(gl::DEPTH_STENCIL_ATTACHMENT);
+            self.framebuffer_complete = match check_framebuffer_status(self.framebuffer_object) {
+                Ok(()) => true,
+                Err(error) => {
+                    error!("Main framebuffer is incomplete: {error}");
+                    false
+                }
+            };
+
+            if self.msaa_samples > 0 {
+                self.create_msaa_targets(window_resolution[0], window_resolution[1]);
+            }
+            if self.ssao.enabled {
+                self.create_ssao_targets(window_resolution[0], window_resolution[1]);
+            }
+            if self.velocity_needed() {
+                self.create_taa_targets(window_resolution[0], window_resolution[1]);
+            }
+            // Recreated at the new size by the next pass that reads them
+            self.scene_depth_copy.delete();
+            self.scene_colour_copy.delete();
+            self.id_texture.delete();
+            self.id_depth.delete();
+		} else if self.msaa_samples > 0 && self.msaa_framebuffer_object == 0 {
+            // MSAA was turned on while there was nothing to render to
+            self.create_msaa_targets(window_resolution[0], window_resolution[1]);
+        }
+		self.window_resolution_prev = window_resolution;
+	}
+
+    // Skips meshes that were hidden behind other geometry in the depth buffer of a recent frame
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        if let (false, Some(mut hi_z)) = (enabled, self.hi_z.take()) {
+            hi_z.delete();
+        }
+        if enabled && !self.capabilities.compute_shaders {
+            warn!("Occlusion culling needs compute shaders, which this OpenGL context doesn't support");
+            return;
+        }
+        if enabled && self.hi_z.is_none() {
+            self.hi_z = Some(HiZBuffer::new());
+        }
+        self.occlusion_visible.clear();
+        self.occlusion_visible_last.clear();
+        self.record_change(ChangeOperation::Setting("occlusion_culling"), 0, 0);
+    }
+
+    pub fn occlusion_culling_enabled(&self) -> bool {
+        self.hi_z.is_some()
+    }
+
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
+
+    // Bucket size, in world units, of the distances transparent meshes are sorted by. Meshes in the same bucket are drawn
+    // in a fixed order instead, so surfaces at nearly the same distance don't swap places from frame to frame
+    pub fn set_transparent_sort_epsilon(&mut self, epsilon: f32) {
+        self.transparent_sort_epsilon = epsilon.max(0.0);
+        self.record_change(ChangeOperation::Setting("transparent_sort_epsilon"), 0, 0);
+    }
+
+    pub fn transparent_sort_epsilon(&self) -> f32 {
+        self.transparent_sort_epsilon
+    }
+
+    pub fn set_lod_settings(&mut self, settings: LodSettings) {
+        self.lod_settings = settings;
+        self.record_change(ChangeOperation::Setting("lod"), 0, 0);
+    }
+
+    pub fn lod_settings(&self) -> LodSettings {
+        self.lod_settings
+    }
+
+    pub fn set_parallax(&mut self, settings: ParallaxSettings) {
+        self.parallax = ParallaxSettings {
+            min_steps: settings.min_steps.max(1),
+            max_steps: settings.max_steps.max(settings.min_steps.max(1)),
+            ..settings
+        };
+        self.record_change(ChangeOperation::Setting("parallax"), 0, 0);
+    }
+
+    pub fn parallax(&self) -> ParallaxSettings {
+        self.parallax
+    }
+
+    pub fn set_texture_lod_bias(&mut self, bias: f32) {
+        self.texture_lod_bias = bias;
+        self.record_change(ChangeOperation::Setting("texture_lod_bias"), 0, 0);
+    }
+
+    fn texture_lod_bias(&self) -> f32 {
+        // Rendering below window resolution makes mips blurrier than needed, rendering above it makes them alias,
+        // so compensate for the ratio between the two, on top of the user offset
+        let render_height = self.render_resolution[1] as f32;
+        let window_height = self.window_resolution_prev[1] as f32;
+        if render_height <= 0.0 || window_height <= 0.0 {
+            return self.texture_lod_bias;
+        }
+        (render_height / window_height).log2() + self.texture_lod_bias
+    }
+
+    pub fn set_framebuffer_format(&mut self, format: FramebufferFormat) {
+        if format == self.framebuffer_format {
+            return;
+        }
+        if format != FramebufferFormat::Rgba8 && !self.capabilities.float_render_targets {
+            warn!("{format:?} render targets are not supported, keeping {:?}", self.framebuffer_format);
+            return;
+        }
+        self.framebuffer_format = format;
+        self.record_change(ChangeOperation::Setting("framebuffer_format"), 0, 0);
+
+        // Forces the render targets to be recreated at the start of the next frame
+        self.window_resolution_prev = [0, 0];
+    }
+
+    // Ordered dithering in the final blit, hides banding in dark gradients
+    pub fn set_dithering(&mut self, enabled: bool) {
+        self.dithering = enabled;
+        self.record_change(ChangeOperation::Setting("dithering"), 0, 0);
+    }
+
+    // Lowers the render resolution when frames take longer than the target, and raises it again when they're fast.
+    // Only the rendered part of the framebuffer changes, so adjusting the scale never reallocates anything
+    pub fn set_dynamic_resolution(&mut self, settings: DynamicResolution) {
+        self.dynamic_resolution = settings;
+        self.record_change(ChangeOperation::Setting("dynamic_resolution"), 0, 0);
+        self.frame_time_average = settings.target_frame_time;
+        self.render_scale_cooldown = 0;
+        if !settings.enabled {
+            self.render_scale = self.max_render_scale();
+        }
+    }
+
+    // Rendering below the window's resolution, and how the frame gets back up to it. Temporal upscaling needs floating
+    // point render targets, and jitters the projection like TAA does
+    pub fn set_upscale(&mut self, settings: UpscaleSettings) {
+        let mut settings = UpscaleSettings {
+            render_scale: settings.render_scale.clamp(0.1, 1.0),
+            ..settings
+        };
+        if settings.mode == UpscaleMode::Temporal && !self.capabilities.float_render_targets {
+            warn!("Temporal upscaling needs floating point render targets, which this OpenGL context doesn't support, upscaling bilinearly");
+            settings.mode = UpscaleMode::Bilinear;
+        }
+        // The TAA targets only exist while something accumulates into them
+        if settings.mode == UpscaleMode::Temporal && !self.temporal_accumulation() {
+            self.window_resolution_prev = [0, 0];
+        }
+        if settings.mode != self.upscale.mode {
+            self.taa_history_valid = false;
+        }
+        self.upscale = settings;
+        self.render_scale = if self.dynamic_resolution.enabled && settings.mode != UpscaleMode::Off {
+            self.render_scale.min(self.max_render_scale())
+        } else {
+            self.max_render_scale()
+        };
+        self.record_change(ChangeOperation::Setting("upscale"), 0, 0);
+    }
+
+    pub fn upscale(&self) -> UpscaleSettings {
+        self.upscale
+    }
+
+    // Highest render scale the upscale settings allow, dynamic resolution works below it
+    fn max_render_scale(&self) -> f32 {
+        match self.upscale.mode {
+            UpscaleMode::Off => 1.0,
+            UpscaleMode::Bilinear | UpscaleMode::Temporal => self.upscale.render_scale,
+        }
+    }
+
+    // Whether frames get jittered and blended into a history, for TAA or temporal upscaling
+    fn temporal_accumulation(&self) -> bool {
+        self.taa_enabled || self.upscale.mode == UpscaleMode::Temporal
+    }
+
+    // Fraction of the window resolution that is currently rendered
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    // Pixels rendered in the last frame, the window resolution times the render scale
+    pub fn render_resolution(&self) -> [i32; 2] {
+        self.render_resolution
+    }
+
+    fn update_render_scale(&mut self, frame_time: f32) {
+        let settings = self.dynamic_resolution;
+        if !settings.enabled || self.upscale.mode == UpscaleMode::Off {
+            return;
+        }
+        let max_scale = settings.max_scale.min(self.max_render_scale());
+        let min_scale = settings.min_scale.clamp(0.1, max_scale);
+
+        // Only step when the average leaves the band around the target, so the scale doesn't flicker
+        self.frame_time_average += (frame_time - self.frame_time_average) * 0.1;
+        if self.render_scale_cooldown > 0 {
+            self.render_scale_cooldown -= 1;
+            return;
+        }
+        let new_scale = if self.frame_time_average > settings.target_frame_time * 1.05 {
+            self.render_scale - settings.step
+        } else if self.frame_time_average < settings.target_frame_time * 0.85 {
+            self.render_scale + settings.step
+        } else {
+            return;
+        };
+        let new_scale = new_scale.clamp(min_scale, max_scale);
+        if new_scale != self.render_scale {
+            self.render_scale = new_scale;
+            self.render_scale_cooldown = RENDER_SCALE_COOLDOWN;
+        }
+    }
+
+    // Replaces the quality governor, giving back everything the old one turned down
+    pub fn set_quality_governor(&mut self, config: QualityGovernorConfig) {
+        let lowered: Vec<QualityLever> = self.quality_governor.levers().iter().filter(|lever| lever.steps > 0).map(|lever| lever.lever).collect();
+        for lever in lowered {
+            self.apply_quality_lever(lever, 0);
+        }
+        self.quality_governor = QualityGovernor::new(config);
+    }
+
+    pub fn quality_governor_config(&self) -> &QualityGovernorConfig {
+        self.quality_governor.config()
+    }
+
+    // Where each lever is, in the order they get turned down
+    pub fn quality_levers(&self) -> &[LeverState] {
+        self.quality_governor.levers()
+    }
+
+    fn update_quality_governor(&mut self, frame_time: f32) {
+        let enabled = [
+            self.ssao.enabled,
+            self.msaa_samples > 0,
+            self.parallax.enabled,
+            self.lod_settings.enabled,
+            self.upscale.mode == UpscaleMode::Temporal,
+        ];
+        let can_lower = |lever| match lever {
+            QualityLever::Ssao => enabled[0],
+            QualityLever::Msaa => enabled[1],
+            QualityLever::Parallax => enabled[2],
+            QualityLever::Lod => enabled[3],
+            QualityLever::RenderScale => enabled[4],
+        };
+        if let Some(change) = self.quality_governor.update(frame_time, can_lower) {
+            info!("{change}");
+            self.apply_quality_lever(change.lever, change.steps);
+        }
+    }
+
+    // Sets a lever's setting to `steps` below what the app had set, remembering that when it's first turned down
+    fn apply_quality_lever(&mut self, lever: QualityLever, steps: u32) {
+        let baseline = &mut self.quality_baseline;
+        match lever {
+            QualityLever::Ssao => {
+                let enabled = *baseline.ssao_enabled.get_or_insert(self.ssao.enabled);
+                self.set_ssao(SsaoSettings {
+                    enabled: enabled && steps == 0,
+                    ..self.ssao
+                });
+            }
+            QualityLever::Msaa => {
+                let samples = *baseline.msaa_samples.get_or_insert(self.msaa_samples) >> steps;
+                self.set_msaa(if samples < 2 { 0 } else { samples });
+            }
+            QualityLever::Parallax => {
+                let (enabled, max_steps) = *baseline.parallax.get_or_insert((self.parallax.enabled, self.parallax.max_steps));
+                self.set_parallax(ParallaxSettings {
+                    enabled: enabled && steps < 2,
+                    max_steps: if steps == 0 { max_steps } else { (max_steps / 2).max(self.parallax.min_steps) },
+                    ..self.parallax
+                });
+            }
+            QualityLever::Lod => {
+                let switch_distance = *baseline.lod_switch_distance.get_or_insert(self.lod_settings.switch_distance);
+                self.set_lod_settings(LodSettings {
+                    switch_distance: switch_distance * 0.5f32.powi(steps as i32),
+                    ..self.lod_settings
+                });
+            }
+            QualityLever::RenderScale => {
+                let render_scale = *baseline.render_scale.get_or_insert(self.upscale.render_scale);
+                self.set_upscale(UpscaleSettings {
+                    render_scale: render_scale * 0.75f32.powi(steps as i32),
+                    ..self.upscale
+                });
+            }
+        }
+        if steps == 0 {
+            let baseline = &mut self.quality_baseline;
+            match lever {
+                QualityLever::Ssao => baseline.ssao_enabled = None,
+                QualityLever::Msaa => baseline.msaa_samples = None,
+                QualityLever::Parallax => baseline.parallax = None,
+                QualityLever::Lod => baseline.lod_switch_distance = None,
+                QualityLever::RenderScale => baseline.render_scale = None,
+            }
+        }
+    }
+
+    pub fn set_ssao(&mut self, settings: SsaoSettings) {
+        // The render targets only exist while SSAO is enabled, recreate them at the start of the next frame
+        if settings.enabled && !self.ssao.enabled {
+            self.window_resolution_prev = [0, 0];
+        }
+        self.ssao = settings;
+        self.record_change(ChangeOperation::Setting("ssao"), 0, 0);
+    }
+
+    pub fn set_exposure(&mut self, settings: ExposureSettings) {
+        if let (false, Some(mut auto_exposure)) = (settings.enabled, self.auto_exposure.take()) {
+            auto_exposure.delete();
+        }
+        if settings.enabled && self.auto_exposure.is_none() {
+            self.auto_exposure = Some(AutoExposure::new());
+        }
+        // A new metering mode starts from its own reading instead of adapting to it
+        if let (true, Some(auto_exposure)) = (settings.metering != self.exposure.metering, &mut self.auto_exposure) {
+            auto_exposure.reset();
+        }
+        self.exposure = settings;
+        self.record_change(ChangeOperation::Setting("exposure"), 0, 0);
+    }
+
+    pub fn exposure(&self) -> ExposureSettings {
+        self.exposure
+    }
+
+    // Spot meters at a point on the window, in pixels like screen_ray
+    pub fn set_exposure_spot(&mut self, screen: Vec2) {
+        let window_resolution = self.output_size();
+        let window_size = Vec2::new(window_resolution.0 as f32, window_resolution.1 as f32).max(Vec2::ONE);
+        self.set_exposure(ExposureSettings {
+            metering: MeteringMode::Spot,
+            spot: (screen / window_size).clamp(Vec2::ZERO, Vec2::ONE),
+            ..self.exposure
+        });
+    }
+
+    fn apply_exposure(&mut self) {
+        let Some(auto_exposure) = &mut self.auto_exposure else {
+            self.frame_graph.skip_pass("auto_exposure", &["scene_colour", "exposure"], &["exposure", "scene_colour"], "auto exposure disabled");
+            return;
+        };
+        self.frame_graph.begin_pass("auto_exposure", &["scene_colour", "exposure"], &["exposure", "scene_colour"]);
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::BindVertexArray(self.fullscreen_vao);
+        }
+        auto_exposure.apply(
+            self.exposure_shader,
+            &self.exposure,
+            &self.framebuffer_texture,
+            self.framebuffer_object,
+            self.post_resolution,
+            self.delta_time,
+        );
+        unsafe {
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CULL_FACE);
+        }
+        self.frame_graph.end_pass();
+    }
+
+    fn init_ssao(&mut self) {
+        // Hemisphere sample kernel, denser towards the centre. The numbers only depend on the sample index, so every
+        // run looks the same
+        let kernel: Vec<Vec3> = (0..SSAO_KERNEL_SIZE as u32)
+            .map(|i| {
+                let random = |dimension| random::uniform(i, 0, 0, dimension);
+                let sample = Vec3::new(random(0) * 2.0 - 1.0, random(1) * 2.0 - 1.0, random(2)).normalize_or_zero();
+                let scale = i as f32 / SSAO_KERNEL_SIZE as f32;
+                sample * random(3) * (0.1 + 0.9 * scale * scale)
+            })
+            .collect();
+
+        // 4x4 tile of random rotations around the normal, one per pixel of the tile
+        let noise: Vec<f32> = (0..16u32)
+            .flat_map(|pixel| [0, 1].map(|dimension| random::uniform(pixel % 4, pixel / 4, 0, dimension) * 2.0 - 1.0))
+            .collect();
+
+        unsafe {
+            gl::UseProgram(self.ssao_shader);
+            gl::Uniform3fv(
+                gl::GetUniformLocation(self.ssao_shader, c"u_kernel".as_ptr()),
+                SSAO_KERNEL_SIZE as i32,
+                kernel.as_ptr() as *const f32,
+            );
+            gl::UseProgram(0);
+
+            gl::GenTextures(1, &mut self.ssao_noise_texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.ssao_noise_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG32F as _, 4, 4, 0, gl::RG, gl::FLOAT, noise.as_ptr() as *const c_void);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as _);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            // The fullscreen shaders generate their vertices, but core profile still wants a vertex array bound
+            gl::GenVertexArrays(1, &mut self.fullscreen_vao);
+        }
+    }
+
+    fn create_ssao_targets(&mut self, width: i32, height: i32) {
+        for i in 0..2 {
+            self.ssao_textures[i].resize(width, height);
+            unsafe {
+                if self.ssao_framebuffer_objects[i] == 0 {
+                    gl::GenFramebuffers(1, &mut self.ssao_framebuffer_objects[i]);
+                }
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.ssao_framebuffer_objects[i]);
+                self.ssao_textures[i].attach(gl::COLOR_ATTACHMENT0);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            }
+            if let Err(error) = check_framebuffer_status(self.ssao_framebuffer_objects[i]) {
+                warn!("SSAO framebuffer is incomplete, disabling SSAO: {error}");
+                self.ssao.enabled = false;
+                return;
+            }
+        }
+    }
+
+    fn apply_ssao(&mut self) {
+        let (reads, writes): (&[&str], &[&str]) = (&["scene_depth", "ssao_noise"], &["ssao", "scene_colour"]);
+        if !self.ssao.enabled {
+            self.frame_graph.skip_pass("ssao", reads, writes, "SSAO disabled");
+            return;
+        }
+        if self.stereo.enabled {
+            self.frame_graph.skip_pass("ssao", reads, writes, "not supported in stereo");
+            return;
+        }
+        self.frame_graph.begin_pass("ssao", reads, writes);
+
+        // The last view's projection is used for the whole frame
+        let projection = self.projection_matrix;
+        let inverse_projection = projection.inverse();
+        let (width, height) = (self.render_resolution[0], self.render_resolution[1]);
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::BindVertexArray(self.fullscreen_vao);
+            gl::Viewport(0, 0, width, height);
+
+            // Occlusion from the resolved depth buffer
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.ssao_framebuffer_objects[0]);
+            gl::UseProgram(self.ssao_shader);
+            gl::UniformMatrix4fv(gl::GetUniformLocation(self.ssao_shader, c"u_projection".as_ptr()), 1, gl::FALSE, projection.to_cols_array().as_ptr());
+            gl::UniformMatrix4fv(gl::GetUniformLocation(self.ssao_shader, c"u_inverse_projection".as_ptr()), 1, gl::FALSE, inverse_projection.to_cols_array().as_ptr());
+            gl::Uniform1f(gl::GetUniformLocation(self.ssao_shader, c"u_radius".as_ptr()), self.ssao.radius);
+            gl::Uniform1f(gl::GetUniformLocation(self.ssao_shader, c"u_bias".as_ptr()), self.ssao.bias);
+            gl::Uniform1f(gl::GetUniformLocation(self.ssao_shader, c"u_intensity".as_ptr()), self.ssao.intensity);
+            gl::Uniform2i(gl::GetUniformLocation(self.ssao_shader, c"u_render_size".as_ptr()), width, height);
+            gl::Uniform1i(gl::GetUniformLocation(self.ssao_shader, c"u_reversed_z".as_ptr()), self.projection.reversed_z as i32);
+            self.depth_buffer_texture.bind(TextureSlot::SceneDepth);
+            TextureBinder::bind(TextureSlot::Noise, self.ssao_noise_texture as i32);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            // Horizontal blur
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.ssao_framebuffer_objects[1]);
+            gl::UseProgram(self.ssao_blur_shader);
+            let direction_location = gl::GetUniformLocation(self.ssao_blur_shader, c"u_direction".as_ptr());
+            gl::Uniform2i(gl::GetUniformLocation(self.ssao_blur_shader, c"u_render_size".as_ptr()), width, height);
+            gl::Uniform2i(direction_location, 1, 0);
+            self.ssao_textures[0].bind(TextureSlot::Albedo);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            // Vertical blur, multiplied into the scene colour
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ZERO, gl::SRC_COLOR);
+            gl::Uniform2i(direction_location, 0, 1);
+            self.ssao_textures[1].bind(TextureSlot::Albedo);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::Disable(gl::BLEND);
+
+            TextureBinder::bind(TextureSlot::Albedo, 0);
+            TextureBinder::bind(TextureSlot::SceneDepth, 0);
+            TextureBinder::bind(TextureSlot::Noise, 0);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CULL_FACE);
+        }
+        self.frame_graph.end_pass();
+    }
+
+    pub fn set_sky(&mut self, sky: SkyConfig) {
+        self.sky = sky;
+        self.record_change(ChangeOperation::Setting("sky"), 0, 0);
+    }
+
+    pub fn set_wind(&mut self, wind: WindConfig) {
+        self.wind = WindConfig {
+            direction: wind.direction.normalize_or_zero(),
+            ..wind
+        };
+        self.record_change(ChangeOperation::Setting("wind"), 0, 0);
+    }
+
+    pub fn wind(&self) -> WindConfig {
+        self.wind
+    }
+
+    pub fn sky(&self) -> SkyConfig {
+        self.sky
+    }
+
+    // Up axis of the world, fixed when the renderer is created
+    pub fn world_up(&self) -> WorldUp {
+        self.world_up
+    }
+
+    // Copies the viewport's part of a framebuffer's depth, and of its colour too if asked, into the scene copies.
+    // They're made on first use after every resize
+    fn copy_scene(&mut self, source: u32, viewport: Rect, colour: bool) {
+        let size = self.window_resolution_prev;
+        unsafe {
+            if self.scene_copy_framebuffer_object == 0 {
+                gl::GenFramebuffers(1, &mut self.scene_copy_framebuffer_object);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.scene_copy_framebuffer_object);
+            if self.scene_depth_copy.id() == 0 {
+                let (depth_format, depth_type) = self.depth_format();
+                self.scene_depth_copy.set_format(depth_format, gl::DEPTH_STENCIL, depth_type);
+                self.scene_depth_copy.resize(size[0], size[1]);
+                self.scene_depth_copy.attach(gl::DEPTH_STENCIL_ATTACHMENT);
+                if self.scene_colour_copy.id() == 0 {
+                    // Don't keep a colour copy from before the resize attached
+                    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, 0, 0);
+                }
+            }
+            // Multisampled colour can only be resolved into the same format
+            if colour && self.scene_colour_copy.id() == 0 {
+                self.scene_colour_copy.set_format(self.framebuffer_format.internal_format(), gl::RGBA, gl::FLOAT);
+                self.scene_colour_copy.resize(size[0], size[1]);
+                self.scene_colour_copy.attach(gl::COLOR_ATTACHMENT0);
+            }
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, source);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.scene_copy_framebuffer_object);
+            let (x0, y0, x1, y1) = (viewport.x, viewport.y, viewport.x + viewport.width, viewport.y + viewport.height);
+            let mask = if colour { gl::DEPTH_BUFFER_BIT | gl::COLOR_BUFFER_BIT } else { gl::DEPTH_BUFFER_BIT };
+            gl::BlitFramebuffer(x0, y0, x1, y1, x0, y0, x1, y1, mask, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, source);
+        }
+    }
+
+    // Generates one of the standard scenes for looking at materials and models, see InspectionLayout. It stands on the
+    // origin, and is lit by the sky's sun like everything else
+    pub fn build_inspection_scene(&mut self, config: &InspectionConfig) -> Result<SceneHandles, String> {
+        profile_scope!("build_inspection_scene");
+        // Laid out Y up, and turned into the world's convention when placed
+        let to_world = self.world_up.y_up_rotation();
+        let mut generated: Vec<(String, Model, Vec3, Vec4)> = Vec::new(); // Name, model, position and tint
+        let mut shown = None; // Loaded model and the offset that puts it in place
+        let mut content = AABB::new();
+        let preview_bounds = |centre: Vec3| AABB {
+            min: centre - PREVIEW_RADIUS,
+            max: centre + PREVIEW_RADIUS,
+        };
+        let (yaw, pitch) = match &config.layout {
+            InspectionLayout::ShaderBalls { columns, rows, roughness, metallic, colour } => {
+                if *columns == 0 || *rows == 0 {
+                    return Err("The shader ball grid needs at least one column and one row".to_string());
+                }
+                let shape = inspection::preview_shape(config.shape);
+                let fraction = |index: u32, count: u32| if count > 1 { index as f32 / (count - 1) as f32 } else { 0.5 };
+                for row in 0..*rows {
+                    for column in 0..*columns {
+                        let mut material = Material::new();
+                        material.scl_rgh = roughness.0 + (roughness.1 - roughness.0) * fraction(column, *columns);
+                        material.scl_mtl = metallic.0 + (metallic.1 - metallic.0) * fraction(row, *rows);
+                        let name = format!("roughness {:.2} metallic {:.2}", material.scl_rgh, material.scl_mtl);
+                        let position = Vec3::new(
+                            (column as f32 - (*columns - 1) as f32 * 0.5) * PREVIEW_SPACING,
+                            (row as f32 + 0.5) * PREVIEW_SPACING,
+                            0.0,
+                        );
+                        content.grow_aabb(&preview_bounds(position));
+                        let model = inspection::single_mesh_model(&name, shape.clone(), material);
+                        generated.push((name, model, position, colour.extend(1.0)));
+                    }
+                }
+                (0.0, -0.15)
+            }
+            InspectionLayout::MaterialRow { model } => {
+                let model = self.models.get(model).ok_or_else(|| format!("Model {model} is not loaded"))?;
+                let mut materials: Vec<(&String, &Material)> = model.materials.iter().collect();
+                materials.sort_by_key(|(name, _)| *name);
+                if materials.is_empty() {
+                    return Err("The model has no materials to show".to_string());
+                }
+                let shape = inspection::preview_shape(config.shape);
+                let count = materials.len();
+                for (index, (name, material)) in materials.into_iter().enumerate() {
+                    let position = Vec3::new((index as f32 - (count - 1) as f32 * 0.5) * PREVIEW_SPACING, PREVIEW_SPACING * 0.5, 0.0);
+                    content.grow_aabb(&preview_bounds(position));
+                    let model = inspection::single_mesh_model(name, shape.clone(), material.clone());
+                    generated.push((name.clone(), model, position, Vec4::ONE));
+                }
+                (0.0, -0.25)
+            }
+            InspectionLayout::Pedestal { model } => {
+                let info = self.model_info(*model).ok_or_else(|| format!("Model {model} is not loaded"))?;
+                let bounds = info.bounds.transformed(&Mat4::from_quat(to_world.inverse()));
+                let size = bounds.max - bounds.min;
+                let radius = (Vec2::new(size.x, size.z).length() * 0.55).max(0.1);
+                let height = radius * 0.3;
+
+                // The bottom centre of the model stands on the middle of the pedestal
+                let base = Vec3::new((bounds.min.x + bounds.max.x) * 0.5, bounds.min.y, (bounds.min.z + bounds.max.z) * 0.5);
+                let offset = Vec3::Y * height - base;
+                let mut material = Material::named("pedestal");
+                material.scl_rgh = 0.6;
+                let pedestal = inspection::single_mesh_model("pedestal", inspection::pedestal(radius, height), material);
+                generated.push(("pedestal".to_string(), pedestal, Vec3::ZERO, Vec4::new(0.5, 0.5, 0.5, 1.0)));
+                shown = Some((*model, offset));
+                content = bounds.translated(offset);
+                content.grow(Vec3::new(-radius, 0.0, -radius));
+                content.grow(Vec3::new(radius, height, radius));
+                (0.6, -0.3)
+            }
+        };
+
+        // A checkerboard reaching well past the scene, with the checks a power of two in size so they line up with
+        // the world units
+        if config.ground {
+            let reach = content.min.abs().max(content.max.abs());
+            let size = reach.x.max(reach.z) * 3.0 + PREVIEW_SPACING * 2.0;
+            let period = 2.0f32.powf((size / 8.0).log2().round());
+            let mut material = Material::named("ground");
+            material.scl_rgh = 0.9;
+            material.tex_alb = self.upload_texture(&mut inspection::checker_texture()) as i32;
+            let ground = inspection::single_mesh_model("ground", inspection::ground(size, period), material);
+            generated.push(("ground".to_string(), ground, Vec3::ZERO, Vec4::ONE));
+        }
+
+        let aspect_ratio = self.render_resolution[0] as f32 / self.render_resolution[1].max(1) as f32;
+        let fov = self.projection.vertical_fov(aspect_ratio).min(self.projection.horizontal_fov(aspect_ratio));
+        let mut scene = SceneHandles {
+            models: Vec::new(),
+            camera: OrbitCamera::framing(&content.transformed(&Mat4::from_quat(to_world)), fov, yaw, pitch),
+            placements: Vec::new(),
+        };
+        for (name, model, position, tint) in generated {
+            match self.add_generated_model(&name, model) {
+                Ok(handle) => {
+                    scene.models.push(handle);
+                    scene.placements.push((handle, Mat4::from_rotation_translation(to_world, to_world * position), tint));
+                }
+                Err(error) => {
+                    self.remove_inspection_scene(scene);
+                    return Err(error);
+                }
+            }
+        }
+        if let Some((model, offset)) = shown {
+            scene.placements.push((model, Mat4::from_translation(to_world * offset), Vec4::ONE));
+        }
+        Ok(scene)
+    }
+
+    // Submits everything in an inspection scene for this frame, like draw_model does for a model
+    pub fn draw_inspection_scene(&mut self, scene: &SceneHandles) {
+        for &(model, matrix, tint) in &scene.placements {
+            self.queue_model(&model, 0, matrix, tint);
+        }
+    }
+
+    // Unloads the models an inspection scene generated
+    pub fn remove_inspection_scene(&mut self, scene: SceneHandles) {
+        for model in scene.models {
+            self.unload_model(model);
+        }
+    }
+
+    // Uploads a model made in code. It has no files, so it's never reloaded and isn't part of snapshots. The name
+    // becomes its tag, which the ID view shows
+    fn add_generated_model(&mut self, name: &str, mut model: Model) -> Result<u64, String> {
+        for mesh in model.meshes.values_mut() {
+            Self::upload_mesh(mesh).map_err(|error| format!("Failed to upload generated mesh \"{name}\": GL error 0x{error:X}"))?;
+        }
+        let mut hasher = DefaultHasher::new();
+        ("generated", name, self.next_generated_model).hash(&mut hasher);
+        self.next_generated_model += 1;
+        let handle = hasher.finish();
+        self.record_change(ChangeOperation::ModelLoad, handle, model.vertex_bytes());
+        self.models.insert(handle, model);
+        self.model_tags.insert(handle, name.to_string());
+        Ok(handle)
+    }
+
+    // Builds a program to draw models with instead of the lit shader. It gets the lit shader's uniforms and material
+    // textures, including the material's custom parameters (float u_custom0 to u_custom3, vec4 u_customVec0 and
+    // u_customVec1), so it has to read the same vertex attributes and declare the global constant block. Programs
+    // that don't are rejected here, rather than drawing garbage later
+    pub fn register_shader(&mut self, vertex: &Path, fragment: &Path) -> Result<ShaderHandle, String> {
+        profile_scope!("register_shader");
+        let files = [self.assets.resolve(vertex)?, self.assets.resolve(fragment)?];
+        let program = self.build_custom_program(&files)?;
+        let handle = self.next_custom_shader;
+        self.next_custom_shader += 1;
+        let modified = files.clone().map(|file| modified_time(&file));
+        self.custom_shaders.insert(handle, CustomShader { files, modified, program });
+        self.record_change(ChangeOperation::ShaderRegister, handle as u64, 0);
+        Ok(ShaderHandle(handle))
+    }
+
+    // Draws a model with a registered shader, or with the lit shader again for None
+    pub fn set_model_shader(&mut self, model: u64, shader: Option<ShaderHandle>) -> Result<(), String> {
+        if !self.models.contains_key(&model) {
+            return Err(format!("There is no model with handle {model}"));
+        }
+        match shader {
+            Some(shader) if !self.custom_shaders.contains_key(&shader.0) => return Err(format!("{shader:?} was never registered")),
+            Some(shader) => self.model_shaders.insert(model, shader),
+            None => self.model_shaders.remove(&model),
+        };
+        self.record_change(ChangeOperation::ModelShader, model, 0);
+        Ok(())
+    }
+
+    pub fn model_shader(&self, model: u64) -> Option<ShaderHandle> {
+        self.model_shaders.get(&model).copied()
+    }
+
+    fn build_custom_program(&mut self, files: &[PathBuf; 2]) -> Result<u32, String> {
+        let program = self.build_program(&[(gl::VERTEX_SHADER, files[0].clone()), (gl::FRAGMENT_SHADER, files[1].clone())])?;
+        let name = files[1].with_extension("");
+        let mut linked = 0;
+        unsafe {
+            gl::GetProgramiv(program, gl::LINK_STATUS, &mut linked);
+        }
+        let mut result = match linked {
+            0 => Err(format!("Shader \"{}\" failed to link", name.display())),
+            _ => Ok(()),
+        };
+        if result.is_ok() && self.capabilities.program_interface_query {
+            result = match gpu_layout::declares::<GlobalConstBuffer>(program) {
+                true => GPU_LAYOUTS.iter().try_for_each(|validate| validate(program)),
+                false => Err(format!("GLSL block \"{}\" is not declared", GlobalConstBuffer::BLOCK_NAME.to_string_lossy())),
+            }
+            .and_then(|_| gpu_layout::validate_vertex_inputs(program, STANDARD_VERTEX_INPUTS))
+            .map_err(|error| format!("Shader \"{}\" can't draw models: {error}", name.display()));
+        }
+        if let Err(error) = result {
+            unsafe {
+                gl::DeleteProgram(program);
+            }
+            return Err(error);
+        }
+        self.assign_lit_samplers(program);
+        Ok(program)
+    }
+
+    // Rebuilds a custom shader from its files, keeping the old program if that fails
+    fn rebuild_custom_shader(&mut self, handle: u32) {
+        let Some(files) = self.custom_shaders.get(&handle).map(|shader| shader.files.clone()) else {
+            return;
+        };
+        match self.build_custom_program(&files) {
+            Ok(program) => {
+                let shader = self.custom_shaders.get_mut(&handle).unwrap();
+                unsafe {
+                    gl::DeleteProgram(shader.program);
+                }
+                shader.program = program;
+                self.record_change(ChangeOperation::ShaderReload, handle as u64, 0);
+                info!("Reloaded shader \"{}\"", files[1].with_extension("").display());
+            }
+            Err(error) => warn!("Failed to reload a custom shader, keeping the old one: {error}"),
+        }
+    }
+
+    // Fills the background of the current view, everything the opaque pass left at the far plane
+    fn draw_sky(&mut self, view_matrix: Mat4) {
+        self.frame_graph.begin_pass("sky", &[], self.raster_targets());
+        let view_rotation = Mat4::from_mat3(Mat3::from_mat4(view_matrix));
+        let inverse_view_rotation_projection = (self.projection_matrix * view_rotation).inverse();
+        let sun_direction = self.sky.sun_direction.normalize_or_zero();
+        unsafe {
+            gl::DepthMask(gl::FALSE);
+            gl::DepthFunc(if self.projection.reversed_z { gl::GEQUAL } else { gl::LEQUAL });
+            gl::Disable(gl::CULL_FACE);
+            gl::UseProgram(self.sky_shader);
+            gl::Uniform1i(gl::GetUniformLocation(self.sky_shader, c"u_reversed_z".as_ptr()), self.projection.reversed_z as i32);
+            gl::UniformMatrix4fv(
+                gl::GetUniformLocation(self.sky_shader, c"u_inverse_view_rotation_projection".as_ptr()),
+                1,
+                gl::FALSE,
+                inverse_view_rotation_projection.to_cols_array().as_ptr(),
+            );
+            gl::Uniform3fv(gl::GetUniformLocation(self.sky_shader, c"u_sun_direction".as_ptr()), 1, sun_direction.as_ref().as_ptr());
+            gl::Uniform3fv(gl::GetUniformLocation(self.sky_shader, c"u_sun_colour".as_ptr()), 1, self.sky.sun_colour(self.world_up).as_ref().as_ptr());
+            gl::Uniform1f(gl::GetUniformLocation(self.sky_shader, c"u_turbidity".as_ptr()), self.sky.turbidity);
+            gl::Uniform3fv(gl::GetUniformLocation(self.sky_shader, c"u_ground_albedo".as_ptr()), 1, self.sky.ground_albedo.as_ref().as_ptr());
+            gl::Uniform3fv(gl::GetUniformLocation(self.sky_shader, c"u_world_up".as_ptr()), 1, self.world_up.up().as_ref().as_ptr());
+            gl::BindVertexArray(self.fullscreen_vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindVertexArray(0);
+            gl::DepthMask(gl::TRUE);
+        }
+        self.frame_graph.end_pass();
+    }
+
+    // Both full-screen effects reconstruct positions with a single projection for the whole frame, so SSAO and TAA
+    // are skipped while stereo is on. Picking and screen_ray keep using the centre camera
+    pub fn set_stereo(&mut self, config: StereoConfig) {
+        if config.enabled != self.stereo.enabled {
+            self.taa_history_valid = false;
+        }
+        self.stereo = config;
+        self.record_change(ChangeOperation::Setting("stereo"), 0, 0);
+    }
+
+    pub fn stereo(&self) -> StereoConfig {
+        self.stereo
+    }
+
+    // Temporal anti-aliasing, turning it on or off starts from a fresh history
+    pub fn set_taa(&mut self, enabled: bool) {
+        if enabled && !self.capabilities.float_render_targets {
+            warn!("TAA needs floating point render targets, which this OpenGL context doesn't support");
+            return;
+        }
+        if enabled && !self.taa_enabled {
+            self.window_resolution_prev = [0, 0];
+        }
+        self.taa_enabled = enabled;
+        self.taa_history_valid = false;
+        self.record_change(ChangeOperation::Setting("taa"), 0, 0);
+    }
+
+    pub fn taa_enabled(&self) -> bool {
+        self.taa_enabled
+    }
+
+    fn create_taa_targets(&mut self, width: i32, height: i32) {
+        // Velocity
+        self.velocity_texture.resize(width, height);
+        unsafe {
+            if self.velocity_framebuffer_object == 0 {
+                gl::GenFramebuffers(1, &mut self.velocity_framebuffer_object);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.velocity_framebuffer_object);
+            self.velocity_texture.attach(gl::COLOR_ATTACHMENT0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        self.previous_velocity_texture.resize(width, height);
+        unsafe {
+            if self.previous_velocity_framebuffer_object == 0 {
+                gl::GenFramebuffers(1, &mut self.previous_velocity_framebuffer_object);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.previous_velocity_framebuffer_object);
+            self.previous_velocity_texture.attach(gl::COLOR_ATTACHMENT0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        // The same velocity, depth tested against the scene for the moving meshes
+        unsafe {
+            if self.object_motion_framebuffer_object == 0 {
+                gl::GenFramebuffers(1, &mut self.object_motion_framebuffer_object);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.object_motion_framebuffer_object);
+            self.velocity_texture.attach(gl::COLOR_ATTACHMENT0);
+            self.depth_buffer_texture.attach(gl::DEPTH_STENCIL_ATTACHMENT);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        let mut framebuffers = vec![
+            self.velocity_framebuffer_object,
+            self.previous_velocity_framebuffer_object,
+            self.object_motion_framebuffer_object,
+        ];
+
+        // History, filtered so it can be sampled between pixels after reprojection
+        for i in 0..2 {
+            self.taa_history_textures[i].resize(width, height);
+            unsafe {
+                if self.taa_history_framebuffer_objects[i] == 0 {
+                    gl::GenFramebuffers(1, &mut self.taa_history_framebuffer_objects[i]);
+                }
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.taa_history_framebuffer_objects[i]);
+                self.taa_history_textures[i].attach(gl::COLOR_ATTACHMENT0);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            }
+            framebuffers.push(self.taa_history_framebuffer_objects[i]);
+        }
+        self.taa_history_valid = false;
+
+        for framebuffer in framebuffers {
+            if let Err(error) = check_framebuffer_status(framebuffer) {
+                warn!("TAA framebuffer is incomplete, disabling TAA, temporal upscaling and motion blur: {error}");
+                self.taa_enabled = false;
+                self.motion_blur.enabled = false;
+                if self.upscale.mode == UpscaleMode::Temporal {
+                    self.upscale.mode = UpscaleMode::Bilinear;
+                }
+                return;
+            }
+        }
+    }
+
+    // Whether the velocity buffer is drawn, for TAA, temporal upscaling or motion blur
+    fn velocity_needed(&self) -> bool {
+        self.temporal_accumulation() || self.motion_blur.enabled
+    }
+
+    // Fills the velocity buffer with how far each pixel moved since last frame, first from the camera's motion alone,
+    // then from the moving meshes over it
+    fn compute_motion_vectors(&mut self) {
+        let (motion_reads, motion_writes): (&[&str], &[&str]) = (&["scene_depth"], &["velocity"]);
+        let (object_reads, object_writes): (&[&str], &[&str]) = (&["const_buffer", "scene_depth", "material_textures"], &["velocity"]);
+        let skip_reason = if !self.velocity_needed() {
+            Some("TAA and motion blur disabled")
+        } else if self.stereo.enabled {
+            Some("not supported in stereo")
+        } else {
+            None
+        };
+        if let Some(reason) = skip_reason {
+            self.frame_graph.skip_pass("motion_vectors", motion_reads, motion_writes, reason);
+            self.frame_graph.skip_pass("object_motion", object_reads, object_writes, reason);
+            return;
+        }
+
+        // Without last frame's view, the camera counts as still
+        if self.motion_vectors_frame.map(|frame| frame + 1) != Some(self.frame_index) {
+            self.previous_view_projection_matrix = self.view_projection_matrix;
+        }
+        let (width, height) = (self.render_resolution[0], self.render_resolution[1]);
+        self.frame_graph.begin_pass("motion_vectors", motion_reads, motion_writes);
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::BindVertexArray(self.fullscreen_vao);
+            gl::Viewport(0, 0, width, height);
+
+            // Camera motion
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.velocity_framebuffer_object);
+            gl::UseProgram(self.motion_shader);
+            gl::UniformMatrix4fv(
+                gl::GetUniformLocation(self.motion_shader, c"u_inverse_view_projection".as_ptr()),
+                1,
+                gl::FALSE,
+                self.view_projection_matrix.inverse().to_cols_array().as_ptr(),
+            );
+            gl::UniformMatrix4fv(
+                gl::GetUniformLocation(self.motion_shader, c"u_previous_view_projection".as_ptr()),
+                1,
+                gl::FALSE,
+                self.previous_view_projection_matrix.to_cols_array().as_ptr(),
+            );
+            gl::Uniform2i(gl::GetUniformLocation(self.motion_shader, c"u_render_size".as_ptr()), width, height);
+            gl::Uniform1i(gl::GetUniformLocation(self.motion_shader, c"u_reversed_z".as_ptr()), self.projection.reversed_z as i32);
+            self.depth_buffer_texture.bind(TextureSlot::SceneDepth);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            TextureBinder::bind(TextureSlot::SceneDepth, 0);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CULL_FACE);
+        }
+        self.frame_graph.end_pass();
+        self.draw_object_motion(object_reads, object_writes);
+        self.previous_view_projection_matrix = self.view_projection_matrix;
+        self.motion_vectors_frame = Some(self.frame_index);
+    }
+
+    // Draws the opaque meshes that moved, or sway in the wind, over the camera's motion. They're depth tested against
+    // the scene without writing depth, pulled slightly forward so the resolved depth of a multisampled frame still
+    // lets them through
+    fn draw_object_motion(&mut self, reads: &'static [&'static str], writes: &'static [&'static str]) {
+        let wind = self.wind.enabled;
+        let moving: Vec<usize> = (0..self.mesh_queue.len())
+            .filter(|&index| {
+                let mesh = &self.mesh_queue[index];
+                !mesh.is_transparent() && (mesh.previous_model_matrix != Some(mesh.model_matrix) || (wind && mesh.material.scl_wind > 0.0))
+            })
+            .collect();
+        if moving.is_empty() {
+            self.frame_graph.skip_pass("object_motion", reads, writes, "nothing moved");
+            return;
+        }
+        self.frame_graph.begin_pass("object_motion", reads, writes);
+        let shader = self.object_motion_shader;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.object_motion_framebuffer_object);
+            gl::Viewport(0, 0, self.render_resolution[0], self.render_resolution[1]);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::DepthMask(gl::FALSE);
+            gl::DepthFunc(if self.projection.reversed_z { gl::GEQUAL } else { gl::LEQUAL });
+            let offset = if self.projection.reversed_z { 1.0 } else { -1.0 };
+            gl::Enable(gl::POLYGON_OFFSET_FILL);
+            gl::PolygonOffset(offset, offset);
+            gl::UseProgram(shader);
+            gl::UniformMatrix4fv(
+                gl::GetUniformLocation(shader, c"u_unjittered_view_projection".as_ptr()),
+                1,
+                gl::FALSE,
+                self.view_projection_matrix.to_cols_array().as_ptr(),
+            );
+            gl::UniformMatrix4fv(
+                gl::GetUniformLocation(shader, c"u_previous_view_projection".as_ptr()),
+                1,
+                gl::FALSE,
+                self.previous_view_projection_matrix.to_cols_array().as_ptr(),
+            );
+            self.const_buffer_gpu.bind_base(0);
+        }
+        for index in moving {
+            let mesh = &self.mesh_queue[index];
+            unsafe {
+                let previous = mesh.previous_model_matrix.unwrap_or(mesh.model_matrix);
+                gl::UniformMatrix4fv(gl::GetUniformLocation(shader, c"u_previous_model_matrix".as_ptr()), 1, gl::FALSE, previous.as_ref().as_ptr());
+                gl::Uniform1i(gl::GetUniformLocation(shader, c"u_teleported".as_ptr()), mesh.previous_model_matrix.is_none() as i32);
+            }
+            self.draw_queue_entry(mesh, shader);
+        }
+        unsafe {
+            gl::Disable(gl::POLYGON_OFFSET_FILL);
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(self.depth_func());
+            gl::BindVertexArray(0);
+            gl::UseProgram(self.triangle_shader);
+        }
+        self.frame_graph.end_pass();
+    }
+
+    fn apply_taa(&mut self) {
+        let (taa_reads, taa_writes): (&[&str], &[&str]) = (
+            &["scene_colour", "taa_history", "velocity", "previous_velocity"],
+            &["taa_history", "scene_colour", "previous_velocity"],
+        );
+        if !self.temporal_accumulation() {
+            self.frame_graph.skip_pass("taa", taa_reads, taa_writes, "TAA disabled");
+            return;
+        }
+        if self.stereo.enabled {
+            self.frame_graph.skip_pass("taa", taa_reads, taa_writes, "not supported in stereo");
+            return;
+        }
+
+        // Temporal upscaling keeps its history at the window's resolution, so only a different window size throws it
+        // away. Without it, a different render resolution means the history no longer lines up
+        let upscale = self.upscale.mode == UpscaleMode::Temporal;
+        let (width, height) = (self.render_resolution[0], self.render_resolution[1]);
+        let output = if upscale { self.window_resolution_prev } else { self.render_resolution };
+        if self.taa_history_resolution != output {
+            self.taa_history_resolution = output;
+            self.taa_history_valid = false;
+        }
+        let previous = self.taa_history_index;
+        let next = 1 - previous;
+
+        // Blend the frame into the history
+        self.frame_graph.begin_pass("taa", taa_reads, taa_writes);
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::BindVertexArray(self.fullscreen_vao);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.taa_history_framebuffer_objects[next]);
+            gl::Viewport(0, 0, output[0], output[1]);
+            gl::UseProgram(self.taa_shader);
+            gl::Uniform2i(gl::GetUniformLocation(self.taa_shader, c"u_render_size".as_ptr()), width, height);
+            gl::Uniform2i(gl::GetUniformLocation(self.taa_shader, c"u_output_size".as_ptr()), output[0], output[1]);
+            gl::Uniform2i(
+                gl::GetUniformLocation(self.taa_shader, c"u_previous_render_size".as_ptr()),
+                self.taa_previous_render_resolution[0],
+                self.taa_previous_render_resolution[1],
+            );
+            gl::Uniform1i(gl::GetUniformLocation(self.taa_shader, c"u_upscale".as_ptr()), upscale as i32);
+            gl::Uniform2f(gl::GetUniformLocation(self.taa_shader, c"u_jitter".as_ptr()), self.taa_jitter.x, self.taa_jitter.y);
+            gl::Uniform1i(gl::GetUniformLocation(self.taa_shader, c"u_history_valid".as_ptr()), self.taa_history_valid as i32);
+            gl::Uniform1f(gl::GetUniformLocation(self.taa_shader, c"u_blend".as_ptr()), TAA_BLEND);
+            self.framebuffer_texture.bind(TextureSlot::Albedo);
+            self.taa_history_textures[previous].bind(TextureSlot::History);
+            self.velocity_texture.bind(TextureSlot::Velocity);
+            self.previous_velocity_texture.bind(TextureSlot::PreviousVelocity);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            // Copy the result back, so everything after this sees the anti-aliased frame. Upscaled, it fills the
+            // framebuffer up to the window's resolution
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.taa_history_framebuffer_objects[next]);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.framebuffer_object);
+            gl::BlitFramebuffer(0, 0, output[0], output[1], 0, 0, output[0], output[1], gl::COLOR_BUFFER_BIT, gl::NEAREST);
+
+            // Keep this frame's velocity for the next one to compare against
+            if upscale {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.velocity_framebuffer_object);
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.previous_velocity_framebuffer_object);
+                gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+            }
+
+            TextureBinder::bind(TextureSlot::Albedo, 0);
+            TextureBinder::bind(TextureSlot::History, 0);
+            TextureBinder::bind(TextureSlot::Velocity, 0);
+            TextureBinder::bind(TextureSlot::PreviousVelocity, 0);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CULL_FACE);
+        }
+        self.frame_graph.end_pass();
+        self.taa_history_index = next;
+        self.taa_history_valid = true;
+        self.taa_previous_render_resolution = self.render_resolution;
+        self.post_resolution = output;
+    }
+
+    // Camera and object motion blur, see MotionBlurSettings
+    pub fn set_motion_blur(&mut self, settings: MotionBlurSettings) {
+        if settings.enabled && !self.capabilities.float_render_targets {
+            warn!("Motion blur needs floating point render targets, which this OpenGL context doesn't support");
+            return;
+        }
+        if settings.enabled && !self.velocity_needed() {
+            self.window_resolution_prev = [0, 0];
+        }
+        self.motion_blur = settings;
+        self.record_change(ChangeOperation::Setting("motion_blur"), 0, 0);
+    }
+
+    pub fn motion_blur(&self) -> MotionBlurSettings {
+        self.motion_blur
+    }
+
+    // Smears the scene colour along the velocity buffer, reading from a copy of it
+    fn apply_motion_blur(&mut self) {
+        let (reads, writes): (&[&str], &[&str]) = (&["scene_colour", "velocity"], &["scene_colour_copy", "scene_colour"]);
+        if !self.motion_blur.enabled {
+            self.frame_graph.skip_pass("motion_blur", reads, writes, "motion blur disabled");
+            return;
+        }
+        if self.stereo.enabled {
+            self.frame_graph.skip_pass("motion_blur", reads, writes, "not supported in stereo");
+            return;
+        }
+        let viewport = self.post_viewport();
+        self.copy_scene(self.framebuffer_object, viewport, true);
+        self.frame_graph.begin_pass("motion_blur", reads, writes);
+        let shader = self.motion_blur_shader;
+        unsafe {
+            let location = |name: &CStr| gl::GetUniformLocation(shader, name.as_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
+            Self::apply_viewport(viewport);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::UseProgram(shader);
+            gl::Uniform2i(location(c"u_size"), viewport.width, viewport.height);
+            gl::Uniform2i(location(c"u_render_size"), self.render_resolution[0], self.render_resolution[1]);
+            gl::Uniform1f(location(c"u_strength"), self.motion_blur.strength.max(0.0));
+            gl::Uniform1f(location(c"u_max_radius"), self.motion_blur.max_radius.max(0.0));
+            gl::Uniform1i(location(c"u_samples"), self.motion_blur.samples.clamp(1, 64) as i32);
+            self.scene_colour_copy.bind(TextureSlot::SceneColour);
+            self.velocity_texture.bind(TextureSlot::Velocity);
+            gl::BindVertexArray(self.fullscreen_vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            TextureBinder::bind(TextureSlot::SceneColour, 0);
+            TextureBinder::bind(TextureSlot::Velocity, 0);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CULL_FACE);
+        }
+        self.frame_graph.end_pass();
+    }
 
-        // Swap front and back buffers
-        self.window.swap_buffers();
+    pub fn set_msaa(&mut self, samples: i32) {
+        // Clamp to what the driver supports
+        let mut max_samples = 0;
+        unsafe {
+            gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples);
+        }
+        let mut samples = samples.max(0);
+        if samples > max_samples {
+            warn!("{samples}x MSAA is not supported, clamping to {max_samples}x");
+            samples = max_samples;
+        }
+        if samples == self.msaa_samples {
+            return;
+        }
+        self.msaa_samples = samples;
+        self.record_change(ChangeOperation::Setting("msaa"), 0, 0);
+
+        // Rebuild the render targets, or free them when MSAA gets turned off. Without anything to render to yet, like
+        // while minimized, update_framebuffer_resolution creates them once there is
+        let window_resolution = self.output_size();
+        if samples > 0 && window_resolution.0 > 0 && window_resolution.1 > 0 {
+            self.create_msaa_targets(window_resolution.0, window_resolution.1);
+        } else {
+            self.delete_msaa_targets();
+        }
     }
 
-	fn update_framebuffer_resolution(&mut self) {
-		let window_resolution = self.window.get_framebuffer_size();
-		let window_resolution = [window_resolution.0, window_resolution.1];
-		if window_resolution != self.window_resolution_prev {
-			Self::resize_texture(
-				&mut self.framebuffer_texture, 
-				window_resolution[0], 
-				window_resolution[1],
-				gl::RGBA16F as _,
-				gl::RGBA,
-				gl::FLOAT,
-			);
-			Self::resize_texture(
-				&mut self.depth_buffer_texture, 
-				window_resolution[0], 
-				window_resolution[1],
-				gl::DEPTH24_STENCIL8 as _,
-				gl::DEPTH_STENCIL,
-				gl::UNSIGNED_INT_24_8,
-			);			
+    fn raster_framebuffer_object(&self) -> u32 {
+        if self.msaa_samples > 0 {
+            self.msaa_framebuffer_object
+        } else {
+            self.framebuffer_object
+        }
+    }
 
-			unsafe {
-				gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_object);
-				gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.framebuffer_texture, 0);
-				gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D, self.depth_buffer_texture, 0);
-			}
-		}
-		self.window_resolution_prev = window_resolution;
-	}
-	
-	fn resize_texture(texture: &mut u32, width: i32, height: i32, tex_format_internal: i32, tex_format: u32, component_type: u32) {
-		unsafe {
-			gl::DeleteTextures(1, texture);
-			gl::GenTextures(1, texture);
-			gl::BindTexture(gl::TEXTURE_2D, *texture);
-			gl::TexImage2D(gl::TEXTURE_2D, 0, tex_format_internal, width, height, 0, tex_format, component_type, null() as *const c_void);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
-			gl::BindTexture(gl::TEXTURE_2D, 0);
-		}
-	}
+    fn create_msaa_targets(&mut self, width: i32, height: i32) {
+        self.delete_msaa_targets();
+        unsafe {
+            gl::GenFramebuffers(1, &mut self.msaa_framebuffer_object);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.msaa_framebuffer_object);
+
+            // Color
+            gl::GenTextures(1, &mut self.msaa_colour_texture);
+            gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, self.msaa_colour_texture);
+            gl::TexImage2DMultisample(gl::TEXTURE_2D_MULTISAMPLE, self.msaa_samples, self.framebuffer_format.internal_format(), width, height, gl::TRUE);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D_MULTISAMPLE, self.msaa_colour_texture, 0);
+
+            // Depth
+            gl::GenTextures(1, &mut self.msaa_depth_texture);
+            gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, self.msaa_depth_texture);
+            gl::TexImage2DMultisample(gl::TEXTURE_2D_MULTISAMPLE, self.msaa_samples, self.depth_format().0, width, height, gl::TRUE);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D_MULTISAMPLE, self.msaa_depth_texture, 0);
+
+            gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+
+        // Fall back to rendering without MSAA rather than into a broken framebuffer
+        if let Err(error) = check_framebuffer_status(self.msaa_framebuffer_object) {
+            warn!("MSAA framebuffer is incomplete, disabling MSAA: {error}");
+            self.delete_msaa_targets();
+            self.msaa_samples = 0;
+        }
+    }
 
+    fn delete_msaa_targets(&mut self) {
+        unsafe {
+            if self.msaa_framebuffer_object != 0 {
+                gl::DeleteFramebuffers(1, &self.msaa_framebuffer_object);
+                gl::DeleteTextures(1, &self.msaa_colour_texture);
+                gl::DeleteTextures(1, &self.msaa_depth_texture);
+            }
+        }
+        self.msaa_framebuffer_object = 0;
+        self.msaa_colour_texture = 0;
+        self.msaa_depth_texture = 0;
+    }
+	
     pub fn update_input(&mut self, input: &mut UserInput) {
+        profile_scope!("update_input");
         // Poll for and process events
-        self.glfw.poll_events();
+        input.begin_frame();
+        if self.iconified {
+            // Nothing gets rendered anyway, so don't spin
+            self.glfw.wait_events_timeout(0.1);
+        } else {
+            self.glfw.poll_events();
+        }
         for (_, event) in glfw::flush_messages(&self.events) {
-            input.process_event(&event);
+            match event {
+                WindowEvent::Focus(focused) => {
+                    self.focused = focused;
+                    self.resume_clock |= focused;
+                }
+                WindowEvent::Iconify(iconified) => {
+                    self.iconified = iconified;
+                    self.resume_clock |= !iconified;
+                }
+                _ => {}
+            }
+            if let Some(event) = input_glfw::translate_event(&event) {
+                input.process_event(&event);
+            }
         }
     }
 
     pub fn load_model(&mut self, path: &Path) -> Result<u64, u32> {
-        // Try to load model
-        let model = Model::load_gltf(path, self);
+        self.load_model_with_options(path, &LoadOptions::default())
+    }
+
+    pub fn load_model_with_options(&mut self, path: &Path, options: &LoadOptions) -> Result<u64, u32> {
+        profile_scope!("load_model");
+        // The model is still known by the path it was asked for, the resolved one is only used to read it
+        let file = match self.assets.resolve(path) {
+            Ok(file) => file,
+            Err(error) => {
+                error!("Error loading model: {error}");
+                return Err(0);
+            }
+        };
+
+        // Try to load model, picking the loader based on the file extension
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        let model = match extension.as_deref() {
+            Some("obj") => Model::load_obj(&file, self, options),
+            #[cfg(feature = "gltf-loader")]
+            _ => Model::load_gltf(&file, self, options),
+            #[cfg(not(feature = "gltf-loader"))]
+            _ => Err(format!("\"{}\" can't be loaded, glTF models need the gltf-loader feature", path.display())),
+        };
         if model.is_err() {
-            println!("Error loading model: {}", model.err().unwrap());
+            error!("Error loading model \"{}\": {}", path.display(), model.err().unwrap());
             return Err(0)
         }
         let mut model_cpu = model.unwrap();
+        model_cpu.apply_load_options(options);
+        model_cpu.recentre();
+        if model_cpu.origin != DVec3::ZERO {
+            info!("Recentred \"{}\" around ({:.0}, {:.0}, {:.0})", path.display(), model_cpu.origin.x, model_cpu.origin.y, model_cpu.origin.z);
+            // The first far away model brings the origin along, so the camera starts out near it
+            if self.origin == DVec3::ZERO {
+                self.set_origin(model_cpu.origin);
+            }
+        }
 
-        // Upload each submesh in the model to OpenGL
-        for (name, mesh) in &mut model_cpu.meshes {
-            println!("Parsing mesh \"{name}\"");
-
-            // Let's put this on the GPU shall we
-            unsafe {
-                // Create GPU buffers
-                gl::GenVertexArrays(1, &mut mesh.vao);
-                gl::GenBuffers(1, &mut mesh.vbo);
-
-                // Bind GPU buffers
-                gl::BindVertexArray(mesh.vao);
-                gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vbo);
-
-                // Define vertex layout
-                gl::VertexAttribPointer(
-                    0,
-                    3,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    size_of::<Vertex>() as i32,
-                    offset_of!(Vertex, position) as *const _,
-                );
-                gl::VertexAttribPointer(
-                    1,
-                    3,
-                    gl::FLOAT,
-                    gl::TRUE,
-                    size_of::<Vertex>() as i32,
-                    offset_of!(Vertex, normal) as *const _,
-                );
-                gl::VertexAttribPointer(
-                    2,
-                    4,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    size_of::<Vertex>() as i32,
-                    offset_of!(Vertex, tangent) as *const _,
-                );
-                gl::VertexAttribPointer(
-                    3,
-                    4,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    size_of::<Vertex>() as i32,
-                    offset_of!(Vertex, colour) as *const _,
-                );
-                gl::VertexAttribPointer(
-                    4,
-                    2,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    size_of::<Vertex>() as i32,
-                    offset_of!(Vertex, uv0) as *const _,
-                );
-                gl::VertexAttribPointer(
-                    5,
-                    2,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    size_of::<Vertex>() as i32,
-                    offset_of!(Vertex, uv1) as *const _,
-                );
-
-                // Enable each attribute
-                gl::EnableVertexAttribArray(0);
-                gl::EnableVertexAttribArray(1);
-                gl::EnableVertexAttribArray(2);
-                gl::EnableVertexAttribArray(3);
-                gl::EnableVertexAttribArray(4);
-                gl::EnableVertexAttribArray(5);
-
-                // Populate vertex buffer
-                gl::BufferData(
-                    gl::ARRAY_BUFFER,
-                    (size_of::<Vertex>() * mesh.verts.len()) as isize,
-                    &mesh.verts[0] as *const Vertex as *const c_void,
-                    gl::STATIC_DRAW,
-                );
-
-                // Unbind buffer
-                gl::BindVertexArray(0);
-                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        // Empty meshes have nothing to upload or draw
+        model_cpu.meshes.retain(|name, mesh| {
+            if mesh.verts.is_empty() {
+                warn!("Mesh \"{name}\" in \"{}\" has no triangles, skipping it", path.display());
+            }
+            !mesh.verts.is_empty()
+        });
+        if model_cpu.meshes.is_empty() {
+            warn!("Model \"{}\" has no geometry", path.display());
+        }
 
-                // If we get an error, stop and don't return the model - this should be very unlikely though
-                let error = gl::GetError();
-                if error != gl::NO_ERROR {
-                    return Err(error);
+        // Upload each submesh in the model to OpenGL
+        let uploaded = model_cpu.meshes.iter_mut().try_for_each(|(name, mesh)| {
+            debug!("Parsing mesh \"{name}\"");
+            Self::upload_mesh(mesh)?;
+            mesh.lods.iter_mut().try_for_each(Self::upload_mesh)
+        });
+        if let Err(error) = uploaded {
+            // The vertex buffers are freed when the model drops, the vertex arrays created so far are not
+            for mesh in model_cpu.meshes.values() {
+                for lod in std::iter::once(mesh).chain(&mesh.lods).filter(|lod| lod.vao != 0) {
+                    unsafe {
+                        gl::DeleteVertexArrays(1, &lod.vao);
+                    }
                 }
             }
+            return Err(error);
         }
 
-        // Upload each material
         for (name, material) in &model_cpu.materials {
-            // Combine name to follow this scheme "test.gltf::materials/mat_name/albedo"
-            let _new_name = format!("{}::materials/{}/albedo", path.display(), name);
-            println!("{:?}", material);
+            debug!("Material \"{name}\": {material:?}");
+        }
+        for mesh in model_cpu.meshes.values_mut() {
+            mesh.release_cpu_data(options.cpu_data);
         }
 
         // Calculate hash
@@ -419,72 +4002,558 @@ impl Renderer {
         let hash_id = s.finish();
 
         // Insert model in to model map
+        self.record_change(ChangeOperation::ModelLoad, hash_id, model_cpu.vertex_bytes());
         self.models.insert(hash_id, model_cpu);
+        self.model_load_args.insert(hash_id, (path.to_path_buf(), options.clone()));
 
         // Return the handle
         Ok(hash_id)
     }
 
+    // Creates the mesh's vertex array and uploads its vertices
+    fn upload_mesh(mesh: &mut Mesh) -> Result<(), GLenum> {
+        // Let's put this on the GPU shall we
+        unsafe {
+            // Create GPU buffers
+            gl::GenVertexArrays(1, &mut mesh.vao);
+            gl::BindVertexArray(mesh.vao);
+
+            // Populate vertex buffer, which also leaves it bound for the layout below
+            mesh.vbo.upload(&mesh.verts);
+
+            // Define vertex layout
+            gl::VertexAttribPointer(
+                0,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, position) as *const _,
+            );
+            gl::VertexAttribPointer(
+                1,
+                3,
+                gl::FLOAT,
+                gl::TRUE,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, normal) as *const _,
+            );
+            gl::VertexAttribPointer(
+                2,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, tangent) as *const _,
+            );
+            gl::VertexAttribPointer(
+                3,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, colour) as *const _,
+            );
+            gl::VertexAttribPointer(
+                4,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, uv0) as *const _,
+            );
+            gl::VertexAttribPointer(
+                5,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>() as i32,
+                offset_of!(Vertex, uv1) as *const _,
+            );
+
+            // Enable each attribute
+            gl::EnableVertexAttribArray(0);
+            gl::EnableVertexAttribArray(1);
+            gl::EnableVertexAttribArray(2);
+            gl::EnableVertexAttribArray(3);
+            gl::EnableVertexAttribArray(4);
+            gl::EnableVertexAttribArray(5);
+
+            // Unbind buffer
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            // If we get an error, stop and don't return the model - this should be very unlikely though
+            let error = gl::GetError();
+            if error != gl::NO_ERROR {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn model_info(&self, handle: u64) -> Option<ModelInfo> {
+        let model = self.models.get(&handle)?;
+        Some(ModelInfo {
+            mesh_count: model.meshes.len(),
+            meshes: {
+                let mut names: Vec<String> = model.meshes.keys().cloned().collect();
+                names.sort();
+                names
+            },
+            bounds: model.bounds().translated(self.model_offset(model)),
+            lod_triangle_counts: model.lod_triangle_counts(),
+            scenes: model.scenes.clone(),
+            cpu_bytes: model.cpu_bytes(),
+        })
+    }
+
     pub fn draw_model(&mut self, model_id: &u64) {
+        self.draw_model_in_layer(model_id, 0);
+    }
+
+    // Lower layers are drawn first, within a layer the order is fixed but otherwise unspecified
+    pub fn draw_model_in_layer(&mut self, model_id: &u64, layer: u8) {
+        self.queue_model(model_id, layer, Mat4::IDENTITY, Vec4::ONE);
+    }
+
+    // Draws a copy of the model moved by `transform`, with its material colour and alpha multiplied by `tint` for this
+    // submission only. Below 1 alpha it gets blended over the scene in the transparent pass
+    pub fn draw_model_tinted(&mut self, model_id: &u64, transform: &Transform, tint: Vec4) {
+        self.queue_model(model_id, 0, transform.trans_matrix(), tint);
+    }
+
+    fn queue_model(&mut self, model_id: &u64, layer: u8, model_matrix: Mat4, tint: Vec4) {
         // Render each mesh separately
         if !self.models.contains_key(model_id) {
             return;
         }
-        for (name, mesh) in &self.models.get(model_id).unwrap().meshes {
-            self.mesh_queue
-                .add(MeshQueueEntry {
-                    vao: mesh.vao,
-                    vbo: mesh.vbo,
-                    n_vertices: mesh.verts.len() as i32,
-                    material: self.models.get(model_id).unwrap().materials.get(name).unwrap().clone(),
-                })
-                .expect("Failed to add mesh to mesh queue");
+        let camera_position = self.camera_view_matrix.inverse().w_axis.truncate();
+        // Custom shaders that failed to build again after the GL resources got recreated have no program
+        let program = match self.model_shaders.get(model_id).and_then(|shader| self.custom_shaders.get(&shader.0)) {
+            Some(shader) if shader.program != 0 => shader.program,
+            _ => self.triangle_shader,
+        };
+        let model = self.models.get_mut(model_id).unwrap();
+        let model_matrix = Mat4::from_translation((model.origin - self.origin).as_vec3()) * model_matrix;
+
+        // Where this submission was last frame. One that wasn't there yet starts out still
+        let submission = self.object_submissions.entry(*model_id).or_insert(0);
+        let key = (*model_id, *submission);
+        *submission += 1;
+        let previous_model_matrix = previous_model_matrix(self.previous_object_transforms.get(&key), &model_matrix, &model.bounds());
+        self.object_transforms.insert(key, model_matrix);
+        for (name, mesh) in &mut model.meshes {
+            let mut hasher = DefaultHasher::new();
+            name.hash(&mut hasher);
+
+            // Pick the LOD from the main camera. Vertices are baked into world space at load time, so the mesh bounds
+            // only need the submission's own transform
+            let mut bounds = mesh.bounds.transformed(&model_matrix);
+            let centre = (bounds.min + bounds.max) * 0.5;
+            let radius = (bounds.max - bounds.min).length() * 0.5;
+            mesh.lod_level = self.lod_settings.select(mesh.lod_level, mesh.lods.len(), camera_position.distance(centre), radius);
+            let lod = mesh.lod(mesh.lod_level);
+            let material = match model.material_overrides.get(name) {
+                Some(handle) => self.material_library[handle].clone(),
+                None => model.materials.get(name).unwrap().clone(),
+            };
+
+            // Swaying foliage can leave its bounds sideways, which occlusion culling has to know about
+            if self.wind.enabled && material.scl_wind > 0.0 {
+                let up = self.world_up.up();
+                let reach = self.wind.reach((bounds.max - bounds.min).dot(up)) * material.scl_wind;
+                bounds.min -= (Vec3::ONE - up) * reach;
+                bounds.max += (Vec3::ONE - up) * reach;
+            }
+            let alpha_mask = material.alpha_mode == AlphaMode::Mask;
+            self.mesh_queue.push(MeshQueueEntry {
+                vao: lod.vao,
+                vbo: lod.vbo.id(),
+                n_vertices: lod.vertex_count() as i32,
+                material,
+                bounds,
+                lod_level: mesh.lod_level,
+                model_matrix,
+                previous_model_matrix,
+                tint,
+                program,
+                sort_key: DrawSortKey {
+                    layer,
+                    alpha_mask,
+                    program,
+                    material_hash: hasher.finish(),
+                    model_id: *model_id,
+                },
+            });
         }
     }
 
-    pub fn load_shader(&mut self, path: &Path) -> Result<u32, &str> {
-        // Create shader program object
-        let program;
-        unsafe {
-            program = gl::CreateProgram();
+    pub fn load_shader(&mut self, path: &Path) -> Result<u32, String> {
+        profile_scope!("load_shader");
+        let program = self.build_program(&[
+            (gl::VERTEX_SHADER, path.with_extension("vert")),
+            (gl::FRAGMENT_SHADER, path.with_extension("frag")),
+        ])?;
+
+        // Catch structs whose GLSL copy has drifted out of sync, when the driver can tell us the offsets
+        for validate in GPU_LAYOUTS.iter().filter(|_| self.capabilities.program_interface_query) {
+            validate(program).map_err(|error| format!("Layout mismatch in shader \"{}\": {error}", path.display()))?;
         }
 
-        // Load and compile shader parts
-        load_shader_part(
-            gl::VERTEX_SHADER,
-            path.with_extension("vert").as_path(),
-            program,
-        );
-        load_shader_part(
-            gl::FRAGMENT_SHADER,
-            path.with_extension("frag").as_path(),
-            program,
-        );
+        Ok(program)
+    }
+
+    pub fn load_compute_shader(&mut self, path: &Path) -> Result<u32, String> {
+        profile_scope!("load_shader");
+        self.build_program(&[(gl::COMPUTE_SHADER, path.with_extension("comp"))])
+    }
+
+    // Links a program from its shader files, or loads the binary the shader cache has of the same sources
+    fn build_program(&mut self, parts: &[(GLenum, PathBuf)]) -> Result<u32, String> {
+        let parts: Vec<(GLenum, PathBuf)> = parts
+            .iter()
+            .map(|(shader_type, path)| Ok((*shader_type, self.assets.resolve(path)?)))
+            .collect::<Result<_, String>>()?;
+        let sources: Vec<(GLenum, String)> = parts
+            .iter()
+            .map(|(shader_type, path)| Ok((*shader_type, read_shader_source(path)?)))
+            .collect::<Result<_, String>>()?;
+        let program = unsafe { gl::CreateProgram() };
+        let key = self.shader_cache.as_ref().map(|cache| cache.key(&sources));
+        if let (Some(cache), Some(key)) = (&self.shader_cache, key) {
+            if cache.load(program, key) {
+                self.shaders_from_cache += 1;
+                return Ok(program);
+            }
+        }
+
+        // Compile and link, keeping the binary for next time
+        let mut linked = 0;
         unsafe {
+            for ((shader_type, source), (_, path)) in sources.iter().zip(&parts) {
+                load_shader_part(*shader_type, path, source, program);
+            }
+            gl::ProgramParameteri(program, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as i32);
             gl::LinkProgram(program);
+            gl::GetProgramiv(program, gl::LINK_STATUS, &mut linked);
+        }
+        self.shaders_compiled += 1;
+        if linked == 0 {
+            error!("Failed to link \"{}\":\n{}", parts[0].1.with_extension("").display(), program_info_log(program));
+        }
+        if let (Some(cache), Some(key), true) = (&self.shader_cache, key, linked != 0) {
+            cache.store(program, key);
         }
-
         Ok(program)
     }
 
-    pub fn upload_texture(&self, texture: &mut Texture) -> u32{
-        unsafe {
-            gl::GenTextures(1, &mut texture.gl_id);
-            gl::BindTexture(gl::TEXTURE_2D, texture.gl_id);
-            gl::TexImage2D(gl::TEXTURE_2D, 0,  gl::RGBA8 as i32, texture.width as i32, texture.height as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE, texture.data.as_ptr()  as *const _);
-            gl::GenerateMipmap(gl::TEXTURE_2D);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    // Where linked shader binaries get cached, None compiles every shader every time. Applies to shaders loaded
+    // afterwards, the built-in ones were loaded from the default cache directory when the renderer was created
+    pub fn set_shader_cache(&mut self, config: Option<ShaderCacheConfig>) {
+        self.shader_cache = config.filter(|_| self.capabilities.program_binary).map(ShaderCache::new);
+    }
+
+    // Logs and records a texture that failed to load, returns the placeholder texture to use instead
+    pub(crate) fn missing_texture(&mut self, material: &str, slot: TextureSlot, reason: String) -> i32 {
+        warn!("{slot:?} texture of material \"{material}\" could not be loaded: {reason}");
+        self.missing_textures.push(MissingTexture {
+            material: material.to_string(),
+            slot,
+            reason,
+        });
+        self.placeholder_texture as i32
+    }
+
+    // Uploads a texture file once, every later request for the same file gets the same texture
+    pub(crate) fn load_shared_texture(&mut self, path: &Path, decode: impl FnOnce() -> Result<Texture, String>) -> Result<u32, String> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(&(gl_id, bytes)) = self.shared_textures.get(&key) {
+            self.shared_texture_bytes_saved += bytes;
+            return Ok(gl_id);
+        }
+        let mut texture = decode()?;
+        let bytes = texture.data.len() * size_of::<u32>();
+        let gl_id = self.upload_texture(&mut texture);
+        self.shared_textures.insert(key, (gl_id, bytes));
+        Ok(gl_id)
+    }
+
+    // Whether a texture file was already uploaded, so loading it again would share it
+    #[cfg(feature = "gltf-loader")]
+    pub(crate) fn has_shared_texture(&self, path: &Path) -> bool {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.shared_textures.contains_key(&key)
+    }
+
+    // Adds a material to the library, or finds the identical one already in it
+    pub fn load_material(&mut self, descriptor: &MaterialDescriptor) -> MaterialHandle {
+        let mut material = Material::named(&descriptor.name);
+        material.scl_rgh = descriptor.roughness;
+        material.scl_mtl = descriptor.metallic;
+        material.scl_emm = descriptor.emissive;
+        material.custom = descriptor.custom;
+        material.sort_bias = descriptor.sort_bias;
+        let textures = [
+            (&descriptor.albedo, TextureSlot::Albedo),
+            (&descriptor.metallic_roughness, TextureSlot::MetallicRoughness),
+            (&descriptor.height, TextureSlot::Height),
+        ];
+        for (path, slot) in textures {
+            let Some(path) = path else {
+                continue;
+            };
+            let resolved = self.assets.resolve(path);
+            let max_dimension = self.texture_quality.max_dimension as usize;
+            let gl_id = match resolved.and_then(|file| self.load_shared_texture(&file, || Texture::load(&file, max_dimension))) {
+                Ok(gl_id) => gl_id as i32,
+                Err(reason) => self.missing_texture(&descriptor.name, slot, format!("\"{}\": {reason}", path.display())),
+            };
+            match slot {
+                TextureSlot::MetallicRoughness => material.tex_mtl_rgh = gl_id,
+                TextureSlot::Height => material.tex_hgt = gl_id,
+                _ => material.tex_alb = gl_id,
+            }
+        }
+        let handle = MaterialHandle(material.content_hash());
+        if let Entry::Vacant(entry) = self.material_library.entry(handle) {
+            entry.insert(material);
+            self.record_change(ChangeOperation::MaterialLoad, handle.0, 0);
+        }
+        self.material_descriptors.entry(handle).or_insert_with(|| descriptor.clone());
+        handle
+    }
+
+    // Draws a mesh of a model with a library material from the next frame on, instead of its own material
+    pub fn reassign_material(&mut self, model: u64, mesh: &str, material: MaterialHandle) -> Result<(), String> {
+        if !self.material_library.contains_key(&material) {
+            return Err(format!("Material {material:?} is not in the material library"));
+        }
+        let handle = model;
+        let model = self.models.get_mut(&handle).ok_or_else(|| format!("Model {handle} is not loaded"))?;
+        if !model.meshes.contains_key(mesh) {
+            return Err(format!("The model has no mesh \"{mesh}\""));
+        }
+        model.material_overrides.insert(mesh.to_string(), material);
+        self.record_change(ChangeOperation::MaterialReassign, handle, 0);
+        Ok(())
+    }
+
+    // Sets the custom shader parameters of a mesh's own material, from the next frame on. Other meshes keep theirs,
+    // even where the materials were identical. A library material reassigned to the mesh is left alone, it gets its
+    // parameters from its descriptor. Reloading the model reads them from the file again
+    pub fn set_material_parameters(&mut self, model: u64, mesh: &str, parameters: CustomParameters) -> Result<(), String> {
+        let handle = model;
+        let model = self.models.get_mut(&handle).ok_or_else(|| format!("Model {handle} is not loaded"))?;
+        let material = model.materials.get_mut(mesh).ok_or_else(|| format!("The model has no mesh \"{mesh}\""))?;
+        material.custom = parameters;
+        self.record_change(ChangeOperation::MaterialParameters, handle, 0);
+        Ok(())
+    }
+
+    // Sets the sort bias of a mesh's own material, see Material::sort_bias. Like set_material_parameters, it leaves
+    // reassigned library materials alone and lasts until the model is reloaded
+    pub fn set_material_sort_bias(&mut self, model: u64, mesh: &str, sort_bias: f32) -> Result<(), String> {
+        let handle = model;
+        let model = self.models.get_mut(&handle).ok_or_else(|| format!("Model {handle} is not loaded"))?;
+        let material = model.materials.get_mut(mesh).ok_or_else(|| format!("The model has no mesh \"{mesh}\""))?;
+        material.sort_bias = sort_bias;
+        self.record_change(ChangeOperation::MaterialParameters, handle, 0);
+        Ok(())
+    }
+
+    pub fn material_library_stats(&self) -> MaterialLibraryStats {
+        MaterialLibraryStats {
+            shared_textures: self.shared_textures.len(),
+            bytes_saved: self.shared_texture_bytes_saved,
+            materials: self.material_library.len(),
+        }
+    }
+
+    pub fn missing_texture_report(&self) -> Vec<MissingTexture> {
+        self.missing_textures.clone()
+    }
+
+    pub fn upload_texture(&mut self, texture: &mut Texture) -> u32{
+        texture.gl_id = self.textures.create(texture.width, texture.height);
+
+        // When streaming, only upload a small preview for now, and keep the full resolution data to upload later.
+        // The data is moved out of the texture that was passed in
+        if self.texture_streaming.enabled && (texture.width > STREAMING_PREVIEW_SIZE || texture.height > STREAMING_PREVIEW_SIZE) {
+            let preview = texture.downsampled(STREAMING_PREVIEW_SIZE);
+            self.textures.update(texture.gl_id, &preview);
+            self.record_change(ChangeOperation::TextureUpload, texture.gl_id as u64, preview.data.len() * size_of::<u32>());
+            self.streamed_textures.insert(
+                texture.gl_id,
+                Texture {
+                    gl_id: texture.gl_id,
+                    width: texture.width,
+                    height: texture.height,
+                    depth: texture.depth,
+                    data: std::mem::take(&mut texture.data),
+                },
+            );
+        } else {
+            self.textures.update(texture.gl_id, texture);
+            self.record_change(ChangeOperation::TextureUpload, texture.gl_id as u64, texture.data.len() * size_of::<u32>());
         }
         return texture.gl_id;
     }
+
+    // GPU memory taken by material textures. The array backend counts whole arrays, unused layers included
+    pub fn texture_memory(&self) -> usize {
+        self.textures.memory_bytes()
+    }
+
+    pub fn texture_backend(&self) -> TextureBackend {
+        self.texture_backend
+    }
+
+    fn record_change(&mut self, operation: ChangeOperation, handle: u64, bytes: usize) {
+        self.change_journal.record(self.frame_index, operation, handle, bytes);
+    }
+
+    // The last `count` state changes, oldest first. Always empty without the change-journal feature
+    pub fn recent_changes(&self, count: usize) -> Vec<ChangeRecord> {
+        self.change_journal.recent(count).copied().collect()
+    }
+
+    // The last `count` state changes as text, grouped by frame, with frames that uploaded a lot marked
+    pub fn dump_change_journal(&self, count: usize) -> String {
+        self.change_journal.dump(count)
+    }
+}
+// Back to front order of a transparent mesh. Distances, offset by the material's sort bias, go into buckets of
+// epsilon, so near ties don't flip with tiny camera moves. Meshes in the same bucket, exact ties included, render in
+// the order of their material, mesh and model, whatever order they were submitted in
+type TransparentSortKey = (Reverse<i64>, u64, u64, u64, [u32; 7]);
+
+fn transparent_sort_key(mesh: &MeshQueueEntry, camera_position: Vec3, epsilon: f32) -> TransparentSortKey {
+    let centre = (mesh.bounds.min + mesh.bounds.max) * 0.5;
+    let bucket = ((camera_position.distance(centre) + mesh.material.sort_bias) / epsilon.max(1e-6)).floor() as i64;
+    let key = &mesh.sort_key;
+    let placement = [centre.x, centre.y, centre.z, mesh.tint.x, mesh.tint.y, mesh.tint.z, mesh.tint.w].map(f32::to_bits);
+    (Reverse(bucket), mesh.material.content_hash(), key.material_hash, key.model_id, placement)
+}
+
+// Whether any corner of the bounds moved further than TELEPORT_DISTANCE between the two transforms
+fn teleported(previous: &Mat4, current: &Mat4, bounds: &AABB) -> bool {
+    let corners = (0..8).map(|i| {
+        Vec3::new(
+            if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+            if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+            if i & 4 == 0 { bounds.min.z } else { bounds.max.z },
+        )
+    });
+    let points: Vec<Vec3> = if bounds.is_empty() { vec![Vec3::ZERO] } else { corners.collect() };
+    points.iter().any(|&point| previous.transform_point3(point).distance(current.transform_point3(point)) > TELEPORT_DISTANCE)
+}
+
+// The model matrix a submission's motion is drawn from. One that wasn't there last frame starts out still, one that
+// teleported gets None so its pixels drop their history
+fn previous_model_matrix(previous: Option<&Mat4>, current: &Mat4, bounds: &AABB) -> Option<Mat4> {
+    match previous {
+        Some(previous) if teleported(previous, current, bounds) => None,
+        Some(previous) => Some(*previous),
+        None => Some(*current),
+    }
+}
+
+// Radical inverse of the index in the given base, a low-discrepancy sequence in [0, 1)
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// What the window's back buffer turned out to be, the current context has to belong to the window
+fn detect_output_encoding() -> OutputEncoding {
+    let (mut component_type, mut red_bits, mut colour_encoding) = (0, 0, 0);
+    unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::GetFramebufferAttachmentParameteriv(gl::FRAMEBUFFER, gl::BACK_LEFT, gl::FRAMEBUFFER_ATTACHMENT_COMPONENT_TYPE, &mut component_type);
+        gl::GetFramebufferAttachmentParameteriv(gl::FRAMEBUFFER, gl::BACK_LEFT, gl::FRAMEBUFFER_ATTACHMENT_RED_SIZE, &mut red_bits);
+        gl::GetFramebufferAttachmentParameteriv(gl::FRAMEBUFFER, gl::BACK_LEFT, gl::FRAMEBUFFER_ATTACHMENT_COLOR_ENCODING, &mut colour_encoding);
+    }
+    if cfg!(feature = "hdr-output") {
+        if component_type as GLenum == gl::FLOAT && red_bits >= 16 {
+            return OutputEncoding::ScRgb;
+        }
+        if red_bits >= 10 {
+            return OutputEncoding::Pq;
+        }
+        warn!("No deep colour framebuffer available, falling back to SDR output");
+    }
+    match colour_encoding as GLenum {
+        gl::SRGB => OutputEncoding::SrgbFramebuffer,
+        _ => OutputEncoding::SrgbShader,
+    }
+}
+
+fn check_framebuffer_status(framebuffer: u32) -> Result<(), FramebufferError> {
+    let status = unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        status
+    };
+    match status {
+        gl::FRAMEBUFFER_COMPLETE => Ok(()),
+        gl::FRAMEBUFFER_UNDEFINED => Err(FramebufferError::Undefined),
+        gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => Err(FramebufferError::IncompleteAttachment),
+        gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => Err(FramebufferError::MissingAttachment),
+        gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => Err(FramebufferError::IncompleteDrawBuffer),
+        gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => Err(FramebufferError::IncompleteReadBuffer),
+        gl::FRAMEBUFFER_UNSUPPORTED => Err(FramebufferError::Unsupported),
+        gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => Err(FramebufferError::IncompleteMultisample),
+        gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => Err(FramebufferError::IncompleteLayerTargets),
+        _ => Err(FramebufferError::Unknown(status)),
+    }
+}
+
+fn program_info_log(program: u32) -> String {
+    let mut log_length = 0;
+    unsafe {
+        gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut log_length);
+    }
+    let mut log = vec![0u8; log_length.max(0) as usize];
+    unsafe {
+        gl::GetProgramInfoLog(program, log_length, std::ptr::null_mut(), log.as_mut_ptr().cast());
+    }
+    String::from_utf8_lossy(&log).trim_end_matches('\0').trim_end().to_string()
 }
-fn load_shader_part(shader_type: GLenum, path: &Path, program: u32) {
-    // Load shader source
-    let mut file = File::open(path).expect("Failed to open shader file");
-    let mut source = String::new();
-    file.read_to_string(&mut source)
-        .expect("Failed to read file");
+
+fn read_shader_source(path: &Path) -> Result<String, String> {
+    let read = |path: &Path| {
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut source))
+            .map_err(|error| format!("Failed to read shader \"{}\": {error}", path.display()))?;
+        Ok(source)
+    };
+    expand_includes(path, &read(path)?, read)
+}
+
+// Replaces every `#include "file"` line with that file, looked up next to the shader. Includes don't nest, they're
+// shared functions like random.glsl
+fn expand_includes(path: &Path, source: &str, read: impl Fn(&Path) -> Result<String, String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(source.len());
+    for line in source.lines() {
+        match line.trim().strip_prefix("#include") {
+            Some(name) => expanded += &read(&path.with_file_name(name.trim().trim_matches('"')))?,
+            None => expanded += line,
+        }
+        expanded.push('\n');
+    }
+    Ok(expanded)
+}
+
+fn load_shader_part(shader_type: GLenum, path: &Path, source: &str, program: u32) {
     let source_len = source.len() as i32;
 
     unsafe {
@@ -506,15 +4575,182 @@ fn load_shader_part(shader_type: GLenum, path: &Path, program: u32) {
             error_message.as_mut_ptr().cast(),
         );
 
-        // Did we get an error?
-        if log_length > 0 {
-            println!(
-                "Shader compilation error!\n{}",
-                std::str::from_utf8(error_message.as_slice()).unwrap()
-            )
+        // Did we get an error? Drivers put warnings in the log too
+        let message = String::from_utf8_lossy(&error_message);
+        let message = message.trim_end_matches('\0').trim_end();
+        if result == 0 {
+            error!("Failed to compile \"{}\":\n{message}", path.display());
+        } else if !message.is_empty() {
+            warn!("Compiled \"{}\" with warnings:\n{message}", path.display());
         }
 
         // Attach to program
         gl::AttachShader(program, shader);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> AABB {
+        let mut bounds = AABB::new();
+        bounds.grow(Vec3::ZERO);
+        bounds.grow(Vec3::ONE);
+        bounds
+    }
+
+    #[test]
+    fn const_buffer_layout_table() {
+        let offsets: Vec<(&str, usize)> = GlobalConstBuffer::fields().iter().map(|field| (field.rust_name, field.offset)).collect();
+        assert_eq!(
+            offsets,
+            [
+                ("view_projection_matrix", offset_of!(GlobalConstBuffer, view_projection_matrix)),
+                ("camera_position", offset_of!(GlobalConstBuffer, camera_position)),
+                ("time_seconds", offset_of!(GlobalConstBuffer, time_seconds)),
+                ("delta_time", offset_of!(GlobalConstBuffer, delta_time)),
+                ("frame_index", offset_of!(GlobalConstBuffer, frame_index)),
+            ]
+        );
+
+        // The std140 offsets the const_buffer block gets, and a size that's a whole number of vec4s
+        assert_eq!(offsets.iter().map(|&(_, offset)| offset).collect::<Vec<_>>(), [0, 64, 80, 84, 88]);
+        assert_eq!(size_of::<GlobalConstBuffer>() % 16, 0);
+        assert!(GlobalConstBuffer::fields().iter().all(|field| field.glsl_name.to_bytes().starts_with(b"u_")));
+    }
+
+    fn draw_key(layer: u8, material_name: &str, model_id: u64) -> DrawSortKey {
+        let mut hasher = DefaultHasher::new();
+        material_name.hash(&mut hasher);
+        DrawSortKey { layer, alpha_mask: false, program: 0, material_hash: hasher.finish(), model_id }
+    }
+
+    #[test]
+    fn draw_order_ignores_submission_order() {
+        let keys = [draw_key(0, "Stone", 2), draw_key(0, "Stone", 1), draw_key(0, "Glass", 1), draw_key(1, "Sky", 0)];
+        let mut forwards = keys.to_vec();
+        let mut backwards: Vec<DrawSortKey> = keys.iter().rev().copied().collect();
+        forwards.sort();
+        backwards.sort();
+        assert_eq!(forwards, backwards);
+
+        // Layers first, then masked materials and programs are grouped, then material and model break ties
+        assert_eq!(forwards.last(), Some(&draw_key(1, "Sky", 0)));
+        let masked = DrawSortKey { alpha_mask: true, ..draw_key(0, "Stone", 1) };
+        assert!(masked > draw_key(0, "Stone", 2) && masked < draw_key(1, "Sky", 0));
+        assert!(draw_key(0, "Stone", 1) < draw_key(0, "Stone", 2));
+    }
+
+    #[test]
+    fn material_hashes_are_stable() {
+        // DefaultHasher::new() uses fixed keys, so the draw order is the same in every run
+        assert_eq!(draw_key(0, "Stone", 0), draw_key(0, "Stone", 0));
+        assert_ne!(draw_key(0, "Stone", 0).material_hash, draw_key(0, "Glass", 0).material_hash);
+    }
+
+    fn transparent_quad(centre: Vec3, material_hash: u64, sort_bias: f32) -> MeshQueueEntry {
+        let mut bounds = AABB::new();
+        bounds.grow(centre - Vec3::new(1.0, 1.0, 0.0));
+        bounds.grow(centre + Vec3::new(1.0, 1.0, 0.0));
+        let mut material = Material::new();
+        material.sort_bias = sort_bias;
+        MeshQueueEntry {
+            vao: 0,
+            vbo: 0,
+            n_vertices: 6,
+            material,
+            bounds,
+            lod_level: 0,
+            model_matrix: Mat4::IDENTITY,
+            previous_model_matrix: Some(Mat4::IDENTITY),
+            tint: Vec4::new(1.0, 1.0, 1.0, 0.5),
+            program: 0,
+            sort_key: DrawSortKey {
+                layer: 0,
+                alpha_mask: false,
+                program: 0,
+                material_hash,
+                model_id: 1,
+            },
+        }
+    }
+
+    fn sorted(meshes: &[MeshQueueEntry], camera_position: Vec3) -> Vec<u64> {
+        let mut sorted: Vec<&MeshQueueEntry> = meshes.iter().collect();
+        sorted.sort_by_cached_key(|mesh| transparent_sort_key(mesh, camera_position, TRANSPARENT_SORT_EPSILON));
+        sorted.iter().map(|mesh| mesh.sort_key.material_hash).collect()
+    }
+
+    #[test]
+    fn coplanar_transparent_quads_sort_deterministically() {
+        let a = transparent_quad(Vec3::ZERO, 1, 0.0);
+        let b = transparent_quad(Vec3::new(0.001, 0.0, 0.0), 2, 0.0);
+        let camera_position = Vec3::new(0.0, 0.0, 10.0);
+        let expected = sorted(&[a.clone(), b.clone()], camera_position);
+        assert_eq!(sorted(&[b, a], camera_position), expected);
+    }
+
+    #[test]
+    fn sort_bias_flips_transparent_order() {
+        let camera_position = Vec3::new(0.0, 0.0, 10.0);
+        let near = transparent_quad(Vec3::new(0.0, 0.0, 1.0), 1, 0.0);
+        let far = transparent_quad(Vec3::ZERO, 2, 0.0);
+        assert_eq!(sorted(&[near.clone(), far.clone()], camera_position), [2, 1]);
+
+        // A positive bias draws the mesh as if it was further away
+        let near = transparent_quad(Vec3::new(0.0, 0.0, 1.0), 1, 2.0);
+        assert_eq!(sorted(&[near, far], camera_position), [1, 2]);
+    }
+
+    #[test]
+    fn new_object_starts_still() {
+        let current = Mat4::from_translation(Vec3::new(100.0, 0.0, 0.0));
+        assert_eq!(previous_model_matrix(None, &current, &unit_box()), Some(current));
+    }
+
+    #[test]
+    fn moving_object_keeps_previous_matrix() {
+        let previous = Mat4::from_translation(Vec3::new(1.0, 0.0, 0.0));
+        let current = Mat4::from_translation(Vec3::new(1.5, 0.0, 0.0));
+        assert_eq!(previous_model_matrix(Some(&previous), &current, &unit_box()), Some(previous));
+    }
+
+    #[test]
+    fn teleported_object_resets_history() {
+        let previous = Mat4::IDENTITY;
+        let current = Mat4::from_translation(Vec3::new(0.0, TELEPORT_DISTANCE + 1.0, 0.0));
+        assert_eq!(previous_model_matrix(Some(&previous), &current, &unit_box()), None);
+    }
+
+    #[test]
+    fn spinning_large_object_teleports() {
+        // The centre stays put, but the far corners swing further than the threshold
+        let mut bounds = AABB::new();
+        bounds.grow(Vec3::splat(-10.0));
+        bounds.grow(Vec3::splat(10.0));
+        let current = Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        assert!(teleported(&Mat4::IDENTITY, &current, &bounds));
+        assert!(!teleported(&Mat4::IDENTITY, &current, &unit_box()));
+    }
+
+    #[test]
+    fn empty_bounds_use_the_origin() {
+        let current = Mat4::from_translation(Vec3::new(TELEPORT_DISTANCE * 0.5, 0.0, 0.0));
+        assert!(!teleported(&Mat4::IDENTITY, &current, &AABB::new()));
+    }
+
+    #[test]
+    fn includes_are_pasted_in_place() {
+        let read = |path: &Path| match path.to_str() {
+            Some("shaders/random.glsl") => Ok("float random() { return 0.5; }".to_string()),
+            _ => Err(format!("Failed to read shader \"{}\"", path.display())),
+        };
+        let source = "#version 420 core\n#include \"random.glsl\"\nvoid main() {}";
+        let expanded = expand_includes(Path::new("shaders/dissolve.frag"), source, read).unwrap();
+        assert_eq!(expanded, "#version 420 core\nfloat random() { return 0.5; }\nvoid main() {}\n");
+
+        let missing = expand_includes(Path::new("shaders/dissolve.frag"), "#include \"noise.glsl\"", read);
+        assert_eq!(missing, Err("Failed to read shader \"shaders/noise.glsl\"".to_string()));
+    }
+}
@@ -0,0 +1,117 @@
+use glam::{Mat4, Vec2};
+use std::{mem::size_of, path::Path};
+
+use crate::{
+    journal::ChangeOperation,
+    profile_scope,
+    structs::{Rect, Transform},
+    texture::{Texture, TextureBinder, TextureSlot},
+    texture_store,
+};
+
+use super::{Decal, DecalHandle, Renderer};
+
+impl Renderer {
+    // Projects a texture onto the opaque geometry inside a box. The box is `size` wide and high, and as deep as it is
+    // narrow, centred on the transform, and the decal faces the transform's +Z. Surfaces whose normal is closer than
+    // `normal_fade` (a cosine) to perpendicular with the decal are skipped, so decals don't smear down the sides of
+    // whatever they hit
+    pub fn add_decal(&mut self, transform: &Transform, size: Vec2, texture: &Path, normal_fade: f32) -> Result<DecalHandle, String> {
+        let texture = self.assets.resolve(texture).map_err(|error| format!("Failed to load decal texture: {error}"))?;
+        let texture = texture.as_path();
+        let mut texture_bytes = 0;
+        let texture = match self.decal_textures.get(texture) {
+            Some(&gl_id) => gl_id,
+            None => {
+                // Decals sample their texture directly, it's never a layer of the material texture arrays
+                let image = Texture::load(texture, self.texture_quality.max_dimension as usize).map_err(|error| format!("Failed to load decal texture: {error}"))?;
+                let mut gl_id = 0;
+                unsafe {
+                    gl::GenTextures(1, &mut gl_id);
+                }
+                texture_store::upload_texture_2d(gl_id, &image);
+                texture_bytes = image.data.len() * size_of::<u32>();
+                unsafe {
+                    // Don't repeat the texture past the edges of the box
+                    gl::BindTexture(gl::TEXTURE_2D, gl_id);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+                    gl::BindTexture(gl::TEXTURE_2D, 0);
+                }
+                self.decal_textures.insert(texture.to_path_buf(), gl_id);
+                gl_id
+            }
+        };
+
+        let handle = self.next_decal;
+        self.next_decal += 1;
+        self.decals.insert(
+            handle,
+            Decal {
+                matrix: transform.trans_matrix() * Mat4::from_scale(size.extend(size.min_element())),
+                texture,
+                normal_fade: normal_fade.clamp(0.0, 0.99),
+            },
+        );
+        self.record_change(ChangeOperation::DecalAdd, handle as u64, texture_bytes);
+        Ok(DecalHandle(handle))
+    }
+
+    // Returns false if the decal was already removed. Its texture stays loaded for the next decal that uses it
+    pub fn remove_decal(&mut self, handle: DecalHandle) -> bool {
+        let removed = self.decals.remove(&handle.0).is_some();
+        if removed {
+            self.record_change(ChangeOperation::DecalRemove, handle.0 as u64, 0);
+        }
+        removed
+    }
+
+    pub(super) fn draw_decals(&mut self, viewport: Rect) {
+        profile_scope!("decals");
+        self.copy_scene(self.raster_framebuffer_object(), viewport, false);
+
+        // Draw the back faces of each box, which still cover its footprint with the camera inside it
+        self.frame_graph.begin_pass("decals", &["const_buffer", "scene_depth", "decal_textures"], self.raster_targets());
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::DepthMask(gl::FALSE);
+            gl::Enable(gl::CULL_FACE);
+            gl::CullFace(gl::FRONT);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::UseProgram(self.decal_shader);
+            let inverse_view_projection = self.const_buffer_cpu.view_projection_matrix.inverse();
+            gl::UniformMatrix4fv(gl::GetUniformLocation(self.decal_shader, c"u_inverse_view_projection".as_ptr()), 1, gl::FALSE, inverse_view_projection.as_ref().as_ptr());
+            gl::Uniform4f(
+                gl::GetUniformLocation(self.decal_shader, c"u_viewport".as_ptr()),
+                viewport.x as f32,
+                viewport.y as f32,
+                viewport.width as f32,
+                viewport.height as f32,
+            );
+            gl::Uniform1i(gl::GetUniformLocation(self.decal_shader, c"u_reversed_z".as_ptr()), self.projection.reversed_z as i32);
+            let sun_direction = self.sky.sun_direction.normalize_or_zero();
+            gl::Uniform3fv(gl::GetUniformLocation(self.decal_shader, c"u_sun_direction".as_ptr()), 1, sun_direction.as_ref().as_ptr());
+            gl::Uniform3fv(gl::GetUniformLocation(self.decal_shader, c"u_sun_colour".as_ptr()), 1, self.sky.sun_colour(self.world_up).as_ref().as_ptr());
+            self.const_buffer_gpu.bind_base(0);
+            self.scene_depth_copy.bind(TextureSlot::SceneDepth);
+            gl::BindVertexArray(self.fullscreen_vao);
+            for decal in self.decals.values() {
+                let inverse = decal.matrix.inverse();
+                let normal = decal.matrix.z_axis.truncate().normalize_or_zero();
+                TextureBinder::bind(TextureSlot::Albedo, decal.texture as i32);
+                gl::UniformMatrix4fv(gl::GetUniformLocation(self.decal_shader, c"u_decal_matrix".as_ptr()), 1, gl::FALSE, decal.matrix.as_ref().as_ptr());
+                gl::UniformMatrix4fv(gl::GetUniformLocation(self.decal_shader, c"u_inverse_decal_matrix".as_ptr()), 1, gl::FALSE, inverse.as_ref().as_ptr());
+                gl::Uniform3fv(gl::GetUniformLocation(self.decal_shader, c"u_decal_normal".as_ptr()), 1, normal.as_ref().as_ptr());
+                gl::Uniform1f(gl::GetUniformLocation(self.decal_shader, c"u_normal_fade".as_ptr()), decal.normal_fade);
+                gl::DrawArrays(gl::TRIANGLES, 0, 36);
+            }
+            TextureBinder::bind(TextureSlot::SceneDepth, 0);
+            TextureBinder::bind(TextureSlot::Albedo, 0);
+            gl::CullFace(gl::BACK);
+            gl::Disable(gl::BLEND);
+            gl::DepthMask(gl::TRUE);
+        }
+        self.frame_graph.end_pass();
+    }
+}
@@ -0,0 +1,105 @@
+use glam::Vec4;
+use memoffset::offset_of;
+use std::mem::size_of;
+
+use crate::{
+    gpu_buffer::GpuBuffer,
+    journal::ChangeOperation,
+    profile_scope,
+    structs::{LineVertex, Transform},
+};
+
+use super::{LineBatch, LineBatchHandle, Renderer};
+
+impl Renderer {
+    // Uploads the lines once, and draws them every frame they're queued with draw_line_batch, without copying them
+    // again. For large debug visualizations that would cost too much to build every frame
+    pub fn create_line_batch(&mut self, vertices: &[LineVertex]) -> LineBatchHandle {
+        let mut batch = LineBatch {
+            vao: 0,
+            vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
+            vertices: vertices.to_vec(),
+        };
+        Self::upload_line_batch(&mut batch);
+        let handle = self.next_line_batch;
+        self.next_line_batch += 1;
+        self.line_batches.insert(handle, batch);
+        self.record_change(ChangeOperation::LineBatchCreate, handle as u64, size_of_val(vertices));
+        LineBatchHandle(handle)
+    }
+
+    // Replaces the lines of a batch, returns false if it was already destroyed
+    pub fn update_line_batch(&mut self, handle: LineBatchHandle, vertices: &[LineVertex]) -> bool {
+        let Some(batch) = self.line_batches.get_mut(&handle.0) else {
+            return false;
+        };
+        batch.vertices = vertices.to_vec();
+        batch.vbo.upload(vertices);
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.record_change(ChangeOperation::LineBatchUpdate, handle.0 as u64, size_of_val(vertices));
+        true
+    }
+
+    // Returns false if the batch was already destroyed
+    pub fn destroy_line_batch(&mut self, handle: LineBatchHandle) -> bool {
+        let Some(mut batch) = self.line_batches.remove(&handle.0) else {
+            return false;
+        };
+        unsafe {
+            gl::DeleteVertexArrays(1, &batch.vao);
+        }
+        batch.vbo.delete();
+        self.record_change(ChangeOperation::LineBatchDestroy, handle.0 as u64, 0);
+        true
+    }
+
+    // Queues a batch for this frame, moved by `transform` and with every vertex colour multiplied by `colour`. One
+    // draw call per batch, however many lines it holds
+    pub fn draw_line_batch(&mut self, handle: LineBatchHandle, transform: &Transform, colour: Vec4) {
+        if self.line_batches.contains_key(&handle.0) {
+            self.line_queue.push((handle.0, transform.trans_matrix(), colour));
+        }
+    }
+
+    pub(super) fn upload_line_batch(batch: &mut LineBatch) {
+        unsafe {
+            gl::GenVertexArrays(1, &mut batch.vao);
+            gl::BindVertexArray(batch.vao);
+            batch.vbo.upload(&batch.vertices);
+            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, size_of::<LineVertex>() as i32, offset_of!(LineVertex, position) as *const _);
+            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, size_of::<LineVertex>() as i32, offset_of!(LineVertex, colour) as *const _);
+            gl::EnableVertexAttribArray(0);
+            gl::EnableVertexAttribArray(1);
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    // Depth tested against the opaque geometry, but blended without writing depth, so lines don't hide each other
+    pub(super) fn draw_line_batches(&mut self) {
+        profile_scope!("lines");
+        self.frame_graph.begin_pass("lines", &["const_buffer", "line_batches"], self.raster_targets());
+        unsafe {
+            gl::DepthMask(gl::FALSE);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::UseProgram(self.line_shader);
+            self.const_buffer_gpu.bind_base(0);
+            for (handle, matrix, colour) in &self.line_queue {
+                let Some(batch) = self.line_batches.get(handle) else {
+                    continue;
+                };
+                gl::UniformMatrix4fv(gl::GetUniformLocation(self.line_shader, c"u_model_matrix".as_ptr()), 1, gl::FALSE, matrix.as_ref().as_ptr());
+                gl::Uniform4fv(gl::GetUniformLocation(self.line_shader, c"u_colour".as_ptr()), 1, colour.as_ref().as_ptr());
+                gl::BindVertexArray(batch.vao);
+                gl::DrawArrays(gl::LINES, 0, batch.vbo.len() as i32);
+            }
+            gl::BindVertexArray(0);
+            gl::Disable(gl::BLEND);
+            gl::DepthMask(gl::TRUE);
+        }
+        self.frame_graph.end_pass();
+    }
+}
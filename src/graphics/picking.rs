@@ -0,0 +1,382 @@
+use glam::{Mat4, Vec2};
+use std::{collections::{hash_map::DefaultHasher, HashMap}, ffi::c_void, hash::{Hash, Hasher}, mem::size_of};
+
+use crate::{
+    id_view::{self, IdLegendEntry, IdSample, IdView},
+    journal::ChangeOperation,
+    mesh::Mesh,
+    raycast::{Ray, RaycastHit},
+    structs::{Frustum, Rect},
+    texture::{TextureBinder, TextureSlot},
+};
+
+use super::{GpuPick, IdEntry, Renderer, GPU_PICK_LATENCY};
+
+impl Renderer {
+    // Draws every queued mesh into the ID buffer. It has its own depth buffer, so the closest surface wins even where
+    // transparent meshes were blended over it. Rebuilds the table the buffer's pixels point into
+    pub(super) fn draw_id_buffer(&mut self, viewport: Rect) {
+        self.frame_graph.begin_pass("id_buffer", &["const_buffer", "material_textures"], &["ids"]);
+        let size = self.window_resolution_prev;
+        unsafe {
+            if self.id_framebuffer_object == 0 {
+                gl::GenFramebuffers(1, &mut self.id_framebuffer_object);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id_framebuffer_object);
+            if self.id_texture.id() == 0 {
+                let (depth_format, depth_type) = self.depth_format();
+                self.id_depth.set_format(depth_format, gl::DEPTH_STENCIL, depth_type);
+                self.id_texture.resize(size[0], size[1]);
+                self.id_depth.resize(size[0], size[1]);
+                self.id_texture.attach(gl::COLOR_ATTACHMENT0);
+                self.id_depth.attach(gl::DEPTH_STENCIL_ATTACHMENT);
+            }
+            Self::apply_viewport(viewport);
+            gl::ClearBufferuiv(gl::COLOR, 0, [0u32; 4].as_ptr());
+            gl::ClearBufferfi(gl::DEPTH_STENCIL, 0, self.projection.depth_clear_value() as f32, 0);
+            gl::UseProgram(self.id_shader);
+            gl::Uniform1i(
+                gl::GetUniformLocation(self.id_shader, c"u_triangle_keys".as_ptr()),
+                (self.id_view == IdView::Triangle) as i32,
+            );
+        }
+
+        let mut entries = Vec::with_capacity(self.mesh_queue.len());
+        for mesh in &self.mesh_queue {
+            let entry = IdEntry {
+                model: mesh.sort_key.model_id,
+                mesh_hash: mesh.sort_key.material_hash,
+                material_key: mesh.material.content_hash(),
+                lod_level: mesh.lod_level,
+            };
+            unsafe {
+                gl::Uniform1ui(gl::GetUniformLocation(self.id_shader, c"u_entry".as_ptr()), entries.len() as u32 + 1);
+                gl::Uniform1ui(gl::GetUniformLocation(self.id_shader, c"u_key".as_ptr()), entry.key(self.id_view));
+            }
+            self.draw_queue_entry(mesh, self.id_shader);
+            entries.push(entry);
+        }
+        self.id_entries = entries;
+        for pick in &mut self.gpu_picks {
+            pick.drawn = true;
+        }
+
+        unsafe {
+            gl::UseProgram(self.triangle_shader);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.raster_framebuffer_object());
+        }
+        self.frame_graph.end_pass();
+    }
+
+    // Replaces the presented frame with the ID buffer's palette colours. They're display referred, like the text overlay
+    pub(super) fn present_id_view(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.output_framebuffer());
+            gl::Viewport(0, 0, self.window_resolution_prev[0], self.window_resolution_prev[1]);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::UseProgram(self.id_view_shader);
+            gl::Uniform2f(
+                gl::GetUniformLocation(self.id_view_shader, c"u_uv_scale".as_ptr()),
+                self.render_resolution[0] as f32 / self.window_resolution_prev[0] as f32,
+                self.render_resolution[1] as f32 / self.window_resolution_prev[1] as f32,
+            );
+            self.id_texture.bind(TextureSlot::Albedo);
+            gl::BindVertexArray(self.fullscreen_vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            TextureBinder::bind(TextureSlot::Albedo, 0);
+        }
+    }
+
+    // Colours every pixel by the mesh, material or triangle it came from instead of shading it. Meant for hunting down
+    // loader bugs, it costs another draw of every mesh
+    pub fn set_id_view(&mut self, view: IdView) {
+        self.id_view = view;
+        if view == IdView::Off {
+            self.id_entries.clear();
+            self.id_texture.delete();
+            self.id_depth.delete();
+        }
+        self.record_change(ChangeOperation::Setting("id_view"), 0, 0);
+    }
+
+    pub fn id_view(&self) -> IdView {
+        self.id_view
+    }
+
+    // What the pixel under a point on the screen (in window pixels, origin top left) came from in the last frame's
+    // ID view. None while the ID view is off, or when nothing was drawn there
+    pub fn id_at(&self, screen: Vec2) -> Option<IdSample> {
+        if self.id_view == IdView::Off || self.id_texture.id() == 0 {
+            return None;
+        }
+        let pixel = self.id_pixel(screen);
+        let id = self.read_ids(pixel[0], pixel[1], 1, 1)[0];
+        self.id_sample(id, &self.id_entries)
+    }
+
+    // The `count` meshes, or materials in the material mode, that cover the most of the last frame's ID view, with
+    // their colours. The triangle mode lists meshes in their mesh mode colours
+    pub fn id_legend(&self, count: usize) -> Vec<IdLegendEntry> {
+        if self.id_view == IdView::Off || self.id_texture.id() == 0 {
+            return Vec::new();
+        }
+        let [width, height] = self.render_resolution;
+        let mut pixels: HashMap<u32, usize> = HashMap::new();
+        for id in self.read_ids(0, 0, width, height) {
+            *pixels.entry(id[0]).or_default() += 1;
+        }
+
+        let view = match self.id_view {
+            IdView::Triangle => IdView::Mesh,
+            view => view,
+        };
+        let mut groups: HashMap<u32, (String, usize)> = HashMap::new();
+        for (index, count) in pixels {
+            let Some(entry) = (index as usize).checked_sub(1).and_then(|index| self.id_entries.get(index)) else {
+                continue;
+            };
+            let Some((mesh, material)) = self.describe_id_entry(entry) else {
+                continue;
+            };
+            let label = match view {
+                IdView::Material => material,
+                _ => format!("{} / {mesh}", self.model_label(entry.model)),
+            };
+            groups.entry(entry.key(view)).or_insert((label, 0)).1 += count;
+        }
+        let mut legend: Vec<(u32, String, usize)> = groups.into_iter().map(|(key, (label, count))| (key, label, count)).collect();
+        legend.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.1.cmp(&b.1)));
+        let total = (width.max(1) * height.max(1)) as f32;
+        legend
+            .into_iter()
+            .take(count)
+            .map(|(key, label, count)| IdLegendEntry {
+                label,
+                colour: id_view::palette(key),
+                coverage: count as f32 / total,
+            })
+            .collect()
+    }
+
+    // Mesh and material name of an ID table entry, None if its model was unloaded since
+    fn describe_id_entry(&self, entry: &IdEntry) -> Option<(String, String)> {
+        let model = self.models.get(&entry.model)?;
+        let mesh = model.meshes.keys().find(|name| {
+            let mut hasher = DefaultHasher::new();
+            name.hash(&mut hasher);
+            hasher.finish() == entry.mesh_hash
+        })?;
+        let material = match model.material_overrides.get(mesh) {
+            Some(handle) => self.material_descriptors.get(handle).map_or_else(|| format!("{handle:?}"), |descriptor| descriptor.name.clone()),
+            None => mesh.clone(),
+        };
+        Some((mesh.clone(), material))
+    }
+
+    // The app's tag for a model, or the name of the file it came from
+    fn model_label(&self, model: u64) -> String {
+        match (self.model_tags.get(&model), self.model_load_args.get(&model)) {
+            (Some(tag), _) => tag.clone(),
+            (None, Some((path, _))) => path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned()),
+            (None, None) => format!("model {model}"),
+        }
+    }
+
+    // Reads a rectangle of the ID buffer back, in render pixels from the bottom left
+    fn read_ids(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<[u32; 4]> {
+        let mut ids = vec![[0u32; 4]; (width.max(0) * height.max(0)) as usize];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id_framebuffer_object);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl::ReadPixels(x, y, width, height, gl::RGBA_INTEGER, gl::UNSIGNED_INT, ids.as_mut_ptr() as *mut c_void);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        ids
+    }
+
+    // Like id_at, but works with the ID view off and doesn't wait for the GPU. The ID buffer is drawn next frame and
+    // read back asynchronously, the callback gets what was under the point a couple of frames later, from end_frame.
+    // Unlike a raycast it sees exactly what was drawn, with LODs, culling and alpha testing. The ID view mode doesn't
+    // matter, except that the sample's colour is the one it shows
+    pub fn pick_gpu(&mut self, screen: Vec2, callback: impl FnOnce(Option<IdSample>) + 'static) {
+        self.gpu_picks.push(GpuPick {
+            screen,
+            callback: Box::new(callback),
+            drawn: false,
+            readback: None,
+        });
+    }
+
+    // Finishes the GPU picks whose readback is old enough, and starts the readbacks of the ones drawn this frame
+    pub(super) fn update_gpu_picks(&mut self) {
+        if self.gpu_picks.is_empty() {
+            return;
+        }
+        let mut finished = Vec::new();
+        for pick in std::mem::take(&mut self.gpu_picks) {
+            match pick.readback {
+                Some((pixel_buffer, frame, entries)) if self.frame_index >= frame + GPU_PICK_LATENCY => {
+                    let mut id = [0u32; 4];
+                    unsafe {
+                        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pixel_buffer);
+                        let mapped = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, size_of::<[u32; 4]>() as isize, gl::MAP_READ_BIT) as *const [u32; 4];
+                        if !mapped.is_null() {
+                            id = *mapped;
+                            gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+                        }
+                        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                        gl::DeleteBuffers(1, &pixel_buffer);
+                    }
+                    finished.push((pick.callback, self.id_sample(id, &entries)));
+                }
+                Some(_) => self.gpu_picks.push(pick),
+                None if !pick.drawn => self.gpu_picks.push(pick),
+                None => {
+                    let pixel = self.id_pixel(pick.screen);
+                    let mut pixel_buffer = 0;
+                    unsafe {
+                        gl::GenBuffers(1, &mut pixel_buffer);
+                        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pixel_buffer);
+                        gl::BufferData(gl::PIXEL_PACK_BUFFER, size_of::<[u32; 4]>() as isize, std::ptr::null(), gl::STREAM_READ);
+                        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id_framebuffer_object);
+                        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+                        // With a pack buffer bound, the pointer is an offset into it
+                        gl::ReadPixels(pixel[0], pixel[1], 1, 1, gl::RGBA_INTEGER, gl::UNSIGNED_INT, std::ptr::null_mut());
+                        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+                        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                    }
+                    self.gpu_picks.push(GpuPick {
+                        readback: Some((pixel_buffer, self.frame_index, self.id_entries.clone())),
+                        ..pick
+                    });
+                }
+            }
+        }
+        for (callback, sample) in finished {
+            callback(sample);
+        }
+    }
+
+    // The ID buffer pixel under a point on the screen (in window pixels, origin top left)
+    fn id_pixel(&self, screen: Vec2) -> [i32; 2] {
+        let window_size = Vec2::new(self.window_resolution_prev[0] as f32, self.window_resolution_prev[1] as f32).max(Vec2::ONE);
+        let render_size = Vec2::new(self.render_resolution[0] as f32, self.render_resolution[1] as f32);
+        let pixel = (Vec2::new(screen.x, window_size.y - screen.y) * render_size / window_size)
+            .floor()
+            .clamp(Vec2::ZERO, render_size - 1.0);
+        [pixel.x as i32, pixel.y as i32]
+    }
+
+    // Resolves an ID buffer pixel against the ID table of the frame it was drawn in
+    fn id_sample(&self, id: [u32; 4], entries: &[IdEntry]) -> Option<IdSample> {
+        let entry = entries.get((id[0] as usize).checked_sub(1)?)?;
+        let (mesh, material) = self.describe_id_entry(entry)?;
+        Some(IdSample {
+            model: entry.model,
+            model_path: self.model_load_args.get(&entry.model).map(|(path, _)| path.clone()),
+            model_tag: self.model_tags.get(&entry.model).cloned(),
+            mesh,
+            material,
+            triangle: id[1],
+            lod_level: entry.lod_level,
+            colour: id_view::palette(id[2]),
+        })
+    }
+
+    // Closest hit against every loaded model, works without rendering anything. Rays and hits are relative to the
+    // origin, to_world gives a hit's world position. Models loaded with CpuData::DropAfterUpload can't be hit
+    pub fn raycast(&self, ray: &Ray) -> Option<RaycastHit> {
+        let mut ray = *ray;
+        let mut closest = None;
+        for handle in self.models.keys() {
+            if let Ok(Some(hit)) = self.raycast_model(*handle, &ray) {
+                ray.length = hit.distance;
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    // Models are tested in their own space, so recentred models are tested at their own scale
+    pub fn raycast_model(&self, handle: u64, ray: &Ray) -> Result<Option<RaycastHit>, String> {
+        let model = self.models.get(&handle).ok_or_else(|| format!("Model {handle} is not loaded"))?;
+        if !model.meshes.values().all(Mesh::has_cpu_positions) {
+            return Err(format!(
+                "\"{}\" dropped its vertices after uploading them, load it with CpuData::KeepPositionsOnly or KeepAll to raycast it",
+                self.model_label(handle)
+            ));
+        }
+        let offset = self.model_offset(model);
+        let hit = model.raycast(&Ray { origin: ray.origin - offset, ..*ray }, handle);
+        Ok(hit.map(|hit| RaycastHit { point: hit.point + offset, ..hit }))
+    }
+
+    // Every hit against every loaded model, sorted from closest to furthest. Skips the same models as raycast
+    pub fn raycast_all(&self, ray: &Ray, hits: &mut Vec<RaycastHit>) {
+        hits.clear();
+        for (handle, model) in &self.models {
+            let offset = self.model_offset(model);
+            let first = hits.len();
+            model.raycast_all(&Ray { origin: ray.origin - offset, ..*ray }, *handle, hits);
+            for hit in &mut hits[first..] {
+                hit.point += offset;
+            }
+        }
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    }
+
+    // Ray from the camera through a point in window pixels, with the origin in the top left like draw_text_2d
+    pub fn screen_ray(&self, screen: Vec2) -> Ray {
+        let window_resolution = self.output_size();
+        let window_size = Vec2::new(window_resolution.0 as f32, window_resolution.1 as f32).max(Vec2::ONE);
+        let ndc = Vec2::new(screen.x / window_size.x * 2.0 - 1.0, 1.0 - screen.y / window_size.y * 2.0);
+        let inverse_view_projection =
+            (self.projection.matrix(window_size.x / window_size.y) * self.camera_view_matrix).inverse();
+        let (near_depth, far_depth) = self.projection.clip_depth_range();
+        let near = inverse_view_projection.project_point3(ndc.extend(near_depth));
+        let far = inverse_view_projection.project_point3(ndc.extend(far_depth));
+        Ray::new(near, far - near, near.distance(far))
+    }
+
+    // Every model whose bounds touch the part of the view frustum behind a screen rectangle (in window pixels, origin
+    // top left), sorted near to far by the distance from the camera to their bounds centre. Models pass if any of
+    // their meshes do. A rectangle under a pixel wide or high picks like a single ray through its centre instead
+    pub fn pick_rect(&self, min_screen: Vec2, max_screen: Vec2) -> Vec<u64> {
+        let (min_screen, max_screen) = (min_screen.min(max_screen), min_screen.max(max_screen));
+        if (max_screen - min_screen).min_element() < 1.0 {
+            let ray = self.screen_ray((min_screen + max_screen) * 0.5);
+            return self.raycast(&ray).map(|hit| hit.model).into_iter().collect();
+        }
+
+        // Scale the rectangle's part of NDC space up to all of it, so the frustum planes hug the rectangle
+        let window_resolution = self.output_size();
+        let window_size = Vec2::new(window_resolution.0 as f32, window_resolution.1 as f32).max(Vec2::ONE);
+        let to_ndc = |screen: Vec2| Vec2::new(screen.x / window_size.x * 2.0 - 1.0, 1.0 - screen.y / window_size.y * 2.0);
+        let (corner_a, corner_b) = (to_ndc(min_screen), to_ndc(max_screen));
+        let (ndc_min, ndc_max) = (corner_a.min(corner_b), corner_a.max(corner_b));
+        let scale = 2.0 / (ndc_max - ndc_min);
+        let offset = -(ndc_min + ndc_max) / (ndc_max - ndc_min);
+        let pick_matrix = Mat4::from_translation(offset.extend(0.0)) * Mat4::from_scale(scale.extend(1.0));
+        let view_projection =
+            pick_matrix * self.projection.matrix(window_size.x / window_size.y) * self.camera_view_matrix;
+        let frustum = Frustum::from_matrix(&view_projection, self.projection.clip_depth_range());
+
+        let camera_position = self.camera_view_matrix.inverse().w_axis.truncate();
+        let mut picked: Vec<(f32, u64)> = self
+            .models
+            .iter()
+            .filter(|(_, model)| {
+                let offset = self.model_offset(model);
+                frustum.intersects_aabb(&model.bounds().translated(offset))
+                    && model.meshes.values().any(|mesh| frustum.intersects_aabb(&mesh.bounds.translated(offset)))
+            })
+            .map(|(handle, model)| {
+                let bounds = model.bounds().translated(self.model_offset(model));
+                (camera_position.distance((bounds.min + bounds.max) * 0.5), *handle)
+            })
+            .collect();
+        picked.sort_by(|a, b| a.0.total_cmp(&b.0));
+        picked.into_iter().map(|(_, handle)| handle).collect()
+    }
+}
@@ -0,0 +1,144 @@
+use crate::snapshot::{ModelRestore, ModelSnapshot, RendererSnapshot, RenderSettings};
+
+use super::{LodSettings, ParallaxSettings, Renderer, SsaoSettings, UpscaleSettings};
+
+impl Renderer {
+    // Names a model, so the app can find it again with find_model after restoring a snapshot
+    pub fn set_model_tag(&mut self, model: u64, tag: &str) {
+        if self.models.contains_key(&model) {
+            self.model_tags.insert(model, tag.to_string());
+        }
+    }
+
+    pub fn find_model(&self, tag: &str) -> Option<u64> {
+        self.model_tags.iter().find(|(_, model_tag)| model_tag.as_str() == tag).map(|(&model, _)| model)
+    }
+
+    // Copies the state the app has handed over, see RendererSnapshot
+    pub fn snapshot(&self) -> RendererSnapshot {
+        let mut models: Vec<ModelSnapshot> = self
+            .model_load_args
+            .iter()
+            .filter_map(|(handle, (path, options))| {
+                let model = self.models.get(handle)?;
+                Some(ModelSnapshot {
+                    tag: self.model_tags.get(handle).cloned(),
+                    path: path.clone(),
+                    options: options.clone(),
+                    material_overrides: model
+                        .material_overrides
+                        .iter()
+                        .filter_map(|(mesh, material)| Some((mesh.clone(), self.material_descriptors.get(material)?.clone())))
+                        .collect(),
+                })
+            })
+            .collect();
+        models.sort_by(|a, b| a.path.cmp(&b.path));
+        let baseline = self.quality_baseline;
+        RendererSnapshot {
+            models,
+            sky: self.sky,
+            wind: self.wind,
+            settings: RenderSettings {
+                projection: self.projection,
+                msaa_samples: baseline.msaa_samples.unwrap_or(self.msaa_samples),
+                taa: self.taa_enabled,
+                ssao: SsaoSettings {
+                    enabled: baseline.ssao_enabled.unwrap_or(self.ssao.enabled),
+                    ..self.ssao
+                },
+                occlusion_culling: self.hi_z.is_some(),
+                lod: LodSettings {
+                    switch_distance: baseline.lod_switch_distance.unwrap_or(self.lod_settings.switch_distance),
+                    ..self.lod_settings
+                },
+                parallax: match baseline.parallax {
+                    Some((enabled, max_steps)) => ParallaxSettings { enabled, max_steps, ..self.parallax },
+                    None => self.parallax,
+                },
+                texture_lod_bias: self.texture_lod_bias,
+                transparent_sort_epsilon: self.transparent_sort_epsilon,
+                dynamic_resolution: self.dynamic_resolution,
+                upscale: UpscaleSettings {
+                    render_scale: baseline.render_scale.unwrap_or(self.upscale.render_scale),
+                    ..self.upscale
+                },
+                framebuffer_format: self.framebuffer_format,
+                dithering: self.dithering,
+                texture_quality: self.texture_quality,
+                reflections: self.reflections,
+                stereo: self.stereo,
+                exposure: self.exposure,
+                motion_blur: self.motion_blur,
+                quality_governor: self.quality_governor.config().clone(),
+            },
+        }
+    }
+
+    // Brings the renderer to the state of a snapshot. Models that are already loaded with the same options are reused,
+    // the others get loaded, or reloaded when their options changed. Loaded models that aren't in the snapshot stay
+    // loaded, but lose their tag. Settings this context can't run stay off with a warning, like when set directly.
+    // Keeps going when something fails, and returns every failure at the end
+    pub fn restore(&mut self, snapshot: &RendererSnapshot) -> Result<(), String> {
+        let mut errors = Vec::new();
+        let settings = &snapshot.settings;
+        self.set_quality_governor(settings.quality_governor.clone());
+        if let Err(error) = self.set_projection(settings.projection) {
+            errors.push(error);
+        }
+        self.set_msaa(settings.msaa_samples);
+        self.set_taa(settings.taa);
+        self.set_ssao(settings.ssao);
+        self.set_occlusion_culling(settings.occlusion_culling);
+        self.set_lod_settings(settings.lod);
+        self.set_parallax(settings.parallax);
+        self.set_texture_lod_bias(settings.texture_lod_bias);
+        self.set_transparent_sort_epsilon(settings.transparent_sort_epsilon);
+        self.set_dynamic_resolution(settings.dynamic_resolution);
+        self.set_upscale(settings.upscale);
+        self.set_framebuffer_format(settings.framebuffer_format);
+        self.set_dithering(settings.dithering);
+        self.set_texture_quality(settings.texture_quality);
+        self.set_reflections(settings.reflections);
+        self.set_stereo(settings.stereo);
+        self.set_exposure(settings.exposure);
+        self.set_motion_blur(settings.motion_blur);
+        self.set_sky(snapshot.sky);
+        self.set_wind(snapshot.wind);
+
+        self.model_tags.clear();
+        for model in &snapshot.models {
+            let handle = match model.restore_plan(&self.model_load_args) {
+                ModelRestore::Reuse(handle) => handle,
+                ModelRestore::Reload(handle) => {
+                    self.model_load_args.insert(handle, (model.path.clone(), model.options.clone()));
+                    self.reload_model(handle);
+                    handle
+                }
+                ModelRestore::Load => match self.load_model_with_options(&model.path, &model.options) {
+                    Ok(handle) => handle,
+                    Err(_) => {
+                        errors.push(format!("Failed to load \"{}\"", model.path.display()));
+                        continue;
+                    }
+                },
+            };
+            if let Some(tag) = &model.tag {
+                self.model_tags.insert(handle, tag.clone());
+            }
+            if let Some(loaded) = self.models.get_mut(&handle) {
+                loaded.material_overrides.clear();
+            }
+            for (mesh, descriptor) in &model.material_overrides {
+                let material = self.load_material(descriptor);
+                if let Err(error) = self.reassign_material(handle, mesh, material) {
+                    errors.push(format!("\"{}\": {error}", model.path.display()));
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("\n")),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use glam::{Mat4, Vec2};
+use std::{ffi::CStr, mem::size_of};
+
+use crate::{
+    journal::ChangeOperation,
+    profile_scope,
+    structs::{Rect, Transform},
+    texture::{TextureBinder, TextureSlot},
+    texture_store,
+    water::{self, WaterConfig, WaterHandle, WaterPlane},
+};
+
+use super::Renderer;
+
+impl Renderer {
+    // Adds a water plane of `extents` world units, spanning the local X and Y axes of `transform` and facing its
+    // local +Z. It's drawn every frame until it's removed
+    pub fn add_water_plane(&mut self, transform: &Transform, extents: Vec2, config: WaterConfig) -> WaterHandle {
+        let mut texture_bytes = 0;
+        if self.water_normal_texture == 0 {
+            let normal_map = water::ripple_normal_map();
+            unsafe {
+                gl::GenTextures(1, &mut self.water_normal_texture);
+                texture_store::upload_texture_2d(self.water_normal_texture, &normal_map);
+                // Ripples need smooth normals, unlike the pixelated material textures
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+                gl::BindTexture(gl::TEXTURE_2D, 0);
+            }
+            texture_bytes = normal_map.data.len() * size_of::<u32>();
+        }
+
+        let handle = self.next_water_plane;
+        self.next_water_plane += 1;
+        self.water_planes.insert(
+            handle,
+            WaterPlane {
+                matrix: transform.trans_matrix() * Mat4::from_scale(extents.extend(1.0)),
+                extents,
+                config,
+            },
+        );
+        self.record_change(ChangeOperation::WaterPlaneAdd, handle as u64, texture_bytes);
+        WaterHandle(handle)
+    }
+
+    // Returns false if the plane was already removed
+    pub fn set_water_config(&mut self, handle: WaterHandle, config: WaterConfig) -> bool {
+        let Some(plane) = self.water_planes.get_mut(&handle.0) else {
+            return false;
+        };
+        plane.config = config;
+        true
+    }
+
+    // Returns false if the plane was already removed
+    pub fn remove_water_plane(&mut self, handle: WaterHandle) -> bool {
+        let removed = self.water_planes.remove(&handle.0).is_some();
+        if removed {
+            self.record_change(ChangeOperation::WaterPlaneRemove, handle.0 as u64, 0);
+        }
+        removed
+    }
+
+    // Refraction and sky reflections on water. Without them, water is a plain tinted surface with moving ripples
+    pub fn set_reflections(&mut self, enabled: bool) {
+        self.reflections = enabled;
+        self.record_change(ChangeOperation::Setting("reflections"), 0, 0);
+    }
+
+    pub fn reflections(&self) -> bool {
+        self.reflections
+    }
+
+    // Blended over the scene, depth tested without writing depth. With reflections, the water refracts a copy of
+    // everything drawn before it
+    pub(super) fn draw_water(&mut self, viewport: Rect) {
+        profile_scope!("water");
+        self.copy_scene(self.raster_framebuffer_object(), viewport, self.reflections);
+        let reads: &[&str] = if self.reflections {
+            &["const_buffer", "scene_depth", "scene_colour_copy", "water_normals"]
+        } else {
+            &["const_buffer", "scene_depth", "water_normals"]
+        };
+        self.frame_graph.begin_pass("water", reads, self.raster_targets());
+        let shader = self.water_shader;
+        unsafe {
+            let location = |name: &CStr| gl::GetUniformLocation(shader, name.as_ptr());
+            gl::DepthMask(gl::FALSE);
+            gl::Disable(gl::CULL_FACE);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::UseProgram(shader);
+            let inverse_view_projection = self.const_buffer_cpu.view_projection_matrix.inverse();
+            gl::UniformMatrix4fv(location(c"u_inverse_view_projection"), 1, gl::FALSE, inverse_view_projection.as_ref().as_ptr());
+            gl::Uniform4f(location(c"u_viewport"), viewport.x as f32, viewport.y as f32, viewport.width as f32, viewport.height as f32);
+            gl::Uniform1i(location(c"u_reversed_z"), self.projection.reversed_z as i32);
+            gl::Uniform1i(location(c"u_reflections"), self.reflections as i32);
+            let sun_direction = self.sky.sun_direction.normalize_or_zero();
+            gl::Uniform3fv(location(c"u_sun_direction"), 1, sun_direction.as_ref().as_ptr());
+            gl::Uniform3fv(location(c"u_sun_colour"), 1, self.sky.sun_colour(self.world_up).as_ref().as_ptr());
+            gl::Uniform1f(location(c"u_turbidity"), self.sky.turbidity);
+            gl::Uniform3fv(location(c"u_ground_albedo"), 1, self.sky.ground_albedo.as_ref().as_ptr());
+            gl::Uniform3fv(location(c"u_world_up"), 1, self.world_up.up().as_ref().as_ptr());
+            self.const_buffer_gpu.bind_base(0);
+            self.scene_depth_copy.bind(TextureSlot::SceneDepth);
+            if self.reflections {
+                self.scene_colour_copy.bind(TextureSlot::SceneColour);
+            }
+            TextureBinder::bind(TextureSlot::Normal, self.water_normal_texture as i32);
+            gl::BindVertexArray(self.fullscreen_vao);
+            for plane in self.water_planes.values() {
+                let config = &plane.config;
+                gl::UniformMatrix4fv(location(c"u_water_matrix"), 1, gl::FALSE, plane.matrix.as_ref().as_ptr());
+                gl::Uniform3fv(location(c"u_water_tangent"), 1, plane.matrix.x_axis.truncate().normalize_or_zero().as_ref().as_ptr());
+                gl::Uniform3fv(location(c"u_water_bitangent"), 1, plane.matrix.y_axis.truncate().normalize_or_zero().as_ref().as_ptr());
+                gl::Uniform3fv(location(c"u_water_normal"), 1, plane.matrix.z_axis.truncate().normalize_or_zero().as_ref().as_ptr());
+                gl::Uniform2f(location(c"u_extents"), plane.extents.x, plane.extents.y);
+                gl::Uniform1f(location(c"u_wave_speed"), config.wave_speed);
+                gl::Uniform1f(location(c"u_wave_scale"), config.wave_scale.max(1e-3));
+                gl::Uniform3fv(location(c"u_tint"), 1, config.tint.as_ref().as_ptr());
+                gl::Uniform1f(location(c"u_reflectivity"), config.reflectivity);
+                gl::DrawArrays(gl::TRIANGLES, 0, 6);
+            }
+            TextureBinder::bind(TextureSlot::SceneDepth, 0);
+            TextureBinder::bind(TextureSlot::SceneColour, 0);
+            TextureBinder::bind(TextureSlot::Normal, 0);
+            gl::Disable(gl::BLEND);
+            gl::DepthMask(gl::TRUE);
+        }
+        self.frame_graph.end_pass();
+    }
+}
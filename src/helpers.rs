@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
-use glam::Vec2;
+use glam::{Vec2, Vec3, Vec4};
+use std::path::Path;
 
 pub fn index_to_coords(index: usize, width: usize) -> glam::Vec2 {
     glam::vec2((index % width) as f32, (index / width) as f32)
@@ -8,11 +9,60 @@ pub fn index_to_coords(index: usize, width: usize) -> glam::Vec2 {
 pub fn coords_to_index(x: usize, y: usize, width: usize) -> usize {
     x + (y * width)
 }
+// Both of these pack into the Pixel32 layout, which is what textures and the window icon expect
 pub fn colour_rgb(red: u8, green: u8, blue: u8) -> u32 {
-    ((red as u32) << 16) + ((green as u32) << 8) + (blue as u32)
+    Pixel32::new(red, green, blue, 255).to_u32()
 }
-pub fn colour_rgba(alpha: u8, red: u8, green: u8, blue: u8) -> u32 {
-    ((alpha as u32) << 24) + ((red as u32) << 16) + ((green as u32) << 8) + (blue as u32)
+pub fn colour_rgba(red: u8, green: u8, blue: u8, alpha: u8) -> u32 {
+    Pixel32::new(red, green, blue, alpha).to_u32()
+}
+
+// One RGBA8 pixel. In memory the bytes are always R, G, B, A in that order, matching GL_RGBA + GL_UNSIGNED_BYTE,
+// so the packed u32 value depends on the platform's endianness - only ever build it through to_u32()
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Pixel32 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Pixel32 {
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Pixel32 { r, g, b, a }
+    }
+
+    pub fn from_u32(value: u32) -> Self {
+        let [r, g, b, a] = value.to_ne_bytes();
+        Pixel32 { r, g, b, a }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_ne_bytes([self.r, self.g, self.b, self.a])
+    }
+
+    // Channels are clamped to [0, 1], srgb applies the same 2.2 gamma encode as the model loaders
+    pub fn from_vec4(colour: Vec4, srgb: bool) -> Self {
+        let mut colour = colour.clamp(Vec4::ZERO, Vec4::ONE);
+        if srgb {
+            colour = colour.truncate().powf(1.0 / 2.2).extend(colour.w);
+        }
+        let bytes = (colour * 255.0).round();
+        Pixel32::new(bytes.x as u8, bytes.y as u8, bytes.z as u8, bytes.w as u8)
+    }
+
+    pub fn from_vec3(colour: Vec3, srgb: bool) -> Self {
+        Self::from_vec4(colour.extend(1.0), srgb)
+    }
+
+    pub fn to_vec4(self) -> Vec4 {
+        Vec4::new(self.r as f32, self.g as f32, self.b as f32, self.a as f32) / 255.0
+    }
+
+    pub fn lerp(self, other: Pixel32, t: f32) -> Pixel32 {
+        Self::from_vec4(self.to_vec4().lerp(other.to_vec4(), t), false)
+    }
 }
 
 pub fn to_argb8(a: u8, r: u8, g: u8, b: u8) -> u32 {
@@ -30,3 +80,82 @@ fn point_inside_triangle(v0: Vec2, v1: Vec2, v2: Vec2, p: Vec2) -> bool {
         && (edge_function(v1, v2, p) > 0.0)
         && (edge_function(v2, v0, p) > 0.0)
 }
+
+// Floating point RGBA image, rows bottom to top like OpenGL reads them back
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec4>,
+}
+
+impl Image {
+    // Channels are clamped to [0, 1] and sRGB encoded, the same way the final blit presents them
+    pub fn write_ppm(&self, path: &Path) -> Result<(), String> {
+        let mut rgb_pixels = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in &self.pixels {
+            let encoded = Vec3::new(linear_to_srgb(pixel.x), linear_to_srgb(pixel.y), linear_to_srgb(pixel.z));
+            let pixel = Pixel32::from_vec4(encoded.extend(pixel.w), false);
+            rgb_pixels.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+        }
+        write_ppm(path, self.width, self.height, &rgb_pixels)
+    }
+}
+
+// The exact sRGB transfer function, input clamped to [0, 1]
+pub fn linear_to_srgb(linear: f32) -> f32 {
+    let linear = linear.clamp(0.0, 1.0);
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Writes tightly packed RGB8 pixels as a binary PPM file. Rows are expected bottom to top, like OpenGL reads them back
+pub fn write_ppm(path: &Path, width: usize, height: usize, rgb_pixels: &[u8]) -> Result<(), String> {
+    let mut file = format!("P6\n{width} {height}\n255\n").into_bytes();
+    for row in rgb_pixels.chunks_exact(width * 3).rev().take(height) {
+        file.extend_from_slice(row);
+    }
+    std::fs::write(path, file).map_err(|error| format!("Failed to write \"{}\": {error}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_u32_round_trip() {
+        let pixel = Pixel32::new(0x12, 0x34, 0x56, 0x78);
+        assert_eq!(Pixel32::from_u32(pixel.to_u32()), pixel);
+        assert_eq!(Pixel32::from_u32(0xDEADBEEF).to_u32(), 0xDEADBEEF);
+    }
+
+    #[test]
+    fn pixel_from_vec4_clamps() {
+        let pixel = Pixel32::from_vec4(Vec4::new(-1.0, 0.5, 2.0, 1.0), false);
+        assert_eq!(pixel, Pixel32::new(0, 128, 255, 255));
+    }
+
+    #[test]
+    fn pixel_from_vec4_srgb_leaves_alpha() {
+        let pixel = Pixel32::from_vec4(Vec4::new(0.0, 0.5, 1.0, 0.5), true);
+        let expected = (0.5f32.powf(1.0 / 2.2) * 255.0).round() as u8;
+        assert_eq!(pixel, Pixel32::new(0, expected, 255, 128));
+    }
+
+    #[test]
+    fn pixel_lerp_endpoints() {
+        let a = Pixel32::new(10, 20, 30, 40);
+        let b = Pixel32::new(200, 150, 100, 250);
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+    }
+
+    #[test]
+    fn colour_rgba_byte_order() {
+        // Memory order is R, G, B, A regardless of endianness
+        assert_eq!(colour_rgba(1, 2, 3, 4).to_ne_bytes(), [1, 2, 3, 4]);
+        assert_eq!(colour_rgb(1, 2, 3).to_ne_bytes(), [1, 2, 3, 255]);
+    }
+}
@@ -0,0 +1,260 @@
+use std::{ffi::c_void, ptr::null};
+
+use glam::{BVec3, Mat4, Vec2, Vec3};
+
+use crate::{
+    structs::AABB,
+    texture::{TextureBinder, TextureSlot},
+};
+
+// Levels at most this wide get read back to the CPU for testing, the finer ones only exist to build them
+const READBACK_WIDTH: i32 = 128;
+// Readbacks are collected this many frames after they were started, so building the pyramid never stalls the pipeline
+const PIXEL_BUFFER_COUNT: usize = 2;
+const GROUP_SIZE: i32 = 8; // Matches local_size in hiz.comp
+
+struct DepthLevel {
+    level: i32,
+    width: i32,
+    height: i32,
+    depth: Vec<f32>, // Farthest depth under each texel, bottom row first
+}
+
+// Depth pyramid of a finished frame, with the view it was rendered from
+struct Readback {
+    view_projection: Mat4,
+    reversed_z: bool,
+    source_size: [i32; 2],
+    levels: Vec<DepthLevel>, // Finest first
+}
+
+// Pyramid of the farthest depth in every 2x2 block of the level below, built from the scene depth at the end of the
+// frame. The coarse levels are read back and used to cull meshes hidden behind closer geometry in later frames
+pub struct HiZBuffer {
+    texture: u32,
+    source_size: [i32; 2],
+    level_sizes: Vec<[i32; 2]>, // Level 0 is half the render resolution, rounded up
+    pixel_buffers: [u32; PIXEL_BUFFER_COUNT],
+    pending: [Option<Readback>; PIXEL_BUFFER_COUNT],
+    next_buffer: usize,
+    current: Option<Readback>,
+}
+
+impl HiZBuffer {
+    pub fn new() -> Self {
+        let mut pixel_buffers = [0; PIXEL_BUFFER_COUNT];
+        unsafe {
+            gl::GenBuffers(PIXEL_BUFFER_COUNT as i32, pixel_buffers.as_mut_ptr());
+        }
+        HiZBuffer {
+            texture: 0,
+            source_size: [0, 0],
+            level_sizes: Vec::new(),
+            pixel_buffers,
+            pending: Default::default(),
+            next_buffer: 0,
+            current: None,
+        }
+    }
+
+    // Builds the pyramid from a depth texture and starts reading it back, collecting the readback started a few
+    // frames ago. `view_projection` is the matrix the depth was rendered with
+    pub fn build(&mut self, shader: u32, depth_texture: u32, source_size: [i32; 2], view_projection: Mat4, reversed_z: bool) {
+        if source_size != self.source_size {
+            self.resize(source_size);
+        }
+
+        unsafe {
+            // Finish the oldest readback in this slot
+            let slot = self.next_buffer;
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.pixel_buffers[slot]);
+            if let Some(mut readback) = self.pending[slot].take() {
+                let size: usize = readback.levels.iter().map(|level| (level.width * level.height) as usize).sum();
+                let mapped = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, (size * 4) as isize, gl::MAP_READ_BIT) as *const f32;
+                if !mapped.is_null() {
+                    let mut texels = std::slice::from_raw_parts(mapped, size);
+                    for level in &mut readback.levels {
+                        let (level_texels, rest) = texels.split_at((level.width * level.height) as usize);
+                        level.depth = level_texels.to_vec();
+                        texels = rest;
+                    }
+                    gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+                    self.current = Some(readback);
+                }
+            }
+
+            // Each level takes the farthest depth of the 2x2 texels under it, the first one straight from the depth buffer
+            gl::UseProgram(shader);
+            gl::Uniform1i(gl::GetUniformLocation(shader, c"u_reversed_z".as_ptr()), reversed_z as i32);
+            for (level, size) in self.level_sizes.iter().enumerate() {
+                let (source_texture, source_level, source_size) = match level {
+                    0 => (depth_texture, 0, source_size),
+                    _ => (self.texture, level as i32 - 1, self.level_sizes[level - 1]),
+                };
+                TextureBinder::bind(TextureSlot::SceneDepth, source_texture as i32);
+                gl::Uniform1i(gl::GetUniformLocation(shader, c"u_source_level".as_ptr()), source_level);
+                gl::Uniform2i(gl::GetUniformLocation(shader, c"u_source_size".as_ptr()), source_size[0], source_size[1]);
+                gl::BindImageTexture(0, self.texture, level as i32, gl::FALSE, 0, gl::WRITE_ONLY, gl::R32F);
+                gl::DispatchCompute(
+                    ((size[0] + GROUP_SIZE - 1) / GROUP_SIZE) as u32,
+                    ((size[1] + GROUP_SIZE - 1) / GROUP_SIZE) as u32,
+                    1,
+                );
+                gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT | gl::TEXTURE_UPDATE_BARRIER_BIT);
+            }
+            TextureBinder::bind(TextureSlot::SceneDepth, 0);
+            gl::BindImageTexture(0, 0, 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::R32F);
+
+            // Start reading the coarse levels back into the pixel buffer, one after the other
+            let mut levels = Vec::new();
+            let mut offset = 0;
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            for (level, size) in self.level_sizes.iter().enumerate().filter(|(_, size)| size[0] <= READBACK_WIDTH) {
+                gl::GetTexImage(gl::TEXTURE_2D, level as i32, gl::RED, gl::FLOAT, offset as *mut c_void);
+                offset += (size[0] * size[1]) as usize * 4;
+                levels.push(DepthLevel {
+                    level: level as i32,
+                    width: size[0],
+                    height: size[1],
+                    depth: Vec::new(),
+                });
+            }
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            self.pending[slot] = Some(Readback {
+                view_projection,
+                reversed_z,
+                source_size,
+                levels,
+            });
+            self.next_buffer = (slot + 1) % PIXEL_BUFFER_COUNT;
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+    }
+
+    fn resize(&mut self, source_size: [i32; 2]) {
+        self.source_size = source_size;
+        self.pending = Default::default();
+        self.current = None;
+        self.level_sizes.clear();
+        let mut size = [(source_size[0] + 1) / 2, (source_size[1] + 1) / 2];
+        loop {
+            self.level_sizes.push(size);
+            if size == [1, 1] {
+                break;
+            }
+            size = [((size[0] + 1) / 2).max(1), ((size[1] + 1) / 2).max(1)];
+        }
+
+        unsafe {
+            // Immutable storage can't change size, so make a new texture
+            gl::DeleteTextures(1, &self.texture);
+            gl::GenTextures(1, &mut self.texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::TexStorage2D(gl::TEXTURE_2D, self.level_sizes.len() as i32, gl::R32F, self.level_sizes[0][0], self.level_sizes[0][1]);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            let readback_size: i32 = self.level_sizes.iter().filter(|size| size[0] <= READBACK_WIDTH).map(|size| size[0] * size[1]).sum();
+            for pixel_buffer in self.pixel_buffers {
+                gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pixel_buffer);
+                gl::BufferData(gl::PIXEL_PACK_BUFFER, (readback_size * 4) as isize, null(), gl::STREAM_READ);
+            }
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+    }
+
+    // Whether the box was completely behind the depth buffer in the last frame that was read back. Boxes crossing the
+    // near plane or the edge of the screen, and anything tested before the first readback arrives, count as visible
+    pub fn is_occluded(&self, aabb: &AABB) -> bool {
+        let Some(readback) = &self.current else {
+            return false;
+        };
+        if aabb.is_empty() || readback.levels.is_empty() {
+            return false;
+        }
+
+        // Screen rectangle and depth range of the box
+        let mut ndc_min = Vec3::splat(f32::INFINITY);
+        let mut ndc_max = Vec3::splat(f32::NEG_INFINITY);
+        for corner in 0..8 {
+            let position = Vec3::select(
+                BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                aabb.max,
+                aabb.min,
+            );
+            let clip = readback.view_projection * position.extend(1.0);
+            if clip.w <= f32::EPSILON {
+                return false;
+            }
+            let ndc = clip.truncate() / clip.w;
+            ndc_min = ndc_min.min(ndc);
+            ndc_max = ndc_max.max(ndc);
+        }
+        if ndc_min.x < -1.0 || ndc_min.y < -1.0 || ndc_max.x > 1.0 || ndc_max.y > 1.0 {
+            return false;
+        }
+        let nearest_depth = if readback.reversed_z { ndc_max.z } else { ndc_min.z * 0.5 + 0.5 };
+
+        // The finest level where the rectangle covers at most 2x2 texels, the 1x1 level always qualifies
+        let source_size = Vec2::new(readback.source_size[0] as f32, readback.source_size[1] as f32);
+        let pixel_min = (ndc_min.truncate() * 0.5 + 0.5) * source_size;
+        let pixel_max = (ndc_max.truncate() * 0.5 + 0.5) * source_size;
+        let level = readback
+            .levels
+            .iter()
+            .find(|level| {
+                let texel_size = (1 << (level.level + 1)) as f32;
+                ((pixel_max / texel_size).floor() - (pixel_min / texel_size).floor()).max_element() <= 1.0
+            })
+            .unwrap_or(readback.levels.last().unwrap());
+
+        let texel_size = (1 << (level.level + 1)) as f32;
+        let clamp_texel = |pixel: Vec2| {
+            let texel = (pixel / texel_size).floor();
+            (
+                (texel.x as i32).clamp(0, level.width - 1),
+                (texel.y as i32).clamp(0, level.height - 1),
+            )
+        };
+        let (x_min, y_min) = clamp_texel(pixel_min);
+        let (x_max, y_max) = clamp_texel(pixel_max);
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                let depth = level.depth[(y * level.width + x) as usize];
+                let visible = if readback.reversed_z { nearest_depth >= depth } else { nearest_depth <= depth };
+                if visible {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // Keeps the depth already read back usable after the renderer's origin moved by `shift`, boxes given in the new
+    // space land where they were in the view the depth came from
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        for readback in self.pending.iter_mut().chain(std::iter::once(&mut self.current)).flatten() {
+            readback.view_projection *= Mat4::from_translation(shift);
+        }
+    }
+
+    // Replaces the GL objects, the pyramid gets rebuilt at the end of the next frame
+    pub fn recreate_gl_resources(&mut self) {
+        self.delete();
+        unsafe {
+            gl::GenBuffers(PIXEL_BUFFER_COUNT as i32, self.pixel_buffers.as_mut_ptr());
+        }
+        self.texture = 0;
+        self.source_size = [0, 0];
+        self.pending = Default::default();
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(PIXEL_BUFFER_COUNT as i32, self.pixel_buffers.as_ptr());
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
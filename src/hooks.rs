@@ -0,0 +1,50 @@
+use glam::Mat4;
+
+use crate::structs::Rect;
+
+// Points in the frame where user hooks get called, and the GL state they can expect
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PassPoint {
+    // Render framebuffer bound and cleared, viewport and scissor set to the current view, nothing else set up yet
+    BeforeOpaque,
+    // Render framebuffer bound, depth test and backface culling enabled, lit shader and const buffer bound
+    AfterOpaque,
+    // Same state as AfterOpaque, after the transparent meshes were blended over the scene
+    AfterTransparent,
+    // Resolved (non-multisampled) framebuffer bound, depth test and culling still enabled, the final blit comes next
+    BeforePostFx,
+    // Window framebuffer bound, depth test and culling disabled, right before swapping buffers
+    AfterPresentBlit,
+}
+
+impl PassPoint {
+    // Name of the hook pass in the frame graph
+    pub fn pass_name(self) -> &'static str {
+        match self {
+            PassPoint::BeforeOpaque => "hooks_before_opaque",
+            PassPoint::AfterOpaque => "hooks_after_opaque",
+            PassPoint::AfterTransparent => "hooks_after_transparent",
+            PassPoint::BeforePostFx => "hooks_before_post_fx",
+            PassPoint::AfterPresentBlit => "hooks_after_present_blit",
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct PassContext {
+    pub framebuffer: u32,
+    pub resolution: [i32; 2],
+    pub viewport: Rect,
+    pub view_projection_matrix: Mat4,
+    pub delta_time: f32,
+    pub(crate) state_dirty: bool,
+}
+
+pub type PassHook = Box<dyn FnMut(&mut PassContext)>;
+
+impl PassContext {
+    // Call this after changing any GL state, so the renderer re-binds everything it needs afterwards
+    pub fn mark_state_dirty(&mut self) {
+        self.state_dirty = true;
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use glam::Vec3;
+
+use crate::helpers::Pixel32;
+
+// Debug view that colours each pixel by what it came from instead of shading it. The colours are hashed from the IDs,
+// so they're the same on every run and screenshots can be compared
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum IdView {
+    #[default]
+    Off = 0,
+    Mesh = 1,     // Model and mesh name
+    Material = 2, // Material contents, meshes with identical materials get the same colour
+    Triangle = 3, // Triangle index within its mesh
+}
+
+impl IdView {
+    // The next mode, for cycling through them with a key
+    pub fn next(self) -> IdView {
+        match self {
+            IdView::Off => IdView::Mesh,
+            IdView::Mesh => IdView::Material,
+            IdView::Material => IdView::Triangle,
+            IdView::Triangle => IdView::Off,
+        }
+    }
+}
+
+// What one pixel of the ID view came from
+#[derive(Debug, Clone)]
+pub struct IdSample {
+    pub model: u64,
+    pub model_path: Option<PathBuf>,
+    pub model_tag: Option<String>,
+    pub mesh: String,
+    pub material: String, // The library material's name if the mesh was reassigned one, otherwise the model's own
+    pub triangle: u32,
+    pub lod_level: usize,
+    pub colour: Pixel32, // As shown in the current mode
+}
+
+// One line of the ID view's legend
+#[derive(Debug, Clone)]
+pub struct IdLegendEntry {
+    pub label: String,
+    pub colour: Pixel32,
+    pub coverage: f32, // Fraction of the view's pixels
+}
+
+// Integer hash (lowbias32), the same as in id.frag
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+// Folds a 64 bit hash into the 32 bits the ID buffer stores
+pub fn fold(key: u64) -> u32 {
+    hash((key ^ (key >> 32)) as u32)
+}
+
+// Bright, saturated colour for a key, the same as in id_view.frag. Display referred, it's drawn after the output
+// encoding
+pub fn palette(key: u32) -> Pixel32 {
+    let bits = hash(key);
+    let hue = (bits & 0xFFFF) as f32 / 65536.0;
+    let saturation = 0.55 + 0.45 * ((bits >> 16) & 0xFF) as f32 / 255.0;
+    let value = 0.65 + 0.35 * (bits >> 24) as f32 / 255.0;
+    let k = (Vec3::new(5.0, 3.0, 1.0) + hue * 6.0) % 6.0;
+    let rgb = value - value * saturation * (k.min(4.0 - k)).clamp(Vec3::ZERO, Vec3::ONE);
+    Pixel32::from_vec3(rgb, false)
+}
+
+// Formats the legend as text, one entry per line with its colour as hex
+pub fn legend_text(legend: &[IdLegendEntry]) -> String {
+    legend
+        .iter()
+        .map(|entry| {
+            let Pixel32 { r, g, b, .. } = entry.colour;
+            format!("#{r:02X}{g:02X}{b:02X} {:5.1}% {}\n", entry.coverage * 100.0, entry.label)
+        })
+        .collect()
+}
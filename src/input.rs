@@ -1,74 +1,134 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
-use glfw::{Action, Key};
+// Keyboard keys, independent of the windowing library. Keys without a variant come through as Unknown with the
+// backend's own key code
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    // Letters
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    // Number row
+    Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9,
+    // Function keys
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    // Navigation and editing
+    Space, Enter, Escape, Tab, Backspace, Insert, Delete, Home, End, PageUp, PageDown, Up, Down, Left, Right,
+    // Modifiers
+    LeftShift, RightShift, LeftControl, RightControl, LeftAlt, RightAlt, LeftSuper, RightSuper,
+    // Punctuation
+    Minus, Equal, Comma, Period, Slash, Semicolon, Apostrophe, LeftBracket, RightBracket, Backslash,
+    GraveAccent,
+    // Keypad
+    Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9, KpDecimal, KpDivide, KpMultiply, KpSubtract, KpAdd,
+    KpEnter,
+    Unknown(i32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(i32),
+}
+
+// Input as the renderer and camera see it. The window backend translates its own events into these,
+// and anything else (tests, replays) can feed them to UserInput directly
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    KeyDown(KeyCode),
+    KeyUp(KeyCode),
+    MouseMove(f32, f32),
+    MouseButton(MouseButton, bool), // Pressed or released
+    Scroll(f32, f32),
+    FileDrop(Vec<PathBuf>),
+    FramebufferResize(i32, i32),
+}
 
 pub struct UserInput {
-    key_state: HashMap<i32, bool>,
-    mouse_button_state: HashMap<i32, bool>,
+    key_state: HashMap<KeyCode, bool>,
+    keys_pressed: HashSet<KeyCode>, // Keys that went down this frame
+    mouse_button_state: HashMap<MouseButton, bool>,
+    buttons_pressed: HashSet<MouseButton>, // Mouse buttons that went down this frame
     mouse_pos: (f32, f32),
+    scroll: f32,
+    dropped_files: Vec<PathBuf>,
 }
 
 impl UserInput {
-    pub fn process_event(&mut self, event: &glfw::WindowEvent) {
-        // Handle key input
-        if let glfw::WindowEvent::Key(key, _, action, _) = event {
-            self.key_state.insert(
-                *key as i32,
-                match action {
-                    Action::Press => true,
-                    Action::Release => false,
-                    Action::Repeat => true,
-                },
-            );
+    pub fn process_event(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::KeyDown(key) => {
+                if !self.is_key_down(*key) {
+                    self.keys_pressed.insert(*key);
+                }
+                self.key_state.insert(*key, true);
+            }
+            InputEvent::KeyUp(key) => {
+                self.key_state.insert(*key, false);
+            }
+            InputEvent::MouseButton(button, pressed) => {
+                if *pressed && !self.get_mouse_down(*button) {
+                    self.buttons_pressed.insert(*button);
+                }
+                self.mouse_button_state.insert(*button, *pressed);
+            }
+            InputEvent::MouseMove(x, y) => self.mouse_pos = (*x, *y),
+            InputEvent::Scroll(_, y) => self.scroll += y,
+            InputEvent::FileDrop(paths) => self.dropped_files.extend(paths.iter().cloned()),
+            // The renderer picks up framebuffer size changes itself
+            InputEvent::FramebufferResize(_, _) => {}
         }
+    }
 
-        // Handle mouse buttons
-        if let glfw::WindowEvent::MouseButton(button, action, _) = event {
-            self.mouse_button_state.insert(
-                *button as i32,
-                match action {
-                    Action::Press => true,
-                    Action::Release => false,
-                    Action::Repeat => true,
-                },
-            );
-        }
+    // Clears the state that only lasts for one frame, called before processing a new frame's events
+    pub fn begin_frame(&mut self) {
+        self.scroll = 0.0;
+        self.keys_pressed.clear();
+        self.buttons_pressed.clear();
+    }
 
-        // Handle mouse position
-        if let glfw::WindowEvent::CursorPos(x, y) = event {
-            self.mouse_pos = (*x as f32, *y as f32);
-        }
+    pub fn is_key_down(&self, key: KeyCode) -> bool {
+        self.key_state.get(&key).copied().unwrap_or(false)
     }
 
-    pub fn is_key_down(&self, key: Key) -> bool {
-        if self.key_state.contains_key(&(key as i32)) {
-            self.key_state[&(key as i32)]
-        } else {
-            false
-        }
+    // True only on the frame the key went down, key repeats don't count
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
     }
 
     pub fn new() -> Self {
         UserInput {
             key_state: HashMap::new(),
+            keys_pressed: HashSet::new(),
             mouse_button_state: HashMap::new(),
+            buttons_pressed: HashSet::new(),
             mouse_pos: (0.0, 0.0),
+            scroll: 0.0,
+            dropped_files: Vec::new(),
         }
     }
 
     pub(crate) fn get_scroll_wheel(&self) -> f32 {
-        0.0
+        self.scroll
     }
 
     pub(crate) fn get_mouse_pos(&self) -> (f32, f32) {
         self.mouse_pos
     }
 
-    pub(crate) fn get_mouse_down(&self, button: glfw::MouseButton) -> bool {
-        if self.mouse_button_state.contains_key(&(button as i32)) {
-            self.mouse_button_state[&(button as i32)]
-        } else {
-            false
-        }
+    pub fn take_dropped_files(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.dropped_files)
+    }
+
+    pub(crate) fn get_mouse_down(&self, button: MouseButton) -> bool {
+        self.mouse_button_state.get(&button).copied().unwrap_or(false)
+    }
+
+    // True only on the frame the button went down
+    pub(crate) fn is_mouse_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
     }
 }
@@ -0,0 +1,131 @@
+use glfw::{Action, Key, WindowEvent};
+
+use crate::input::{InputEvent, KeyCode, MouseButton};
+
+// Translates a GLFW window event into a crate input event, events the renderer doesn't care about give None
+pub fn translate_event(event: &WindowEvent) -> Option<InputEvent> {
+    match event {
+        WindowEvent::Key(key, _, action, _) => match action {
+            Action::Press | Action::Repeat => Some(InputEvent::KeyDown(translate_key(*key))),
+            Action::Release => Some(InputEvent::KeyUp(translate_key(*key))),
+        },
+        WindowEvent::MouseButton(button, action, _) => {
+            let button = match button {
+                glfw::MouseButton::Button1 => MouseButton::Left,
+                glfw::MouseButton::Button2 => MouseButton::Right,
+                glfw::MouseButton::Button3 => MouseButton::Middle,
+                other => MouseButton::Other(*other as i32),
+            };
+            Some(InputEvent::MouseButton(button, *action != Action::Release))
+        }
+        WindowEvent::CursorPos(x, y) => Some(InputEvent::MouseMove(*x as f32, *y as f32)),
+        WindowEvent::Scroll(x, y) => Some(InputEvent::Scroll(*x as f32, *y as f32)),
+        WindowEvent::FileDrop(paths) => Some(InputEvent::FileDrop(paths.clone())),
+        WindowEvent::FramebufferSize(width, height) => Some(InputEvent::FramebufferResize(*width, *height)),
+        _ => None,
+    }
+}
+
+fn translate_key(key: Key) -> KeyCode {
+    match key {
+        Key::A => KeyCode::A,
+        Key::B => KeyCode::B,
+        Key::C => KeyCode::C,
+        Key::D => KeyCode::D,
+        Key::E => KeyCode::E,
+        Key::F => KeyCode::F,
+        Key::G => KeyCode::G,
+        Key::H => KeyCode::H,
+        Key::I => KeyCode::I,
+        Key::J => KeyCode::J,
+        Key::K => KeyCode::K,
+        Key::L => KeyCode::L,
+        Key::M => KeyCode::M,
+        Key::N => KeyCode::N,
+        Key::O => KeyCode::O,
+        Key::P => KeyCode::P,
+        Key::Q => KeyCode::Q,
+        Key::R => KeyCode::R,
+        Key::S => KeyCode::S,
+        Key::T => KeyCode::T,
+        Key::U => KeyCode::U,
+        Key::V => KeyCode::V,
+        Key::W => KeyCode::W,
+        Key::X => KeyCode::X,
+        Key::Y => KeyCode::Y,
+        Key::Z => KeyCode::Z,
+        Key::Num0 => KeyCode::Num0,
+        Key::Num1 => KeyCode::Num1,
+        Key::Num2 => KeyCode::Num2,
+        Key::Num3 => KeyCode::Num3,
+        Key::Num4 => KeyCode::Num4,
+        Key::Num5 => KeyCode::Num5,
+        Key::Num6 => KeyCode::Num6,
+        Key::Num7 => KeyCode::Num7,
+        Key::Num8 => KeyCode::Num8,
+        Key::Num9 => KeyCode::Num9,
+        Key::F1 => KeyCode::F1,
+        Key::F2 => KeyCode::F2,
+        Key::F3 => KeyCode::F3,
+        Key::F4 => KeyCode::F4,
+        Key::F5 => KeyCode::F5,
+        Key::F6 => KeyCode::F6,
+        Key::F7 => KeyCode::F7,
+        Key::F8 => KeyCode::F8,
+        Key::F9 => KeyCode::F9,
+        Key::F10 => KeyCode::F10,
+        Key::F11 => KeyCode::F11,
+        Key::F12 => KeyCode::F12,
+        Key::Space => KeyCode::Space,
+        Key::Enter => KeyCode::Enter,
+        Key::Escape => KeyCode::Escape,
+        Key::Tab => KeyCode::Tab,
+        Key::Backspace => KeyCode::Backspace,
+        Key::Insert => KeyCode::Insert,
+        Key::Delete => KeyCode::Delete,
+        Key::Home => KeyCode::Home,
+        Key::End => KeyCode::End,
+        Key::PageUp => KeyCode::PageUp,
+        Key::PageDown => KeyCode::PageDown,
+        Key::Up => KeyCode::Up,
+        Key::Down => KeyCode::Down,
+        Key::Left => KeyCode::Left,
+        Key::Right => KeyCode::Right,
+        Key::LeftShift => KeyCode::LeftShift,
+        Key::RightShift => KeyCode::RightShift,
+        Key::LeftControl => KeyCode::LeftControl,
+        Key::RightControl => KeyCode::RightControl,
+        Key::LeftAlt => KeyCode::LeftAlt,
+        Key::RightAlt => KeyCode::RightAlt,
+        Key::LeftSuper => KeyCode::LeftSuper,
+        Key::RightSuper => KeyCode::RightSuper,
+        Key::Minus => KeyCode::Minus,
+        Key::Equal => KeyCode::Equal,
+        Key::Comma => KeyCode::Comma,
+        Key::Period => KeyCode::Period,
+        Key::Slash => KeyCode::Slash,
+        Key::Semicolon => KeyCode::Semicolon,
+        Key::Apostrophe => KeyCode::Apostrophe,
+        Key::LeftBracket => KeyCode::LeftBracket,
+        Key::RightBracket => KeyCode::RightBracket,
+        Key::Backslash => KeyCode::Backslash,
+        Key::GraveAccent => KeyCode::GraveAccent,
+        Key::Kp0 => KeyCode::Kp0,
+        Key::Kp1 => KeyCode::Kp1,
+        Key::Kp2 => KeyCode::Kp2,
+        Key::Kp3 => KeyCode::Kp3,
+        Key::Kp4 => KeyCode::Kp4,
+        Key::Kp5 => KeyCode::Kp5,
+        Key::Kp6 => KeyCode::Kp6,
+        Key::Kp7 => KeyCode::Kp7,
+        Key::Kp8 => KeyCode::Kp8,
+        Key::Kp9 => KeyCode::Kp9,
+        Key::KpDecimal => KeyCode::KpDecimal,
+        Key::KpDivide => KeyCode::KpDivide,
+        Key::KpMultiply => KeyCode::KpMultiply,
+        Key::KpSubtract => KeyCode::KpSubtract,
+        Key::KpAdd => KeyCode::KpAdd,
+        Key::KpEnter => KeyCode::KpEnter,
+        other => KeyCode::Unknown(other as i32),
+    }
+}
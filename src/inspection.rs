@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+};
+
+use glam::{vec2, vec3, Mat4, Vec2, Vec3, Vec4};
+
+use crate::{
+    camera::Camera,
+    gpu_buffer::GpuBuffer,
+    helpers::Pixel32,
+    material::Material,
+    mesh::{Mesh, Model},
+    structs::{Vertex, AABB},
+    texture::Texture,
+};
+
+// Preview objects fit in a unit cube
+pub const PREVIEW_RADIUS: f32 = 0.5;
+// Distance between the centres of neighbouring preview objects
+pub const PREVIEW_SPACING: f32 = 1.25;
+// Quads around a sphere's equator are twice this, knots get more along their length
+const PREVIEW_SEGMENTS: u32 = 32;
+
+// What the materials of an inspection scene are shown on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PreviewShape {
+    Sphere,
+    #[allow(dead_code)]
+    Knot, // Trefoil tube, shows concave and convex curvature at once
+}
+
+// Which standard setup build_inspection_scene makes
+#[derive(Debug, Clone, PartialEq)]
+pub enum InspectionLayout {
+    // A wall of preview objects, roughness going from the first to the second value along the columns and metallic
+    // along the rows, bottom to top. The colour is linear
+    ShaderBalls {
+        columns: u32,
+        rows: u32,
+        roughness: (f32, f32),
+        metallic: (f32, f32),
+        colour: Vec3,
+    },
+    // A loaded model standing on a pedestal
+    Pedestal { model: u64 },
+    // Every material of a loaded model on its own preview object, in a row sorted by name
+    MaterialRow { model: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InspectionConfig {
+    pub layout: InspectionLayout,
+    pub shape: PreviewShape,
+    pub ground: bool, // Neutral checkerboard under the scene
+}
+
+impl Default for InspectionConfig {
+    fn default() -> Self {
+        InspectionConfig {
+            layout: InspectionLayout::ShaderBalls {
+                columns: 5,
+                rows: 5,
+                roughness: (0.05, 1.0),
+                metallic: (0.0, 1.0),
+                colour: vec3(0.8, 0.3, 0.2),
+            },
+            shape: PreviewShape::Sphere,
+            ground: true,
+        }
+    }
+}
+
+// A camera looking at a point from a distance. Yaw and pitch work like Camera's, both 0 looks along the horizon
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OrbitCamera {
+    pub target: Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32, // Negative looks down on the target
+}
+
+impl OrbitCamera {
+    // Far enough away that a sphere around the bounds fits in the field of view
+    pub fn framing(bounds: &AABB, fov: f32, yaw: f32, pitch: f32) -> OrbitCamera {
+        let radius = ((bounds.max - bounds.min).length() * 0.5).max(0.01);
+        OrbitCamera {
+            target: (bounds.min + bounds.max) * 0.5,
+            distance: radius / (fov * 0.5).sin(),
+            yaw,
+            pitch,
+        }
+    }
+
+    // Moves the camera onto the orbit, facing the target
+    pub fn apply(&self, camera: &mut Camera) {
+        let forward = vec3(-self.yaw.sin() * self.pitch.cos(), self.pitch.sin(), -self.yaw.cos() * self.pitch.cos());
+        camera.transform.translation = self.target - camera.world_up.y_up_rotation() * forward * self.distance;
+        camera.set_orientation(self.yaw, self.pitch);
+    }
+}
+
+// Everything build_inspection_scene made. It's drawn with draw_inspection_scene every frame like a model, and
+// remove_inspection_scene unloads the generated models again. The models it shows but didn't make stay loaded
+pub struct SceneHandles {
+    pub models: Vec<u64>, // Generated models: preview objects, the pedestal and the ground
+    pub camera: OrbitCamera,
+    pub(crate) placements: Vec<(u64, Mat4, Vec4)>, // Model, transform and tint of everything in the scene
+}
+
+// Triangle list of a surface given as position and normal over u and v from 0 to 1. Texture coordinates are u and v
+// times `uv_scale`, tangents follow u
+fn parametric(u_segments: u32, v_segments: u32, uv_scale: Vec2, surface: impl Fn(f32, f32) -> (Vec3, Vec3)) -> Vec<Vertex> {
+    let vertex = |i: u32, j: u32| {
+        let (u, v) = (i as f32 / u_segments as f32, j as f32 / v_segments as f32);
+        let (position, normal) = surface(u, v);
+        let along_u = surface(u + 1e-3, v).0 - position;
+        let along_v = surface(u, v + 1e-3).0 - position;
+        // Poles have no direction along u, any tangent will do there
+        let tangent = (along_u - normal * normal.dot(along_u)).try_normalize().unwrap_or_else(|| normal.any_orthonormal_vector());
+        let handedness = if normal.cross(tangent).dot(along_v) < 0.0 { -1.0 } else { 1.0 };
+        let uv = vec2(u, v) * uv_scale;
+        Vertex {
+            position,
+            normal,
+            tangent: tangent.extend(handedness),
+            colour: Vec4::ONE,
+            uv0: uv,
+            uv1: uv,
+        }
+    };
+
+    let mut verts = Vec::with_capacity((u_segments * v_segments * 6) as usize);
+    for j in 0..v_segments {
+        for i in 0..u_segments {
+            let corners = [vertex(i, j), vertex(i + 1, j), vertex(i + 1, j + 1), vertex(i, j + 1)];
+            for [a, b, c] in [[corners[0], corners[1], corners[2]], [corners[0], corners[2], corners[3]]] {
+                // Counter-clockwise seen from the side the normals face. The triangles that collapse at the poles
+                // are dropped
+                let face = (b.position - a.position).cross(c.position - a.position);
+                if face.length_squared() < 1e-12 {
+                    continue;
+                }
+                match face.dot(a.normal + b.normal + c.normal) < 0.0 {
+                    true => verts.extend([a, c, b]),
+                    false => verts.extend([a, b, c]),
+                }
+            }
+        }
+    }
+    verts
+}
+
+pub fn preview_shape(shape: PreviewShape) -> Vec<Vertex> {
+    match shape {
+        PreviewShape::Sphere => parametric(PREVIEW_SEGMENTS * 2, PREVIEW_SEGMENTS, vec2(2.0, 1.0), |u, v| {
+            let (theta, phi) = (u * TAU, v * PI);
+            let normal = vec3(phi.sin() * theta.cos(), phi.cos(), -phi.sin() * theta.sin());
+            (normal * PREVIEW_RADIUS, normal)
+        }),
+        PreviewShape::Knot => {
+            // (2, 3) torus knot facing +Z, with a Frenet frame from finite differences. Its curvature never vanishes,
+            // so the frame is defined everywhere
+            const TUBE: f32 = 0.4;
+            let curve = |t: f32| {
+                let angle = t * TAU;
+                let radius = 2.0 + (3.0 * angle).cos();
+                vec3(radius * (2.0 * angle).cos(), radius * (2.0 * angle).sin(), -(3.0 * angle).sin())
+            };
+            let scale = PREVIEW_RADIUS / (3.0 + TUBE);
+            parametric(PREVIEW_SEGMENTS * 8, PREVIEW_SEGMENTS / 2, vec2(8.0, 1.0), |u, v| {
+                let step = 1e-2;
+                let centre = curve(u);
+                let tangent = (curve(u + step) - curve(u - step)).normalize();
+                let bend = curve(u + step) + curve(u - step) - 2.0 * centre;
+                let normal = (bend - tangent * tangent.dot(bend)).normalize();
+                let binormal = tangent.cross(normal);
+                let angle = v * TAU;
+                let direction = normal * angle.cos() + binormal * angle.sin();
+                ((centre + direction * TUBE) * scale, direction)
+            })
+        }
+    }
+}
+
+// Cylinder standing on the origin
+pub fn pedestal(radius: f32, height: f32) -> Vec<Vertex> {
+    let around = |u: f32| {
+        let angle = u * TAU;
+        vec3(angle.cos(), 0.0, -angle.sin())
+    };
+    let mut verts = parametric(PREVIEW_SEGMENTS * 2, 1, vec2(4.0, 1.0), |u, v| {
+        (around(u) * radius + Vec3::Y * height * (1.0 - v), around(u))
+    });
+    verts.extend(parametric(PREVIEW_SEGMENTS * 2, 1, Vec2::ONE, |u, v| (around(u) * radius * v + Vec3::Y * height, Vec3::Y)));
+    verts
+}
+
+// Square centred on the origin, facing up, with the checker texture repeating every `period` units
+pub fn ground(size: f32, period: f32) -> Vec<Vertex> {
+    parametric(1, 1, Vec2::splat(size / period), |u, v| (vec3(u - 0.5, 0.0, v - 0.5) * size, Vec3::Y))
+}
+
+// Two by two checks in two neutral greys
+pub fn checker_texture() -> Texture {
+    let size = 64;
+    let data = (0..size * size)
+        .map(|index| {
+            let light = (index % size < size / 2) != (index / size < size / 2);
+            let value = if light { 170 } else { 120 };
+            Pixel32::new(value, value, value, 255).to_u32()
+        })
+        .collect();
+    Texture {
+        gl_id: 0,
+        width: size,
+        height: size,
+        depth: 4,
+        data,
+    }
+}
+
+// A model with a single mesh, both the mesh and its material named `name`
+pub fn single_mesh_model(name: &str, verts: Vec<Vertex>, material: Material) -> Model {
+    let mut bounds = AABB::new();
+    for vertex in &verts {
+        bounds.grow(vertex.position);
+    }
+    let mesh = Mesh {
+        verts,
+        positions: Vec::new(),
+        vao: 0,
+        vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
+        bounds,
+        lods: Vec::new(),
+        lod_level: 0,
+    };
+    Model {
+        meshes: HashMap::from([(name.to_string(), mesh)]),
+        materials: HashMap::from([(name.to_string(), material)]),
+        ..Model::new()
+    }
+}
@@ -0,0 +1,126 @@
+use std::{collections::VecDeque, fmt::Write};
+
+use crate::profiler;
+
+// Records kept, the oldest ones get dropped after that
+const CAPACITY: usize = 1024;
+// Frames that uploaded more than this in total are marked in the dump
+pub const HEAVY_FRAME_BYTES: usize = 4 * 1024 * 1024;
+
+// Something that changed the renderer's state, and may have made it do work
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChangeOperation {
+    ModelLoad,
+    ModelReload,
+    ModelUnload,
+    TextureUpload,
+    TextureStream, // A full resolution texture replaced its streaming preview
+    TextureReload,
+    MaterialLoad,
+    MaterialReassign,
+    MaterialParameters, // A model material's custom parameters or sort bias were set
+    ShaderRegister,
+    ShaderReload,
+    ModelShader, // A model's custom shader was set or cleared
+    DecalAdd,
+    DecalRemove,
+    WaterPlaneAdd,
+    WaterPlaneRemove,
+    LineBatchCreate,
+    LineBatchUpdate,
+    LineBatchDestroy,
+    Setting(&'static str), // Name of the setting
+}
+
+impl ChangeOperation {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeOperation::ModelLoad => "model_load",
+            ChangeOperation::ModelReload => "model_reload",
+            ChangeOperation::ModelUnload => "model_unload",
+            ChangeOperation::TextureUpload => "texture_upload",
+            ChangeOperation::TextureStream => "texture_stream",
+            ChangeOperation::TextureReload => "texture_reload",
+            ChangeOperation::MaterialLoad => "material_load",
+            ChangeOperation::MaterialReassign => "material_reassign",
+            ChangeOperation::MaterialParameters => "material_parameters",
+            ChangeOperation::ShaderRegister => "shader_register",
+            ChangeOperation::ShaderReload => "shader_reload",
+            ChangeOperation::ModelShader => "model_shader",
+            ChangeOperation::DecalAdd => "decal_add",
+            ChangeOperation::DecalRemove => "decal_remove",
+            ChangeOperation::WaterPlaneAdd => "water_plane_add",
+            ChangeOperation::WaterPlaneRemove => "water_plane_remove",
+            ChangeOperation::LineBatchCreate => "line_batch_create",
+            ChangeOperation::LineBatchUpdate => "line_batch_update",
+            ChangeOperation::LineBatchDestroy => "line_batch_destroy",
+            ChangeOperation::Setting(name) => name,
+        }
+    }
+}
+
+// One journal entry, fixed size so recording never allocates
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChangeRecord {
+    pub frame_index: u64,
+    pub operation: ChangeOperation,
+    pub handle: u64,  // Model, texture, material, decal, water plane or line batch the change was made to, 0 for settings
+    pub bytes: usize, // Uploaded to the GPU because of the change
+}
+
+// Bounded log of the state changes of the last frames, to find out what caused a frame spike. Every change also
+// shows up as an instant event in the profiler trace. Without the change-journal feature nothing gets recorded
+pub struct ChangeJournal {
+    records: VecDeque<ChangeRecord>,
+}
+
+impl ChangeJournal {
+    pub fn new() -> Self {
+        ChangeJournal {
+            records: VecDeque::with_capacity(if cfg!(feature = "change-journal") { CAPACITY } else { 0 }),
+        }
+    }
+
+    pub fn record(&mut self, frame_index: u64, operation: ChangeOperation, handle: u64, bytes: usize) {
+        if !cfg!(feature = "change-journal") {
+            return;
+        }
+        if self.records.len() == CAPACITY {
+            self.records.pop_front();
+        }
+        self.records.push_back(ChangeRecord {
+            frame_index,
+            operation,
+            handle,
+            bytes,
+        });
+        profiler::instant(operation.name());
+    }
+
+    // The last `count` records, oldest first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &ChangeRecord> {
+        self.records.iter().skip(self.records.len().saturating_sub(count))
+    }
+
+    // The last `count` records as text, grouped by frame. Frames that uploaded more than HEAVY_FRAME_BYTES are marked
+    pub fn dump(&self, count: usize) -> String {
+        let records: Vec<&ChangeRecord> = self.recent(count).collect();
+        let mut text = String::new();
+        for frame in records.chunk_by(|a, b| a.frame_index == b.frame_index) {
+            let bytes: usize = frame.iter().map(|record| record.bytes).sum();
+            let marker = if bytes > HEAVY_FRAME_BYTES { "  <-- heavy" } else { "" };
+            writeln!(text, "Frame {}: {} changes, {:.2} MiB uploaded{marker}", frame[0].frame_index, frame.len(), bytes as f32 / (1024.0 * 1024.0)).unwrap();
+            for record in frame {
+                write!(text, "    {:<20} {:016X}", record.operation.name(), record.handle).unwrap();
+                if record.bytes > 0 {
+                    write!(text, " {:.1} KiB", record.bytes as f32 / 1024.0).unwrap();
+                }
+                text.push('\n');
+            }
+        }
+        if text.is_empty() {
+            text = "No changes recorded\n".to_string();
+        }
+        text
+    }
+}
@@ -0,0 +1,66 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+// Minimal logger for the log crate, printing to stderr. Levels come from the RUST_LOG environment variable, in the
+// same format env_logger uses: a comma separated list of `level` and `module=level` entries, where the longest
+// matching module prefix wins, e.g. RUST_LOG=warn,rust_render_gl::graphics=debug. Without it, info and up is shown
+pub struct Logger {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>, // Longest prefix first
+}
+
+impl Logger {
+    pub fn from_env() -> Self {
+        Self::parse(&std::env::var("RUST_LOG").unwrap_or_default())
+    }
+
+    pub fn parse(spec: &str) -> Self {
+        let mut logger = Logger {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((module, level)) => match level.trim().parse() {
+                    Ok(level) => logger.modules.push((module.trim().to_string(), level)),
+                    Err(_) => eprintln!("Ignoring invalid RUST_LOG level \"{level}\" for \"{module}\""),
+                },
+                None => match entry.parse() {
+                    Ok(level) => logger.default = level,
+                    // A bare module name turns on everything for that module, like env_logger
+                    Err(_) => logger.modules.push((entry.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        logger.modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        logger
+    }
+
+    // Installs the logger for the whole process, can only be done once
+    pub fn install(self) -> Result<(), log::SetLoggerError> {
+        let max_level = self.modules.iter().map(|(_, level)| *level).chain([self.default]).max().unwrap_or(LevelFilter::Info);
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| target == module || target.strip_prefix(module.as_str()).is_some_and(|rest| rest.starts_with("::")))
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
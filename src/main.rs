@@ -1,55 +1,943 @@
 #![allow(clippy::identity_op)]
 #![allow(clippy::needless_return)]
 
+mod assets;
+mod benchmark;
 mod camera;
+mod capabilities;
+mod cli;
+mod embed_host;
+mod exposure;
 mod graphics;
 mod input;
+mod journal;
+mod input_glfw;
+mod logger;
 mod material;
 mod mesh;
+#[cfg(feature = "gltf-loader")]
+mod gltf_loader;
+mod obj;
 mod structs;
 mod texture;
 mod helpers;
-use std::path::Path;
+mod hooks;
+mod id_view;
+mod inspection;
+mod frame_graph;
+mod frame_history;
+mod gpu_buffer;
+mod gpu_layout;
+mod hiz;
+mod profiler;
+mod quality;
+mod random;
+mod raycast;
+mod shader_cache;
+mod simplify;
+mod snapshot;
+mod text;
+mod texture_store;
+mod water;
+#[cfg(feature = "gltf-loader")]
+mod workers;
+use std::{cell::Cell, collections::{HashMap, VecDeque}, path::Path, rc::Rc};
 
-use camera::Camera;
-use graphics::Renderer;
-use input::UserInput;
-
-use structs::Transform;
+use camera::{Camera, CameraSmoothing};
+use cli::{InspectLayout, Options};
+use embed_host::EmbedHost;
+use exposure::{ExposureSettings, MeteringMode};
+use graphics::{DynamicResolution, MotionBlurSettings, Renderer, SkyConfig, SsaoSettings, StereoConfig};
+use hooks::PassPoint;
+use helpers::Pixel32;
+use id_view::IdView;
+use inspection::{InspectionConfig, InspectionLayout, OrbitCamera};
+use input::{KeyCode, MouseButton, UserInput};
+use log::{error, warn};
+use logger::Logger;
+use material::{CustomParameters, MaterialDescriptor};
+use quality::QualityGovernorConfig;
+use structs::{Rect, Transform, AABB};
+use texture::{Texture, TextureStreamingConfig};
+use water::WaterConfig;
 
 fn main() {
+    // Diagnostics go through the log crate, filtered by RUST_LOG
+    Logger::from_env().install().expect("No other logger is installed");
+
+    // Parse command line
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            println!("{message}");
+            std::process::exit(1);
+        }
+    };
+    if let Some((old, new)) = &options.compare {
+        match benchmark::compare(old, new, options.threshold) {
+            Ok(0) => std::process::exit(0),
+            Ok(_) => std::process::exit(1),
+            Err(error) => {
+                error!("{error}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(out) = &options.out {
+        if let Err(error) = std::fs::create_dir_all(out) {
+            error!("Failed to create output directory \"{}\": {error}", out.display());
+            std::process::exit(1);
+        }
+    }
+
     // Create renderer and input
     let mut renderer = 
-        Renderer::new(1280, 720, "FlanRustRenderer (OpenGL)")
+        Renderer::new(options.width, options.height, "FlanRustRenderer (OpenGL)", !options.headless, options.world_up, options.texture_backend)
             .expect("Failed to initialize renderer");
+    renderer.set_profiling(options.trace.is_some());
+    renderer.set_hot_reload(options.hot_reload);
+    renderer.set_shader_cache(options.shader_cache.clone());
+    for root in &options.asset_roots {
+        renderer.add_asset_root(root);
+    }
     let mut user_input = UserInput::new();
+    renderer.set_msaa(4);
+    renderer.set_framebuffer_format(options.framebuffer_format);
+    if let Some((paper_white, peak)) = options.hdr_nits {
+        renderer.set_hdr_nits(paper_white, peak);
+    }
+    // The command line parser validated the projection, but the context may not support reversed-Z
+    if let Err(error) = renderer.set_projection(options.projection) {
+        warn!("{error}");
+    }
+    renderer.set_dithering(true);
+    if let Some(epsilon) = options.transparent_sort_epsilon {
+        renderer.set_transparent_sort_epsilon(epsilon);
+    }
+    if let Some(output) = options.stereo {
+        renderer.set_stereo(StereoConfig {
+            enabled: true,
+            output,
+            ..Default::default()
+        });
+    }
+    renderer.set_ssao(SsaoSettings {
+        enabled: true,
+        ..Default::default()
+    });
+    renderer.set_taa(true);
+    renderer.set_sky(SkyConfig {
+        enabled: true,
+        ..renderer.sky()
+    });
+    renderer.set_title_stats(true);
+    renderer.set_window_icon(&Texture::checkerboard(32, 8, Pixel32::new(255, 140, 0, 255), Pixel32::new(40, 40, 40, 255)));
+
+    // Headless captures should all come out at the requested size
+    renderer.set_dynamic_resolution(DynamicResolution {
+        enabled: !options.headless,
+        ..Default::default()
+    });
+    renderer.set_upscale(options.upscale);
+    renderer.set_quality_governor(QualityGovernorConfig {
+        enabled: !options.headless,
+        ..Default::default()
+    });
+    renderer.set_frame_history(Some(60));
+    renderer.set_frame_history_downscale(2); // Half resolution is plenty to spot a glitch, at a quarter of the memory
+    renderer.set_texture_quality(options.texture_quality);
+    renderer.set_texture_lod_bias(options.texture_lod_bias);
+    renderer.set_texture_streaming(TextureStreamingConfig {
+        enabled: true,
+        bytes_per_frame: 4 * 1024 * 1024,
+    });
+
+    // Make sure a linear 50% grey reaches the window as sRGB 128, a hidden window's back buffer can't be trusted
+    if !options.headless {
+        if let Err(error) = renderer.check_output_encoding() {
+            warn!("{error}");
+        }
+    }
+
+    // Upload the meshes to the GPU, falling back to the example model
+    let mut models = Vec::new();
+    let inspecting = options.inspect_layout.is_some();
+    if options.models.is_empty() && !inspecting && cfg!(feature = "gltf-loader") {
+        let model_spyro = renderer
+            .load_model(Path::new("assets/models/spyro.gltf"))
+            .expect("Failed to upload model!");
+        renderer.set_model_tag(model_spyro, "spyro");
+        models.push(model_spyro);
+    } else if options.models.is_empty() && !inspecting {
+        warn!("No --model given, and the example model needs the gltf-loader feature");
+    }
+    for path in &options.models {
+        match renderer.load_model_with_options(path, &options.load_options) {
+            Ok(model) => {
+                if let Some(info) = renderer.model_info(model) {
+                    // Say which asset root it came from, the path alone may match in several
+                    let file = renderer.resolve_asset(path).unwrap_or_else(|_| path.clone());
+                    println!("Loaded \"{}\" with {} meshes", file.display(), info.mesh_count);
+                    if info.lod_triangle_counts.len() > 1 {
+                        println!("Generated LODs for \"{}\" with {:?} triangles", path.display(), info.lod_triangle_counts);
+                    }
+                    if info.scenes.len() > 1 {
+                        println!("\"{}\" has {} scenes, pick one with --gltf-scene: {:?}", path.display(), info.scenes.len(), info.scenes);
+                    }
+                }
+                renderer.set_model_tag(model, &path.display().to_string());
+                models.push(model);
+            }
+            Err(_) => {
+                error!("Failed to load model \"{}\"", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Swap every material for the one from the command line, the models share its textures
+    if let Some(albedo) = &options.material {
+        let material = renderer.load_material(&MaterialDescriptor {
+            name: albedo.display().to_string(),
+            albedo: Some(albedo.clone()),
+            ..Default::default()
+        });
+        for &model in &models {
+            for mesh in renderer.model_info(model).map(|info| info.meshes).unwrap_or_default() {
+                renderer.reassign_material(model, &mesh, material).expect("Mesh names come from the model itself");
+            }
+        }
+    }
+    // --inspect shows its model in a generated scene instead of where it is, the shader balls need no model at all
+    let mut inspection = options.inspect_layout.map(|layout| {
+        let model = options.inspect.as_ref().map(|path| match renderer.load_model_with_options(path, &options.load_options) {
+            Ok(model) => model,
+            Err(_) => {
+                error!("Failed to load model \"{}\"", path.display());
+                std::process::exit(1);
+            }
+        });
+        let layout = match (layout, model) {
+            (InspectLayout::Pedestal, Some(model)) => InspectionLayout::Pedestal { model },
+            (InspectLayout::Materials, Some(model)) => InspectionLayout::MaterialRow { model },
+            _ => InspectionConfig::default().layout,
+        };
+        match renderer.build_inspection_scene(&InspectionConfig { layout, ..Default::default() }) {
+            Ok(scene) => scene,
+            Err(error) => {
+                error!("{error}");
+                std::process::exit(1);
+            }
+        }
+    });
+
+    // Cel shading for F1, drawn through the same per-model override hook apps would use
+    let toon_shader = match renderer.register_shader(Path::new("assets/shaders/lit.vert"), Path::new("assets/shaders/toon.frag")) {
+        Ok(shader) => Some(shader),
+        Err(error) => {
+            warn!("Toon shading is unavailable: {error}");
+            None
+        }
+    };
+
+    // Dissolve effect for K, configured entirely through the material's custom parameters
+    let dissolve_shader = match renderer.register_shader(Path::new("assets/shaders/lit.vert"), Path::new("assets/shaders/dissolve.frag")) {
+        Ok(shader) => Some(shader),
+        Err(error) => {
+            warn!("The dissolve effect is unavailable: {error}");
+            None
+        }
+    };
+
+    let library = renderer.material_library_stats();
+    if library.bytes_saved > 0 {
+        println!(
+            "Sharing {} texture files between models saved {:.1} MiB of uploads, {} library materials",
+            library.shared_textures,
+            library.bytes_saved as f32 / (1024.0 * 1024.0),
+            library.materials
+        );
+    }
+
+    let vertex_memory: usize = models.iter().filter_map(|&model| renderer.model_info(model)).map(|info| info.cpu_bytes).sum();
+    println!(
+        "Models keep {:.1} MiB of vertex data in memory, --cpu-data decides how much",
+        vertex_memory as f32 / (1024.0 * 1024.0)
+    );
+    println!(
+        "Material textures use {:.1} MiB of GPU memory, stored as {:?}",
+        renderer.texture_memory() as f32 / (1024.0 * 1024.0),
+        renderer.texture_backend()
+    );
+
+    // List any textures that got replaced by the placeholder checkerboard
+    for missing in renderer.missing_texture_report() {
+        warn!("Missing {:?} texture in material \"{}\": {}", missing.slot, missing.material, missing.reason);
+    }
 
-    // Upload the mesh to the GPU
-    let model_spyro = renderer
-        .load_model(Path::new("assets/models/spyro.gltf"))
-        .expect("Failed to upload model!");
+    // Measure instead of running interactively
+    if let Some(report) = &options.benchmark {
+        if let Err(error) = benchmark::run(&mut renderer, &models, options.benchmark_frames, report) {
+            error!("{error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Tint the scene while T is held, as an example of a custom pass hook
+    let tint_enabled = Rc::new(Cell::new(false));
+    let tint_shader = renderer
+        .load_shader(Path::new("assets/shaders/tint"))
+        .expect("Shader loading failed!");
+    let mut tint_vao = 0;
+    unsafe {
+        gl::GenVertexArrays(1, &mut tint_vao);
+    }
+    let tint_enabled_hook = tint_enabled.clone();
+    renderer.add_pass_hook(
+        PassPoint::BeforePostFx,
+        Box::new(move |context| {
+            if !tint_enabled_hook.get() {
+                return;
+            }
+            unsafe {
+                gl::Disable(gl::DEPTH_TEST);
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::DST_COLOR, gl::ZERO);
+                gl::UseProgram(tint_shader);
+                gl::BindVertexArray(tint_vao);
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            context.mark_state_dirty();
+        }),
+    );
 
     // Create a camera
     let mut camera = Camera::new(
         Transform {
-            translation: glam::vec3(0.0, 0.0, 3.0),
+            translation: renderer.world_up().y_up_rotation() * glam::vec3(0.0, 0.0, 3.0),
             rotation: glam::quat(0.0, 0.0, 0.0, 1.0),
             scale: glam::vec3(1.0, 1.0, 1.0),
         },
         5.0,
         0.005,
     );
+    camera.world_up = renderer.world_up();
+    if let Some(scene) = &inspection {
+        scene.camera.apply(&mut camera);
+    }
+    // Headless captures follow their input exactly, so they stay reproducible
+    if options.headless {
+        camera.smoothing = CameraSmoothing::NONE;
+    }
+
+    // --embed presents into a texture of its own instead of the window, like an application embedding the renderer
+    let mut embed_host = options.embed.then(|| EmbedHost::new(options.embed_framebuffer));
 
     // Main loop
+    let mut frames_rendered = 0;
+    let mut show_stats = false;
+    let mut decals = VecDeque::new();
+    let mut placements = HashMap::new(); // Where dropped models were put, the others are drawn where they were loaded
+    let mut bounds_lines = None;
+    let mut bounds_stale = false; // Set when the models change, the batch gets refilled instead of created again
+    let mut show_bounds = false;
+    let mut show_overview = false;
+    let mut time_paused = false;
+    let mut water = None;
+    let mut saved_state = None;
+    let mut ghost_biased = false;
+    let mut box_pick_start = None;
+    let mut ray_hits = Vec::new();
     loop {
         if renderer.should_close() {
             break;
         }
+        if options.headless && frames_rendered == options.frames {
+            break;
+        }
         renderer.update_input(&mut user_input);
-        camera.update(&user_input, 0.016); //todo: actual delta time
+        tint_enabled.set(user_input.is_key_down(KeyCode::T));
+
+        // Save the last few frames after spotting a glitch
+        if user_input.is_key_pressed(KeyCode::F12) {
+            match renderer.dump_frame_history(Path::new("frame_history")) {
+                Ok(count) => println!(
+                    "Saved {count} frames to frame_history/ (history uses {} KiB)",
+                    renderer.frame_history_memory() / 1024
+                ),
+                Err(error) => error!("{error}"),
+            }
+        }
+
+        // Rebuild every GPU resource, as if the GL context was lost
+        if user_input.is_key_pressed(KeyCode::F5) {
+            if let Err(error) = renderer.recreate_gl_resources() {
+                error!("{error}");
+                std::process::exit(1);
+            }
+        }
+
+        // Print which passes ran last frame, in order. With shift, write them as a GraphViz graph instead
+        if user_input.is_key_pressed(KeyCode::F7) && user_input.is_key_down(KeyCode::LeftShift) {
+            match std::fs::write("frame_graph.dot", renderer.dump_frame_graph_dot()) {
+                Ok(()) => println!("Saved the frame graph to frame_graph.dot"),
+                Err(error) => error!("Failed to write frame_graph.dot: {error}"),
+            }
+        } else if user_input.is_key_pressed(KeyCode::F7) {
+            print!("{}", renderer.dump_frame_graph());
+        }
+
+        // Print the state changes of the last frames, to see what a hitch was caused by
+        if user_input.is_key_pressed(KeyCode::F2) {
+            print!("{}", renderer.dump_change_journal(64));
+        }
+
+        // Toggle cel shading on the most recently loaded model
+        if let (true, Some(toon_shader), Some(&model)) = (user_input.is_key_pressed(KeyCode::F1), toon_shader, models.last()) {
+            let shader = match renderer.model_shader(model) {
+                Some(_) => None,
+                None => Some(toon_shader),
+            };
+            if let Err(error) = renderer.set_model_shader(model, shader) {
+                error!("{error}");
+            }
+        }
+
+        // Toggle the dissolve effect on the first loaded model. Every frame of the cycle comes from the global time, the
+        // shader only gets the speed, noise scale and edge from the materials
+        if let (true, Some(dissolve_shader), Some(&model)) = (user_input.is_key_pressed(KeyCode::K), dissolve_shader, models.first()) {
+            let shader = match renderer.model_shader(model) {
+                Some(_) => None,
+                None => Some(dissolve_shader),
+            };
+            let parameters = CustomParameters {
+                scalars: [0.25, 8.0, 0.05, 0.0],
+                vectors: [glam::vec4(1.0, 0.4, 0.1, 4.0), glam::Vec4::ZERO],
+            };
+            for mesh in renderer.model_info(model).map(|info| info.meshes).unwrap_or_default() {
+                if let Err(error) = renderer.set_material_parameters(model, &mesh, parameters) {
+                    error!("{error}");
+                }
+            }
+            if let Err(error) = renderer.set_model_shader(model, shader) {
+                error!("{error}");
+            }
+        }
+
+        // Colour the pixels by mesh, material or triangle instead of shading them. L lists what covers the most of the
+        // screen, and middle-clicking a pixel prints what it came from. Left drag turns the camera, so it can't pick
+        if user_input.is_key_pressed(KeyCode::I) {
+            renderer.set_id_view(renderer.id_view().next());
+            println!("ID view: {:?}", renderer.id_view());
+        }
+        if user_input.is_key_pressed(KeyCode::L) && renderer.id_view() != IdView::Off {
+            print!("{}", id_view::legend_text(&renderer.id_legend(10)));
+        }
+        if user_input.is_mouse_pressed(MouseButton::Middle) {
+            let (x, y) = user_input.get_mouse_pos();
+            if let Some(sample) = renderer.id_at(glam::vec2(x, y)) {
+                let Pixel32 { r, g, b, .. } = sample.colour;
+                println!(
+                    "#{r:02X}{g:02X}{b:02X} model {} ({}), mesh \"{}\", material \"{}\", triangle {}, LOD {}",
+                    sample.model,
+                    sample.model_tag.or(sample.model_path.map(|path| path.display().to_string())).unwrap_or_default(),
+                    sample.mesh,
+                    sample.material,
+                    sample.triangle,
+                    sample.lod_level
+                );
+            }
+        }
+
+        // P picks what's under the cursor both ways: the raycast answers right away, the GPU pick a couple of frames
+        // later with what was actually drawn there
+        if user_input.is_key_pressed(KeyCode::P) {
+            let (x, y) = user_input.get_mouse_pos();
+            match renderer.raycast(&renderer.screen_ray(glam::vec2(x, y))) {
+                Some(hit) => println!("Raycast pick: model {}, mesh \"{}\", triangle {}", hit.model, hit.mesh_name, hit.triangle_index),
+                None => println!("Raycast pick: nothing"),
+            }
+            renderer.pick_gpu(glam::vec2(x, y), |sample| match sample {
+                Some(sample) => println!("GPU pick: model {}, mesh \"{}\", triangle {}", sample.model, sample.mesh, sample.triangle),
+                None => println!("GPU pick: nothing"),
+            });
+            // Everything the ray passes through, including what's hidden behind the first hit
+            renderer.raycast_all(&renderer.screen_ray(glam::vec2(x, y)), &mut ray_hits);
+            if ray_hits.len() > 1 {
+                let behind: Vec<String> = ray_hits[1..]
+                    .iter()
+                    .map(|hit| format!("model {} mesh \"{}\" at {:.2}", hit.model, hit.mesh_name, hit.distance))
+                    .collect();
+                println!("Behind it: {}", behind.join(", "));
+            }
+        }
+
+        // Drag with the right mouse button to list the models inside the box, nearest first
+        if user_input.is_mouse_pressed(MouseButton::Right) {
+            let (x, y) = user_input.get_mouse_pos();
+            box_pick_start = Some(glam::vec2(x, y));
+        }
+        if let (Some(start), false) = (box_pick_start, user_input.get_mouse_down(MouseButton::Right)) {
+            let (x, y) = user_input.get_mouse_pos();
+            println!("Box pick: {:?}", renderer.pick_rect(start, glam::vec2(x, y)));
+            box_pick_start = None;
+        }
+
+        // O saves the models and settings, R goes back to them. Handles may change on the way, the models are found
+        // again by their tags
+        if user_input.is_key_pressed(KeyCode::O) {
+            saved_state = Some(renderer.snapshot());
+            println!("Saved the renderer state");
+        }
+        if let (true, Some(snapshot)) = (user_input.is_key_pressed(KeyCode::R), &saved_state) {
+            if let Err(error) = renderer.restore(snapshot) {
+                error!("{error}");
+            }
+            models = snapshot
+                .models
+                .iter()
+                .filter_map(|model| renderer.find_model(model.tag.as_deref()?))
+                .collect();
+            placements.retain(|model, _| models.contains(model));
+            bounds_stale = true;
+        }
+
+        // Move the sun across the sky while [ or ] is held
+        let sun_speed = match (user_input.is_key_down(KeyCode::LeftBracket), user_input.is_key_down(KeyCode::RightBracket)) {
+            (true, false) => -0.5,
+            (false, true) => 0.5,
+            _ => 0.0,
+        };
+        if sun_speed != 0.0 {
+            let mut sky = renderer.sky();
+            let axis = renderer.world_up().y_up_rotation() * glam::vec3(0.0, 0.3, -1.0).normalize();
+            let rotation = glam::Quat::from_axis_angle(axis, sun_speed * renderer.delta_time());
+            sky.sun_direction = (rotation * sky.sun_direction).normalize();
+            renderer.set_sky(sky);
+        }
+
+        // Colour meshes by their LOD level
+        if user_input.is_key_pressed(KeyCode::F8) {
+            let mut lod_settings = renderer.lod_settings();
+            lod_settings.debug_view = !lod_settings.debug_view;
+            renderer.set_lod_settings(lod_settings);
+        }
+
+        // Stop and start the wind in the foliage
+        if user_input.is_key_pressed(KeyCode::F4) {
+            let mut wind = renderer.wind();
+            wind.enabled = !wind.enabled;
+            renderer.set_wind(wind);
+            if let Some(handle) = water {
+                renderer.set_water_config(handle, water_config(wind.enabled));
+            }
+        }
+
+        // Outline every model's bounds, the lines are freed again when hidden
+        if user_input.is_key_pressed(KeyCode::F11) {
+            show_bounds = !show_bounds;
+            if !show_bounds {
+                if let Some((lines, _)) = bounds_lines.take() {
+                    renderer.destroy_line_batch(lines);
+                }
+            }
+        }
+
+        // Compare with and without parallax occlusion mapping
+        if user_input.is_key_pressed(KeyCode::F10) {
+            let mut parallax = renderer.parallax();
+            parallax.enabled = !parallax.enabled;
+            renderer.set_parallax(parallax);
+        }
+
+        // Cinematic camera smoothing for captures
+        if user_input.is_key_pressed(KeyCode::C) {
+            camera.smoothing = if camera.smoothing == CameraSmoothing::CINEMATIC {
+                CameraSmoothing::default()
+            } else {
+                CameraSmoothing::CINEMATIC
+            };
+        }
+
+        // Freeze everything animated by the renderer's clock
+        if user_input.is_key_pressed(KeyCode::Z) {
+            time_paused = !time_paused;
+            renderer.set_time_paused(time_paused);
+        }
+
+        // Compare with and without occlusion culling
+        if user_input.is_key_pressed(KeyCode::F9) {
+            renderer.set_occlusion_culling(!renderer.occlusion_culling_enabled());
+        }
+
+        // Compare with and without TAA
+        if user_input.is_key_pressed(KeyCode::F6) {
+            renderer.set_taa(!renderer.taa_enabled());
+        }
+
+        // V toggles camera and object motion blur
+        if user_input.is_key_pressed(KeyCode::V) {
+            let motion_blur = renderer.motion_blur();
+            renderer.set_motion_blur(MotionBlurSettings {
+                enabled: !motion_blur.enabled,
+                ..motion_blur
+            });
+        }
+
+        // E cycles auto exposure through the metering modes and off, - and = compensate by a third of a stop
+        if user_input.is_key_pressed(KeyCode::E) {
+            let exposure = renderer.exposure();
+            let (enabled, metering) = match (exposure.enabled, exposure.metering) {
+                (false, _) => (true, MeteringMode::Average),
+                (true, MeteringMode::Average) => (true, MeteringMode::CentreWeighted),
+                (true, MeteringMode::CentreWeighted) => (true, MeteringMode::Spot),
+                (true, MeteringMode::Spot) => (false, MeteringMode::Average),
+            };
+            renderer.set_exposure(ExposureSettings { enabled, metering, ..exposure });
+            println!("Auto exposure: {}", if enabled { format!("{metering:?} metering") } else { "off".to_string() });
+        }
+        for (key, stops) in [(KeyCode::Minus, -1.0 / 3.0), (KeyCode::Equal, 1.0 / 3.0)] {
+            if user_input.is_key_pressed(key) {
+                let exposure = renderer.exposure();
+                renderer.set_exposure(ExposureSettings {
+                    compensation: exposure.compensation + stops,
+                    ..exposure
+                });
+                println!("Exposure compensation {:+.1} EV", exposure.compensation + stops);
+            }
+        }
+
+        // Hold M to spot meter wherever the cursor points, with the metering weights shown over the frame
+        let metering_debug = user_input.is_key_down(KeyCode::M) && renderer.exposure().enabled;
+        if metering_debug {
+            let (x, y) = user_input.get_mouse_pos();
+            renderer.set_exposure_spot(glam::vec2(x, y));
+        }
+        if metering_debug != renderer.exposure().heat_map {
+            renderer.set_exposure(ExposureSettings {
+                heat_map: metering_debug,
+                ..renderer.exposure()
+            });
+        }
+
+        // Load any models dropped onto the window
+        for path in user_input.take_dropped_files() {
+            let extension = path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+            match extension.as_deref() {
+                Some("gltf") | Some("glb") | Some("obj") => {
+                    // The focus point is whatever the centre of the screen shows, or a few metres in front of the
+                    // camera. Cast the ray before loading, so it can't hit the new model
+                    let [width, height] = renderer.window_size();
+                    let centre_ray = renderer.screen_ray(glam::vec2(width as f32, height as f32) * 0.5);
+                    let hit = renderer.raycast(&centre_ray);
+                    if let Ok(model) = renderer.load_model(&path) {
+                        renderer.set_model_tag(model, &path.display().to_string());
+                        // Stand the model's bottom centre on what was hit, or centre it on the point in the air, then
+                        // frame it from the direction the camera is already looking in
+                        if let Some(info) = renderer.model_info(model) {
+                            let bounds = info.bounds;
+                            let up = renderer.world_up().up();
+                            let centre = (bounds.min + bounds.max) * 0.5;
+                            let translation = match &hit {
+                                Some(hit) => hit.point - (centre - up * ((bounds.max - bounds.min).dot(up) * 0.5)),
+                                None => centre_ray.origin + centre_ray.direction * 5.0 - centre,
+                            };
+                            let aspect_ratio = width as f32 / height.max(1) as f32;
+                            let fov = options.projection.vertical_fov(aspect_ratio).min(options.projection.horizontal_fov(aspect_ratio));
+                            OrbitCamera::framing(&bounds.translated(translation), fov, camera.yaw, camera.pitch).apply(&mut camera);
+                            placements.insert(
+                                model,
+                                Transform {
+                                    translation,
+                                    rotation: glam::Quat::IDENTITY,
+                                    scale: glam::Vec3::ONE,
+                                },
+                            );
+                        }
+                        models.push(model);
+                        bounds_stale = true;
+                    }
+                }
+                _ => warn!("Ignoring dropped file \"{}\": unsupported file type", path.display()),
+            }
+        }
+        // Keys let go of in another window never get released here, so the camera only flies while focused
+        if renderer.is_focused() {
+            camera.update(&user_input, 0.016); //todo: actual delta time
+        }
+        renderer.follow_camera(&mut camera);
         renderer.update_camera(&camera);
+        if let Some(host) = &mut embed_host {
+            if let Err(error) = host.fit(&mut renderer) {
+                error!("{error}");
+                std::process::exit(1);
+            }
+        }
         renderer.begin_frame();
-        renderer.draw_model(&model_spyro);
+        for model in &models {
+            match placements.get(model) {
+                Some(transform) => renderer.draw_model_tinted(model, transform, glam::Vec4::ONE),
+                None => renderer.draw_model(model),
+            }
+        }
+        if let Some(scene) = &inspection {
+            renderer.draw_inspection_scene(scene);
+        }
+
+        // The bounds lines are uploaded once, and only refilled after the models change. They're relative to the
+        // origin they were built at, and follow it when it moves
+        if show_bounds && (bounds_stale || bounds_lines.is_none()) {
+            let lines: Vec<_> = models
+                .iter()
+                .filter_map(|&model| {
+                    let offset = placements.get(&model).map_or(glam::Vec3::ZERO, |transform: &Transform| transform.translation);
+                    Some(renderer.model_info(model)?.bounds.translated(offset))
+                })
+                .flat_map(|bounds| bounds.edge_lines(glam::vec4(1.0, 0.8, 0.1, 1.0)))
+                .collect();
+            let batch = match bounds_lines {
+                Some((batch, _)) if renderer.update_line_batch(batch, &lines) => batch,
+                _ => renderer.create_line_batch(&lines),
+            };
+            bounds_lines = Some((batch, renderer.origin()));
+            bounds_stale = false;
+        }
+        if let (true, Some((lines, origin))) = (show_bounds, bounds_lines) {
+            let transform = Transform {
+                translation: renderer.to_relative(origin),
+                rotation: glam::Quat::IDENTITY,
+                scale: glam::Vec3::ONE,
+            };
+            renderer.draw_line_batch(lines, &transform, glam::Vec4::ONE);
+
+            // Label the top of each box with the model handle that picking reports
+            let up = renderer.world_up().up();
+            for &model in &models {
+                let offset = placements.get(&model).map_or(glam::Vec3::ZERO, |transform: &Transform| transform.translation);
+                if let Some(info) = renderer.model_info(model) {
+                    let bounds = info.bounds.translated(offset);
+                    let top = (bounds.min + bounds.max) * 0.5 + up * ((bounds.max - bounds.min).dot(up) * 0.5);
+                    renderer.draw_text_3d(top, 1.5, glam::vec4(1.0, 0.8, 0.1, 1.0), &format!("model {model}"));
+                }
+            }
+        }
+
+        // Hold G to preview placing a copy of the first model wherever the cursor points. Its materials sort as if they
+        // were a bit closer meanwhile, so the see-through copy stays on top of the transparent surfaces it sinks into.
+        // At least two sort buckets closer, so it never shares one with them
+        let placing = user_input.is_key_down(KeyCode::G);
+        if let (true, Some(&model)) = (placing != ghost_biased, models.first()) {
+            let sort_bias = if placing { -(renderer.transparent_sort_epsilon() * 2.0).max(0.5) } else { 0.0 };
+            for mesh in renderer.model_info(model).map(|info| info.meshes).unwrap_or_default() {
+                if let Err(error) = renderer.set_material_sort_bias(model, &mesh, sort_bias) {
+                    error!("{error}");
+                }
+            }
+            ghost_biased = placing;
+        }
+        if let (true, Some(&model)) = (placing, models.first()) {
+            let (x, y) = user_input.get_mouse_pos();
+            let hit = renderer.raycast(&renderer.screen_ray(glam::vec2(x, y)));
+            if let (Some(hit), Some(info)) = (hit, renderer.model_info(model)) {
+                // Stand the bottom centre of the model on the hit point
+                let bounds = info.bounds;
+                let base = glam::vec3((bounds.min.x + bounds.max.x) * 0.5, bounds.min.y, (bounds.min.z + bounds.max.z) * 0.5);
+                let transform = Transform {
+                    translation: hit.point - base,
+                    rotation: glam::Quat::IDENTITY,
+                    scale: glam::Vec3::ONE,
+                };
+                renderer.draw_model_tinted(&model, &transform, glam::vec4(0.3, 1.0, 0.3, 0.4));
+            }
+        }
+
+        // Press B to stamp a bullet hole wherever the cursor points, keeping the last 32
+        if user_input.is_key_pressed(KeyCode::B) {
+            let (x, y) = user_input.get_mouse_pos();
+            let ray = renderer.screen_ray(glam::vec2(x, y));
+            if let Some(hit) = renderer.raycast(&ray) {
+                // Face the decal towards the side the ray came from
+                let normal = if hit.normal.dot(ray.direction) > 0.0 { -hit.normal } else { hit.normal };
+                let transform = Transform {
+                    translation: hit.point,
+                    rotation: glam::Quat::from_rotation_arc(glam::Vec3::Z, normal),
+                    scale: glam::Vec3::ONE,
+                };
+                let size = glam::Vec2::splat(hit.distance * 0.05);
+                match renderer.add_decal(&transform, size, Path::new("assets/textures/bullet_hole.png"), 0.3) {
+                    Ok(decal) => decals.push_back(decal),
+                    Err(error) => error!("{error}"),
+                }
+                if decals.len() > 32 {
+                    renderer.remove_decal(decals.pop_front().unwrap());
+                }
+            }
+        }
+
+        // Press H to flood the first model a little above its floor, calm while the wind is off. Again to turn the
+        // water's reflections off, and a third time to drain it
+        if user_input.is_key_pressed(KeyCode::H) {
+            match water.take() {
+                None => {
+                    if let Some(info) = models.first().and_then(|&model| renderer.model_info(model)) {
+                        let bounds = info.bounds;
+                        let up = renderer.world_up().up();
+                        let size = bounds.max - bounds.min;
+                        let rotation = glam::Quat::from_rotation_arc(glam::Vec3::Z, up);
+                        let transform = Transform {
+                            translation: (bounds.min + bounds.max) * 0.5 - up * (size.dot(up) * 0.46),
+                            rotation,
+                            scale: glam::Vec3::ONE,
+                        };
+                        let extents = glam::vec2((rotation * glam::Vec3::X).abs().dot(size), (rotation * glam::Vec3::Y).abs().dot(size));
+                        renderer.set_reflections(true);
+                        water = Some(renderer.add_water_plane(&transform, extents, water_config(renderer.wind().enabled)));
+                    }
+                }
+                Some(handle) if renderer.reflections() => {
+                    renderer.set_reflections(false);
+                    water = Some(handle);
+                }
+                Some(handle) => {
+                    renderer.remove_water_plane(handle);
+                    renderer.set_reflections(true);
+                }
+            }
+        }
+
+        // N shows the models from above in a corner of the window, instead of in stereo
+        if user_input.is_key_pressed(KeyCode::N) {
+            show_overview = !show_overview;
+        }
+        if show_overview {
+            let mut bounds = AABB::new();
+            for &model in &models {
+                let offset = placements.get(&model).map_or(glam::Vec3::ZERO, |transform: &Transform| transform.translation);
+                if let Some(info) = renderer.model_info(model) {
+                    bounds.grow_aabb(&info.bounds.translated(offset));
+                }
+            }
+            if !bounds.is_empty() {
+                let overview = Camera::new(
+                    Transform {
+                        translation: (bounds.min + bounds.max) * 0.5 + renderer.world_up().up() * (bounds.max - bounds.min).length() * 1.5,
+                        rotation: renderer.world_up().y_up_rotation() * glam::Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+                        scale: glam::Vec3::ONE,
+                    },
+                    0.0,
+                    0.0,
+                );
+                // Once there's a view of its own the camera isn't drawn by end_frame, so it's drawn first
+                let [width, height] = renderer.window_size();
+                renderer.render_view(&camera, Rect { x: 0, y: 0, width, height });
+                let viewport = Rect {
+                    x: width - width / 4 - 16,
+                    y: 16,
+                    width: width / 4,
+                    height: height / 4,
+                };
+                renderer.render_view(&overview, viewport);
+            }
+        }
+
+        // Frame stats overlay, toggled with F3
+        if user_input.is_key_pressed(KeyCode::F3) {
+            show_stats = !show_stats;
+        }
+        if show_stats {
+            let delta_time = renderer.delta_time().max(f32::EPSILON);
+            let mut stats = format!(
+                "{:.0} FPS ({:.2} ms)\nRender scale {:.0}%, {:?} upscale\nOutput {:?}\nOpenGL {}.{}",
+                1.0 / delta_time,
+                delta_time * 1000.0,
+                renderer.render_scale() * 100.0,
+                renderer.upscale().mode,
+                renderer.output_encoding(),
+                renderer.capabilities().version.0,
+                renderer.capabilities().version.1
+            );
+            stats += &format!("\nTime {:.1} s{}", renderer.time(), if time_paused { " (paused)" } else { "" });
+            // The camera's own position is relative to the renderer's origin, far out that's not where it is
+            let position = renderer.to_world(camera.transform.translation);
+            stats += &format!("\nCamera at ({:.2}, {:.2}, {:.2})", position.x, position.y, position.z);
+            if renderer.occlusion_culling_enabled() {
+                let culling = renderer.culling_stats();
+                stats += &format!("\n{} of {} meshes occlusion culled", culling.occlusion_culled, culling.submitted);
+            }
+            let lowered: Vec<String> = renderer
+                .quality_levers()
+                .iter()
+                .filter(|lever| lever.steps > 0)
+                .map(|lever| format!("{:?} -{}", lever.lever, lever.steps))
+                .collect();
+            if !lowered.is_empty() {
+                stats += &format!("\nQuality lowered: {}", lowered.join(", "));
+            }
+            let missing_textures = renderer.missing_texture_report().len();
+            if missing_textures > 0 {
+                stats += &format!("\n{missing_textures} missing textures");
+            }
+            let stereo = renderer.stereo();
+            if stereo.enabled {
+                stats += &format!("\nStereo {:?}", stereo.output);
+            }
+            // Changes of the last frame, with the uploads they caused
+            let last_frame: Vec<_> = renderer
+                .recent_changes(256)
+                .into_iter()
+                .filter(|change| change.frame_index + 1 == renderer.frame_index())
+                .collect();
+            if !last_frame.is_empty() {
+                let bytes: usize = last_frame.iter().map(|change| change.bytes).sum();
+                stats += &format!("\n{} state changes, {} KiB uploaded", last_frame.len(), bytes / 1024);
+            }
+            renderer.draw_text_2d(8.0, 8.0, 2.0, glam::vec4(1.0, 1.0, 1.0, 1.0), &stats);
+        }
+
+        // Save the frame when rendering headless, supersampled captures are rendered separately from the frame
+        let capture_path = options.out.as_ref().map(|out| out.join(format!("frame_{frames_rendered:04}.ppm")));
+        if let (Some(path), true) = (&capture_path, options.supersample > 1) {
+            let result = renderer
+                .render_supersampled(&camera, options.supersample, frames_rendered)
+                .and_then(|image| image.write_ppm(path));
+            if let Err(error) = result {
+                error!("{error}");
+                std::process::exit(1);
+            }
+        }
         renderer.end_frame();
+        if let Some(host) = &embed_host {
+            host.present(&mut renderer);
+        }
+        if let (Some(path), false) = (&capture_path, options.supersample > 1) {
+            if let Err(error) = renderer.capture_frame(path) {
+                error!("{error}");
+                std::process::exit(1);
+            }
+        }
+        frames_rendered += 1;
+    }
+
+    // Unload the generated scene's models, like an app leaving its inspector would
+    if let Some(scene) = inspection.take() {
+        renderer.remove_inspection_scene(scene);
+    }
+
+    // Write the profile
+    if let Some(trace) = &options.trace {
+        if let Err(error) = renderer.dump_trace(trace) {
+            error!("{error}");
+        }
+    }
+}
+
+// The water only ripples while the wind blows
+fn water_config(wind_enabled: bool) -> WaterConfig {
+    WaterConfig {
+        wave_speed: if wind_enabled { WaterConfig::default().wave_speed } else { 0.0 },
+        ..Default::default()
     }
 }
@@ -1,4 +1,32 @@
-use glam::Vec3;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+use glam::{Vec3, Vec4};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Materials whose name contains one of these sway in the wind, unless the model file says otherwise
+const FOLIAGE_NAMES: [&str; 6] = ["leaf", "leaves", "plant", "foliage", "grass", "bush"];
+
+// How the albedo alpha is used, same modes as glTF
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AlphaMode {
+    Opaque, // Alpha is ignored
+    Mask,   // Cut out below the cutoff, with alpha-to-coverage when MSAA is on. Still drawn in the opaque pass
+    Blend,  // Blended over the scene in the transparent pass
+}
+
+// Free slots for quick material effects in custom shaders, without touching the renderer. Shaders read them as
+// u_custom0 to u_custom3 and u_customVec0 and u_customVec1. The renderer itself gives them no meaning
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CustomParameters {
+    pub scalars: [f32; 4],
+    pub vectors: [Vec4; 2],
+}
 
 #[derive(Debug, Clone)]
 pub struct Material {
@@ -7,11 +35,59 @@ pub struct Material {
     pub tex_nrm: i32,
     pub tex_mtl_rgh: i32,
     pub tex_emm: i32,
+    pub tex_hgt: i32, // Height map for parallax occlusion mapping, white is highest
 
     // Scalars
     pub scl_rgh: f32,
     pub scl_mtl: f32,
     pub scl_emm: Vec3,
+    pub scl_hgt: f32,  // Depth of the height map's black below its white, in texture coordinates
+    pub bias_hgt: f32, // Height map value that lines up with the actual surface, 1 makes everything sink in
+    pub scl_wind: f32, // How far the wind moves the vertices, relative to WindConfig::strength. 0 keeps the mesh still
+    pub frq_wind: f32, // Sways per second
+
+    // Alpha
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32, // Only used by AlphaMode::Mask
+    pub sort_bias: f32,    // World units added to its transparent sort distance, positive draws it earlier, behind the others
+
+    pub custom: CustomParameters,
+}
+
+// A material in the renderer's material library, identified by the hash of its contents. Identical materials get the
+// same handle, however many times they're loaded
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(pub(crate) u64);
+
+// A material given by its texture files and factors, for materials that don't come with a model
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MaterialDescriptor {
+    pub name: String, // Only picks the defaults, like the wind effect for plants
+    pub albedo: Option<PathBuf>,
+    pub metallic_roughness: Option<PathBuf>,
+    pub height: Option<PathBuf>,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub emissive: Vec3,
+    pub sort_bias: f32,
+    pub custom: CustomParameters,
+}
+
+impl Default for MaterialDescriptor {
+    fn default() -> Self {
+        MaterialDescriptor {
+            name: String::from("untitled"),
+            albedo: None,
+            metallic_roughness: None,
+            height: None,
+            roughness: 1.0,
+            metallic: 0.0,
+            emissive: Vec3::ZERO,
+            sort_bias: 0.0,
+            custom: CustomParameters::default(),
+        }
+    }
 }
 
 impl Material {
@@ -21,9 +97,44 @@ impl Material {
             tex_nrm: -1,
             tex_mtl_rgh: -1,
             tex_emm: -1,
-            scl_rgh: 0.0,
+            tex_hgt: -1,
+            scl_rgh: 1.0, // Same default as glTF
             scl_mtl: 0.0,
             scl_emm: Vec3::ZERO,
+            scl_hgt: 0.05,
+            bias_hgt: 1.0,
+            scl_wind: 0.0,
+            frq_wind: 0.5,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5, // Same default as glTF
+            sort_bias: 0.0,
+            custom: CustomParameters::default(),
+        }
+    }
+
+    // Default material for a name, with the wind effect on for names that sound like plants
+    pub fn named(name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        Material {
+            scl_wind: match FOLIAGE_NAMES.iter().any(|hint| name.contains(hint)) {
+                true => 1.0,
+                false => 0.0,
+            },
+            ..Material::new()
         }
     }
+
+    // Hash of everything that affects rendering. Textures loaded from the same file share their GL id, so materials
+    // using the same files with the same factors hash the same
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        [self.tex_alb, self.tex_nrm, self.tex_mtl_rgh, self.tex_emm, self.tex_hgt].hash(&mut hasher);
+        let scalars = [self.scl_rgh, self.scl_mtl, self.scl_emm.x, self.scl_emm.y, self.scl_emm.z, self.scl_hgt, self.bias_hgt, self.scl_wind, self.frq_wind, self.sort_bias];
+        scalars.map(f32::to_bits).hash(&mut hasher);
+        let custom = self.custom.scalars.iter().chain(self.custom.vectors.iter().flat_map(|vector| vector.as_ref()));
+        custom.map(|value| value.to_bits()).collect::<Vec<u32>>().hash(&mut hasher);
+        self.alpha_mode.hash(&mut hasher);
+        self.alpha_cutoff.to_bits().hash(&mut hasher);
+        hasher.finish()
+    }
 }
@@ -1,332 +1,570 @@
-use crate::graphics::Renderer;
-use crate::material::Material;
-use crate::structs::Transform;
-use crate::{structs::Vertex, texture::Texture};
-use glam::Vec4Swizzles;
-use glam::{Mat4, Vec2, Vec3, Vec4};
-use gltf::buffer::Data;
-use std::{collections::HashMap, path::Path};
+use crate::gpu_buffer::GpuBuffer;
+use crate::material::{Material, MaterialHandle};
+use crate::simplify::simplify;
+use crate::structs::{Vertex, WorldUp, AABB};
+use glam::{DVec3, Mat4, Vec3};
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 pub struct Mesh {
-    pub verts: Vec<Vertex>,
+    pub verts: Vec<Vertex>, // Empty once uploaded, unless the model was loaded with CpuData::KeepAll
+    pub positions: Vec<Vec3>, // Only kept with CpuData::KeepPositionsOnly, one per vertex
     pub vao: u32,
-    pub vbo: u32,
+    pub vbo: GpuBuffer<Vertex>,
+    pub bounds: AABB,
+    pub lods: Vec<Mesh>, // Reduced versions of this mesh, most detailed first, sharing its bounds
+    pub lod_level: usize, // Currently drawn level, 0 being this mesh itself
 }
 
 pub struct Model {
     pub meshes: HashMap<String, Mesh>, // Where the String is the material id
     pub materials: HashMap<String, Material>, // Where the String is the material id
+    pub source_files: Vec<SourceFile>, // Every file the model was loaded from, for hot reloading
+    pub scenes: Vec<String>, // Names of the scenes in the file, by index. Empty for formats without scenes
+    pub material_overrides: HashMap<String, MaterialHandle>, // Library materials drawn instead of the mesh's own
+    pub origin: DVec3, // World position the vertices are relative to, only away from zero for recentred models
 }
 
-// So what this function needs to do: &[u8] -(reinterpret)> &[SrcCompType] -(convert)> &[DstCompType]
-fn reinterpret_then_convert<SrcCompType, DstCompType>(input_buffer: &[u8]) -> Vec<DstCompType>
-where
-    DstCompType: From<SrcCompType>,
-    SrcCompType: Copy,
-{
-    // &[u8] -> &[SrcCompType]
-    let input_ptr = input_buffer.as_ptr();
-    let src_comp_buffer: &[SrcCompType] = unsafe {
-        std::slice::from_raw_parts(
-            std::mem::transmute(input_ptr),
-            input_buffer.len() / std::mem::size_of::<SrcCompType>(),
-        )
-    };
-
-    // &[SrcCompType] -> Vec<DstCompType>
-    let mut dst_comp_vec = Vec::<DstCompType>::new();
-    for item in src_comp_buffer {
-        dst_comp_vec.push(DstCompType::from(*item));
-    }
-
-    // Return
-    dst_comp_vec
+// Models whose bounds centre is further than this from the world origin get recentred at load, f32 positions out
+// there are too coarse to render or pick with
+pub const RECENTRE_DISTANCE: f32 = 1.0e4;
+// Recentred origins are rounded to a multiple of this, so the offsets between them stay exact
+pub const ORIGIN_GRID: f64 = 1024.0;
+
+// A file that contributed to a model, with its modification time when it was read
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub modified: Option<SystemTime>,
+    pub textures: Vec<u32>, // GL ids of the textures decoded from this file, if it's an image
 }
 
-fn convert_gltf_buffer_to_f32(input_buffer: &[u8], accessor: &gltf::Accessor) -> Vec<f32> {
-    // Convert based on data type
-    // First we make a f64 vector (this way we can do fancy generics magic and still convert u32 to f32)
-    let values64 = match accessor.data_type() {
-        gltf::accessor::DataType::I8 => reinterpret_then_convert::<i8, f64>(input_buffer),
-        gltf::accessor::DataType::U8 => reinterpret_then_convert::<u8, f64>(input_buffer),
-        gltf::accessor::DataType::I16 => reinterpret_then_convert::<i16, f64>(input_buffer),
-        gltf::accessor::DataType::U16 => reinterpret_then_convert::<u16, f64>(input_buffer),
-        gltf::accessor::DataType::U32 => reinterpret_then_convert::<u32, f64>(input_buffer),
-        gltf::accessor::DataType::F32 => reinterpret_then_convert::<f32, f64>(input_buffer),
-    };
-
-    // Then we convert that to a f32 vector - this feels cursed as heck but let's ignore that, it'll be fine!
-    let mut values32 = Vec::<f32>::new();
-    values32.resize(values64.len(), 0.0);
-    for i in 0..values32.len() {
-        values32[i] = values64[i] as f32;
-    }
-
-    // Return
-    values32
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UpAxis {
+    Y,
+    Z,
 }
 
-fn create_vertex_array(
-    primitive: &gltf::Primitive,
-    mesh_data: &[Data],
-    local_matrix: Mat4,
-) -> Mesh {
-    let mut position_vec = Vec::<Vec3>::new();
-    let mut normal_vec = Vec::<Vec3>::new();
-    let mut tangent_vec = Vec::<Vec4>::new();
-    let mut colour_vec = Vec::<Vec4>::new();
-    let mut texcoord0_vec = Vec::<Vec2>::new();
-    let mut texcoord1_vec = Vec::<Vec2>::new();
-    let mut indices = Vec::<u16>::new();
-
-    // Loop over all the primitive attributes
-    for (name, accessor) in primitive.attributes() {
-        // Get buffer view
-        let bufferview = accessor.view().unwrap();
-
-        // Find location in buffer
-        let buffer_index = bufferview.buffer().index();
-        let buffer_offset = bufferview.offset();
-        let buffer_end = bufferview.offset() + bufferview.length();
-
-        // Find location in buffer
-        let buffer_base = &mesh_data[buffer_index].0;
-        let buffer_slice = buffer_base.get(buffer_offset..buffer_end).unwrap();
-
-        // Assign to the vectors
-        match name.to_string().as_str() {
-            "POSITION" => {
-                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
-                for i in (0..accessor.count() * 3).step_by(3) {
-                    let slice = &values[i..i + 3];
-                    position_vec.push(Vec3::from_slice(slice));
-                }
+// Import settings for assets with different units or conventions, applied while loading
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LoadOptions {
+    pub uniform_scale: f32,
+    pub up_axis: UpAxis,
+    pub weld_vertices: Option<f32>, // Epsilon to merge nearly identical vertices with
+    pub flip_winding: bool,
+    pub degenerate_triangles: DegenerateTriangles,
+    pub generate_lods: Option<Vec<f32>>, // Triangle ratio of each LOD to generate, e.g. [0.5, 0.25, 0.1]
+    pub scene_selection: SceneSelection,
+    pub cpu_data: CpuData,
+}
+
+// What of a mesh's vertices stays in memory after they're uploaded. Raycasts and picking need the positions, and
+// recreating the GL resources reads models that kept nothing from their files again
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CpuData {
+    KeepAll,
+    DropAfterUpload,
+    KeepPositionsOnly, // A sixth of the vertex size
+}
+
+// Which scenes of a glTF file get loaded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SceneSelection {
+    Default, // The file's default scene, or the first scene if it doesn't name one
+    Index(usize),
+    All, // Every scene, with mesh names prefixed by their scene
+}
+
+// What to do with zero-area triangles. Triangles with NaN or infinite positions are always dropped
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DegenerateTriangles {
+    Drop,
+    Keep, // Gets a fallback up-facing normal where the normal can't be computed
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            uniform_scale: 1.0,
+            up_axis: UpAxis::Y,
+            weld_vertices: None,
+            flip_winding: false,
+            degenerate_triangles: DegenerateTriangles::Drop,
+            generate_lods: None,
+            scene_selection: SceneSelection::Default,
+            cpu_data: CpuData::KeepAll,
+        }
+    }
+}
+
+impl LoadOptions {
+    // Transform applied on top of the file's own node transforms, converting the asset's up axis to the world's
+    pub fn root_matrix(&self, world_up: WorldUp) -> Mat4 {
+        let axis_conversion = match self.up_axis {
+            UpAxis::Y => Mat4::IDENTITY,
+            UpAxis::Z => Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+        };
+        Mat4::from_scale(Vec3::splat(self.uniform_scale)) * Mat4::from_quat(world_up.y_up_rotation()) * axis_conversion
+    }
+}
+
+// Assigns face normals to every triangle in a de-indexed vertex list that doesn't have a normal yet
+pub(crate) fn generate_flat_normals(verts: &mut [Vertex]) {
+    for triangle in verts.chunks_exact_mut(3) {
+        if triangle.iter().all(|vertex| vertex.normal != Vec3::ZERO) {
+            continue;
+        }
+        let edge1 = triangle[1].position - triangle[0].position;
+        let edge2 = triangle[2].position - triangle[0].position;
+        let normal = edge1.cross(edge2).normalize_or_zero();
+        for vertex in triangle.iter_mut() {
+            if vertex.normal == Vec3::ZERO {
+                vertex.normal = normal;
             }
-            "NORMAL" => {
-                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
-                for i in (0..accessor.count() * 3).step_by(3) {
-                    let slice = &values[i..i + 3];
-                    normal_vec.push(Vec3::from_slice(slice));
-                }
+        }
+    }
+}
+
+// Removes triangles with non-finite positions, plus zero-area triangles when asked to, and gives any remaining
+// triangle without a usable normal a fallback one. Returns the number of removed triangles
+fn filter_degenerate_triangles(verts: &mut Vec<Vertex>, mode: DegenerateTriangles) -> usize {
+    let triangle_count = verts.len() / 3;
+    let mut kept = Vec::with_capacity(triangle_count * 3);
+    for triangle in verts.chunks_exact(3) {
+        if !triangle.iter().all(|vertex| vertex.position.is_finite()) {
+            continue;
+        }
+        let area = (triangle[1].position - triangle[0].position).cross(triangle[2].position - triangle[0].position);
+        if area.length_squared() <= f32::MIN_POSITIVE && mode == DegenerateTriangles::Drop {
+            continue;
+        }
+        for vertex in triangle {
+            let mut vertex = *vertex;
+            if !vertex.normal.is_finite() || vertex.normal == Vec3::ZERO {
+                vertex.normal = Vec3::Y;
             }
-            "TANGENT" => {
-                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
-                for i in (0..accessor.count() * 4).step_by(4) {
-                    let slice = &values[i..i + 4];
-                    tangent_vec.push(Vec4::from_slice(slice));
+            kept.push(vertex);
+        }
+    }
+    *verts = kept;
+    triangle_count - verts.len() / 3
+}
+
+// Snaps vertices whose position, normal and uv0 are all within epsilon of an earlier vertex onto that vertex.
+// Meshes are drawn de-indexed, so this closes cracks between nearly matching vertices rather than shrinking the
+// buffer. Returns the number of unique vertices left
+pub(crate) fn weld_vertices(verts: &mut [Vertex], epsilon: f32) -> usize {
+    let epsilon = epsilon.max(f32::EPSILON);
+    let cell_of = |position: Vec3| (position / epsilon).floor().as_ivec3();
+
+    // Bucket the unique vertices by grid cell, so only neighbouring cells need to be compared
+    let mut grid = HashMap::<glam::IVec3, Vec<Vertex>>::new();
+    let mut unique_count = 0;
+    for vertex in verts.iter_mut() {
+        let cell = cell_of(vertex.position);
+        let mut matched = None;
+        'search: for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let Some(candidates) = grid.get(&(cell + glam::ivec3(x, y, z))) else {
+                        continue;
+                    };
+                    if let Some(candidate) = candidates.iter().find(|candidate| {
+                        candidate.position.abs_diff_eq(vertex.position, epsilon)
+                            && candidate.normal.abs_diff_eq(vertex.normal, epsilon)
+                            && candidate.uv0.abs_diff_eq(vertex.uv0, epsilon)
+                    }) {
+                        matched = Some(*candidate);
+                        break 'search;
+                    }
                 }
             }
-            "TEXCOORD_0" => {
-                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
-                for i in (0..accessor.count() * 2).step_by(2) {
-                    let slice = &values[i..i + 2];
-                    texcoord0_vec.push(Vec2::from_slice(slice));
+        }
+        match matched {
+            Some(candidate) => *vertex = candidate,
+            None => {
+                grid.entry(cell).or_default().push(*vertex);
+                unique_count += 1;
+            }
+        }
+    }
+    unique_count
+}
+
+impl Mesh {
+    // Builds a simplified copy of the mesh for each ratio, ratios that don't reduce the previous level are skipped
+    pub(crate) fn generate_lods(&mut self, ratios: &[f32]) {
+        let mut previous_triangles = self.verts.len() / 3;
+        for &ratio in ratios {
+            let verts = simplify(&self.verts, ratio);
+            if verts.is_empty() || verts.len() / 3 >= previous_triangles {
+                continue;
+            }
+            previous_triangles = verts.len() / 3;
+            self.lods.push(Mesh {
+                verts,
+                positions: Vec::new(),
+                vao: 0,
+                vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
+                bounds: self.bounds,
+                lods: Vec::new(),
+                lod_level: 0,
+            });
+        }
+    }
+
+    // Frees the parts of the CPU copy the policy doesn't keep, call it once the vertices are uploaded
+    pub(crate) fn release_cpu_data(&mut self, policy: CpuData) {
+        match policy {
+            CpuData::KeepAll => return,
+            CpuData::KeepPositionsOnly => self.positions = self.verts.iter().map(|vertex| vertex.position).collect(),
+            CpuData::DropAfterUpload => {}
+        }
+        self.verts = Vec::new();
+        for lod in &mut self.lods {
+            lod.release_cpu_data(policy);
+        }
+    }
+
+    // Whether positions are still in memory, in either CPU copy
+    pub fn has_cpu_positions(&self) -> bool {
+        !self.verts.is_empty() || !self.positions.is_empty()
+    }
+
+    // Vertices on the GPU, which the CPU copy may no longer have
+    pub fn vertex_count(&self) -> usize {
+        self.verts.len().max(self.vbo.len())
+    }
+
+    // The mesh itself for level 0, or the closest existing LOD
+    pub fn lod(&self, level: usize) -> &Mesh {
+        match level.min(self.lods.len()) {
+            0 => self,
+            level => &self.lods[level - 1],
+        }
+    }
+}
+
+impl Model {
+    // Applies the per-vertex import options that aren't part of the root transform
+    pub(crate) fn apply_load_options(&mut self, options: &LoadOptions) {
+        for (name, mesh) in &mut self.meshes {
+            let removed = filter_degenerate_triangles(&mut mesh.verts, options.degenerate_triangles);
+            if removed > 0 {
+                warn!("Dropped {removed} degenerate triangles from mesh \"{name}\"");
+                mesh.bounds = AABB::new();
+                for vertex in &mesh.verts {
+                    mesh.bounds.grow(vertex.position);
                 }
             }
-            "TEXCOORD_1" => {
-                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
-                for i in (0..accessor.count() * 2).step_by(2) {
-                    let slice = &values[i..i + 2];
-                    texcoord1_vec.push(Vec2::from_slice(slice));
+            if options.flip_winding {
+                for triangle in mesh.verts.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
                 }
             }
-            "COLOR_0" => {
-                let values = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
-                for i in (0..accessor.count() * 4).step_by(4) {
-                    let slice = &values[i..i + 4];
-                    colour_vec.push(Vec4::from_slice(slice));
+            if let Some(epsilon) = options.weld_vertices {
+                weld_vertices(&mut mesh.verts, epsilon);
+                mesh.bounds = AABB::new();
+                for vertex in &mesh.verts {
+                    mesh.bounds.grow(vertex.position);
                 }
             }
-            _ => {}
+            if let Some(ratios) = &options.generate_lods {
+                mesh.generate_lods(ratios);
+            }
         }
     }
 
-    // Find indices
-    {
-        // Get accessor
-        let accessor = primitive.indices().unwrap();
-
-        // Get buffer view
-        let bufferview = accessor.view().unwrap();
+    // Triangle count of every LOD level summed over the meshes, level 0 being the meshes themselves. Meshes with fewer
+    // LODs count with their least detailed one
+    pub fn lod_triangle_counts(&self) -> Vec<usize> {
+        let level_count = self.meshes.values().map(|mesh| mesh.lods.len() + 1).max().unwrap_or(1);
+        (0..level_count)
+            .map(|level| self.meshes.values().map(|mesh| mesh.lod(level).vertex_count() / 3).sum())
+            .collect()
+    }
 
-        // Find location in buffer
-        let buffer_index = bufferview.buffer().index();
-        let buffer_offset = bufferview.offset();
-        let buffer_end = bufferview.offset() + bufferview.length();
+    // Size of the vertices of every mesh and LOD, as uploaded
+    pub fn vertex_bytes(&self) -> usize {
+        self.meshes
+            .values()
+            .flat_map(|mesh| std::iter::once(mesh).chain(&mesh.lods))
+            .map(|mesh| mesh.vertex_count() * std::mem::size_of::<Vertex>())
+            .sum()
+    }
 
-        // Find location in buffer
-        let buffer_base = &mesh_data[buffer_index].0;
-        let buffer_slice = buffer_base.get(buffer_offset..buffer_end).unwrap();
+    // Memory the CPU copies of the vertices of every mesh and LOD take, see CpuData
+    pub fn cpu_bytes(&self) -> usize {
+        self.meshes
+            .values()
+            .flat_map(|mesh| std::iter::once(mesh).chain(&mesh.lods))
+            .map(|mesh| std::mem::size_of_val(mesh.verts.as_slice()) + std::mem::size_of_val(mesh.positions.as_slice()))
+            .sum()
+    }
 
-        // Convert from raw buffer to f32 vec - this is incredibly cursed but it'll have to do
-        let indices_f32 = convert_gltf_buffer_to_f32(buffer_slice, &accessor);
-        for index in indices_f32 {
-            indices.push(index as u16);
+    // World space bounds of all meshes together
+    pub fn bounds(&self) -> AABB {
+        let mut bounds = AABB::new();
+        for mesh in self.meshes.values() {
+            bounds.grow_aabb(&mesh.bounds);
         }
+        bounds
     }
 
-    // Create vertex array
-    let mut mesh_out = Mesh {
-        verts: Vec::new(),
-        vao: 0,
-        vbo: 0,
-    };
-    for index in indices {
-        let mut vertex = Vertex {
-            position: Vec3::new(0., 0., 0.),
-            normal: Vec3::new(0., 0., 0.),
-            tangent: Vec4::new(0., 0., 0., 0.),
-            colour: Vec4::new(1., 1., 1., 1.),
-            uv0: Vec2::new(0., 0.),
-            uv1: Vec2::new(0., 0.),
-        };
-        if !position_vec.is_empty() {
-            let pos3 = position_vec[index as usize];
-            vertex.position = (local_matrix * pos3.extend(1.0)).xyz();
-        }
-        if !normal_vec.is_empty() {
-            vertex.normal = local_matrix.transform_vector3(normal_vec[index as usize]);
-        }
-        if !tangent_vec.is_empty() {
-            let tangent_vec3 = local_matrix.transform_vector3(tangent_vec[index as usize].xyz());
-            vertex.tangent.x = tangent_vec3.x;
-            vertex.tangent.y = tangent_vec3.y;
-            vertex.tangent.z = tangent_vec3.z;
-            vertex.tangent.w = tangent_vec[index as usize].w;
-        }
-        if !texcoord0_vec.is_empty() {
-            vertex.uv0 = texcoord0_vec[index as usize];
+    pub(crate) fn new() -> Model {
+        Model {
+            meshes: HashMap::new(),
+            materials: HashMap::new(),
+            source_files: Vec::new(),
+            scenes: Vec::new(),
+            material_overrides: HashMap::new(),
+            origin: DVec3::ZERO,
         }
-        if !texcoord1_vec.is_empty() {
-            vertex.uv1 = texcoord1_vec[index as usize];
+    }
+
+    // Moves the vertices of a model far from the world origin close to it, and keeps where they were in `origin`.
+    // Vertex positions far out have already lost precision when they were loaded as f32, but everything done with
+    // them afterwards works at the scale of the model instead of the scale of its coordinates
+    pub(crate) fn recentre(&mut self) {
+        let bounds = self.bounds();
+        let centre = (bounds.min + bounds.max) * 0.5;
+        if bounds.is_empty() || centre.abs().max_element() < RECENTRE_DISTANCE {
+            return;
         }
-        if !colour_vec.is_empty() {
-            vertex.colour.x = f32::powf(colour_vec[index as usize].x, 1.0 / 2.2);
-            if vertex.colour.x > 1.0 {
-                vertex.colour.x = 1.0
-            }
-            vertex.colour.y = f32::powf(colour_vec[index as usize].y, 1.0 / 2.2);
-            if vertex.colour.y > 1.0 {
-                vertex.colour.y = 1.0
-            }
-            vertex.colour.z = f32::powf(colour_vec[index as usize].z, 1.0 / 2.2);
-            if vertex.colour.z > 1.0 {
-                vertex.colour.z = 1.0
+        let origin = (centre.as_dvec3() / ORIGIN_GRID).round() * ORIGIN_GRID;
+        let shift = |mesh: &mut Mesh| {
+            for vertex in &mut mesh.verts {
+                vertex.position = (vertex.position.as_dvec3() - origin).as_vec3();
             }
+            mesh.bounds.min = (mesh.bounds.min.as_dvec3() - origin).as_vec3();
+            mesh.bounds.max = (mesh.bounds.max.as_dvec3() - origin).as_vec3();
+        };
+        for mesh in self.meshes.values_mut() {
+            shift(mesh);
+            mesh.lods.iter_mut().for_each(&shift);
         }
-        mesh_out.verts.push(vertex);
+        self.origin += origin;
     }
-    mesh_out
-}
 
-fn traverse_nodes(
-    node: &gltf::Node,
-    mesh_data: &Vec<Data>,
-    local_transform: Mat4,
-    primitives_processed: &mut HashMap<String, Mesh>,
-) {
-    // Convert translation in GLTF model to a Mat4.
-    let node_transform = Transform {
-        scale: glam::vec3(
-            node.transform().decomposed().2[0],
-            node.transform().decomposed().2[1],
-            node.transform().decomposed().2[2],
-        ),
-        rotation: glam::quat(
-            node.transform().decomposed().1[0],
-            node.transform().decomposed().1[1],
-            node.transform().decomposed().1[2],
-            node.transform().decomposed().1[3],
-        ),
-        translation: glam::vec3(
-            node.transform().decomposed().0[0],
-            node.transform().decomposed().0[1],
-            node.transform().decomposed().0[2],
-        ),
-    };
-
-    let new_local_transform = local_transform * node_transform.local_matrix();
-
-    // If it has a mesh, process it
-    let mesh = node.mesh();
-    if let Some(mesh) = mesh {
-        // Get mesh
-        let primitives = mesh.primitives();
-
-        for primitive in primitives {
-            let mut mesh_buffer_data =
-                create_vertex_array(&primitive, mesh_data, new_local_transform);
-            let material = String::from(primitive.material().name().unwrap_or("None"));
-            #[allow(clippy::map_entry)] // This was really annoying and made the code less readable
-            if primitives_processed.contains_key(&material) {
-                let mesh: &mut Mesh = primitives_processed.get_mut(&material).unwrap();
-                mesh.verts.append(&mut mesh_buffer_data.verts);
-            } else {
-                primitives_processed.insert(material, mesh_buffer_data);
+    pub(crate) fn add_source_file(&mut self, path: &Path) -> &mut SourceFile {
+        let index = match self.source_files.iter().position(|file| file.path == path) {
+            Some(index) => index,
+            None => {
+                self.source_files.push(SourceFile {
+                    path: path.to_path_buf(),
+                    modified: modified_time(path),
+                    textures: Vec::new(),
+                });
+                self.source_files.len() - 1
             }
-        }
+        };
+        &mut self.source_files[index]
     }
 
-    // If it has children, process those
-    for child in node.children() {
-        traverse_nodes(&child, mesh_data, new_local_transform, primitives_processed);
+    pub(crate) fn add_source_texture(&mut self, path: &Path, gl_id: u32) {
+        self.add_source_file(path).textures.push(gl_id);
     }
 }
 
-impl Model {
-    pub(crate) fn load_gltf(path: &Path, renderer: &mut Renderer) -> Result<Model, String> {
-        let mut model = Model::new();
+// None when the file is missing or the platform doesn't track modification times
+pub(crate) fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
 
-        // Load GLTF from file
-        let gltf_file = gltf::import(path);
-        if gltf_file.is_err() {
-            return Err("Failed to load GLTF file {path}!".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random;
+    use glam::{Vec2, Vec4};
+
+    fn vertex(position: Vec3) -> Vertex {
+        Vertex {
+            position,
+            normal: Vec3::ZERO,
+            tangent: Vec4::ZERO,
+            colour: Vec4::ONE,
+            uv0: Vec2::ZERO,
+            uv1: Vec2::ZERO,
         }
-        let (gltf_document, mesh_data, image_data) = gltf_file.unwrap();
-
-        // Loop over each scene
-        let scene = gltf_document.default_scene();
-        if let Some(scene) = scene {
-            // For each scene, get the nodes
-            for node in scene.nodes() {
-                traverse_nodes(&node, &mesh_data, Mat4::IDENTITY, &mut model.meshes);
-            }
+    }
+
+    fn triangles(triangles: &[[Vec3; 3]]) -> Vec<Vertex> {
+        triangles.iter().flatten().map(|&position| vertex(position)).collect()
+    }
+
+    fn mesh(verts: Vec<Vertex>) -> Mesh {
+        let mut bounds = AABB::new();
+        verts.iter().for_each(|vertex| bounds.grow(vertex.position));
+        Mesh {
+            verts,
+            positions: Vec::new(),
+            vao: 0,
+            vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
+            bounds,
+            lods: Vec::new(),
+            lod_level: 0,
         }
+    }
 
-        // Get all the textures from the GLTF
-        for material in gltf_document.materials() {
-            let mut new_material = Material::new(); // this is unused for now
-
-            // Get PBR parameters
-            new_material.scl_rgh = material.pbr_metallic_roughness().roughness_factor();
-            new_material.scl_mtl = material.pbr_metallic_roughness().metallic_factor();
-            new_material.scl_emm = material.emissive_factor().into();
-
-            // Try to find textures
-            let tex_info_alb = material.pbr_metallic_roughness().base_color_texture();
-            let _tex_info_mtl_rgh = material
-                .pbr_metallic_roughness()
-                .metallic_roughness_texture();
-            let _tex_info_nrm = material.normal_texture();
-            let _tex_info_emm = material.emissive_texture();
-
-            // Get the texture data
-            if let Some(tex) = tex_info_alb {
-                new_material.tex_alb = renderer.upload_texture(&mut Texture::load_texture_from_gltf_image(&image_data[tex.texture().source().index()])) as i32;            
-            }
+    const GOOD: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+    const ZERO_AREA: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::X];
 
-            model.materials.insert(
-                String::from(material.name().unwrap_or("untitled")),
-                new_material,
-            );
+    #[test]
+    fn degenerate_triangles_dropped() {
+        let not_finite = [Vec3::ZERO, Vec3::new(f32::NAN, 0.0, 0.0), Vec3::Y];
+        let mut verts = triangles(&[GOOD, ZERO_AREA, not_finite]);
+        assert_eq!(filter_degenerate_triangles(&mut verts, DegenerateTriangles::Drop), 2);
+        assert_eq!(verts.iter().map(|vertex| vertex.position).collect::<Vec<_>>(), GOOD);
+    }
+
+    #[test]
+    fn degenerate_triangles_kept_with_fallback_normal() {
+        let infinite = [Vec3::ZERO, Vec3::splat(f32::INFINITY), Vec3::Y];
+        let mut verts = triangles(&[GOOD, ZERO_AREA, infinite]);
+        verts[0].normal = Vec3::Z;
+        verts[1].normal = Vec3::new(f32::NAN, 0.0, 0.0);
+        assert_eq!(filter_degenerate_triangles(&mut verts, DegenerateTriangles::Keep), 1);
+        assert_eq!(verts.len(), 6);
+        assert_eq!(verts[0].normal, Vec3::Z);
+        assert!(verts[1..].iter().all(|vertex| vertex.normal == Vec3::Y));
+    }
+
+    #[test]
+    fn random_triangles_come_out_usable() {
+        // Coordinates from a handful of values, so plenty of triangles share corners or are not finite
+        let values = [0.0, 1.0, -1.0, 1.0e-30, f32::NAN, f32::INFINITY];
+        let positions: Vec<Vec3> = (0..3000)
+            .map(|index| {
+                let pick = |dimension| values[(random::uniform(index, 0, 0, dimension) * values.len() as f32) as usize];
+                Vec3::new(pick(0), pick(1), pick(2))
+            })
+            .collect();
+        for mode in [DegenerateTriangles::Drop, DegenerateTriangles::Keep] {
+            let mut verts: Vec<Vertex> = positions.iter().map(|&position| vertex(position)).collect();
+            generate_flat_normals(&mut verts);
+            let removed = filter_degenerate_triangles(&mut verts, mode);
+            assert_eq!(verts.len() + removed * 3, positions.len());
+            assert!(verts.iter().all(|vertex| vertex.position.is_finite() && vertex.normal.is_finite()));
+            assert!(verts.iter().all(|vertex| vertex.normal != Vec3::ZERO));
         }
-        Ok(model)
     }
 
-    pub(crate) fn new() -> Model {
-        Model {
-            meshes: HashMap::new(),
-            materials: HashMap::new(),
+    fn mesh_with_lod() -> Mesh {
+        let mut with_lod = mesh(triangles(&[GOOD, [Vec3::ZERO, Vec3::Y, Vec3::Z]]));
+        with_lod.lods.push(mesh(triangles(&[GOOD])));
+        with_lod
+    }
+
+    #[test]
+    fn keep_all_keeps_the_vertices() {
+        let mut mesh = mesh_with_lod();
+        mesh.release_cpu_data(CpuData::KeepAll);
+        assert_eq!(mesh.verts.len(), 6);
+        assert_eq!(mesh.lods[0].verts.len(), 3);
+        assert!(mesh.positions.is_empty());
+    }
+
+    #[test]
+    fn keep_positions_only() {
+        let mut mesh = mesh_with_lod();
+        mesh.release_cpu_data(CpuData::KeepPositionsOnly);
+        assert!(mesh.verts.is_empty());
+        assert_eq!(mesh.positions, [GOOD, [Vec3::ZERO, Vec3::Y, Vec3::Z]].concat());
+        assert_eq!(mesh.lods[0].positions, GOOD);
+        assert!(mesh.has_cpu_positions());
+    }
+
+    #[test]
+    fn drop_after_upload() {
+        let mut model = Model::new();
+        model.meshes.insert(String::from("mesh"), mesh_with_lod());
+        let vertex_bytes = model.vertex_bytes();
+        assert_eq!(model.cpu_bytes(), vertex_bytes);
+        model.meshes.values_mut().for_each(|mesh| mesh.release_cpu_data(CpuData::DropAfterUpload));
+        assert_eq!(model.cpu_bytes(), 0);
+        assert!(model.meshes.values().all(|mesh| !mesh.has_cpu_positions() && mesh.lods.iter().all(|lod| !lod.has_cpu_positions())));
+    }
+
+    #[test]
+    fn load_options_on_empty_and_degenerate_meshes() {
+        let mut model = Model::new();
+        model.meshes.insert(String::from("empty"), mesh(Vec::new()));
+        model.meshes.insert(String::from("degenerate"), mesh(triangles(&[ZERO_AREA])));
+        model.apply_load_options(&LoadOptions {
+            weld_vertices: Some(0.01),
+            generate_lods: Some(vec![0.5]),
+            ..LoadOptions::default()
+        });
+        assert!(model.meshes.values().all(|mesh| mesh.verts.is_empty() && mesh.bounds.is_empty()));
+        assert!(model.bounds().is_empty());
+        assert_eq!(model.lod_triangle_counts(), [0]);
+    }
+
+    fn positions(verts: &[Vertex]) -> Vec<Vec3> {
+        verts.iter().map(|vertex| vertex.position).collect()
+    }
+
+    // An exported cube drawn without indices, every face with its own normal and uvs
+    fn de_indexed_cube() -> Vec<Vertex> {
+        let mut verts = Vec::new();
+        for normal in [Vec3::X, Vec3::NEG_X, Vec3::Y, Vec3::NEG_Y, Vec3::Z, Vec3::NEG_Z] {
+            let (u, v) = (Vec3::new(normal.y, normal.z, normal.x), Vec3::new(normal.z, normal.x, normal.y));
+            let corner = |a: f32, b: f32| Vertex {
+                normal,
+                uv0: Vec2::new(a, b),
+                ..vertex(normal * 0.5 + u * (a - 0.5) + v * (b - 0.5))
+            };
+            let quad = [corner(0.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0)];
+            verts.extend([quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
         }
+        verts
+    }
+
+    #[test]
+    fn welding_a_cube() {
+        // Hard edges keep a vertex per face corner, without normals or uvs only the 8 corners are left
+        let mut verts = de_indexed_cube();
+        assert_eq!(weld_vertices(&mut verts, 0.001), 24);
+        assert_eq!(positions(&verts), positions(&de_indexed_cube()));
+        let mut verts: Vec<Vertex> = de_indexed_cube().iter().map(|corner| vertex(corner.position)).collect();
+        assert_eq!(weld_vertices(&mut verts, 0.001), 8);
+        assert_eq!(verts.len(), 36);
+    }
+
+    #[test]
+    fn welding_within_epsilon() {
+        let mut nudged = de_indexed_cube();
+        nudged[3].position += Vec3::splat(0.004);
+        let mut verts = nudged.clone();
+        assert_eq!(weld_vertices(&mut verts, 0.01), 24);
+        assert_eq!(verts[3].position, verts[0].position);
+        let mut verts = nudged.clone();
+        assert_eq!(weld_vertices(&mut verts, 0.001), 25);
+        assert_eq!(positions(&verts), positions(&nudged));
+
+        // Zero still welds exact copies
+        let mut verts = de_indexed_cube();
+        assert_eq!(weld_vertices(&mut verts, 0.0), 24);
+    }
+
+    #[test]
+    fn welding_compares_position_normal_and_uv() {
+        let mut verts = triangles(&[GOOD, GOOD]);
+        assert_eq!(weld_vertices(&mut verts.clone(), 0.01), 3);
+        verts[3].normal = Vec3::Z;
+        assert_eq!(weld_vertices(&mut verts.clone(), 0.01), 4);
+        verts[4].uv0 = Vec2::ONE;
+        assert_eq!(weld_vertices(&mut verts.clone(), 0.01), 5);
+        verts[5].position.z = 0.5;
+        assert_eq!(weld_vertices(&mut verts, 0.01), 6);
     }
 }
@@ -0,0 +1,331 @@
+use crate::gpu_buffer::GpuBuffer;
+use crate::graphics::Renderer;
+use crate::material::Material;
+use crate::mesh::{generate_flat_normals, LoadOptions, Mesh, Model};
+use crate::structs::{normal_matrix, Vertex, AABB};
+use crate::texture::{Texture, TextureSlot};
+use glam::{Vec2, Vec3, Vec4};
+use log::warn;
+use std::{collections::HashMap, fs, path::Path};
+
+// Turns a 1-based (or negative, relative to the end) OBJ index into a 0-based index
+fn resolve_index(token: &str, count: usize) -> Option<usize> {
+    let index = token.parse::<i64>().ok()?;
+    let resolved = match index {
+        1.. => index - 1,
+        ..=-1 => count as i64 + index,
+        0 => return None,
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return None;
+    }
+    Some(resolved as usize)
+}
+
+fn parse_floats<'a>(tokens: impl Iterator<Item = &'a str>) -> Vec<f32> {
+    tokens.map(|token| token.parse::<f32>().unwrap_or(0.0)).collect()
+}
+
+pub(crate) fn load_texture(path: &Path, renderer: &mut Renderer, model: &mut Model, material: &str, slot: TextureSlot) -> i32 {
+    let max_dimension = renderer.texture_quality().max_dimension as usize;
+    match renderer.load_shared_texture(path, || Texture::load(path, max_dimension)) {
+        Ok(gl_id) => {
+            model.add_source_texture(path, gl_id);
+            gl_id as i32
+        }
+        Err(reason) => renderer.missing_texture(material, slot, format!("\"{}\": {reason}", path.display())),
+    }
+}
+
+fn load_mtl(
+    path: &Path,
+    renderer: &mut Renderer,
+    model: &mut Model,
+    diffuse_colours: &mut HashMap<String, Vec3>,
+) {
+    // Load MTL from file. Watch it even when it's missing, so creating it triggers a reload
+    model.add_source_file(path);
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(_) => {
+            warn!("Failed to load material library \"{}\"", path.display());
+            return;
+        }
+    };
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut current_material = String::from("untitled");
+    for line in source.lines() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        // Texture statements can have options in front of the path, so the path is the last token
+        let last_token = line.split_whitespace().last().unwrap_or("");
+        match keyword {
+            "newmtl" => {
+                current_material = line.trim_start()["newmtl".len()..].trim().to_string();
+                model.materials.insert(current_material.clone(), Material::named(&current_material));
+            }
+            "Kd" => {
+                let values = parse_floats(tokens);
+                if values.len() >= 3 {
+                    diffuse_colours.insert(current_material.clone(), Vec3::from_slice(&values[0..3]));
+                }
+            }
+            "Ke" => {
+                let values = parse_floats(tokens);
+                if let (Some(material), true) = (model.materials.get_mut(&current_material), values.len() >= 3) {
+                    material.scl_emm = Vec3::from_slice(&values[0..3]);
+                }
+            }
+            "Pr" | "Pm" => {
+                let value = parse_floats(tokens).first().copied().unwrap_or(0.0);
+                if let Some(material) = model.materials.get_mut(&current_material) {
+                    match keyword {
+                        "Pr" => material.scl_rgh = value,
+                        _ => material.scl_mtl = value,
+                    }
+                }
+            }
+            "map_Kd" => {
+                let texture = load_texture(&directory.join(last_token), renderer, model, &current_material, TextureSlot::Albedo);
+                if let Some(material) = model.materials.get_mut(&current_material) {
+                    material.tex_alb = texture;
+                }
+            }
+            "bump" | "map_Bump" | "map_bump" | "norm" => {
+                let texture = load_texture(&directory.join(last_token), renderer, model, &current_material, TextureSlot::Normal);
+                if let Some(material) = model.materials.get_mut(&current_material) {
+                    material.tex_nrm = texture;
+                }
+            }
+            "disp" | "map_disp" => {
+                let texture = load_texture(&directory.join(last_token), renderer, model, &current_material, TextureSlot::Height);
+                if let Some(material) = model.materials.get_mut(&current_material) {
+                    material.tex_hgt = texture;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The triangles of one OBJ object or group that share a material
+struct ObjMesh {
+    material: String,
+    verts: Vec<Vertex>,
+}
+
+// Parses the geometry of an OBJ file, fan-triangulating polygons. Faces are split by object or group and then by
+// material, keyed like Model::meshes: the bare material name outside any o/g statement, "object/material" inside one.
+// mtllib statements are handed to load_library, which fills in the diffuse colours the faces get as vertex colours
+fn parse_obj(
+    source: &str,
+    mut load_library: impl FnMut(&str, &mut HashMap<String, Vec3>),
+) -> Result<HashMap<String, ObjMesh>, String> {
+    let mut meshes = HashMap::<String, ObjMesh>::new();
+    let mut positions = Vec::<Vec3>::new();
+    let mut normals = Vec::<Vec3>::new();
+    let mut texcoords = Vec::<Vec2>::new();
+    let mut diffuse_colours = HashMap::<String, Vec3>::new();
+    let mut current_material = String::from("None");
+    let mut current_object = String::new();
+
+    for (line_number, line) in source.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let values = parse_floats(tokens);
+                if values.len() < 3 {
+                    return Err(format!("Invalid vertex position on line {}", line_number + 1));
+                }
+                positions.push(Vec3::from_slice(&values[0..3]));
+            }
+            Some("vn") => {
+                let values = parse_floats(tokens);
+                if values.len() < 3 {
+                    return Err(format!("Invalid vertex normal on line {}", line_number + 1));
+                }
+                normals.push(Vec3::from_slice(&values[0..3]).normalize_or_zero());
+            }
+            Some("vt") => {
+                // OBJ has the texture origin in the bottom left, we want it in the top left like glTF
+                let values = parse_floats(tokens);
+                let u = values.first().copied().unwrap_or(0.0);
+                let v = values.get(1).copied().unwrap_or(0.0);
+                texcoords.push(Vec2::new(u, 1.0 - v));
+            }
+            Some("f") => {
+                // Parse each v, v/vt, v//vn or v/vt/vn corner into a vertex
+                let colour = match diffuse_colours.get(&current_material) {
+                    Some(colour) => colour.powf(1.0 / 2.2).min(Vec3::ONE).extend(1.0),
+                    None => Vec4::ONE,
+                };
+                let mut corners = Vec::<Vertex>::new();
+                for corner in tokens {
+                    let mut indices = corner.split('/');
+                    let position = match indices.next().and_then(|index| resolve_index(index, positions.len())) {
+                        Some(index) => positions[index],
+                        None => return Err(format!("Invalid face index \"{corner}\" on line {}", line_number + 1)),
+                    };
+                    let uv0 = indices
+                        .next()
+                        .and_then(|index| resolve_index(index, texcoords.len()))
+                        .map_or(Vec2::ZERO, |index| texcoords[index]);
+                    let normal = indices
+                        .next()
+                        .and_then(|index| resolve_index(index, normals.len()))
+                        .map_or(Vec3::ZERO, |index| normals[index]);
+                    corners.push(Vertex {
+                        position,
+                        normal,
+                        tangent: Vec4::new(0., 0., 0., 0.),
+                        colour,
+                        uv0,
+                        uv1: Vec2::new(0., 0.),
+                    });
+                }
+
+                // Fan-triangulate polygons
+                let key = match current_object.is_empty() {
+                    true => current_material.clone(),
+                    false => format!("{current_object}/{current_material}"),
+                };
+                let mesh = meshes.entry(key).or_insert_with(|| ObjMesh {
+                    material: current_material.clone(),
+                    verts: Vec::new(),
+                });
+                for i in 1..corners.len().saturating_sub(1) {
+                    mesh.verts.push(corners[0]);
+                    mesh.verts.push(corners[i]);
+                    mesh.verts.push(corners[i + 1]);
+                }
+            }
+            Some("usemtl") => {
+                current_material = line.trim_start()["usemtl".len()..].trim().to_string();
+            }
+            Some("mtllib") => {
+                let library = line.trim_start()["mtllib".len()..].trim();
+                load_library(library, &mut diffuse_colours);
+            }
+            // Groups without a name go back to the default group
+            Some("o") | Some("g") => {
+                current_object = line.trim_start()[1..].trim().to_string();
+            }
+            _ => {}
+        }
+    }
+    Ok(meshes)
+}
+
+impl Model {
+    pub(crate) fn load_obj(path: &Path, renderer: &mut Renderer, options: &LoadOptions) -> Result<Model, String> {
+        let mut model = Model::new();
+
+        // Load OBJ from file
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(_) => return Err(format!("Failed to load OBJ file {}!", path.display())),
+        };
+        model.add_source_file(path);
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let meshes = parse_obj(&source, |library, diffuse_colours| {
+            load_mtl(&directory.join(library), renderer, &mut model, diffuse_colours)
+        })?;
+
+        // Finalize the meshes
+        let root_matrix = options.root_matrix(renderer.world_up());
+        let root_normal_matrix = normal_matrix(&root_matrix);
+        for (name, obj_mesh) in meshes {
+            let mut mesh = Mesh {
+                verts: obj_mesh.verts,
+                positions: Vec::new(),
+                vao: 0,
+                vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
+                bounds: AABB::new(),
+                lods: Vec::new(),
+                lod_level: 0,
+            };
+            for vertex in &mut mesh.verts {
+                vertex.position = root_matrix.transform_point3(vertex.position);
+                vertex.normal = (root_normal_matrix * vertex.normal).normalize_or_zero();
+            }
+            generate_flat_normals(&mut mesh.verts);
+            for vertex in &mesh.verts {
+                mesh.bounds.grow(vertex.position);
+            }
+
+            // Every mesh needs a material under its own key, even if the file didn't provide one. Meshes split by
+            // object get a copy of their material, the same way the glTF loader handles scene prefixes
+            if !model.materials.contains_key(&name) {
+                let material = match model.materials.get(&obj_mesh.material) {
+                    Some(material) => material.clone(),
+                    None => Material::named(&obj_mesh.material),
+                };
+                model.materials.insert(name.clone(), material);
+            }
+            model.meshes.insert(name, mesh);
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_objects_and_groups() {
+        let source = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+usemtl Stone
+f 1 2 3
+o Cube
+f 1 2 3 4
+usemtl Wood
+f -4 -3 -2
+g Floor
+usemtl Stone
+f 1/1 2/2 3/3
+f 1//1 3//1 4//1
+g
+f 1 2 3 4
+";
+        let meshes = parse_obj(source, |_, _| {}).unwrap();
+        let mut triangles: Vec<(&str, &str, usize)> = meshes
+            .iter()
+            .map(|(name, mesh)| (name.as_str(), mesh.material.as_str(), mesh.verts.len() / 3))
+            .collect();
+        triangles.sort();
+        assert_eq!(
+            triangles,
+            [
+                ("Cube/Stone", "Stone", 2),
+                ("Cube/Wood", "Wood", 1),
+                ("Floor/Stone", "Stone", 2),
+                ("Stone", "Stone", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn diffuse_colour_from_library() {
+        let source = "mtllib test.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl Red\nf 1 2 3\n";
+        let meshes = parse_obj(source, |library, colours| {
+            assert_eq!(library, "test.mtl");
+            colours.insert(String::from("Red"), Vec3::X);
+        })
+        .unwrap();
+        assert!(meshes["Red"].verts.iter().all(|vertex| vertex.colour == Vec4::new(1.0, 0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn invalid_index_is_an_error() {
+        assert!(parse_obj("v 0 0 0\nf 1 2 3\n", |_, _| {}).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+// Oldest events get dropped once a thread has recorded this many
+const EVENTS_PER_THREAD: usize = 65536;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(0);
+static THREAD_BUFFERS: Mutex<Vec<Arc<Mutex<ThreadBuffer>>>> = Mutex::new(Vec::new());
+static START_TIME: OnceLock<Instant> = OnceLock::new();
+
+struct Event {
+    name: &'static str,
+    start_us: f64,
+    duration_us: Option<f64>, // None for instant events
+}
+
+struct ThreadBuffer {
+    thread_id: u32,
+    thread_name: String,
+    events: VecDeque<Event>,
+}
+
+thread_local! {
+    static BUFFER: Arc<Mutex<ThreadBuffer>> = {
+        let buffer = Arc::new(Mutex::new(ThreadBuffer {
+            thread_id: NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed),
+            thread_name: std::thread::current().name().unwrap_or("unnamed").to_string(),
+            events: VecDeque::new(),
+        }));
+        THREAD_BUFFERS.lock().unwrap().push(buffer.clone());
+        buffer
+    };
+}
+
+fn now_us() -> f64 {
+    START_TIME.get_or_init(Instant::now).elapsed().as_secs_f64() * 1_000_000.0
+}
+
+// Recording is off by default, in which case a scope costs one atomic load
+pub fn set_enabled(enabled: bool) {
+    START_TIME.get_or_init(Instant::now);
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Records the time between its creation and its drop, use it through profile_scope!
+pub struct ProfileScope {
+    name: &'static str,
+    start_us: Option<f64>,
+}
+
+impl ProfileScope {
+    pub fn new(name: &'static str) -> Self {
+        let start_us = ENABLED.load(Ordering::Relaxed).then(now_us);
+        ProfileScope { name, start_us }
+    }
+}
+
+impl Drop for ProfileScope {
+    fn drop(&mut self) {
+        let Some(start_us) = self.start_us else {
+            return;
+        };
+        push_event(Event {
+            name: self.name,
+            start_us,
+            duration_us: Some(now_us() - start_us),
+        });
+    }
+}
+
+// Marks a moment on the current thread's track, like a state change that may explain the timings around it
+pub fn instant(name: &'static str) {
+    if ENABLED.load(Ordering::Relaxed) {
+        push_event(Event {
+            name,
+            start_us: now_us(),
+            duration_us: None,
+        });
+    }
+}
+
+fn push_event(event: Event) {
+    BUFFER.with(|buffer| {
+        let mut buffer = buffer.lock().unwrap();
+        if buffer.events.len() == EVENTS_PER_THREAD {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back(event);
+    });
+}
+
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_scope = $crate::profiler::ProfileScope::new($name);
+    };
+}
+
+fn escape_json(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Writes every recorded event, from every thread, as chrome://tracing / Perfetto JSON
+pub fn dump_trace(path: &Path) -> Result<(), String> {
+    let mut json = String::from("{\"traceEvents\":[\n");
+    let mut first = true;
+    for buffer in THREAD_BUFFERS.lock().unwrap().iter() {
+        let buffer = buffer.lock().unwrap();
+        if !first {
+            json.push_str(",\n");
+        }
+        first = false;
+        let _ = write!(
+            json,
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+            buffer.thread_id,
+            escape_json(&buffer.thread_name)
+        );
+        for event in &buffer.events {
+            let _ = write!(
+                json,
+                ",\n{{\"name\":\"{}\",\"pid\":1,\"tid\":{},\"ts\":{:.3},",
+                escape_json(event.name),
+                buffer.thread_id,
+                event.start_us
+            );
+            let _ = match event.duration_us {
+                Some(duration_us) => write!(json, "\"ph\":\"X\",\"dur\":{duration_us:.3}}}"),
+                None => write!(json, "\"ph\":\"i\",\"s\":\"t\"}}"),
+            };
+        }
+    }
+    json.push_str("\n]}\n");
+    std::fs::write(path, json).map_err(|error| format!("Failed to write \"{}\": {error}", path.display()))
+}
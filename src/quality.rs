@@ -0,0 +1,277 @@
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// A setting the quality governor can turn down, one step at a time
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum QualityLever {
+    Parallax,    // Half the march steps, then off
+    Ssao,        // Off
+    Lod,         // Half the LOD switch distance per step
+    Msaa,        // Half the samples per step, down to none
+    RenderScale, // Three quarters of the upscaler's render scale per step, only while upscaling temporally
+}
+
+impl QualityLever {
+    // How far the lever can be turned down
+    pub fn max_steps(self) -> u32 {
+        match self {
+            QualityLever::Ssao => 1,
+            QualityLever::Parallax | QualityLever::Lod | QualityLever::Msaa | QualityLever::RenderScale => 2,
+        }
+    }
+}
+
+// A lever, and the fraction of the frame time one step of it is expected to save
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LeverConfig {
+    pub lever: QualityLever,
+    pub cost: f32,
+}
+
+// Turns settings down when frames are too slow for too long, and back up when there's room for them again. Runs next
+// to dynamic resolution, which reacts faster but only changes the resolution
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QualityGovernorConfig {
+    pub enabled: bool,
+    pub target_frame_time: f32, // Seconds
+    pub over_frames: u32,       // Frames the average has to stay over the target before a lever is turned down
+    pub headroom_frames: u32,   // Frames with headroom before a lever is turned back up
+    pub headroom: f32,          // Fraction of the target the average has to stay under, with the lever's cost added
+    pub levers: Vec<LeverConfig>, // In the order they get turned down, they're turned back up in reverse
+}
+
+impl Default for QualityGovernorConfig {
+    fn default() -> Self {
+        QualityGovernorConfig {
+            enabled: false,
+            target_frame_time: 1.0 / 60.0,
+            over_frames: 30,
+            headroom_frames: 120,
+            headroom: 0.85,
+            levers: vec![
+                LeverConfig { lever: QualityLever::RenderScale, cost: 0.2 },
+                LeverConfig { lever: QualityLever::Parallax, cost: 0.05 },
+                LeverConfig { lever: QualityLever::Lod, cost: 0.1 },
+                LeverConfig { lever: QualityLever::Ssao, cost: 0.15 },
+                LeverConfig { lever: QualityLever::Msaa, cost: 0.15 },
+            ],
+        }
+    }
+}
+
+// Where a lever currently is, 0 steps is the quality the app asked for
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LeverState {
+    pub lever: QualityLever,
+    pub cost: f32,
+    pub steps: u32,
+}
+
+// A lever the governor just moved
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeverChange {
+    pub lever: QualityLever,
+    pub steps: u32, // New position
+    pub raised: bool,
+    pub average_frame_time: f32,
+    pub target_frame_time: f32,
+}
+
+impl fmt::Display for LeverChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (direction, comparison) = if self.raised { ("up", "under") } else { ("down", "over") };
+        write!(
+            f,
+            "Turned {:?} {direction} to {} step(s) below full quality, frames averaged {:.2} ms, {comparison} the {:.2} ms target",
+            self.lever,
+            self.steps,
+            self.average_frame_time * 1000.0,
+            self.target_frame_time * 1000.0
+        )
+    }
+}
+
+// Only decides, the renderer applies the changes. Doesn't touch any GL state, so frame time sequences can be fed to
+// it directly
+pub struct QualityGovernor {
+    config: QualityGovernorConfig,
+    levers: Vec<LeverState>,
+    frame_time_average: f32,
+    frames_over: u32,
+    frames_under: u32,
+}
+
+impl QualityGovernor {
+    pub fn new(config: QualityGovernorConfig) -> Self {
+        let levers = config.levers.iter().map(|lever| LeverState { lever: lever.lever, cost: lever.cost, steps: 0 }).collect();
+        QualityGovernor {
+            frame_time_average: config.target_frame_time,
+            config,
+            levers,
+            frames_over: 0,
+            frames_under: 0,
+        }
+    }
+
+    pub fn config(&self) -> &QualityGovernorConfig {
+        &self.config
+    }
+
+    pub fn levers(&self) -> &[LeverState] {
+        &self.levers
+    }
+
+    // Feeds the last frame's time in. `can_lower` says whether turning a lever down would change anything right now,
+    // so a setting the app already turned off doesn't use up a step. Moves at most one lever per call
+    pub fn update(&mut self, frame_time: f32, can_lower: impl Fn(QualityLever) -> bool) -> Option<LeverChange> {
+        if !self.config.enabled {
+            return None;
+        }
+        let target = self.config.target_frame_time;
+        self.frame_time_average += (frame_time - self.frame_time_average) * 0.1;
+
+        // Raising the last lowered lever must leave enough room for its cost, so it doesn't get lowered right after
+        let raise = self.levers.iter().rposition(|lever| lever.steps > 0);
+        let raise_fits = raise.is_some_and(|index| {
+            self.frame_time_average * (1.0 + self.levers[index].cost) < target * self.config.headroom
+        });
+        if self.frame_time_average > target {
+            self.frames_over += 1;
+            self.frames_under = 0;
+        } else if raise_fits {
+            self.frames_under += 1;
+            self.frames_over = 0;
+        } else {
+            self.frames_over = 0;
+            self.frames_under = 0;
+        }
+
+        let (index, raised) = if self.frames_over >= self.config.over_frames {
+            let index = self
+                .levers
+                .iter()
+                .position(|lever| lever.steps < lever.lever.max_steps() && can_lower(lever.lever))?;
+            self.levers[index].steps += 1;
+            (index, false)
+        } else if self.frames_under >= self.config.headroom_frames {
+            let index = raise?;
+            self.levers[index].steps -= 1;
+            (index, true)
+        } else {
+            return None;
+        };
+
+        // Every change has to prove itself over a full window of frames again
+        self.frames_over = 0;
+        self.frames_under = 0;
+        Some(LeverChange {
+            lever: self.levers[index].lever,
+            steps: self.levers[index].steps,
+            raised,
+            average_frame_time: self.frame_time_average,
+            target_frame_time: target,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn governor() -> QualityGovernor {
+        QualityGovernor::new(QualityGovernorConfig {
+            enabled: true,
+            ..QualityGovernorConfig::default()
+        })
+    }
+
+    // Feeds the same frame time in for a number of frames, returning the frame index of each change
+    fn run(governor: &mut QualityGovernor, frame_time: f32, frames: usize) -> Vec<(usize, QualityLever, u32, bool)> {
+        (0..frames)
+            .filter_map(|frame| {
+                let change = governor.update(frame_time, |_| true)?;
+                Some((frame, change.lever, change.steps, change.raised))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn disabled_governor_never_moves() {
+        let mut governor = QualityGovernor::new(QualityGovernorConfig::default());
+        assert!(run(&mut governor, 0.1, 1000).is_empty());
+        assert!(governor.levers().iter().all(|lever| lever.steps == 0));
+    }
+
+    #[test]
+    fn slow_frames_step_levers_down_in_order() {
+        let mut governor = governor();
+        let changes = run(&mut governor, 0.025, 30 * 4);
+        assert_eq!(
+            changes,
+            [
+                (29, QualityLever::RenderScale, 1, false),
+                (59, QualityLever::RenderScale, 2, false),
+                (89, QualityLever::Parallax, 1, false),
+                (119, QualityLever::Parallax, 2, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn levers_that_cant_lower_are_skipped() {
+        let mut governor = governor();
+        let change = (0..30).find_map(|_| governor.update(0.025, |lever| lever != QualityLever::RenderScale));
+        assert_eq!(change.map(|change| change.lever), Some(QualityLever::Parallax));
+    }
+
+    #[test]
+    fn frames_near_target_hold_the_levers() {
+        let mut governor = governor();
+        run(&mut governor, 0.025, 30);
+        assert_eq!(governor.levers()[0].steps, 1);
+
+        // Just under the target isn't over it, but doesn't leave headroom for the lever's cost either
+        let target = governor.config().target_frame_time;
+        assert!(run(&mut governor, target * 0.95, 1000).is_empty());
+        assert_eq!(governor.levers()[0].steps, 1);
+    }
+
+    #[test]
+    fn fast_frames_restore_after_the_headroom_window() {
+        let mut governor = governor();
+        run(&mut governor, 0.025, 60);
+        assert_eq!(governor.levers()[0].steps, 2);
+
+        // The average has to fall under the headroom first, then stay there for a full window per step
+        let changes = run(&mut governor, 0.005, 1000);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|&(_, lever, _, raised)| lever == QualityLever::RenderScale && raised));
+        assert_eq!(changes[1].0 - changes[0].0, governor.config().headroom_frames as usize);
+        assert!(governor.levers().iter().all(|lever| lever.steps == 0));
+    }
+
+    #[test]
+    fn levers_restore_in_reverse_order() {
+        let mut governor = governor();
+        run(&mut governor, 0.025, 90);
+        let raised: Vec<(QualityLever, u32)> = run(&mut governor, 0.005, 1000)
+            .into_iter()
+            .map(|(_, lever, steps, _)| (lever, steps))
+            .collect();
+        assert_eq!(raised, [(QualityLever::Parallax, 0), (QualityLever::RenderScale, 1), (QualityLever::RenderScale, 0)]);
+    }
+
+    #[test]
+    fn a_single_spike_changes_nothing() {
+        let mut governor = governor();
+        let target = governor.config().target_frame_time;
+        run(&mut governor, target * 0.5, 100);
+        assert!(run(&mut governor, 0.1, 1).is_empty());
+        assert!(run(&mut governor, target * 0.5, 100).is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+// Counter-based random numbers. Every number is a hash of where it's used, instead of the next step of a running
+// state, so it comes out the same whatever order or thread it's asked for in. The same inputs give the same numbers
+// on every run, which is what makes seeded captures and benchmarks reproducible
+
+// pcg4d from Jarzynski and Olano, "Hash Functions for GPU Rendering" (2020). Every output bit depends on every input
+pub fn pcg4d(input: [u32; 4]) -> [u32; 4] {
+    let mut v = input.map(|x| x.wrapping_mul(1664525).wrapping_add(1013904223));
+    let mix = |v: &mut [u32; 4]| {
+        v[0] = v[0].wrapping_add(v[1].wrapping_mul(v[3]));
+        v[1] = v[1].wrapping_add(v[2].wrapping_mul(v[0]));
+        v[2] = v[2].wrapping_add(v[0].wrapping_mul(v[1]));
+        v[3] = v[3].wrapping_add(v[1].wrapping_mul(v[2]));
+    };
+    mix(&mut v);
+    v = v.map(|x| x ^ (x >> 16));
+    mix(&mut v);
+    v
+}
+
+// Uniform number in [0, 1) for sample `dimension` of pixel (x, y) in `frame`. Things that aren't pixels use x and y
+// as their own indices. Different dimensions are independent, so each random decision of a sample gets its own.
+// Shaders have the same function as random_uniform in assets/shaders/random.glsl
+pub fn uniform(x: u32, y: u32, frame: u32, dimension: u32) -> f32 {
+    unit_float(pcg4d([x, y, frame, dimension])[0])
+}
+
+// The top 24 bits, all of which an f32 can hold, so 1.0 is never reached
+fn unit_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::Wrapping;
+
+    const GLSL: &str = include_str!("../assets/shaders/random.glsl");
+
+    type Statement = (&'static str, fn(&mut [Wrapping<u32>; 4]));
+
+    // random.glsl run the way a GPU would, statement for statement. GLSL uint arithmetic wraps around and its vector
+    // operators work per component. Each statement is checked to still be in the shader, so the two can't drift apart
+    fn glsl_pcg4d(input: [u32; 4]) -> [u32; 4] {
+        let mut v = input.map(Wrapping);
+        let statements: [Statement; 6] = [
+            ("v = v * 1664525u + 1013904223u;", |v| *v = v.map(|x| x * Wrapping(1664525) + Wrapping(1013904223))),
+            ("v.x += v.y * v.w;", |v| v[0] += v[1] * v[3]),
+            ("v.y += v.z * v.x;", |v| v[1] += v[2] * v[0]),
+            ("v.z += v.x * v.y;", |v| v[2] += v[0] * v[1]),
+            ("v.w += v.y * v.z;", |v| v[3] += v[1] * v[2]),
+            ("v ^= v >> 16u;", |v| *v = v.map(|x| x ^ (x >> 16))),
+        ];
+        let body = GLSL.split("uvec4 pcg4d(uvec4 v) {").nth(1).and_then(|rest| rest.split("return v;").next()).unwrap();
+        for line in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (_, statement) = statements.iter().find(|(glsl, _)| *glsl == line).unwrap_or_else(|| panic!("Unknown GLSL \"{line}\""));
+            statement(&mut v);
+        }
+        v.map(|x| x.0)
+    }
+
+    // float(pcg4d(...).x >> 8u) / 16777216.0, an exact conversion and a division by a power of two
+    fn glsl_random_uniform(x: u32, y: u32, frame: u32, dimension: u32) -> f32 {
+        assert!(GLSL.contains("return float(pcg4d(uvec4(x, y, frame, dimension)).x >> 8u) / 16777216.0;"));
+        (glsl_pcg4d([x, y, frame, dimension])[0] >> 8) as f32 / 16777216.0
+    }
+
+    fn grid() -> impl Iterator<Item = [u32; 4]> {
+        (0..16).flat_map(|x| (0..16).flat_map(move |y| (0..4).flat_map(move |frame| (0..4).map(move |dimension| [x, y, frame, dimension]))))
+    }
+
+    #[test]
+    fn rust_and_glsl_agree_bit_for_bit() {
+        for [x, y, frame, dimension] in grid().chain([[u32::MAX; 4], [u32::MAX, 0, 1 << 31, 12345]]) {
+            assert_eq!(pcg4d([x, y, frame, dimension]), glsl_pcg4d([x, y, frame, dimension]));
+            assert_eq!(uniform(x, y, frame, dimension).to_bits(), glsl_random_uniform(x, y, frame, dimension).to_bits());
+        }
+    }
+
+    #[test]
+    fn same_inputs_give_the_same_numbers() {
+        for [x, y, frame, dimension] in grid() {
+            assert_eq!(uniform(x, y, frame, dimension).to_bits(), uniform(x, y, frame, dimension).to_bits());
+        }
+        assert_ne!(uniform(3, 4, 5, 0), uniform(3, 4, 5, 1));
+        assert_ne!(uniform(3, 4, 5, 0), uniform(3, 4, 6, 0));
+    }
+
+    #[test]
+    fn numbers_are_in_the_unit_interval() {
+        let values: Vec<f32> = grid().map(|[x, y, frame, dimension]| uniform(x, y, frame, dimension)).collect();
+        assert!(values.iter().all(|value| (0.0..1.0).contains(value)));
+        assert_eq!(unit_float(0), 0.0);
+        assert!(unit_float(u32::MAX) < 1.0);
+
+        // Roughly uniform too: every tenth of the interval gets its share of the 4096 numbers
+        for bucket in 0..10 {
+            let count = values.iter().filter(|value| (**value * 10.0) as usize == bucket).count();
+            assert!((300..520).contains(&count), "bucket {bucket} has {count} numbers");
+        }
+    }
+}
@@ -0,0 +1,226 @@
+#![allow(dead_code)]
+use crate::mesh::{Mesh, Model};
+use crate::structs::AABB;
+use glam::Vec3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3, // Expected to be normalized
+    pub length: f32,     // Hits further away than this are ignored, use f32::INFINITY for unbounded rays
+}
+
+#[derive(Debug, Clone)]
+pub struct RaycastHit {
+    pub distance: f32,
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub model: u64,
+    pub mesh_name: String,
+    pub triangle_index: usize,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3, length: f32) -> Self {
+        Ray {
+            origin,
+            direction: direction.normalize_or_zero(),
+            length,
+        }
+    }
+
+    // Slab test, returns whether the ray enters the box before its length runs out
+    pub fn intersects_aabb(&self, aabb: &AABB) -> bool {
+        let inverse_direction = self.direction.recip();
+        let t0 = (aabb.min - self.origin) * inverse_direction;
+        let t1 = (aabb.max - self.origin) * inverse_direction;
+        let t_near = t0.min(t1).max_element().max(0.0);
+        let t_far = t0.max(t1).min_element().min(self.length);
+        t_near <= t_far
+    }
+
+    // Möller-Trumbore, returns the distance along the ray. Both sides of the triangle count as a hit
+    pub fn intersect_triangle(&self, v0: Vec3, v1: Vec3, v2: Vec3) -> Option<f32> {
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        // Relative to the triangle's size, so tiny and huge triangles are treated the same
+        if determinant.abs() <= f32::EPSILON * edge1.length() * edge2.length() {
+            return None;
+        }
+        let inverse_determinant = 1.0 / determinant;
+        let s = self.origin - v0;
+        let u = s.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inverse_determinant;
+        (t >= 0.0 && t <= self.length).then_some(t)
+    }
+}
+
+// Appends every hit against the mesh's triangles to `hits`, unsorted. Uses whichever CPU copy of the positions the
+// mesh kept, meshes that kept neither have nothing to hit
+fn raycast_mesh(ray: &Ray, handle: u64, name: &str, mesh: &Mesh, hits: &mut Vec<RaycastHit>) {
+    if !ray.intersects_aabb(&mesh.bounds) {
+        return;
+    }
+    if mesh.verts.is_empty() {
+        let triangles = mesh.positions.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]);
+        raycast_triangles(ray, handle, name, triangles, hits);
+    } else {
+        let triangles = mesh.verts.chunks_exact(3).map(|triangle| [triangle[0].position, triangle[1].position, triangle[2].position]);
+        raycast_triangles(ray, handle, name, triangles, hits);
+    }
+}
+
+fn raycast_triangles(ray: &Ray, handle: u64, name: &str, triangles: impl Iterator<Item = [Vec3; 3]>, hits: &mut Vec<RaycastHit>) {
+    for (triangle_index, [v0, v1, v2]) in triangles.enumerate() {
+        let Some(distance) = ray.intersect_triangle(v0, v1, v2) else {
+            continue;
+        };
+
+        // Face the normal towards the ray origin
+        let mut normal = (v1 - v0).cross(v2 - v0).normalize_or_zero();
+        if normal.dot(ray.direction) > 0.0 {
+            normal = -normal;
+        }
+        hits.push(RaycastHit {
+            distance,
+            point: ray.origin + ray.direction * distance,
+            normal,
+            model: handle,
+            mesh_name: name.to_string(),
+            triangle_index,
+        });
+    }
+}
+
+impl Model {
+    // Vertices are already in world space, so the hits are too. There is no BVH, every triangle in a mesh
+    // whose bounds the ray touches gets tested
+    pub fn raycast_all(&self, ray: &Ray, handle: u64, hits: &mut Vec<RaycastHit>) {
+        for (name, mesh) in &self.meshes {
+            raycast_mesh(ray, handle, name, mesh, hits);
+        }
+    }
+
+    pub fn raycast(&self, ray: &Ray, handle: u64) -> Option<RaycastHit> {
+        // Shorten the ray after each hit, so meshes behind the closest hit get culled by their bounds
+        let mut ray = *ray;
+        let mut closest = None;
+        let mut hits = Vec::new();
+        for (name, mesh) in &self.meshes {
+            raycast_mesh(&ray, handle, name, mesh, &mut hits);
+            if let Some(hit) = hits.drain(..).min_by(|a, b| a.distance.total_cmp(&b.distance)) {
+                ray.length = hit.distance;
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu_buffer::GpuBuffer;
+    use crate::structs::Vertex;
+    use glam::{Vec2, Vec4};
+
+    // A 2x2 quad facing +z at the given height, as two triangles
+    fn quad(z: f32, keep_vertices: bool) -> Mesh {
+        let positions: Vec<Vec3> = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .into_iter()
+            .map(|(x, y)| Vec3::new(x, y, z))
+            .collect();
+        let mut bounds = AABB::new();
+        positions.iter().for_each(|&position| bounds.grow(position));
+        let verts = positions
+            .iter()
+            .map(|&position| Vertex {
+                position,
+                normal: Vec3::Z,
+                tangent: Vec4::ZERO,
+                colour: Vec4::ONE,
+                uv0: Vec2::ZERO,
+                uv1: Vec2::ZERO,
+            })
+            .collect();
+        Mesh {
+            verts: if keep_vertices { verts } else { Vec::new() },
+            positions: if keep_vertices { Vec::new() } else { positions },
+            vao: 0,
+            vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STATIC_DRAW),
+            bounds,
+            lods: Vec::new(),
+            lod_level: 0,
+        }
+    }
+
+    fn two_quads() -> Model {
+        let mut model = Model::new();
+        model.meshes.insert(String::from("top"), quad(1.0, true));
+        model.meshes.insert(String::from("bottom"), quad(-1.0, false));
+        model
+    }
+
+    #[test]
+    fn closest_hit_from_above() {
+        let ray = Ray::new(Vec3::new(0.5, 0.25, 5.0), -Vec3::Z, f32::INFINITY);
+        let hit = two_quads().raycast(&ray, 7).unwrap();
+        assert_eq!(hit.mesh_name, "top");
+        assert_eq!(hit.model, 7);
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!(hit.point.abs_diff_eq(Vec3::new(0.5, 0.25, 1.0), 1e-5));
+        assert_eq!(hit.normal, Vec3::Z);
+    }
+
+    #[test]
+    fn normal_faces_the_ray() {
+        let ray = Ray::new(Vec3::new(0.5, 0.25, -5.0), Vec3::Z, f32::INFINITY);
+        let hit = two_quads().raycast(&ray, 0).unwrap();
+        assert_eq!(hit.mesh_name, "bottom");
+        assert_eq!(hit.normal, -Vec3::Z);
+    }
+
+    #[test]
+    fn raycast_all_finds_every_hit() {
+        let ray = Ray::new(Vec3::new(-0.5, 0.5, 5.0), -Vec3::Z, f32::INFINITY);
+        let mut hits = Vec::new();
+        two_quads().raycast_all(&ray, 0, &mut hits);
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        let names: Vec<&str> = hits.iter().map(|hit| hit.mesh_name.as_str()).collect();
+        assert_eq!(names, ["top", "bottom"]);
+        assert_eq!(hits[0].triangle_index, 1);
+    }
+
+    #[test]
+    fn ray_length_limits_hits() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::Z, 3.0);
+        assert!(two_quads().raycast(&ray, 0).is_none());
+        let ray = Ray { length: 5.0, ..ray };
+        assert_eq!(two_quads().raycast(&ray, 0).unwrap().mesh_name, "top");
+    }
+
+    #[test]
+    fn misses_outside_the_bounds() {
+        let ray = Ray::new(Vec3::new(2.0, 0.0, 5.0), -Vec3::Z, f32::INFINITY);
+        assert!(two_quads().raycast(&ray, 0).is_none());
+        assert!(!ray.intersects_aabb(&quad(0.0, true).bounds));
+    }
+
+    #[test]
+    fn degenerate_and_parallel_triangles_miss() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::Z, f32::INFINITY);
+        assert!(ray.intersect_triangle(Vec3::ZERO, Vec3::X, Vec3::X * 2.0).is_none());
+        let ray = Ray::new(Vec3::new(-5.0, 0.1, 0.0), Vec3::X, f32::INFINITY);
+        assert!(ray.intersect_triangle(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).is_none());
+    }
+}
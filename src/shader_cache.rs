@@ -0,0 +1,192 @@
+use std::{
+    ffi::c_void,
+    fs,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use gl::types::GLenum;
+use log::warn;
+
+use crate::capabilities::gl_string;
+
+// Start of every cache file, bump the number when the layout changes
+const MAGIC: &[u8; 8] = b"RRGLSC01";
+const HEADER_SIZE: usize = 24; // Magic, binary format, binary length and checksum of the binary
+// Holds the driver the cached binaries were made by, they can't be loaded by any other driver
+const DRIVER_FILE: &str = "driver.txt";
+
+// Where linked program binaries get cached, and how big the cache may grow before the least recently used
+// binaries are deleted
+#[derive(Debug, Clone)]
+pub struct ShaderCacheConfig {
+    pub directory: PathBuf,
+    pub max_bytes: u64,
+}
+
+impl Default for ShaderCacheConfig {
+    fn default() -> Self {
+        ShaderCacheConfig {
+            directory: PathBuf::from("shader_cache"),
+            max_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+// Linked program binaries on disk, keyed by a hash of the driver and every source file of the program. Anything
+// wrong with a cache file just means the program gets compiled again
+pub struct ShaderCache {
+    config: ShaderCacheConfig,
+    driver: String,
+}
+
+impl ShaderCache {
+    // Opens the cache, emptying it when it was made by another driver. The current context's driver is used
+    pub fn new(config: ShaderCacheConfig) -> Self {
+        let driver = unsafe { [gl::VENDOR, gl::RENDERER, gl::VERSION].map(|name| gl_string(name)).join("\n") };
+        let cache = ShaderCache { config, driver };
+        let driver_file = cache.config.directory.join(DRIVER_FILE);
+        if fs::read_to_string(&driver_file).ok().as_deref() != Some(cache.driver.as_str()) {
+            for file in cache.binary_files() {
+                let _ = fs::remove_file(file.0);
+            }
+            let written = fs::create_dir_all(&cache.config.directory).and_then(|_| fs::write(&driver_file, &cache.driver));
+            if let Err(error) = written {
+                warn!("Shader cache \"{}\" is not writable: {error}", cache.config.directory.display());
+            }
+        }
+        cache
+    }
+
+    // Content hash of a program's sources, together with the driver that compiles them
+    pub fn key(&self, sources: &[(GLenum, String)]) -> u64 {
+        let mut hash = fnv1a(FNV_OFFSET, self.driver.as_bytes());
+        for (shader_type, source) in sources {
+            hash = fnv1a(hash, &shader_type.to_le_bytes());
+            hash = fnv1a(hash, source.as_bytes());
+        }
+        hash
+    }
+
+    // Tries to fill the program with a cached binary, returns whether the driver accepted it
+    pub fn load(&self, program: u32, key: u64) -> bool {
+        let path = self.binary_path(key);
+        let Ok(file) = fs::read(&path) else {
+            return false;
+        };
+        let Some((format, binary)) = parse_binary_file(&file) else {
+            warn!("Ignoring corrupted shader cache file \"{}\"", path.display());
+            let _ = fs::remove_file(&path);
+            return false;
+        };
+
+        // Drivers may reject binaries at any time, after an update for example
+        let mut linked = 0;
+        unsafe {
+            gl::ProgramBinary(program, format, binary.as_ptr() as *const c_void, binary.len() as i32);
+            gl::GetProgramiv(program, gl::LINK_STATUS, &mut linked);
+        }
+        if linked == 0 {
+            let _ = fs::remove_file(&path);
+            return false;
+        }
+
+        // Mark it as recently used for pruning
+        if let Ok(file) = fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        true
+    }
+
+    // Stores the binary of a linked program, and prunes the cache if it got too big
+    pub fn store(&self, program: u32, key: u64) {
+        let mut length = 0;
+        unsafe {
+            gl::GetProgramiv(program, gl::PROGRAM_BINARY_LENGTH, &mut length);
+        }
+        if length <= 0 {
+            return;
+        }
+        let mut binary = vec![0u8; length as usize];
+        let mut format = 0;
+        unsafe {
+            gl::GetProgramBinary(program, length, &mut length, &mut format, binary.as_mut_ptr() as *mut c_void);
+        }
+        binary.truncate(length.max(0) as usize);
+
+        let mut file = Vec::with_capacity(HEADER_SIZE + binary.len());
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&format.to_le_bytes());
+        file.extend_from_slice(&(binary.len() as u32).to_le_bytes());
+        file.extend_from_slice(&fnv1a(FNV_OFFSET, &binary).to_le_bytes());
+        file.extend_from_slice(&binary);
+
+        // Write to a temporary file first, so a crash halfway never leaves a truncated binary behind
+        let path = self.binary_path(key);
+        let temporary = path.with_extension("tmp");
+        if fs::write(&temporary, &file).and_then(|_| fs::rename(&temporary, &path)).is_err() {
+            let _ = fs::remove_file(&temporary);
+            return;
+        }
+        self.prune();
+    }
+
+    // Deletes the least recently used binaries until the cache fits in its size limit
+    fn prune(&self) {
+        let mut files = self.binary_files();
+        let mut total: u64 = files.iter().map(|file| file.1).sum();
+        files.sort_by_key(|file| file.2);
+        for (path, size, _) in files {
+            if total <= self.config.max_bytes {
+                break;
+            }
+            if fs::remove_file(path).is_ok() {
+                total -= size;
+            }
+        }
+    }
+
+    fn binary_path(&self, key: u64) -> PathBuf {
+        self.config.directory.join(format!("{key:016x}.bin"))
+    }
+
+    // Path, size and last use of every cached binary
+    fn binary_files(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let Ok(entries) = fs::read_dir(&self.config.directory) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension().and_then(|extension| extension.to_str()) != Some("bin") {
+                    return None;
+                }
+                let metadata = fs::metadata(&path).ok()?;
+                Some((path, metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)))
+            })
+            .collect()
+    }
+}
+
+// Binary format and binary of a cache file, None if it's damaged in any way
+fn parse_binary_file(file: &[u8]) -> Option<(GLenum, &[u8])> {
+    if file.len() < HEADER_SIZE || &file[0..8] != MAGIC {
+        return None;
+    }
+    let format = u32::from_le_bytes(file[8..12].try_into().ok()?);
+    let length = u32::from_le_bytes(file[12..16].try_into().ok()?) as usize;
+    let checksum = u64::from_le_bytes(file[16..24].try_into().ok()?);
+    let binary = &file[HEADER_SIZE..];
+    (binary.len() == length && fnv1a(FNV_OFFSET, binary) == checksum).then_some((format, binary))
+}
+
+// 64-bit FNV-1a, unlike std's hasher it gives the same hashes across Rust versions
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
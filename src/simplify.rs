@@ -0,0 +1,296 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    ops::AddAssign,
+};
+
+use glam::{DVec3, Vec3};
+
+use crate::structs::Vertex;
+
+// Open edges get a plane perpendicular to their triangle with this much weight, so borders keep their outline
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+// Symmetric 4x4 matrix measuring the summed squared distance to a set of planes, upper triangle stored row by row
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // Plane through `point` with unit `normal`
+    fn from_plane(normal: DVec3, point: DVec3, weight: f64) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        let d = -normal.dot(point);
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|value| value * weight))
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let q = &self.0;
+        q[0] * p.x * p.x + 2.0 * q[1] * p.x * p.y + 2.0 * q[2] * p.x * p.z + 2.0 * q[3] * p.x
+            + q[4] * p.y * p.y + 2.0 * q[5] * p.y * p.z + 2.0 * q[6] * p.y
+            + q[7] * p.z * p.z + 2.0 * q[8] * p.z
+            + q[9]
+    }
+}
+
+impl AddAssign for Quadric {
+    fn add_assign(&mut self, other: Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+    }
+}
+
+// Moving vertex `from` onto vertex `to`, only valid while neither vertex changed since it was queued
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed, so the binary heap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+struct Simplifier {
+    positions: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+    corners: Vec<[Vertex; 3]>, // Attributes stay per corner, so UV and normal seams survive
+    triangle_alive: Vec<bool>,
+    vertex_triangles: Vec<Vec<u32>>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    removed: Vec<bool>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(verts: &[Vertex]) -> Self {
+        // Corners at exactly the same position share a vertex
+        let mut positions = Vec::new();
+        let mut position_ids = HashMap::<[u32; 3], u32>::new();
+        let mut triangles = Vec::new();
+        let mut corners = Vec::new();
+        for triangle in verts.chunks_exact(3) {
+            let ids = [0, 1, 2].map(|i| {
+                let position = triangle[i].position;
+                *position_ids.entry(position.to_array().map(f32::to_bits)).or_insert_with(|| {
+                    positions.push(position);
+                    (positions.len() - 1) as u32
+                })
+            });
+            triangles.push(ids);
+            corners.push([triangle[0], triangle[1], triangle[2]]);
+        }
+
+        let mut vertex_triangles = vec![Vec::new(); positions.len()];
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut edge_counts = HashMap::<(u32, u32), u32>::new();
+        for (triangle_index, triangle) in triangles.iter().enumerate() {
+            for (i, &vertex) in triangle.iter().enumerate() {
+                vertex_triangles[vertex as usize].push(triangle_index as u32);
+                let next = triangle[(i + 1) % 3];
+                *edge_counts.entry((vertex.min(next), vertex.max(next))).or_default() += 1;
+            }
+
+            // Area weighted plane of the triangle
+            let [p0, p1, p2] = triangle.map(|vertex| positions[vertex as usize].as_dvec3());
+            let cross = (p1 - p0).cross(p2 - p0);
+            let area = cross.length() * 0.5;
+            if area <= f64::MIN_POSITIVE {
+                continue;
+            }
+            let quadric = Quadric::from_plane(cross / (area * 2.0), p0, area);
+            for vertex in triangle {
+                quadrics[*vertex as usize] += quadric;
+            }
+        }
+
+        // Keep open edges in place with a plane along the edge, perpendicular to the triangle
+        for triangle in &triangles {
+            let [p0, p1, p2] = triangle.map(|vertex| positions[vertex as usize].as_dvec3());
+            let normal = (p1 - p0).cross(p2 - p0).normalize_or_zero();
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                if edge_counts[&(a.min(b), a.max(b))] != 1 {
+                    continue;
+                }
+                let (pa, pb) = (positions[a as usize].as_dvec3(), positions[b as usize].as_dvec3());
+                let edge = pb - pa;
+                let quadric = Quadric::from_plane(edge.cross(normal).normalize_or_zero(), pa, BOUNDARY_WEIGHT * edge.length_squared());
+                quadrics[a as usize] += quadric;
+                quadrics[b as usize] += quadric;
+            }
+        }
+
+        let vertex_count = positions.len();
+        let mut simplifier = Simplifier {
+            positions,
+            triangle_alive: vec![true; triangles.len()],
+            triangles,
+            corners,
+            vertex_triangles,
+            quadrics,
+            versions: vec![0; vertex_count],
+            removed: vec![false; vertex_count],
+            heap: BinaryHeap::new(),
+        };
+        for (a, b) in edge_counts.into_keys() {
+            simplifier.queue_edge(a, b);
+        }
+        simplifier
+    }
+
+    // Queues the cheaper direction of collapsing the edge between a and b
+    fn queue_edge(&mut self, a: u32, b: u32) {
+        let mut quadric = self.quadrics[a as usize];
+        quadric += self.quadrics[b as usize];
+        let cost_to_a = quadric.error(self.positions[a as usize].as_dvec3());
+        let cost_to_b = quadric.error(self.positions[b as usize].as_dvec3());
+        let (from, to, cost) = if cost_to_b <= cost_to_a { (a, b, cost_to_b) } else { (b, a, cost_to_a) };
+        self.heap.push(Collapse {
+            cost,
+            from,
+            to,
+            versions: (self.versions[from as usize], self.versions[to as usize]),
+        });
+    }
+
+    fn neighbours(&self, vertex: u32) -> HashSet<u32> {
+        let mut neighbours = HashSet::new();
+        for &triangle in &self.vertex_triangles[vertex as usize] {
+            if self.triangle_alive[triangle as usize] {
+                neighbours.extend(self.triangles[triangle as usize].iter().filter(|&&other| other != vertex));
+            }
+        }
+        neighbours
+    }
+
+    // Rejects collapses that would pinch the surface into a non-manifold shape or flip a triangle over
+    fn can_collapse(&self, from: u32, to: u32) -> bool {
+        let shared_neighbours = self.neighbours(from).intersection(&self.neighbours(to)).count();
+        let shared_triangles = self.vertex_triangles[from as usize]
+            .iter()
+            .filter(|&&triangle| self.triangle_alive[triangle as usize] && self.triangles[triangle as usize].contains(&to))
+            .count();
+        if shared_neighbours > shared_triangles {
+            return false;
+        }
+
+        let target = self.positions[to as usize];
+        for &triangle in &self.vertex_triangles[from as usize] {
+            let indices = self.triangles[triangle as usize];
+            if !self.triangle_alive[triangle as usize] || indices.contains(&to) {
+                continue;
+            }
+            let before = indices.map(|vertex| self.positions[vertex as usize]);
+            let after = indices.map(|vertex| if vertex == from { target } else { self.positions[vertex as usize] });
+            let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+            let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+            if normal_before.dot(normal_after) <= 0.0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn collapse(&mut self, from: u32, to: u32) -> usize {
+        // Triangles along the edge disappear. Their corners tell which attributes continue across the edge
+        let mut removed_triangles = 0;
+        let mut attribute_pairs = Vec::new();
+        for &triangle in &self.vertex_triangles[from as usize] {
+            let triangle = triangle as usize;
+            if !self.triangle_alive[triangle] || !self.triangles[triangle].contains(&to) {
+                continue;
+            }
+            self.triangle_alive[triangle] = false;
+            removed_triangles += 1;
+            let corner_of = |vertex: u32| self.triangles[triangle].iter().position(|&other| other == vertex).unwrap();
+            attribute_pairs.push((self.corners[triangle][corner_of(from)], self.corners[triangle][corner_of(to)]));
+        }
+
+        // Move the remaining triangles over, taking the attributes from the other end of the edge on the same side of
+        // any seam, or keeping their own when there is no match
+        let target = self.positions[to as usize];
+        let moved: Vec<u32> = self.vertex_triangles[from as usize]
+            .iter()
+            .copied()
+            .filter(|&triangle| self.triangle_alive[triangle as usize])
+            .collect();
+        for &triangle in &moved {
+            let triangle = triangle as usize;
+            let corner = self.triangles[triangle].iter().position(|&vertex| vertex == from).unwrap();
+            self.triangles[triangle][corner] = to;
+            let vertex = &mut self.corners[triangle][corner];
+            if let Some((_, replacement)) = attribute_pairs
+                .iter()
+                .find(|(original, _)| original.uv0 == vertex.uv0 && original.normal == vertex.normal)
+            {
+                *vertex = *replacement;
+            }
+            vertex.position = target;
+        }
+
+        let quadric = self.quadrics[from as usize];
+        self.quadrics[to as usize] += quadric;
+        self.vertex_triangles[to as usize].extend(moved);
+        self.vertex_triangles[to as usize].retain(|&triangle| self.triangle_alive[triangle as usize]);
+        self.vertex_triangles[from as usize].clear();
+        self.removed[from as usize] = true;
+        self.versions[to as usize] += 1;
+        for neighbour in self.neighbours(to) {
+            self.queue_edge(to, neighbour);
+        }
+        removed_triangles
+    }
+}
+
+// Reduces a de-indexed triangle list to roughly `ratio` of its triangles, by collapsing edges onto one of their
+// endpoints in order of least quadric error (Garland and Heckbert). Stops early when no valid collapse is left
+pub fn simplify(verts: &[Vertex], ratio: f32) -> Vec<Vertex> {
+    let mut simplifier = Simplifier::new(verts);
+    let mut triangle_count = simplifier.triangles.len();
+    let target_count = ((triangle_count as f32 * ratio.clamp(0.0, 1.0)).ceil() as usize).max(1);
+
+    while triangle_count > target_count {
+        let Some(collapse) = simplifier.heap.pop() else {
+            break;
+        };
+        let (from, to) = (collapse.from as usize, collapse.to as usize);
+        if simplifier.removed[from]
+            || simplifier.removed[to]
+            || collapse.versions != (simplifier.versions[from], simplifier.versions[to])
+        {
+            continue;
+        }
+        if !simplifier.can_collapse(collapse.from, collapse.to) {
+            continue;
+        }
+        triangle_count -= simplifier.collapse(collapse.from, collapse.to);
+    }
+
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    for (corners, alive) in simplifier.corners.iter().zip(&simplifier.triangle_alive) {
+        if *alive {
+            output.extend_from_slice(corners);
+        }
+    }
+    output
+}
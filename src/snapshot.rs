@@ -0,0 +1,168 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Projection,
+    exposure::ExposureSettings,
+    graphics::{DynamicResolution, FramebufferFormat, LodSettings, MotionBlurSettings, ParallaxSettings, SkyConfig, SsaoSettings, StereoConfig, UpscaleSettings, WindConfig},
+    material::MaterialDescriptor,
+    mesh::LoadOptions,
+    quality::QualityGovernorConfig,
+    texture::TextureQuality,
+};
+
+// Everything an app has handed to the renderer that outlives a frame, for save systems. Holds no GL ids or caches, so
+// it can be written to disk and restored in another run. Model placement isn't in here, draw_model is immediate mode
+// and the app submits every model again each frame. Settings the quality governor turned down are stored as the app
+// set them
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RendererSnapshot {
+    pub models: Vec<ModelSnapshot>, // Sorted by path
+    pub sky: SkyConfig,
+    pub wind: WindConfig,
+    pub settings: RenderSettings,
+}
+
+// A loaded model. Handles aren't guaranteed to survive a restore, apps find their models again by tag
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ModelSnapshot {
+    pub tag: Option<String>,
+    pub path: PathBuf,
+    pub options: LoadOptions,
+    pub material_overrides: BTreeMap<String, MaterialDescriptor>, // Library materials reassigned to meshes, by mesh name
+}
+
+// How restore brings back a model of the snapshot
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ModelRestore {
+    Reuse(u64),  // Already loaded with the same options
+    Reload(u64), // Loaded from the same path, but with other options
+    Load,
+}
+
+impl ModelSnapshot {
+    // `loaded` is the path and options of each loaded model, by handle
+    pub(crate) fn restore_plan(&self, loaded: &HashMap<u64, (PathBuf, LoadOptions)>) -> ModelRestore {
+        match loaded.iter().find(|(_, (path, _))| *path == self.path) {
+            Some((&handle, (_, options))) if *options == self.options => ModelRestore::Reuse(handle),
+            Some((&handle, _)) => ModelRestore::Reload(handle),
+            None => ModelRestore::Load,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RenderSettings {
+    pub projection: Projection,
+    pub msaa_samples: i32,
+    pub taa: bool,
+    pub ssao: SsaoSettings,
+    pub occlusion_culling: bool,
+    pub lod: LodSettings,
+    pub parallax: ParallaxSettings,
+    pub texture_lod_bias: f32, // The user offset, without the render scale compensation
+    pub transparent_sort_epsilon: f32,
+    pub dynamic_resolution: DynamicResolution,
+    pub upscale: UpscaleSettings,
+    pub framebuffer_format: FramebufferFormat,
+    pub dithering: bool,
+    pub texture_quality: TextureQuality,
+    pub reflections: bool,
+    pub stereo: StereoConfig,
+    pub exposure: ExposureSettings,
+    pub motion_blur: MotionBlurSettings,
+    pub quality_governor: QualityGovernorConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_snapshot() -> RendererSnapshot {
+        RendererSnapshot {
+            models: vec![ModelSnapshot {
+                tag: Some(String::from("sponza")),
+                path: PathBuf::from("assets/models/sponza.gltf"),
+                options: LoadOptions {
+                    uniform_scale: 0.01,
+                    generate_lods: Some(vec![0.5, 0.25]),
+                    ..LoadOptions::default()
+                },
+                material_overrides: BTreeMap::from([(
+                    String::from("Curtain"),
+                    MaterialDescriptor {
+                        albedo: Some(PathBuf::from("assets/textures/red.png")),
+                        sort_bias: 2.0,
+                        ..MaterialDescriptor::default()
+                    },
+                )]),
+            }],
+            sky: SkyConfig::default(),
+            wind: WindConfig::default(),
+            settings: RenderSettings {
+                projection: Projection::default(),
+                msaa_samples: 4,
+                taa: true,
+                ssao: SsaoSettings::default(),
+                occlusion_culling: true,
+                lod: LodSettings::default(),
+                parallax: ParallaxSettings::default(),
+                texture_lod_bias: -0.5,
+                transparent_sort_epsilon: 0.01,
+                dynamic_resolution: DynamicResolution::default(),
+                upscale: UpscaleSettings::default(),
+                framebuffer_format: FramebufferFormat::Rgba16F,
+                dithering: true,
+                texture_quality: TextureQuality::default(),
+                reflections: false,
+                stereo: StereoConfig::default(),
+                exposure: ExposureSettings::default(),
+                motion_blur: MotionBlurSettings::default(),
+                quality_governor: QualityGovernorConfig::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn restore_plan_matches_models_by_path() {
+        let model = &sample_snapshot().models[0];
+        let other_path = (PathBuf::from("assets/models/cube.obj"), LoadOptions::default());
+        let mut loaded = HashMap::from([(1, other_path)]);
+        assert_eq!(model.restore_plan(&loaded), ModelRestore::Load);
+
+        loaded.insert(2, (model.path.clone(), LoadOptions::default()));
+        assert_eq!(model.restore_plan(&loaded), ModelRestore::Reload(2));
+
+        loaded.insert(2, (model.path.clone(), model.options.clone()));
+        assert_eq!(model.restore_plan(&loaded), ModelRestore::Reuse(2));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_round_trip() {
+        let snapshot = sample_snapshot();
+        let text = serde_json::to_string(&snapshot).unwrap();
+        let restored: RendererSnapshot = serde_json::from_str(&text).unwrap();
+        assert_eq!(restored, snapshot);
+    }
+
+    // Previous frame model matrices are per frame, restoring them in another run would draw a jump as motion
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_excludes_transform_history() {
+        let value = serde_json::to_value(sample_snapshot()).unwrap();
+        let mut keys: Vec<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["models", "settings", "sky", "wind"]);
+        let text = value.to_string();
+        assert!(!text.contains("transform") && !text.contains("matrix"));
+    }
+}
@@ -1,4 +1,4 @@
-use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use glam::{Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Vertex {
@@ -10,6 +10,14 @@ pub struct Vertex {
     pub uv1: Vec2,
 }
 
+// One end of a line in a line batch, every two vertices make a line. The colour is linear
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LineVertex {
+    pub position: Vec3,
+    pub colour: Vec4,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct FragIn {
     pub position: Vec4,
@@ -25,6 +33,36 @@ pub struct Transform {
     pub scale: Vec3,
 }
 
+// A point in an object's own space, before its transform is applied
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LocalPoint(pub Vec3);
+
+// A point in world space - mesh vertices are in this space after loading
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WorldPoint(pub Vec3);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// Six inward facing planes (xyz normal, w distance), a point p is inside when dot(plane.xyz, p) + plane.w >= 0
+// for every plane
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+#[derive(Debug, Copy, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct AABB {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
 impl FragIn {
 	#[allow(dead_code)]
     pub fn lerp(&self, rhs: FragIn, t: f32) -> FragIn {
@@ -38,27 +76,54 @@ impl FragIn {
     }
 }
 
+// Which world axis points up, both conventions are right-handed. Y-up matches glTF and OpenGL, Z-up matches many
+// level editors and physics engines. A renderer's convention is fixed when it's created. Convention dependent are the
+// Transform helpers up/forward/view_matrix, Camera movement, LoadOptions::root_matrix, the sky's zenith and the
+// height the wind measures. Model matrices, raycasts, decals and the sun and wind directions are plain world space
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WorldUp {
+    Y, // Identity rotations look along -Z
+    Z, // Identity rotations look along +Y
+}
+
+impl WorldUp {
+    pub fn up(self) -> Vec3 {
+        match self {
+            WorldUp::Y => Vec3::Y,
+            WorldUp::Z => Vec3::Z,
+        }
+    }
+
+    // Rotates Y-up directions and assets into this convention, the identity for Y-up
+    pub fn y_up_rotation(self) -> Quat {
+        match self {
+            WorldUp::Y => Quat::IDENTITY,
+            WorldUp::Z => Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+        }
+    }
+}
+
 impl Transform {
+    // The same in both conventions
     pub fn right(&self) -> Vec3 {
         self.rotation * Vec3::X
     }
 
 	#[allow(dead_code)]
-    pub fn up(&self) -> Vec3 {
-        self.rotation * Vec3::Y
+    pub fn up(&self, world_up: WorldUp) -> Vec3 {
+        self.rotation * world_up.up()
     }
 
-    pub fn forward(&self) -> Vec3 {
-        self.rotation * -Vec3::Z
+    pub fn forward(&self, world_up: WorldUp) -> Vec3 {
+        self.rotation * (world_up.y_up_rotation() * -Vec3::Z)
     }
-    pub fn view_matrix(&self) -> Mat4 {
+    pub fn view_matrix(&self, world_up: WorldUp) -> Mat4 {
         Mat4::look_at_rh(
             self.translation,
-            self.translation + self.forward(),
-            glam::vec3(0.0, 1.0, 0.0),
+            self.translation + self.forward(world_up),
+            world_up.up(),
         )
     }
-	#[allow(dead_code)]
     pub fn trans_matrix(&self) -> Mat4 {
         Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
     }
@@ -67,4 +132,201 @@ impl Transform {
             * Mat4::from_quat(self.rotation)
             * Mat4::from_scale(self.scale)
     }
+
+    #[allow(dead_code)]
+    pub fn to_world(&self, point: LocalPoint) -> WorldPoint {
+        WorldPoint(self.local_matrix().transform_point3(point.0))
+    }
+
+    #[allow(dead_code)]
+    pub fn to_local(&self, point: WorldPoint) -> LocalPoint {
+        LocalPoint(self.local_matrix().inverse().transform_point3(point.0))
+    }
+
+    #[allow(dead_code)]
+    pub fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        (normal_matrix(&self.local_matrix()) * normal).normalize_or_zero()
+    }
+}
+
+// Normals need the inverse-transpose of a transform, otherwise non-uniform scale bends them away from the surface
+pub fn normal_matrix(matrix: &Mat4) -> Mat3 {
+    Mat3::from_mat4(*matrix).inverse().transpose()
+}
+
+impl Rect {
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+}
+
+impl Frustum {
+    // Extracts the planes of a view projection matrix (Gribb and Hartmann). `clip_depth_range` is the clip space z of
+    // the near and far plane, in either order
+    pub fn from_matrix(matrix: &Mat4, clip_depth_range: (f32, f32)) -> Self {
+        let (row0, row1, row2, row3) = (matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3));
+        let (z_min, z_max) = (clip_depth_range.0.min(clip_depth_range.1), clip_depth_range.0.max(clip_depth_range.1));
+        let planes = [row3 + row0, row3 - row0, row3 + row1, row3 - row1, row2 - row3 * z_min, row3 * z_max - row2];
+        Frustum {
+            planes: planes.map(|plane| plane / plane.truncate().length().max(f32::EPSILON)),
+        }
+    }
+
+    // Conservative: boxes near a corner of the frustum can pass while being just outside it
+    pub fn intersects_aabb(&self, aabb: &AABB) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+impl AABB {
+    pub fn new() -> Self {
+        // Start inverted so the first grow() snaps the box to that point
+        AABB {
+            min: Vec3::splat(f32::INFINITY),
+            max: Vec3::splat(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn grow(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn grow_aabb(&mut self, other: &AABB) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    #[allow(dead_code)]
+    // The box's 12 edges as line batch vertices, for showing bounds with the line renderer
+    pub fn edge_lines(&self, colour: Vec4) -> Vec<LineVertex> {
+        // Bit 0 of a corner index picks max.x over min.x, bit 1 y and bit 2 z. Edges connect corners one bit apart
+        let corner = |index: usize| Vec3::new(
+            if index & 1 != 0 { self.max.x } else { self.min.x },
+            if index & 2 != 0 { self.max.y } else { self.min.y },
+            if index & 4 != 0 { self.max.z } else { self.min.z },
+        );
+        let mut lines = Vec::with_capacity(24);
+        for index in 0..8 {
+            for bit in [1, 2, 4] {
+                if index & bit == 0 {
+                    lines.push(LineVertex { position: corner(index), colour });
+                    lines.push(LineVertex { position: corner(index | bit), colour });
+                }
+            }
+        }
+        lines
+    }
+
+    pub fn translated(&self, offset: Vec3) -> AABB {
+        if self.is_empty() {
+            return *self;
+        }
+        AABB {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    pub fn transformed(&self, matrix: &Mat4) -> AABB {
+        // An empty box stays empty, no matter the transform
+        if self.is_empty() {
+            return *self;
+        }
+
+        // Transform all 8 corners and fit a new box around them - conservative, but always correct
+        let mut aabb_out = AABB::new();
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            aabb_out.grow(matrix.transform_point3(corner));
+        }
+        aabb_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stretched_transform() -> Transform {
+        Transform {
+            translation: Vec3::new(1.0, 2.0, 3.0),
+            rotation: Quat::from_rotation_z(0.5),
+            scale: Vec3::new(4.0, 1.0, 0.5),
+        }
+    }
+
+    #[test]
+    fn local_world_round_trip() {
+        let transform = stretched_transform();
+        let point = LocalPoint(Vec3::new(-1.0, 0.5, 2.0));
+        let world = transform.to_world(point);
+        assert!(world.0.abs_diff_eq(transform.local_matrix().transform_point3(point.0), 1e-5));
+        assert!(transform.to_local(world).0.abs_diff_eq(point.0, 1e-5));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        // A 45 degree slope, squashed along x. Transforming its normal like a point would tilt it off the surface
+        let transform = stretched_transform();
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let along_surface = Vec3::new(1.0, -1.0, 0.0);
+        let world_surface = transform.local_matrix().transform_vector3(along_surface);
+        let world_normal = transform.normal_to_world(normal);
+        assert!(world_normal.dot(world_surface).abs() < 1e-5);
+        assert!((world_normal.length() - 1.0).abs() < 1e-5);
+        assert!(transform.local_matrix().transform_vector3(normal).dot(world_surface).abs() > 0.1);
+    }
+
+    #[test]
+    fn normal_matrix_of_a_rotation_is_the_rotation() {
+        let matrix = Mat4::from_rotation_translation(Quat::from_rotation_y(1.0), Vec3::splat(5.0));
+        assert!(normal_matrix(&matrix).abs_diff_eq(Mat3::from_rotation_y(1.0), 1e-5));
+    }
+
+    #[test]
+    fn aabb_transformed_rotated_45_degrees() {
+        let mut aabb = AABB::new();
+        aabb.grow(Vec3::new(-1.0, -1.0, -1.0));
+        aabb.grow(Vec3::new(1.0, 1.0, 1.0));
+        let matrix = Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)) * Mat4::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        let rotated = aabb.transformed(&matrix);
+
+        // The corners swing out to sqrt(2) along x and z, y is untouched
+        let reach = std::f32::consts::SQRT_2;
+        assert!(rotated.min.abs_diff_eq(Vec3::new(10.0 - reach, -1.0, -reach), 1e-5));
+        assert!(rotated.max.abs_diff_eq(Vec3::new(10.0 + reach, 1.0, reach), 1e-5));
+    }
+
+    #[test]
+    fn aabb_transformed_empty_stays_empty() {
+        let matrix = Mat4::from_scale_rotation_translation(Vec3::splat(2.0), Quat::from_rotation_x(1.0), Vec3::ONE);
+        assert!(AABB::new().transformed(&matrix).is_empty());
+    }
+
+    #[test]
+    fn aabb_grow_aabb() {
+        let mut aabb = AABB::new();
+        aabb.grow(Vec3::ZERO);
+        let mut other = AABB::new();
+        other.grow(Vec3::new(-1.0, 2.0, 0.5));
+        aabb.grow_aabb(&other);
+        assert_eq!(aabb.min, Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(aabb.max, Vec3::new(0.0, 2.0, 0.5));
+    }
 }
@@ -0,0 +1,265 @@
+use std::{ffi::c_void, mem::size_of};
+
+use glam::{Vec2, Vec4};
+
+use crate::gpu_buffer::GpuBuffer;
+use crate::texture::{TextureBinder, TextureSlot};
+
+// Size of a glyph in pixels at scale 1
+pub const GLYPH_SIZE: f32 = 8.0;
+
+const FIRST_CHARACTER: char = ' ';
+const UNKNOWN_GLYPH: usize = 95;
+const ATLAS_COLUMNS: usize = 16;
+const ATLAS_ROWS: usize = 6;
+const FLOATS_PER_VERTEX: usize = 8; // Position, texcoord, colour
+
+// Printable ASCII, one byte per row with the leftmost pixel in the lowest bit. Based on the public domain font8x8
+// by Daniel Hepper. The last glyph is a box, which is drawn for every other character
+#[rustfmt::skip]
+const FONT: [[u8; 8]; 96] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // '#'
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // '%'
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '''
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // '('
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // '0'
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // '1'
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // '2'
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // '3'
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // '4'
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // '5'
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // '6'
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // '7'
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // '8'
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // '9'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // ':'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ';'
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // '='
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // '>'
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // '?'
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // '@'
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // 'A'
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // 'B'
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // 'C'
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // 'D'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // 'E'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // 'F'
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // 'L'
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // 'O'
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // 'P'
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // 'Q'
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // 'S'
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // 'Y'
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // 'Z'
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // '['
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // '\'
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ']'
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // '_'
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // 'b'
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], // 'd'
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], // 'e'
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], // 'f'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'g'
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // 'k'
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // 'o'
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // 'p'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // 'r'
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // 's'
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'y'
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // 'z'
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // '}'
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+    [0x7E, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7E, 0x00], // Unknown
+];
+
+// Batches screen space text into one vertex buffer, drawn on top of the presented frame
+pub struct TextOverlay {
+    texture: u32,
+    vao: u32,
+    vbo: GpuBuffer<f32>,
+    vertices: Vec<f32>,
+}
+
+impl TextOverlay {
+    pub fn new() -> Self {
+        let mut overlay = TextOverlay {
+            texture: 0,
+            vao: 0,
+            vbo: GpuBuffer::new(gl::ARRAY_BUFFER, gl::STREAM_DRAW),
+            vertices: Vec::new(),
+        };
+        overlay.create_gl_resources();
+        overlay
+    }
+
+    fn create_gl_resources(&mut self) {
+        // Unpack the font into a 16x6 glyph atlas, one byte per pixel
+        let atlas_width = ATLAS_COLUMNS * 8;
+        let atlas_height = ATLAS_ROWS * 8;
+        let mut pixels = vec![0u8; atlas_width * atlas_height];
+        for (glyph, rows) in FONT.iter().enumerate() {
+            let (column, row) = (glyph % ATLAS_COLUMNS, glyph / ATLAS_COLUMNS);
+            for (y, bits) in rows.iter().enumerate() {
+                for x in 0..8 {
+                    if bits >> x & 1 != 0 {
+                        pixels[(row * 8 + y) * atlas_width + column * 8 + x] = 255;
+                    }
+                }
+            }
+        }
+
+        unsafe {
+            gl::GenTextures(1, &mut self.texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R8 as _, atlas_width as i32, atlas_height as i32, 0, gl::RED, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const c_void);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            let stride = (FLOATS_PER_VERTEX * size_of::<f32>()) as i32;
+            gl::GenVertexArrays(1, &mut self.vao);
+            gl::BindVertexArray(self.vao);
+            self.vbo.upload(&[]);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, std::ptr::null::<c_void>());
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (2 * size_of::<f32>()) as _);
+            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, (4 * size_of::<f32>()) as _);
+            gl::EnableVertexAttribArray(0);
+            gl::EnableVertexAttribArray(1);
+            gl::EnableVertexAttribArray(2);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+    }
+
+    // Adds a quad per visible glyph, with the top left corner of the first glyph at `position` in pixels.
+    // Glyphs that are entirely off screen are skipped
+    pub fn queue(&mut self, position: Vec2, scale: f32, colour: Vec4, text: &str, screen_size: Vec2) {
+        let size = GLYPH_SIZE * scale;
+        let mut cursor = position;
+        for character in text.chars() {
+            if character == '\n' {
+                cursor = Vec2::new(position.x, cursor.y + size);
+                continue;
+            }
+            let min = cursor;
+            let max = cursor + size;
+            cursor.x += size;
+            if character == ' ' || max.x <= 0.0 || max.y <= 0.0 || min.x >= screen_size.x || min.y >= screen_size.y {
+                continue;
+            }
+
+            let glyph = match character {
+                '!'..='~' => character as usize - FIRST_CHARACTER as usize,
+                _ => UNKNOWN_GLYPH,
+            };
+            let uv_min = Vec2::new(
+                (glyph % ATLAS_COLUMNS) as f32 / ATLAS_COLUMNS as f32,
+                (glyph / ATLAS_COLUMNS) as f32 / ATLAS_ROWS as f32,
+            );
+            let uv_max = uv_min + Vec2::new(1.0 / ATLAS_COLUMNS as f32, 1.0 / ATLAS_ROWS as f32);
+            let corners = [
+                (min.x, min.y, uv_min.x, uv_min.y),
+                (min.x, max.y, uv_min.x, uv_max.y),
+                (max.x, max.y, uv_max.x, uv_max.y),
+                (min.x, min.y, uv_min.x, uv_min.y),
+                (max.x, max.y, uv_max.x, uv_max.y),
+                (max.x, min.y, uv_max.x, uv_min.y),
+            ];
+            for (x, y, u, v) in corners {
+                self.vertices.extend_from_slice(&[x, y, u, v, colour.x, colour.y, colour.z, colour.w]);
+            }
+        }
+    }
+
+    // Draws and then clears everything queued this frame into the currently bound framebuffer
+    pub fn draw(&mut self, shader: u32, screen_size: Vec2) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            self.vbo.upload(&self.vertices);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::UseProgram(shader);
+            gl::Uniform2f(gl::GetUniformLocation(shader, c"u_screen_size".as_ptr()), screen_size.x, screen_size.y);
+            TextureBinder::bind(TextureSlot::Albedo, self.texture as i32);
+            gl::DrawArrays(gl::TRIANGLES, 0, (self.vertices.len() / FLOATS_PER_VERTEX) as i32);
+            TextureBinder::bind(TextureSlot::Albedo, 0);
+            gl::Disable(gl::BLEND);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+        self.vertices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    // Drops the queued text without drawing it
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn recreate_gl_resources(&mut self) {
+        self.delete();
+        self.create_gl_resources();
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+        self.vbo.delete();
+    }
+}
@@ -1,6 +1,10 @@
 #![allow(dead_code)]
 use crate::helpers::*;
-use std::path::Path;
+use gl::types::GLenum;
+use glam::Vec4;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{ffi::CStr, path::Path};
 
 pub struct Texture {
     pub gl_id: u32,
@@ -10,6 +14,197 @@ pub struct Texture {
     pub data: Vec<u32>,
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct TextureStreamingConfig {
+    pub enabled: bool,
+    pub bytes_per_frame: usize,
+}
+
+// Caps the size material textures are kept at. Larger images are box filtered down by powers of two while they're
+// decoded, before anything gets uploaded. Applies to textures loaded after it's set
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TextureQuality {
+    pub max_dimension: u32, // Largest width or height, in pixels
+}
+
+impl Default for TextureQuality {
+    fn default() -> Self {
+        // Larger than any texture size GL guarantees, so nothing gets scaled down
+        TextureQuality { max_dimension: 16384 }
+    }
+}
+
+// Fixed texture unit per semantic slot, so every shader can rely on the same numbering.
+// Fullscreen passes read their colour input from the albedo unit
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureSlot {
+    Albedo = 0,
+    Normal = 1,
+    MetallicRoughness = 2,
+    Emissive = 3,
+    Atlas = 4,
+    Shadow = 5,
+    SceneDepth = 6,
+    Noise = 7,
+    History = 8,
+    Velocity = 9,
+    Height = 10,
+    AlbedoArray = 11, // The array backend's material textures, next to the 2D ones since a unit can't serve both
+    MetallicRoughnessArray = 12,
+    HeightArray = 13,
+    Exposure = 14,
+    SceneColour = 15,
+    PreviousVelocity = 16,
+}
+
+// A texture that couldn't be loaded and got replaced by the placeholder
+#[derive(Debug, Clone)]
+pub struct MissingTexture {
+    pub material: String,
+    pub slot: TextureSlot,
+    pub reason: String,
+}
+
+// Owns the ActiveTexture/BindTexture pairs, so textures always end up on the unit their sampler reads from
+pub struct TextureBinder;
+
+impl TextureBinder {
+    // Binds a texture to a slot, negative ids (no texture) unbind it
+    pub fn bind(slot: TextureSlot, gl_id: i32) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + slot as u32);
+            gl::BindTexture(gl::TEXTURE_2D, gl_id.max(0) as u32);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    // Points a sampler uniform at a slot's texture unit, only needs to happen once per program
+    pub fn assign_sampler(program: u32, sampler_name: &CStr, slot: TextureSlot) {
+        unsafe {
+            let location = gl::GetUniformLocation(program, sampler_name.as_ptr());
+            if location == -1 {
+                return;
+            }
+            gl::UseProgram(program);
+            gl::Uniform1i(location, slot as i32);
+            gl::UseProgram(0);
+        }
+    }
+}
+
+// A 2D texture the renderer draws into, its storage gets (re)created by resize and the GL object is deleted on drop.
+// Like GpuBuffer, it must be dropped while its context is still current
+pub struct GpuTexture {
+    id: u32,
+    width: i32,
+    height: i32,
+    internal_format: GLenum,
+    format: GLenum,
+    component_type: GLenum,
+    filter: GLenum,
+}
+
+impl GpuTexture {
+    pub fn new(internal_format: GLenum, format: GLenum, component_type: GLenum) -> Self {
+        GpuTexture {
+            id: 0,
+            width: 0,
+            height: 0,
+            internal_format,
+            format,
+            component_type,
+            filter: gl::NEAREST,
+        }
+    }
+
+    // Filtered textures can be sampled between pixels, for upscaling or reprojection
+    pub fn with_filter(mut self, filter: GLenum) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn size(&self) -> [i32; 2] {
+        [self.width, self.height]
+    }
+
+    pub fn byte_size(&self) -> usize {
+        let bytes_per_pixel = match self.internal_format {
+            gl::R8 => 1,
+            gl::RG16F | gl::R11F_G11F_B10F | gl::DEPTH24_STENCIL8 => 4,
+            gl::RGBA16F | gl::DEPTH32F_STENCIL8 => 8,
+            gl::RGBA32F | gl::RGBA32UI => 16,
+            _ => 4,
+        };
+        self.width.max(0) as usize * self.height.max(0) as usize * bytes_per_pixel
+    }
+
+    // Takes effect on the next resize
+    pub fn set_format(&mut self, internal_format: GLenum, format: GLenum, component_type: GLenum) {
+        self.internal_format = internal_format;
+        self.format = format;
+        self.component_type = component_type;
+    }
+
+    // Reallocates the storage, discarding the contents. Framebuffers keep the texture attached
+    pub fn resize(&mut self, width: i32, height: i32) {
+        unsafe {
+            if self.id == 0 {
+                gl::GenTextures(1, &mut self.id);
+            }
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                self.internal_format as i32,
+                width,
+                height,
+                0,
+                self.format,
+                self.component_type,
+                std::ptr::null(),
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, self.filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, self.filter as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn bind(&self, slot: TextureSlot) {
+        TextureBinder::bind(slot, self.id as i32);
+    }
+
+    // Attaches the texture to the currently bound framebuffer
+    pub fn attach(&self, attachment: GLenum) {
+        unsafe {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, gl::TEXTURE_2D, self.id, 0);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.id != 0 {
+            unsafe {
+                gl::DeleteTextures(1, &self.id);
+            }
+        }
+        self.id = 0;
+        self.width = 0;
+        self.height = 0;
+    }
+}
+
+impl Drop for GpuTexture {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
+
 #[derive(PartialEq)]
 pub enum FilterMode {
     Point,
@@ -41,57 +236,172 @@ enum PixelComp {
 }
 
 impl Texture {
-    pub fn load(path: &Path) -> Self {
+    // Decodes an image file, scaled down to fit within max_dimension
+    #[cfg(feature = "gltf-loader")]
+    pub fn load(path: &Path, max_dimension: usize) -> Result<Self, String> {
         //Load image
         let loaded_image = stb_image::image::load(path);
 
-        //Map the image data to argb8 format
+        //Map the image data to the Pixel32 layout
         if let stb_image::image::LoadResult::ImageU8(image) = loaded_image {
-            if image.depth == 4 {
-                let data = (0..image.data.len() / 4)
-                    .map(|id| {
-                        colour_rgba(
-                            image.data[id * 4 + 3],
-                            image.data[id * 4],
-                            image.data[id * 4 + 1],
-                            image.data[id * 4 + 2],
-                        )
-                    })
-                    .collect();
-                Self {
-                    gl_id: 0,
-                    width: image.width,
-                    height: image.height,
-                    depth: image.depth,
-                    data,
+            let (width, height, depth) = (image.width, image.height, image.depth);
+            let data = &image.data;
+            match depth {
+                4 => Ok(Self::from_pixels(width, height, max_dimension, |id| {
+                    Pixel32::new(data[id * 4], data[id * 4 + 1], data[id * 4 + 2], data[id * 4 + 3])
+                })),
+                3 => Ok(Self::from_pixels(width, height, max_dimension, |id| {
+                    Pixel32::new(data[id * 3], data[id * 3 + 1], data[id * 3 + 2], 255)
+                })),
+                // Greyscale, with or without alpha
+                1 | 2 => Ok(Self::from_pixels(width, height, max_dimension, |id| {
+                    let grey = data[id * depth];
+                    let alpha = if depth == 2 { data[id * 2 + 1] } else { 255 };
+                    Pixel32::new(grey, grey, grey, alpha)
+                })),
+                _ => Err(format!("unsupported channel count {depth}")),
+            }
+            .map(|texture| Texture { depth, ..texture })
+        } else if let stb_image::image::LoadResult::Error(error) = loaded_image {
+            Err(error)
+        } else {
+            Err("floating point images are not supported".to_string())
+        }
+    }
+
+    #[cfg(not(feature = "gltf-loader"))]
+    pub fn load(path: &Path, _max_dimension: usize) -> Result<Self, String> {
+        Err(format!("can't decode \"{}\", image loading needs the gltf-loader feature", path.display()))
+    }
+
+    // Procedural checkerboard, cell_size pixels per square
+    pub fn checkerboard(size: usize, cell_size: usize, colour_a: Pixel32, colour_b: Pixel32) -> Texture {
+        let data = (0..size * size)
+            .map(|index| {
+                let (x, y) = (index % size / cell_size, index / size / cell_size);
+                if (x + y) % 2 == 0 { colour_a.to_u32() } else { colour_b.to_u32() }
+            })
+            .collect();
+        Texture {
+            gl_id: 0,
+            width: size,
+            height: size,
+            depth: 4,
+            data,
+        }
+    }
+
+    pub fn solid(colour: Pixel32) -> Texture {
+        Texture {
+            gl_id: 0,
+            width: 1,
+            height: 1,
+            depth: 4,
+            data: vec![colour.to_u32()],
+        }
+    }
+
+    // Builds a texture from decoded pixels, box filtered down by the power of two that fits it within max_dimension.
+    // Each output row is averaged straight from the decoded image, so a scaled down image never exists at full size
+    // as a texture
+    pub fn from_pixels(width: usize, height: usize, max_dimension: usize, pixel: impl Fn(usize) -> Pixel32) -> Texture {
+        let max_dimension = max_dimension.max(1);
+        let mut factor = 1;
+        while (width / factor).max(1) > max_dimension || (height / factor).max(1) > max_dimension {
+            factor *= 2;
+        }
+        let (new_width, new_height) = ((width / factor).max(1), (height / factor).max(1));
+        let mut data = Vec::with_capacity(new_width * new_height);
+        if factor == 1 {
+            data.extend((0..width * height).map(|index| pixel(index).to_u32()));
+        } else {
+            // Blocks at the edges of images smaller than the factor only cover what's there
+            let block_width = factor.min(width);
+            let mut row = vec![Vec4::ZERO; new_width];
+            for y in 0..new_height {
+                row.fill(Vec4::ZERO);
+                let source_rows = y * factor..((y + 1) * factor).min(height);
+                let block_size = (source_rows.len() * block_width) as f32;
+                for source_y in source_rows {
+                    for source_x in 0..new_width * block_width {
+                        row[source_x / block_width] += pixel(coords_to_index(source_x, source_y, width)).to_vec4();
+                    }
                 }
-            } else if image.depth == 3 {
-                let data = (0..image.data.len() / 3)
-                    .map(|id| {
-                        colour_rgba(
-                            255,
-                            image.data[id * 3],
-                            image.data[id * 3 + 1],
-                            image.data[id * 3 + 2],
-                        )
-                    })
-                    .collect();
-                Self {
-                    gl_id: 0,
-                    width: image.width,
-                    height: image.height,
-                    depth: image.depth,
-                    data,
+                data.extend(row.iter().map(|sum| Pixel32::from_vec4(*sum / block_size, false).to_u32()));
+            }
+        }
+        Texture {
+            gl_id: 0,
+            width: new_width,
+            height: new_height,
+            depth: 4,
+            data,
+        }
+    }
+
+    // Halves the texture with a 2x2 box filter until it fits within max_dimension
+    pub fn downsampled(&self, max_dimension: usize) -> Texture {
+        let mut width = self.width;
+        let mut height = self.height;
+        let mut data = self.data.clone();
+        while width > max_dimension || height > max_dimension {
+            let new_width = (width / 2).max(1);
+            let new_height = (height / 2).max(1);
+            let mut new_data = Vec::with_capacity(new_width * new_height);
+            for y in 0..new_height {
+                for x in 0..new_width {
+                    // Clamp to the edge for odd sizes
+                    let x0 = (x * 2).min(width - 1);
+                    let x1 = (x * 2 + 1).min(width - 1);
+                    let y0 = (y * 2).min(height - 1);
+                    let y1 = (y * 2 + 1).min(height - 1);
+                    let pixels = [
+                        data[coords_to_index(x0, y0, width)],
+                        data[coords_to_index(x1, y0, width)],
+                        data[coords_to_index(x0, y1, width)],
+                        data[coords_to_index(x1, y1, width)],
+                    ];
+
+                    // Average each channel separately
+                    let sum: glam::Vec4 = pixels.iter().map(|pixel| Pixel32::from_u32(*pixel).to_vec4()).sum();
+                    new_data.push(Pixel32::from_vec4(sum / 4.0, false).to_u32());
                 }
-            } else {
-                panic!("Unsupported texture type");
             }
-        } else {
-            panic!("Unsupported texture type");
+            width = new_width;
+            height = new_height;
+            data = new_data;
+        }
+        Texture {
+            gl_id: 0,
+            width,
+            height,
+            depth: self.depth,
+            data,
+        }
+    }
+
+    // Scales the texture to any size by picking the nearest pixel, so nearest filtered sampling of the result looks
+    // like sampling the original
+    pub fn resized_nearest(&self, width: usize, height: usize) -> Texture {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let source_y = (y * self.height / height).min(self.height - 1);
+            for x in 0..width {
+                let source_x = (x * self.width / width).min(self.width - 1);
+                data.push(self.data[coords_to_index(source_x, source_y, self.width)]);
+            }
+        }
+        Texture {
+            gl_id: 0,
+            width,
+            height,
+            depth: self.depth,
+            data,
         }
     }
 
-    pub fn load_texture_from_gltf_image(image: &gltf::image::Data) -> Texture {
+    #[cfg(feature = "gltf-loader")]
+    pub fn load_texture_from_gltf_image(image: &gltf::image::Data, max_dimension: usize) -> Result<Texture, String> {
         // Get pixel swizzle pattern
         let swizzle_pattern = match image.format {
             gltf::image::Format::R8 => vec![PixelComp::Red],
@@ -128,41 +438,45 @@ impl Texture {
                 PixelComp::Skip,
                 PixelComp::Alpha,
             ],
-            _ => panic!("Texture format unsupported!"),
+            format => return Err(format!("unsupported image format {:?}", format)),
         };
-        Texture {
-            gl_id: 0,
-            width: image.width as usize,
-            height: image.height as usize,
-            depth: 4,
-            data: {
-                let mut data = Vec::<u32>::new();
-                for i in (0..image.pixels.len()).step_by(swizzle_pattern.len()) {
-                    let mut new_pixel = 0xFFFFFFFFu32;
-                    for (comp, entry) in swizzle_pattern.iter().enumerate() {
-                        match entry {
-                            PixelComp::Skip => {}
-                            PixelComp::Red => {
-                                new_pixel = new_pixel & 0xFFFFFF00 | image.pixels[i + comp] as u32
-                            }
-                            PixelComp::Green => {
-                                new_pixel =
-                                    new_pixel & 0xFFFF00FF | (image.pixels[i + comp] as u32) << 8
-                            }
-                            PixelComp::Blue => {
-                                new_pixel =
-                                    new_pixel & 0xFF00FFFF | (image.pixels[i + comp] as u32) << 16
-                            }
-                            PixelComp::Alpha => {
-                                new_pixel =
-                                    new_pixel & 0x00FFFFFF | (image.pixels[i + comp] as u32) << 24
-                            }
-                        }
-                    }
-                    data.push(new_pixel);
+        let stride = swizzle_pattern.len();
+        Ok(Self::from_pixels(image.width as usize, image.height as usize, max_dimension, |id| {
+            let mut new_pixel = Pixel32::new(255, 255, 255, 255);
+            for (comp, entry) in swizzle_pattern.iter().enumerate() {
+                match entry {
+                    PixelComp::Skip => {}
+                    PixelComp::Red => new_pixel.r = image.pixels[id * stride + comp],
+                    PixelComp::Green => new_pixel.g = image.pixels[id * stride + comp],
+                    PixelComp::Blue => new_pixel.b = image.pixels[id * stride + comp],
+                    PixelComp::Alpha => new_pixel.a = image.pixels[id * stride + comp],
                 }
-                data
-            },
-        }
+            }
+            new_pixel
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_texture_byte_size_by_format() {
+        let sized = |internal_format| {
+            let mut texture = GpuTexture::new(internal_format, gl::RGBA, gl::FLOAT);
+            assert_eq!(texture.byte_size(), 0);
+            (texture.width, texture.height) = (8, 4);
+            let byte_size = texture.byte_size();
+
+            // Never created, so deleting doesn't need a context
+            texture.delete();
+            assert_eq!(texture.size(), [0, 0]);
+            byte_size
+        };
+        assert_eq!(sized(gl::R8), 32);
+        assert_eq!(sized(gl::R11F_G11F_B10F), 128);
+        assert_eq!(sized(gl::RGBA16F), 256);
+        assert_eq!(sized(gl::RGBA32F), 512);
     }
 }
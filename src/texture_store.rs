@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use crate::texture::{Texture, TextureBinder, TextureSlot};
+
+// Layer sizes of the array backend. Textures get rescaled to the smallest class that holds their larger side, bigger
+// textures are scaled down into the last one
+pub const SIZE_CLASSES: [usize; 4] = [256, 512, 1024, 2048];
+// Layers an array starts with once its first texture arrives, it doubles whenever it runs out
+const INITIAL_LAYERS: usize = 8;
+
+// Which kind of GL texture material textures live in, picked when the renderer is created
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TextureBackend {
+    #[default]
+    Individual, // One 2D texture per image, at its own size
+    ArrayPerSize, // One layer of a 2D texture array per image, an array per size class
+}
+
+// Owns the material textures. Materials, the loaders and streaming only see the ids it hands out, which are never 0
+// or negative, so they don't care which backend is active
+pub trait TextureStore {
+    // Reserves a texture for an image of this size, it has no contents until the first update
+    fn create(&mut self, width: usize, height: usize) -> u32;
+    // Replaces the contents, the image doesn't have to be the size the texture was created for
+    fn update(&mut self, id: u32, texture: &Texture);
+    fn delete(&mut self, id: u32);
+    // Binds a texture to a slot, negative ids unbind it. Returns the layer the shader has to sample
+    fn bind(&self, slot: TextureSlot, id: i32) -> i32;
+    // GPU memory allocated for textures, mip chains included
+    fn memory_bytes(&self) -> usize;
+}
+
+pub fn new_store(backend: TextureBackend) -> Box<dyn TextureStore> {
+    match backend {
+        TextureBackend::Individual => Box::new(IndividualTextures { bytes: HashMap::new() }),
+        TextureBackend::ArrayPerSize => Box::new(ArrayTextures {
+            arrays: SIZE_CLASSES.map(TextureArray::new),
+            textures: HashMap::new(),
+            next_id: 1,
+        }),
+    }
+}
+
+// Uploads an image into a 2D texture and builds its mip chain
+pub fn upload_texture_2d(gl_id: u32, texture: &Texture) {
+    unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, gl_id);
+        gl::TexImage2D(gl::TEXTURE_2D, 0,  gl::RGBA8 as i32, texture.width as i32, texture.height as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE, texture.data.as_ptr()  as *const _);
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    }
+}
+
+// Bytes of an RGBA8 image with its full mip chain
+fn mip_chain_bytes(width: usize, height: usize) -> usize {
+    let (mut width, mut height, mut bytes) = (width.max(1), height.max(1), 0);
+    loop {
+        bytes += width * height * 4;
+        if width == 1 && height == 1 {
+            return bytes;
+        }
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+    }
+}
+
+struct IndividualTextures {
+    bytes: HashMap<u32, usize>,
+}
+
+impl TextureStore for IndividualTextures {
+    fn create(&mut self, _width: usize, _height: usize) -> u32 {
+        let mut gl_id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut gl_id);
+        }
+        self.bytes.insert(gl_id, 0);
+        gl_id
+    }
+
+    fn update(&mut self, id: u32, texture: &Texture) {
+        upload_texture_2d(id, texture);
+        self.bytes.insert(id, mip_chain_bytes(texture.width, texture.height));
+    }
+
+    fn delete(&mut self, id: u32) {
+        if self.bytes.remove(&id).is_some() {
+            unsafe {
+                gl::DeleteTextures(1, &id);
+            }
+        }
+    }
+
+    fn bind(&self, slot: TextureSlot, id: i32) -> i32 {
+        TextureBinder::bind(slot, id);
+        0
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.bytes.values().sum()
+    }
+}
+
+// The square layers of one size class. Its GL texture is created when the first texture of the class arrives
+struct TextureArray {
+    gl_id: u32,
+    size: usize,
+    layers: usize,     // Allocated
+    used: usize,       // Layers below this have been handed out at some point
+    free: Vec<usize>,  // Handed out and deleted again
+}
+
+impl TextureArray {
+    fn new(size: usize) -> Self {
+        TextureArray {
+            gl_id: 0,
+            size,
+            layers: 0,
+            used: 0,
+            free: Vec::new(),
+        }
+    }
+
+    fn mip_levels(&self) -> i32 {
+        self.size.ilog2() as i32 + 1
+    }
+
+    fn allocate(&mut self) -> usize {
+        if let Some(layer) = self.free.pop() {
+            return layer;
+        }
+        if self.used == self.layers {
+            self.grow((self.layers * 2).max(INITIAL_LAYERS));
+        }
+        self.used += 1;
+        self.used - 1
+    }
+
+    // Moves every layer into a bigger array. Layers can't be added to a GL texture array, and image copies need GL 4.3,
+    // so the old contents make a round trip through memory. Only happens when the class doubles
+    fn grow(&mut self, layers: usize) {
+        let mut new_id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut new_id);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, new_id);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAX_LEVEL, self.mip_levels() - 1);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            for level in 0..self.mip_levels() {
+                let size = (self.size >> level).max(1) as i32;
+                gl::TexImage3D(gl::TEXTURE_2D_ARRAY, level, gl::RGBA8 as i32, size, size, layers as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE, std::ptr::null());
+                if self.gl_id == 0 {
+                    continue;
+                }
+                let mut pixels = vec![0u32; (size * size) as usize * self.layers];
+                gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.gl_id);
+                gl::GetTexImage(gl::TEXTURE_2D_ARRAY, level, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+                gl::BindTexture(gl::TEXTURE_2D_ARRAY, new_id);
+                gl::TexSubImage3D(gl::TEXTURE_2D_ARRAY, level, 0, 0, 0, size, size, self.layers as i32, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const _);
+            }
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+            if self.gl_id != 0 {
+                gl::DeleteTextures(1, &self.gl_id);
+            }
+        }
+        self.gl_id = new_id;
+        self.layers = layers;
+    }
+
+    // Fills one layer, the mip chain is built per layer so neighbouring layers never bleed into it
+    fn upload(&self, layer: usize, texture: &Texture) {
+        let mut level_texture = match texture.width == self.size && texture.height == self.size {
+            true => None,
+            false => Some(texture.resized_nearest(self.size, self.size)),
+        };
+        for level in 0..self.mip_levels() {
+            let image = level_texture.as_ref().unwrap_or(texture);
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.gl_id);
+                gl::TexSubImage3D(gl::TEXTURE_2D_ARRAY, level, 0, 0, layer as i32, image.width as i32, image.height as i32, 1, gl::RGBA, gl::UNSIGNED_BYTE, image.data.as_ptr() as *const _);
+                gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+            }
+            level_texture = Some(image.downsampled((image.width / 2).max(1)));
+        }
+    }
+
+    fn memory_bytes(&self) -> usize {
+        mip_chain_bytes(self.size, self.size) * self.layers
+    }
+}
+
+struct ArrayTextures {
+    arrays: [TextureArray; SIZE_CLASSES.len()],
+    textures: HashMap<u32, (usize, usize)>, // Size class and layer of each id
+    next_id: u32,
+}
+
+impl TextureStore for ArrayTextures {
+    fn create(&mut self, width: usize, height: usize) -> u32 {
+        let size = width.max(height);
+        let class = SIZE_CLASSES.iter().position(|&class_size| class_size >= size).unwrap_or(SIZE_CLASSES.len() - 1);
+        let layer = self.arrays[class].allocate();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.textures.insert(id, (class, layer));
+        id
+    }
+
+    fn update(&mut self, id: u32, texture: &Texture) {
+        if let Some(&(class, layer)) = self.textures.get(&id) {
+            self.arrays[class].upload(layer, texture);
+        }
+    }
+
+    fn delete(&mut self, id: u32) {
+        if let Some((class, layer)) = self.textures.remove(&id) {
+            self.arrays[class].free.push(layer);
+        }
+    }
+
+    fn bind(&self, slot: TextureSlot, id: i32) -> i32 {
+        let array_slot = match slot {
+            TextureSlot::MetallicRoughness => TextureSlot::MetallicRoughnessArray,
+            TextureSlot::Height => TextureSlot::HeightArray,
+            _ => TextureSlot::AlbedoArray,
+        };
+        let (gl_id, layer) = match self.textures.get(&(id.max(0) as u32)) {
+            Some(&(class, layer)) => (self.arrays[class].gl_id, layer as i32),
+            None => (0, 0),
+        };
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + array_slot as u32);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, gl_id);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+        layer
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.arrays.iter().map(TextureArray::memory_bytes).sum()
+    }
+}
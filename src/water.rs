@@ -0,0 +1,75 @@
+use std::f32::consts::TAU;
+
+use glam::{vec3, vec4, Mat4, Vec2, Vec3};
+
+use crate::{helpers::Pixel32, random, texture::Texture};
+
+// Size of the generated ripple normal map, it tiles
+pub const NORMAL_MAP_SIZE: usize = 256;
+
+// Looks of a water plane
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WaterConfig {
+    pub wave_speed: f32,   // Normal map tiles the ripples scroll per second
+    pub wave_scale: f32,   // World units one tile of the normal map covers
+    pub tint: Vec3,        // Colour of deep water, linear
+    pub reflectivity: f32, // Multiplies the Fresnel term, 0 only shows what's below the surface
+}
+
+impl Default for WaterConfig {
+    fn default() -> Self {
+        WaterConfig {
+            wave_speed: 0.03,
+            wave_scale: 4.0,
+            tint: vec3(0.02, 0.12, 0.14),
+            reflectivity: 1.0,
+        }
+    }
+}
+
+// Identifies a water plane added with add_water_plane
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WaterHandle(pub(crate) u32);
+
+// A rectangle spanning the local X and Y axes of its transform, facing local +Z like a decal
+pub(crate) struct WaterPlane {
+    pub matrix: Mat4, // Unit square to world space
+    pub extents: Vec2,
+    pub config: WaterConfig,
+}
+
+// Tiling ripple normals, as a sum of sine waves that all repeat a whole number of times across the map. Two copies of
+// it scroll across the water in different directions and at different scales
+pub fn ripple_normal_map() -> Texture {
+    // Wave vectors in whole cycles per tile and amplitudes, longer waves are taller
+    let waves: Vec<(Vec2, f32, f32)> = (0..24)
+        .map(|wave| {
+            let random = |dimension| random::uniform(wave, 0, 0, dimension);
+            let angle = random(0) * TAU;
+            let cycles = 1.0 + (random(1) * 7.0).floor();
+            let direction = Vec2::from_angle(angle) * cycles;
+            (direction.round(), 0.6 / cycles, random(2) * TAU)
+        })
+        .filter(|(direction, _, _)| *direction != Vec2::ZERO)
+        .collect();
+
+    let size = NORMAL_MAP_SIZE;
+    let data = (0..size * size)
+        .map(|index| {
+            let position = Vec2::new((index % size) as f32, (index / size) as f32) / size as f32;
+            let mut slope = Vec2::ZERO;
+            for &(direction, amplitude, phase) in &waves {
+                slope += direction * (amplitude * (TAU * direction.dot(position) + phase).cos());
+            }
+            let normal = (-slope * 0.15).extend(1.0).normalize();
+            Pixel32::from_vec4(vec4(normal.x, normal.y, normal.z, 1.0) * 0.5 + 0.5, false).to_u32()
+        })
+        .collect();
+    Texture {
+        gl_id: 0,
+        width: size,
+        height: size,
+        depth: 4,
+        data,
+    }
+}
@@ -0,0 +1,38 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+// Threads parallel_map uses at most, past this loading is bound by memory bandwidth rather than cores
+const MAX_WORKERS: usize = 8;
+
+// Runs `work` on every item, spread over a thread per core, and returns the results in the items' order. A thread
+// takes the next item whenever it finishes one, so a few large items don't leave the others idle. `progress` gets
+// the number of finished items after each one, from the thread that finished it
+pub fn parallel_map<T: Sync, R: Send>(items: &[T], work: impl Fn(&T) -> R + Sync, progress: impl Fn(usize) + Sync) -> Vec<R> {
+    let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get).min(MAX_WORKERS).min(items.len());
+    let next = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            thread::Builder::new()
+                .name(format!("worker {worker}"))
+                .spawn_scoped(scope, || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    let result = work(item);
+                    results.lock().unwrap()[index] = Some(result);
+                    progress(finished.fetch_add(1, Ordering::Relaxed) + 1);
+                })
+                .expect("Failed to start a worker thread");
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(|result| result.expect("Every item was worked on")).collect()
+}